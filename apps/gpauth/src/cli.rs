@@ -1,4 +1,6 @@
-use auth::{BrowserAuthenticator, auth_prelogin};
+use std::{io::Write, path::PathBuf, time::Duration};
+
+use auth::{AutomationRecipe, BrowserAuthenticator, DEFAULT_REMOTE_TIMEOUT, SamlAutomation, auth_prelogin};
 use clap::Parser;
 use gpapi::{
   auth::{SamlAuthData, SamlAuthResult},
@@ -97,6 +99,30 @@ struct Cli {
   )]
   browser: Option<String>,
 
+  #[arg(
+    long,
+    requires = "browser",
+    help = "The address the `--browser remote` authentication server listens on, e.g., '0.0.0.0:8443'. Defaults to the local IP address with a random port"
+  )]
+  remote_bind: Option<String>,
+
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    requires = "browser",
+    default_value_t = DEFAULT_REMOTE_TIMEOUT.as_secs(),
+    help = "How long the `--browser remote` authentication waits for the authentication data"
+  )]
+  remote_timeout: u64,
+
+  #[arg(
+    long,
+    value_name = "RECIPE",
+    conflicts_with = "browser",
+    help = "Complete the SAML login without a browser, filling the IdP forms as described in the TOML recipe file. Only for IdPs that work without JavaScript"
+  )]
+  automation: Option<PathBuf>,
+
   #[cfg(feature = "webview-auth")]
   #[arg(long, help = "The HiDPI mode, useful for high-resolution screens")]
   hidpi: bool,
@@ -144,6 +170,7 @@ impl Cli {
     }
 
     let openssl_conf = self.prepare_env()?;
+    let recipe = self.automation.as_deref().map(AutomationRecipe::load).transpose()?;
 
     let server = normalize_server(&self.server)?;
     let gp_params = self.build_gp_params();
@@ -157,6 +184,17 @@ impl Cli {
       None => auth_prelogin(&server, &gp_params, self.external_browser_requested(), self.gateway).await?,
     };
 
+    if let Some(recipe) = recipe {
+      let auth_host_id = gp_params.os_profile().host_identity().host_id().to_string();
+      let automation = SamlAutomation::new(&server, &auth_request, &recipe, &gp_params);
+      let auth_result = automation.authenticate().await;
+
+      print_auth_result(auth_result, Some(&auth_host_id));
+
+      drop(openssl_conf);
+      return Ok(());
+    }
+
     #[cfg(feature = "webview-auth")]
     let browser = self
       .browser
@@ -168,7 +206,9 @@ impl Cli {
 
     if let Some(browser) = browser {
       let auth_host_id = gp_params.os_profile().host_identity().host_id().to_string();
-      let authenticator = BrowserAuthenticator::new(&auth_request, browser);
+      let authenticator = BrowserAuthenticator::new(&auth_request, browser)
+        .remote_bind(self.remote_bind.as_deref())
+        .remote_timeout(Duration::from_secs(self.remote_timeout));
       let auth_result = authenticator.authenticate().await;
 
       print_auth_result(auth_result, Some(&auth_host_id));
@@ -208,7 +248,7 @@ impl Cli {
   fn external_browser_requested(&self) -> bool {
     #[cfg(feature = "webview-auth")]
     {
-      self.default_browser || self.browser.is_some()
+      self.default_browser || self.browser.is_some() || self.automation.is_some()
     }
 
     #[cfg(not(feature = "webview-auth"))]
@@ -241,6 +281,11 @@ pub async fn run() {
     handle_error(err, &cli);
     std::process::exit(1);
   }
+
+  // The result is printed, exit without waiting for the threads left behind, e.g., the remote
+  // authentication reading the terminal, so that the caller does not hang on the output
+  let _ = std::io::stdout().flush();
+  std::process::exit(0);
 }
 
 pub fn print_auth_result(auth_result: anyhow::Result<SamlAuthData>, host_id: Option<&str>) {
@@ -375,6 +420,55 @@ mod tests {
     assert!(cli.external_browser_requested());
   }
 
+  #[test]
+  fn automation_requests_redirect_binding_and_conflicts_with_browser() {
+    let cli = Cli::try_parse_from([
+      "gpauth",
+      "portal.example.com",
+      "--automation",
+      "/etc/gpauth/recipe.toml",
+    ])
+    .expect("gpauth args should parse");
+
+    assert_eq!(
+      cli.automation.as_deref(),
+      Some(std::path::Path::new("/etc/gpauth/recipe.toml"))
+    );
+    assert!(cli.external_browser_requested());
+
+    assert!(
+      Cli::try_parse_from([
+        "gpauth",
+        "portal.example.com",
+        "--automation",
+        "recipe.toml",
+        "--browser"
+      ])
+      .is_err()
+    );
+  }
+
+  #[test]
+  fn remote_browser_options_parse() {
+    let cli = Cli::try_parse_from([
+      "gpauth",
+      "portal.example.com",
+      "--browser",
+      "remote",
+      "--remote-bind",
+      "0.0.0.0:8443",
+      "--remote-timeout",
+      "120",
+    ])
+    .expect("gpauth args should parse");
+
+    assert_eq!(cli.remote_bind.as_deref(), Some("0.0.0.0:8443"));
+    assert_eq!(cli.remote_timeout, 120);
+
+    let cli = Cli::try_parse_from(["gpauth", "portal.example.com"]).expect("gpauth args should parse");
+    assert_eq!(cli.remote_timeout, DEFAULT_REMOTE_TIMEOUT.as_secs());
+  }
+
   #[test]
   fn client_version_arg_sets_profile_client_version() {
     let cli = Cli::try_parse_from(["gpauth", "portal.example.com", "--client-version", "legacy-client"])
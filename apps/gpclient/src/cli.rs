@@ -1,11 +1,14 @@
-use std::{env::temp_dir, fs::File, str::FromStr};
+use std::{env::temp_dir, fs::File, path::PathBuf, str::FromStr};
 
 use anyhow::bail;
 use clap::{Parser, Subcommand};
 use gpapi::{
   clap::{Args, InfoLevelVerbosity, handle_error},
   log_format::{LogFormat, write_json_record},
-  utils::openssl,
+  utils::{
+    http_record::{self, HttpRecordMode},
+    openssl,
+  },
 };
 use log::info;
 use sysinfo::{Pid, System};
@@ -14,9 +17,12 @@ use tokio::fs;
 
 use crate::{
   GP_CLIENT_LOCK_FILE,
-  connect::{ConnectArgs, ConnectHandler},
+  connect::{ConnectArgs, ConnectHandler, TunnelDisconnected},
+  cookie::{CookieArgs, CookieHandler},
   disconnect::{DisconnectArgs, DisconnectHandler},
+  exec::{ExecArgs, ExecHandler},
   hip::{HipArgs, HipHandler},
+  identity::{IdentityArgs, IdentityHandler},
   launch_gui::{LaunchGuiArgs, LaunchGuiHandler},
 };
 
@@ -46,6 +52,12 @@ enum CliCommand {
   LaunchGui(LaunchGuiArgs),
   #[command(about = "Generate HIP report")]
   Hip(HipArgs),
+  #[command(about = "Run a command inside the network namespace of the VPN connection")]
+  Exec(ExecArgs),
+  #[command(about = "Manage the cached portal cookies")]
+  Cookie(CookieArgs),
+  #[command(about = "Manage the persisted host identities")]
+  Identity(IdentityArgs),
 }
 
 #[derive(Parser)]
@@ -86,6 +98,23 @@ struct Cli {
   )]
   log_format: LogFormat,
 
+  #[arg(
+    long,
+    global = true,
+    value_name = "DIR",
+    conflicts_with = "replay",
+    help = "Save the portal and gateway HTTP exchanges to the directory, with the credentials redacted"
+  )]
+  record: Option<PathBuf>,
+
+  #[arg(
+    long,
+    global = true,
+    value_name = "DIR",
+    help = "Serve the HTTP exchanges saved with `--record` from the directory instead of the network"
+  )]
+  replay: Option<PathBuf>,
+
   #[command(flatten)]
   verbose: InfoLevelVerbosity,
 }
@@ -124,6 +153,14 @@ impl Cli {
       .unwrap_or(false)
   }
 
+  fn http_record_mode(&self) -> Option<HttpRecordMode> {
+    match (&self.record, &self.replay) {
+      (Some(dir), _) => Some(HttpRecordMode::Record(dir.clone())),
+      (None, Some(dir)) => Some(HttpRecordMode::Replay(dir.clone())),
+      (None, None) => None,
+    }
+  }
+
   fn fix_openssl(&self) -> anyhow::Result<Option<NamedTempFile>> {
     if self.fix_openssl {
       let file = openssl::fix_openssl_env()?;
@@ -135,7 +172,11 @@ impl Cli {
 
   async fn run(&self) -> anyhow::Result<()> {
     // check if an instance is running
-    if !matches!(self.command, CliCommand::Disconnect(_)) && self.is_running().await {
+    if !matches!(
+      self.command,
+      CliCommand::Disconnect(_) | CliCommand::Exec(_) | CliCommand::Cookie(_) | CliCommand::Identity(_)
+    ) && self.is_running().await
+    {
       bail!("Another instance of the client is already running");
     }
 
@@ -153,11 +194,18 @@ impl Cli {
       info!("TLS errors will be ignored");
     }
 
+    if let Some(mode) = self.http_record_mode() {
+      http_record::init(mode)?;
+    }
+
     match &self.command {
       CliCommand::Connect(args) => ConnectHandler::new(args, &shared_args).handle().await,
       CliCommand::Disconnect(args) => DisconnectHandler::new(args).handle().await,
       CliCommand::LaunchGui(args) => LaunchGuiHandler::new(args).handle().await,
       CliCommand::Hip(args) => HipHandler::new(args).handle().await,
+      CliCommand::Exec(args) => ExecHandler::new(args).handle().await,
+      CliCommand::Cookie(args) => CookieHandler::new(args, &shared_args).handle().await,
+      CliCommand::Identity(args) => IdentityHandler::new(args, &shared_args).handle().await,
     }
   }
 }
@@ -194,11 +242,20 @@ pub(crate) async fn run() {
   info!("gpclient started: {}", VERSION);
 
   if let Err(err) = cli.run().await {
+    let exit_code = process_exit_code(&err);
     handle_error(err, &cli);
-    std::process::exit(1);
+    std::process::exit(exit_code);
   }
 }
 
+/// A tunnel that ended on its own exits with a code per disconnect reason, other failures with 1
+fn process_exit_code(err: &anyhow::Error) -> i32 {
+  err
+    .chain()
+    .find_map(|err| err.downcast_ref::<TunnelDisconnected>())
+    .map_or(1, TunnelDisconnected::process_exit_code)
+}
+
 #[cfg(test)]
 mod tests {
   use std::{
@@ -243,6 +300,24 @@ mod tests {
     assert!(msg.contains("json"), "error should list the valid values: {msg}");
   }
 
+  #[test]
+  fn record_and_replay_select_the_http_record_mode() {
+    assert!(parse_cli(&["gpclient", "disconnect"]).http_record_mode().is_none());
+    assert!(matches!(
+      parse_cli(&["gpclient", "connect", "portal.example.com", "--record", "/tmp/rec"]).http_record_mode(),
+      Some(HttpRecordMode::Record(dir)) if dir == PathBuf::from("/tmp/rec")
+    ));
+    assert!(matches!(
+      parse_cli(&["gpclient", "--replay", "/tmp/rec", "connect", "portal.example.com"]).http_record_mode(),
+      Some(HttpRecordMode::Replay(dir)) if dir == PathBuf::from("/tmp/rec")
+    ));
+
+    let err = Cli::try_parse_from(["gpclient", "--record", "/a", "--replay", "/b", "disconnect"])
+      .err()
+      .expect("--record and --replay should conflict");
+    assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+  }
+
   /// A `Write` that keeps what was written, so the logger can be driven for
   /// real instead of asserting on how it was configured.
   #[derive(Clone, Default)]
@@ -3,10 +3,10 @@ use gpapi::{
   clap::args::Os,
   cookie_store,
   gp_params::CscMode,
-  os_profile::{ClientOs, OsProfile},
+  os_profile::{ClientOs, OsProfile, identity_store},
 };
 use log::warn;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Args)]
 pub(crate) struct ConnectArgs {
@@ -32,6 +32,12 @@ pub(crate) struct ConnectArgs {
   #[arg(long, help = "Read the gpauth authentication result from standard input")]
   pub(super) cookie_on_stdin: bool,
 
+  #[arg(
+    long,
+    help = "Do not answer a Kerberos (SPNEGO) challenge with the ticket from the credential cache, use SAML or password authentication instead"
+  )]
+  pub(super) no_kerberos: bool,
+
   #[arg(
     long,
     help = "Read and write the portal cookie cache, optionally specify the cache file path",
@@ -40,6 +46,20 @@ pub(crate) struct ConnectArgs {
   )]
   pub(super) cookie_cache: Option<String>,
 
+  #[arg(
+    long,
+    value_name = "PATH",
+    help = "The persisted host identity file, defaults to the one `gpclient identity` manages"
+  )]
+  pub(super) identity: Option<String>,
+
+  #[arg(
+    long,
+    conflicts_with = "identity",
+    help = "Collect the host identity on every run instead of reusing the persisted one"
+  )]
+  pub(super) no_persist_identity: bool,
+
   #[arg(long, short, help = "The VPNC script to use", required_if_eq("script_tun", "true"))]
   pub(super) script: Option<String>,
 
@@ -49,31 +69,53 @@ pub(crate) struct ConnectArgs {
   #[arg(long, short = 'S', help = "Pass traffic to '--script' program, not tun")]
   pub(super) script_tun: bool,
 
+  #[arg(
+    long,
+    conflicts_with = "script_tun",
+    help = "Move the tunnel interface into the network namespace, create it if missing. Use `gpclient exec --netns` to run commands in it"
+  )]
+  pub(super) netns: Option<String>,
+
   #[arg(long, help = "Connect the server as a gateway, instead of a portal")]
   pub(super) as_gateway: bool,
 
   #[arg(
     long,
-    help = "Use HIP (Host Integrity Protection) extension, optionally specify the HIP script path",
+    help = "Use HIP (Host Integrity Protection) extension. Without a value openconnect runs the installed HIP script as a CSD wrapper, specify a HIP script path to run it instead, or `native` to have gpclient generate and submit the report itself",
     default_missing_value = "",
     num_args=0..=1
   )]
   pub(super) hip: Option<String>,
 
+  #[arg(
+    long,
+    requires = "hip",
+    help = "TOML file declaring the HIP categories (anti-malware, disk encryption, firewall, patch management, DLP) to report, overriding the detected ones. Applies to the native HIP submission"
+  )]
+  pub(super) hip_profile: Option<PathBuf>,
+
   #[arg(long, help = "The user used to run the HIP script")]
   pub(super) hip_user: Option<String>,
 
   #[arg(
     short,
     long,
-    help = "Use SSL client certificate file in pkcs#8 (.pem) or pkcs#12 (.p12, .pfx) format"
+    help = "Use SSL client certificate file in pkcs#8 (.pem) or pkcs#12 (.p12, .pfx) format, or a pkcs11: URI"
   )]
   pub(super) certificate: Option<String>,
 
-  #[arg(short = 'k', long, help = "Use SSL private key file in pkcs#8 (.pem) format")]
+  #[arg(
+    short = 'k',
+    long,
+    help = "Use SSL private key file in pkcs#8 (.pem) format, or a pkcs11: URI"
+  )]
   pub(super) sslkey: Option<String>,
 
-  #[arg(short = 'p', long, help = "The key passphrase of the private key")]
+  #[arg(
+    short = 'p',
+    long,
+    help = "The key passphrase of the private key, or the PIN of the pkcs11: token"
+  )]
   pub(super) key_password: Option<String>,
 
   #[arg(long, hide = true)]
@@ -106,6 +148,20 @@ pub(crate) struct ConnectArgs {
   #[arg(long, value_enum, default_value_t = CscMode::Auto, help = "CSC support mode: auto, yes, or no")]
   pub(super) csc: CscMode,
 
+  #[arg(
+    long,
+    value_name = "FILE",
+    help = "TOML or YAML file answering the portal's client-side checks (plist values, registry keys and values, running processes) instead of the system"
+  )]
+  pub(super) csc_answers: Option<PathBuf>,
+
+  #[arg(
+    long,
+    conflicts_with = "as_gateway",
+    help = "Print the client-side checks requested by the portal as a `--csc-answers` template, then exit without connecting"
+  )]
+  pub(super) csc_dump: bool,
+
   #[arg(long, help = "Disable DTLS and ESP")]
   pub(super) no_dtls: bool,
 
@@ -146,14 +202,36 @@ pub(crate) struct ConnectArgs {
     num_args=0..=1
   )]
   pub(super) browser: Option<String>,
+
+  #[arg(
+    long,
+    requires = "browser",
+    help = "The address the `--browser remote` authentication server listens on, e.g., '0.0.0.0:8443'"
+  )]
+  pub(super) remote_bind: Option<String>,
+
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    requires = "browser",
+    help = "How long the `--browser remote` authentication waits for the authentication data"
+  )]
+  pub(super) remote_timeout: Option<u64>,
 }
 
-pub(super) fn build_os_profile(args: &ConnectArgs) -> OsProfile {
-  build_os_profile_with_host_id(args, None)
+pub(super) fn build_os_profile(args: &ConnectArgs, identity_store: Option<&Path>) -> OsProfile {
+  build_os_profile_with_host_id(args, identity_store, None)
 }
 
-pub(super) fn build_os_profile_with_host_id(args: &ConnectArgs, host_id: Option<&str>) -> OsProfile {
+pub(super) fn build_os_profile_with_host_id(
+  args: &ConnectArgs,
+  identity_store: Option<&Path>,
+  host_id: Option<&str>,
+) -> OsProfile {
   let mut builder = OsProfile::builder(ClientOs::from(args.os));
+  if let Some(path) = identity_store {
+    builder = builder.identity_store(path);
+  }
   if let Some(client_version) = args.client_version.as_deref() {
     builder = builder.client_version(client_version.to_string());
   }
@@ -166,6 +244,10 @@ pub(super) fn build_os_profile_with_host_id(args: &ConnectArgs, host_id: Option<
   builder.build()
 }
 
+pub(super) fn identity_store_path(args: &ConnectArgs) -> Option<PathBuf> {
+  (!args.no_persist_identity).then(|| identity_store::identity_path(args.identity.as_deref()))
+}
+
 pub(super) fn cookie_cache_path(args: &ConnectArgs) -> Option<PathBuf> {
   args.cookie_cache.as_deref().map(|path| {
     let custom = (!path.is_empty()).then_some(path);
@@ -232,6 +314,38 @@ mod tests {
     assert!(help.contains("Automatically try gateways in priority order until gateway auth/config succeeds"));
   }
 
+  #[test]
+  fn kerberos_is_enabled_by_default() {
+    use clap::Parser;
+
+    let cli = ConnectArgsTestCli::try_parse_from(["test", "portal.example.com"]).unwrap();
+    assert!(!cli.args.no_kerberos);
+
+    let cli = ConnectArgsTestCli::try_parse_from(["test", "portal.example.com", "--no-kerberos"]).unwrap();
+    assert!(cli.args.no_kerberos);
+  }
+
+  #[test]
+  fn remote_browser_options_require_browser() {
+    use clap::Parser;
+
+    let cli = ConnectArgsTestCli::try_parse_from([
+      "test",
+      "portal.example.com",
+      "--browser",
+      "remote",
+      "--remote-bind",
+      "0.0.0.0:8443",
+      "--remote-timeout",
+      "300",
+    ])
+    .unwrap();
+    assert_eq!(cli.args.remote_bind.as_deref(), Some("0.0.0.0:8443"));
+    assert_eq!(cli.args.remote_timeout, Some(300));
+
+    assert!(ConnectArgsTestCli::try_parse_from(["test", "portal.example.com", "--remote-timeout", "300"]).is_err());
+  }
+
   #[test]
   fn gateway_flag_alone_parses() {
     use clap::Parser;
@@ -311,7 +425,7 @@ mod tests {
     ])
     .expect("deprecated profile override flags should remain parse-compatible");
 
-    let profile = build_os_profile(&cli.args);
+    let profile = build_os_profile(&cli.args, None);
 
     assert_ne!(profile.user_agent(), "legacy-agent");
     assert_ne!(profile.os_version(), "legacy-os");
@@ -326,7 +440,7 @@ mod tests {
     let cli = ConnectArgsTestCli::try_parse_from(["test", "portal.example.com", "--os", "Linux"])
       .expect("connect args should parse");
 
-    let profile = build_os_profile_with_host_id(&cli.args, Some("auth-host-id"));
+    let profile = build_os_profile_with_host_id(&cli.args, None, Some("auth-host-id"));
 
     assert_eq!(profile.host_identity().host_id(), "auth-host-id");
   }
@@ -384,6 +498,31 @@ mod tests {
     assert_eq!(cli.args.csd_wrapper.as_deref(), Some("/tmp/legacy-hip.sh"));
   }
 
+  #[test]
+  fn netns_conflicts_with_script_tun() {
+    use clap::Parser;
+    use clap::error::ErrorKind;
+
+    let cli = ConnectArgsTestCli::try_parse_from(["test", "portal.example.com", "--netns", "corp"])
+      .expect("--netns should parse");
+    assert_eq!(cli.args.netns.as_deref(), Some("corp"));
+
+    let result = ConnectArgsTestCli::try_parse_from([
+      "test",
+      "portal.example.com",
+      "--netns",
+      "corp",
+      "--script",
+      "/tmp/tun.sh",
+      "--script-tun",
+    ]);
+    let err = match result {
+      Ok(_) => panic!("--netns and --script-tun must conflict"),
+      Err(err) => err,
+    };
+    assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+  }
+
   #[test]
   fn auto_gateway_and_gateway_are_mutually_exclusive() {
     use clap::Parser;
@@ -398,4 +537,47 @@ mod tests {
     };
     assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
   }
+
+  #[test]
+  fn hip_profile_requires_hip() {
+    use clap::Parser;
+    use clap::error::ErrorKind;
+
+    let result = ConnectArgsTestCli::try_parse_from(["test", "portal.example.com", "--hip-profile", "/etc/hip.toml"]);
+    let err = match result {
+      Ok(_) => panic!("--hip-profile must require --hip"),
+      Err(err) => err,
+    };
+    assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+
+    let cli =
+      ConnectArgsTestCli::try_parse_from(["test", "portal.example.com", "--hip", "--hip-profile", "/etc/hip.toml"])
+        .expect("connect args should parse");
+    assert_eq!(cli.args.hip.as_deref(), Some(""));
+    assert_eq!(cli.args.hip_profile, Some(PathBuf::from("/etc/hip.toml")));
+  }
+
+  #[test]
+  fn csc_dump_conflicts_with_as_gateway() {
+    use clap::Parser;
+    use clap::error::ErrorKind;
+
+    let result = ConnectArgsTestCli::try_parse_from(["test", "gateway.example.com", "--as-gateway", "--csc-dump"]);
+    let err = match result {
+      Ok(_) => panic!("--csc-dump must conflict with --as-gateway"),
+      Err(err) => err,
+    };
+    assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+
+    let cli = ConnectArgsTestCli::try_parse_from([
+      "test",
+      "portal.example.com",
+      "--csc-answers",
+      "/etc/gpclient/csc.yaml",
+      "--csc-dump",
+    ])
+    .expect("connect args should parse");
+    assert!(cli.args.csc_dump);
+    assert_eq!(cli.args.csc_answers, Some(PathBuf::from("/etc/gpclient/csc.yaml")));
+  }
 }
@@ -0,0 +1,94 @@
+use gpapi::{
+  error::ClientUpgradeRequired,
+  os_profile::OsProfile,
+  portal::client_version::{self, KnownVersions, compare_versions},
+};
+use log::{info, warn};
+
+use super::{ConnectArgs, ConnectHandler};
+
+/// The version negotiated with the portal on an earlier run, when it is newer than the default one
+pub(super) fn apply_remembered_version(args: &ConnectArgs, profile: OsProfile) -> OsProfile {
+  if args.client_version.is_some() {
+    return profile;
+  }
+
+  let path = client_version::version_path(None);
+  let known = client_version::load(&path, &args.server, profile.client_os());
+  match remembered_version(&known, profile.client_version()) {
+    Some(version) => {
+      info!(
+        "Using the client version negotiated with the portal before: {}",
+        version
+      );
+      profile.with_client_version(version)
+    }
+    None => profile,
+  }
+}
+
+fn remembered_version<'a>(known: &'a KnownVersions, current: &str) -> Option<&'a str> {
+  known
+    .negotiated
+    .as_deref()
+    .filter(|version| compare_versions(version, current).is_gt())
+}
+
+impl ConnectHandler<'_> {
+  /// Switch to a client version the server accepts after it rejected the reported one, the error is
+  /// returned when it is another error or there is no newer version to try
+  pub(super) fn negotiate_client_version(&self, portal: &str, err: anyhow::Error) -> anyhow::Result<()> {
+    let Some(rejected) = err.root_cause().downcast_ref::<ClientUpgradeRequired>() else {
+      return Err(err);
+    };
+
+    if self.args.client_version.is_some() {
+      return Err(err.context("The server rejected the version given with `--client-version`"));
+    }
+
+    let os_profile = self.os_profile.borrow().clone();
+    let known = client_version::load(&client_version::version_path(None), portal, os_profile.client_os());
+    let Some(version) = known.next_version(rejected, os_profile.client_version()) else {
+      return Err(err.context("No newer client version is known, specify one with `--client-version`"));
+    };
+
+    warn!(
+      "The server rejected client version {}, retrying with {}",
+      os_profile.client_version(),
+      version
+    );
+    self.os_profile.replace(os_profile.with_client_version(&version));
+    self.negotiated_client_version.replace(Some(version));
+
+    Ok(())
+  }
+
+  /// Remember the negotiated client version, and the version the portal config advertises as a
+  /// candidate for the next negotiation
+  pub(super) fn remember_client_version(&self, portal: &str, portal_version: Option<&str>) {
+    let negotiated = self.negotiated_client_version.borrow();
+    let path = client_version::version_path(None);
+    let client_os = self.os_profile.borrow().client_os();
+
+    if let Err(err) = client_version::remember(&path, portal, client_os, negotiated.as_deref(), portal_version) {
+      warn!("Failed to save the client version to {}: {}", path.display(), err);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn remembered_version_must_be_newer_than_the_default() {
+    let known = KnownVersions {
+      negotiated: Some("6.2.0-10".to_string()),
+      portal: Some("6.3.0-33".to_string()),
+    };
+
+    assert_eq!(remembered_version(&known, "6.1.0-5"), Some("6.2.0-10"));
+    assert_eq!(remembered_version(&known, "6.2.0-10"), None);
+    assert_eq!(remembered_version(&KnownVersions::default(), "6.1.0-5"), None);
+  }
+}
@@ -2,7 +2,7 @@ use anyhow::{Context, bail};
 use gpapi::{
   auth::SamlAuthResult,
   clap::ToVerboseArg,
-  credential::{Credential, PasswordCredential},
+  credential::{Credential, PasswordCredential, PreloginCredential},
   portal::{Prelogin, StandardPrelogin},
   process::auth_launcher::SamlAuthLauncher,
 };
@@ -85,6 +85,8 @@ impl ConnectHandler<'_> {
           .sslkey(self.args.sslkey.as_deref())
           .key_password(key_password.as_deref())
           .browser(browser)
+          .remote_bind(self.args.remote_bind.as_deref())
+          .remote_timeout(self.args.remote_timeout)
           .log_format(self.shared_args.log_format)
           .verbose(verbose);
 
@@ -133,6 +135,12 @@ impl ConnectHandler<'_> {
 
         Ok(password_cred.into())
       }
+
+      Prelogin::Kerberos(prelogin) => {
+        info!("Authenticated as {} with Kerberos", prelogin.username());
+        let cred = PreloginCredential::new(prelogin.username(), Some(prelogin.prelogin_cookie()), None, None);
+        Ok(Credential::Prelogin(cred))
+      }
     }
   }
 
@@ -1,30 +1,40 @@
 use std::{
   fs,
+  path::PathBuf,
   sync::{
-    Arc, Mutex,
+    Arc,
     atomic::{AtomicBool, Ordering},
   },
+  time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use anyhow::bail;
 use gpapi::{
   clap::report,
-  cookie_store,
+  cookie_store::{self, StoredCookie},
   credential::{AuthCookieCredential, Credential},
-  gateway::{GatewayLogin, GatewayLoginContext, SessionExtensionAuth, gateway_login, gateway_login_with_context},
+  gateway::{
+    DEFAULT_HIP_REPORT_INTERVAL, GatewayLogin, GatewayLoginContext, GatewaySelection, HipReportContext,
+    SessionExtensionAuth, gateway_login, gateway_login_with_context,
+  },
   gp_params::GpParams,
-  os_profile::OsProfile,
-  portal::prelogin,
+  os_profile::{ClientOs, OsProfile},
+  portal::{gateway_cache::CachedGateways, prelogin},
   process::users::{get_non_root_user, get_user_by_name},
-  utils::shutdown_signal,
+  service::vpn_state::{DisconnectReason, TunnelInfo},
+  session::SessionInfo,
+  utils::{pkcs11, shutdown_signal},
 };
 use inquire::Text;
 use log::{Level, info, warn};
-use openconnect::{Vpn, VpnBuilder};
-use tokio::{runtime::Handle, task::JoinHandle};
+use openconnect::{Vpn, VpnBuilder, VpnEvent, VpnExitReason, find_csd_wrapper};
 
 use crate::{
   GP_CLIENT_LOCK_FILE,
-  session::{SessionContextInput, build_session_context, session_info_from_vpn, spawn_session_runtime_with_info},
+  hip::{NativeHipReportGenerator, WrapperHipReportGenerator, spawn_hip_runtime},
+  session::{
+    SessionContextInput, build_session_context, report_disconnect, report_tunnel_info, spawn_session_runtime_with_info,
+  },
 };
 
 use super::{ConnectHandler, args::cookie_cache_path};
@@ -36,6 +46,15 @@ struct GatewayLoginSession {
   extension_auth: SessionExtensionAuth,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HipMode {
+  Disabled,
+  /// gpclient runs the hipreportcheck/hipreport exchange itself once the tunnel is up
+  Native,
+  /// openconnect runs the given script as a CSD wrapper, the installed one when empty
+  Wrapper(String),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum GatewayConnectFailureStage {
   BeforeTunnel,
@@ -76,38 +95,99 @@ impl GatewayConnectError {
   }
 }
 
+/// The tunnel ended without a disconnect request, the reason decides the process exit code
+#[derive(Debug)]
+pub(crate) struct TunnelDisconnected {
+  reason: DisconnectReason,
+  exit_code: i32,
+}
+
+impl TunnelDisconnected {
+  /// The exit code of `gpclient connect`, 1 is kept for the other failures
+  pub(crate) fn process_exit_code(&self) -> i32 {
+    match self.reason {
+      DisconnectReason::UserRequested | DisconnectReason::Error => 1,
+      DisconnectReason::SessionExpired => 10,
+      DisconnectReason::GatewayLogout => 11,
+      DisconnectReason::AuthFailed => 12,
+      DisconnectReason::DpdTimeout => 13,
+      DisconnectReason::NetworkLost => 14,
+      DisconnectReason::ConnectFailed => 15,
+    }
+  }
+}
+
+impl std::fmt::Display for TunnelDisconnected {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "VPN disconnected ({}), openconnect exited with status {}",
+      self.reason, self.exit_code
+    )
+  }
+}
+
+impl std::error::Error for TunnelDisconnected {}
+
 impl ConnectHandler<'_> {
   pub(super) async fn try_cached_cookie(&self, server: &str) -> Option<()> {
     let path = cookie_cache_path(self.args)?;
     let host_id = self.os_profile.borrow().host_identity().host_id().to_string();
-    let stored = cookie_store::load(&path, server, &host_id)?;
+    let stored = cookie_store::load(&path, server, self.args.user.as_deref(), &host_id)?;
+
+    let cred: Credential = (&stored.auth_cookie).into();
+    let mut gp_params = self.build_gp_params();
+    gp_params.set_is_gateway(true);
+
+    if let Some(gateway_cookie) = stored.gateway_cookie() {
+      info!(
+        "Reconnecting to {} with the cached gateway cookie (expires_at={:?})",
+        stored.last_gateway, stored.gateway_cookie_expires_at
+      );
+
+      let extension_auth = SessionExtensionAuth::new(cred.clone(), gp_params.clone());
+      match self
+        .connect_gateway(server, &stored.last_gateway, gateway_cookie, false, extension_auth)
+        .await
+      {
+        Ok(()) => return Some(()),
+        Err(err) if err.is_before_tunnel() => {
+          warn!(
+            "Cached gateway cookie rejected by gateway {}: {}. Falling back to the portal cookie.",
+            stored.last_gateway,
+            err.as_error()
+          );
+          cookie_store::remove_gateway_cookie(&path, &stored);
+        }
+        Err(err) => {
+          warn!("Gateway connect failed after cached gateway cookie: {}", err.as_error());
+          return None;
+        }
+      }
+    }
 
     if !stored.auth_cookie.can_authenticate_gateway() {
       warn!(
-        "Cached portal cookie for {} is not usable for gateway authentication. Clearing cache.",
+        "Cached portal cookie for {} is not usable for gateway authentication. Removing it from the cache.",
         stored.server
       );
-      cookie_store::clear(&path);
+      cookie_store::remove(&path, &stored);
       return None;
     }
 
     info!(
-      "Using cached portal cookie for {} (saved_at={}, gateway={})",
-      stored.server, stored.saved_at, stored.last_gateway
+      "Using cached portal cookie for {} (user={}, saved_at={}, expires_at={:?}, gateway={})",
+      stored.server, stored.username, stored.saved_at, stored.expires_at, stored.last_gateway
     );
 
-    let cred: Credential = (&stored.auth_cookie).into();
-    let mut gp_params = self.build_gp_params();
-    gp_params.set_is_gateway(true);
-
     let login_session = match self.login_gateway(&stored.last_gateway, &cred, &gp_params, None).await {
       Ok(session) => session,
       Err(err) => {
         warn!(
-          "Cached portal cookie rejected by gateway {}: {}. Clearing cache and falling back to portal auth.",
+          "Cached portal cookie rejected by gateway {}: {}. Removing it from the cache and falling back to portal auth.",
           stored.last_gateway, err
         );
-        cookie_store::clear(&path);
+        cookie_store::remove(&path, &stored);
         return None;
       }
     };
@@ -137,20 +217,111 @@ impl ConnectHandler<'_> {
     allow_extend_session: bool,
     gateway_context: Option<GatewayLoginContext>,
   ) -> anyhow::Result<()> {
+    self
+      .try_gateway_with_prelogin(portal, gateway, allow_extend_session, gateway_context)
+      .await
+      .map_err(GatewayConnectError::into_error)
+  }
+
+  /// Authenticate against the gateways the portal pushed last time, in priority order, when the
+  /// portal cannot be reached. The next gateway is tried while the tunnel is not up yet.
+  pub(super) async fn connect_cached_gateways(&self, portal: &str, cached: &CachedGateways) -> anyhow::Result<()> {
+    let gateways = cached
+      .gateways_by_priority()
+      .into_iter()
+      .filter(|gateway| {
+        self
+          .args
+          .gateway
+          .as_deref()
+          .is_none_or(|selected| gateway.name() == selected || gateway.server() == selected)
+      })
+      .collect::<Vec<_>>();
+
+    if gateways.is_empty() {
+      bail!(
+        "None of the cached gateways of {} matches the specified gateway",
+        portal
+      );
+    }
+
+    info!(
+      "Portal unreachable, trying {} cached gateway(s) in priority order",
+      gateways.len()
+    );
+
+    let selection = if self.args.gateway.is_some() {
+      GatewaySelection::Manual
+    } else {
+      GatewaySelection::Auto
+    };
+
+    let mut last_err = None;
+    for gateway in &gateways {
+      info!("Cached gateway: attempting gateway {}", gateway);
+      let gateway_context = GatewayLoginContext::new(gateway, selection).with_connect_method(cached.connect_method());
+
+      match self
+        .try_gateway_with_prelogin(portal, gateway.server(), false, Some(gateway_context))
+        .await
+      {
+        Ok(()) => return Ok(()),
+        Err(err) if err.is_before_tunnel() => {
+          warn!(
+            "Cached gateway {} failed before tunnel setup: {}",
+            gateway,
+            err.as_error()
+          );
+          last_err = Some(err.into_error());
+        }
+        Err(err) => return Err(err.into_error()),
+      }
+    }
+
+    let detail = last_err
+      .map(|e| e.to_string())
+      .unwrap_or_else(|| "unknown error".to_string());
+    bail!(
+      "All {} cached gateway(s) failed to connect; last error: {}",
+      gateways.len(),
+      detail
+    );
+  }
+
+  async fn try_gateway_with_prelogin(
+    &self,
+    portal: &str,
+    gateway: &str,
+    allow_extend_session: bool,
+    gateway_context: Option<GatewayLoginContext>,
+  ) -> Result<(), GatewayConnectError> {
     info!("Performing the gateway authentication...");
 
-    let mut gp_params = self.build_gp_params();
-    gp_params.set_is_gateway(true);
+    let gateway_params = || {
+      let mut gp_params = self.build_gp_params();
+      gp_params.set_is_gateway(true);
+      gp_params
+    };
 
     let gateway_browser_auth_allowed = true;
-    let prelogin = prelogin(gateway, &gp_params, self.direct_gateway_prelogin_options()).await?;
+    let prelogin = loop {
+      match prelogin(gateway, &gateway_params(), self.direct_gateway_prelogin_options()).await {
+        Ok(prelogin) => break prelogin,
+        Err(err) => self
+          .negotiate_client_version(portal, err)
+          .map_err(GatewayConnectError::before_tunnel)?,
+      }
+    };
     let cred = self
       .obtain_credential(&prelogin, gateway, gateway_browser_auth_allowed)
-      .await?;
+      .await
+      .map_err(GatewayConnectError::before_tunnel)?;
 
     let login_session = self
-      .login_gateway(gateway, &cred, &gp_params, gateway_context.as_ref())
-      .await?;
+      .login_gateway(gateway, &cred, &gateway_params(), gateway_context.as_ref())
+      .await
+      .map_err(GatewayConnectError::before_tunnel)?;
+    self.remember_client_version(portal, None);
 
     self
       .connect_gateway(
@@ -161,7 +332,6 @@ impl ConnectHandler<'_> {
         login_session.extension_auth,
       )
       .await
-      .map_err(GatewayConnectError::into_error)
   }
 
   /// Connect to a gateway using the official client's auth flow:
@@ -263,13 +433,14 @@ impl ConnectHandler<'_> {
       return;
     }
 
-    let stored = cookie_store::StoredCookie::new(
+    let stored = StoredCookie::new(
       portal.to_string(),
       auth_cookie.username().to_string(),
       self.os_profile.borrow().host_identity().host_id().to_string(),
       gateway.to_string(),
       auth_cookie.clone(),
-    );
+    )
+    .with_lifetime(*self.portal_cookie_lifetime.borrow());
 
     match cookie_store::save(&path, &stored) {
       Ok(()) => info!("Saved portal cookie cache to {}", path.display()),
@@ -277,6 +448,20 @@ impl ConnectHandler<'_> {
     }
   }
 
+  /// Where to cache the gateway cookie of the tunnel, and the entry it goes into
+  fn gateway_cookie_cache(&self, portal: &str, gateway: &str, username: &str) -> Option<(PathBuf, StoredCookie)> {
+    let path = cookie_cache_path(self.args)?;
+    let stored = StoredCookie::new(
+      portal.to_string(),
+      username.to_string(),
+      self.os_profile.borrow().host_identity().host_id().to_string(),
+      gateway.to_string(),
+      AuthCookieCredential::new(username, "", ""),
+    );
+
+    Some((path, stored))
+  }
+
   fn print_direct_gateway_recommendation(&self, gateway: &str) {
     if !self.args.cookie_on_stdin {
       return;
@@ -342,10 +527,16 @@ impl ConnectHandler<'_> {
     extension_auth: SessionExtensionAuth,
   ) -> Result<(), GatewayConnectError> {
     let mtu = self.args.mtu.unwrap_or(0);
-    let (hip, csd_wrapper) = self.determine_hip_script();
+    let hip_mode = determine_hip_mode(self.args.hip.as_deref(), self.args.csd_wrapper.as_deref());
+    let (hip, csd_wrapper) = match &hip_mode {
+      HipMode::Wrapper(csd_wrapper) => (true, Some(csd_wrapper.clone())),
+      HipMode::Disabled | HipMode::Native => (false, None),
+    };
     let hip_user = self.determine_hip_user();
     let csd_uid = get_uid(&hip_user).map_err(GatewayConnectError::before_tunnel)?;
     let os_profile = self.os_profile.borrow().clone();
+    let gateway_cookie_cache = self.gateway_cookie_cache(portal, gateway, extension_auth.username());
+    let gateway_cookie = cookie.to_string();
 
     let session_ctx = build_session_context(SessionContextInput {
       portal: portal.to_string(),
@@ -356,14 +547,30 @@ impl ConnectHandler<'_> {
       sslkey: self.args.sslkey.clone(),
       key_password: self.latest_key_password.borrow().clone(),
       disable_ipv6: self.args.disable_ipv6,
+      ignore_tls_errors: self.shared_args.ignore_tls_errors,
       extension_auth: Some(extension_auth),
     });
+    let native_hip = (hip_mode == HipMode::Native).then(|| {
+      let generator = NativeHipReportGenerator::new(
+        self.args.os,
+        os_profile.client_version().to_string(),
+        os_profile.host_identity().host_id().to_string(),
+      )
+      .with_hip_profile(self.args.hip_profile.clone());
+      let fallback = wrapper_hip_fallback(self.args.csd_wrapper.as_deref(), csd_uid, os_profile.client_os());
+      let interval = self
+        .hip_report_interval
+        .borrow()
+        .map_or(DEFAULT_HIP_REPORT_INTERVAL, Duration::from_secs);
+      (session_ctx.session_args().clone(), generator, fallback, interval)
+    });
     let vpn_builder = Vpn::builder(gateway, cookie)
       .script(self.args.script.clone())
       .interface(self.args.interface.clone())
       .script_tun(self.args.script_tun)
+      .netns(self.args.netns.clone())
       .certificate(self.args.certificate.clone())
-      .sslkey(self.args.sslkey.clone())
+      .sslkey(self.tunnel_sslkey())
       .key_password(self.latest_key_password.borrow().clone())
       .hip(hip)
       .csd_uid(csd_uid)
@@ -379,42 +586,88 @@ impl ConnectHandler<'_> {
       .build()
       .map_err(|err| GatewayConnectError::before_tunnel(err.into()))?;
 
-    let vpn = Arc::new(vpn);
-    let vpn_clone = vpn.clone();
-    let runtime_handle = Handle::current();
-    let session_ctx = Arc::new(Mutex::new(Some(session_ctx)));
-    let session_task: Arc<Mutex<Option<JoinHandle<()>>>> = Arc::new(Mutex::new(None));
-    let session_task_on_connect = Arc::clone(&session_task);
-    let session_ctx_on_connect = Arc::clone(&session_ctx);
-    let tunnel_established = Arc::new(AtomicBool::new(false));
-    let tunnel_established_on_connect = Arc::clone(&tunnel_established);
+    let mut handle = vpn.start();
+    let controller = handle.controller();
+    let mut session_ctx = Some(session_ctx);
+    let mut native_hip = native_hip;
+    let mut session_task = None;
+    let mut hip_task = None;
+    let mut tunnel_established = false;
+    let mut connect_result = 1;
+    let mut exit_reason = VpnExitReason::Error;
     let disconnect_requested = Arc::new(AtomicBool::new(false));
     let disconnect_requested_on_signal = Arc::clone(&disconnect_requested);
 
-    tokio::spawn(async move {
+    let signal_task = tokio::spawn(async move {
       shutdown_signal().await;
       info!("Received the interrupt signal, disconnecting...");
       disconnect_requested_on_signal.store(true, Ordering::SeqCst);
-      vpn_clone.disconnect();
+      controller.disconnect().await;
     });
 
     let log_format = self.shared_args.log_format;
-    let connect_result = vpn.connect(move |vpn_session_info| {
-      tunnel_established_on_connect.store(true, Ordering::SeqCst);
-      write_pid_file();
+    while let Some(event) = handle.next_event().await {
+      match event {
+        VpnEvent::TunnelUp(vpn_session_info) => {
+          tunnel_established = true;
+          write_pid_file();
+          report_tunnel_info(log_format, &TunnelInfo::from(&vpn_session_info.tunnel_info));
+
+          if let Some((session_args, generator, fallback, interval)) = native_hip.take() {
+            match vpn_session_info.client_ip.clone() {
+              Some(client_ip) => {
+                let hip_ctx = HipReportContext::new(gateway.to_string(), session_args, client_ip)
+                  .with_client_ipv6(vpn_session_info.client_ipv6.clone());
+                hip_task = Some(spawn_hip_runtime(hip_ctx, generator, fallback, interval));
+              }
+              None => warn!("Skipping HIP check: the gateway did not assign a tunnel IPv4 address"),
+            }
+          }
+
+          if let Some((path, stored)) = &gateway_cookie_cache {
+            let expires_at = gateway_session_expires_at(vpn_session_info.user_expires, vpn_session_info.lifetime_secs);
+            match cookie_store::save_gateway_cookie(path, stored, &gateway_cookie, expires_at) {
+              Ok(()) => info!("Saved gateway cookie cache to {}", path.display()),
+              Err(err) => warn!("Failed to save gateway cookie cache to {}: {}", path.display(), err),
+            }
+          }
+
+          let Some(session_ctx) = session_ctx.take() else {
+            continue;
+          };
+          let session_info = SessionInfo {
+            allow_extend_session,
+            ..vpn_session_info.into()
+          };
+          info!("VPN session info: {}", session_info.log_summary());
+
+          session_task = Some(spawn_session_runtime_with_info(session_ctx, session_info, log_format));
+        }
+        VpnEvent::SessionInfoChanged(vpn_session_info) => {
+          report_tunnel_info(log_format, &TunnelInfo::from(&vpn_session_info.tunnel_info));
+          let session_info = SessionInfo {
+            allow_extend_session,
+            ..vpn_session_info.into()
+          };
+          info!("VPN session re-established: {}", session_info.log_summary());
+        }
+        VpnEvent::Reconnecting => info!("VPN connection lost, reconnecting..."),
+        VpnEvent::Disconnected { reason, exit_code } => {
+          report_disconnect(log_format, reason, exit_code);
+          connect_result = exit_code;
+          exit_reason = reason;
+        }
+        VpnEvent::Connecting | VpnEvent::Paused => {}
+      }
+    }
 
-      let Some(session_ctx) = session_ctx_on_connect.lock().unwrap().take() else {
-        return;
-      };
-      let session_info = session_info_from_vpn(vpn_session_info, allow_extend_session);
-      info!("VPN session info: {}", session_info.log_summary());
+    signal_task.abort();
 
-      let task = spawn_session_runtime_with_info(&runtime_handle, session_ctx, session_info, log_format);
-      session_task_on_connect.lock().unwrap().replace(task);
-    });
-    let tunnel_established = tunnel_established.load(Ordering::SeqCst);
+    if let Some(task) = session_task {
+      task.abort();
+    }
 
-    if let Some(task) = session_task.lock().unwrap().take() {
+    if let Some(task) = hip_task {
       task.abort();
     }
 
@@ -424,19 +677,20 @@ impl ConnectHandler<'_> {
     }
 
     let disconnect_requested = disconnect_requested.load(Ordering::SeqCst);
-    classify_openconnect_result(connect_result, tunnel_established, disconnect_requested)
-  }
-
-  fn determine_hip_script(&self) -> (bool, Option<String>) {
-    if let Some(hip) = &self.args.hip {
-      return if hip.is_empty() {
-        (true, None)
-      } else {
-        (true, Some(hip.clone()))
-      };
+    // Disconnecting logs out of the gateway, so the cookie is no longer valid
+    if disconnect_requested
+      && tunnel_established
+      && let Some((path, stored)) = &gateway_cookie_cache
+    {
+      cookie_store::remove_gateway_cookie(path, stored);
     }
 
-    (self.args.csd_wrapper.is_some(), self.args.csd_wrapper.clone())
+    classify_openconnect_result(
+      connect_result,
+      tunnel_established,
+      disconnect_requested,
+      exit_reason.into(),
+    )
   }
 
   fn determine_hip_user(&self) -> Option<String> {
@@ -446,12 +700,72 @@ impl ConnectHandler<'_> {
 
     self.args.csd_user.clone()
   }
+
+  /// For a `pkcs11:` certificate, pass the PIN entered for the portal to openconnect in the key URI
+  fn tunnel_sslkey(&self) -> Option<String> {
+    let Some(cert) = self
+      .args
+      .certificate
+      .as_deref()
+      .filter(|cert| pkcs11::is_pkcs11_uri(cert))
+    else {
+      return self.args.sslkey.clone();
+    };
+
+    let pin = self.latest_key_password.borrow().clone();
+    pkcs11::tunnel_key_uri(cert, self.args.sslkey.as_deref(), pin.as_deref())
+  }
+}
+
+/// When the gateway session ends, `user_expires` is absolute, the lifetime counts from now
+fn gateway_session_expires_at(user_expires: Option<u32>, lifetime_secs: Option<u32>) -> Option<u64> {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs();
+
+  user_expires
+    .map(u64::from)
+    .or_else(|| lifetime_secs.map(|lifetime| now + u64::from(lifetime)))
+}
+
+/// The `--hip` value selecting the native submission
+const NATIVE_HIP: &str = "native";
+
+fn determine_hip_mode(hip: Option<&str>, csd_wrapper: Option<&str>) -> HipMode {
+  match (hip, csd_wrapper) {
+    (Some(NATIVE_HIP), _) => HipMode::Native,
+    // A bare `--hip` lets openconnect find the installed wrapper
+    (Some(script), _) => HipMode::Wrapper(script.to_string()),
+    // The deprecated flag always meant "let openconnect run this wrapper"
+    (None, Some(csd_wrapper)) => HipMode::Wrapper(csd_wrapper.to_string()),
+    (None, None) => HipMode::Disabled,
+  }
+}
+
+/// The CSD wrapper generating the report when the native submission fails, the deprecated
+/// `--csd-wrapper` or the installed one
+fn wrapper_hip_fallback(
+  csd_wrapper: Option<&str>,
+  csd_uid: u32,
+  client_os: ClientOs,
+) -> Option<WrapperHipReportGenerator> {
+  let wrapper = csd_wrapper
+    .filter(|wrapper| !wrapper.is_empty())
+    .map(String::from)
+    .or_else(|| find_csd_wrapper().map(String::from));
+  if wrapper.is_none() {
+    info!("No CSD wrapper installed to fall back to when the native HIP submission fails");
+  }
+
+  wrapper.map(|wrapper| WrapperHipReportGenerator::new(wrapper, csd_uid, client_os.clientos().to_string()))
 }
 
 fn classify_openconnect_result(
   exit_code: i32,
   tunnel_established: bool,
   disconnect_requested: bool,
+  reason: DisconnectReason,
 ) -> Result<(), GatewayConnectError> {
   if exit_code == 0 {
     return Ok(());
@@ -461,7 +775,7 @@ fn classify_openconnect_result(
     return Ok(());
   }
 
-  let error = anyhow::anyhow!("OpenConnect exited with status {}", exit_code);
+  let error = anyhow::Error::new(TunnelDisconnected { reason, exit_code });
   if tunnel_established {
     Err(GatewayConnectError::after_tunnel(error))
   } else {
@@ -504,39 +818,123 @@ fn apply_os_profile(builder: VpnBuilder, profile: &OsProfile) -> VpnBuilder {
 mod tests {
   use super::*;
 
+  #[test]
+  fn gateway_session_expiry_prefers_the_absolute_time() {
+    assert_eq!(
+      gateway_session_expires_at(Some(1_700_000_000), Some(3600)),
+      Some(1_700_000_000)
+    );
+    assert_eq!(gateway_session_expires_at(None, None), None);
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let expires_at = gateway_session_expires_at(None, Some(3600)).unwrap();
+    assert!((now + 3600..=now + 3601).contains(&expires_at));
+  }
+
   #[test]
   fn openconnect_success_is_not_a_gateway_failure() {
-    assert!(classify_openconnect_result(0, false, false).is_ok());
-    assert!(classify_openconnect_result(0, true, false).is_ok());
+    assert!(classify_openconnect_result(0, false, false, DisconnectReason::Error).is_ok());
+    assert!(classify_openconnect_result(0, true, false, DisconnectReason::Error).is_ok());
   }
 
   #[test]
   fn openconnect_failure_before_callback_is_retryable_gateway_failure() {
-    let err = classify_openconnect_result(1, false, false).expect_err("nonzero exit should fail");
+    let err =
+      classify_openconnect_result(1, false, false, DisconnectReason::Error).expect_err("nonzero exit should fail");
 
     assert!(err.is_before_tunnel());
   }
 
   #[test]
   fn openconnect_failure_after_callback_is_terminal_gateway_failure() {
-    let err = classify_openconnect_result(1, true, false).expect_err("nonzero exit should fail");
+    let err =
+      classify_openconnect_result(1, true, false, DisconnectReason::Error).expect_err("nonzero exit should fail");
 
     assert!(!err.is_before_tunnel());
   }
 
   #[test]
   fn interrupted_exit_after_requested_disconnect_is_success() {
-    assert!(classify_openconnect_result(OPENCONNECT_INTERRUPTED_EXIT_CODE, true, true).is_ok());
+    assert!(
+      classify_openconnect_result(OPENCONNECT_INTERRUPTED_EXIT_CODE, true, true, DisconnectReason::Error).is_ok()
+    );
   }
 
   #[test]
   fn interrupted_exit_without_requested_disconnect_is_failure() {
-    let err = classify_openconnect_result(OPENCONNECT_INTERRUPTED_EXIT_CODE, true, false)
+    let err = classify_openconnect_result(OPENCONNECT_INTERRUPTED_EXIT_CODE, true, false, DisconnectReason::Error)
       .expect_err("unexpected interrupt should fail");
 
     assert!(!err.is_before_tunnel());
   }
 
+  #[test]
+  fn tunnel_failure_carries_the_disconnect_reason() {
+    let err = classify_openconnect_result(1, true, false, DisconnectReason::SessionExpired)
+      .expect_err("nonzero exit should fail")
+      .into_error();
+    let disconnected = err.downcast_ref::<TunnelDisconnected>().unwrap();
+
+    assert_eq!(disconnected.reason, DisconnectReason::SessionExpired);
+    assert_eq!(disconnected.exit_code, 1);
+    assert_eq!(disconnected.process_exit_code(), 10);
+  }
+
+  #[test]
+  fn each_disconnect_reason_has_its_own_exit_code() {
+    let reasons = [
+      DisconnectReason::SessionExpired,
+      DisconnectReason::GatewayLogout,
+      DisconnectReason::AuthFailed,
+      DisconnectReason::DpdTimeout,
+      DisconnectReason::NetworkLost,
+      DisconnectReason::ConnectFailed,
+    ];
+    let mut codes = reasons
+      .iter()
+      .map(|&reason| TunnelDisconnected { reason, exit_code: 1 }.process_exit_code())
+      .collect::<Vec<_>>();
+    codes.sort();
+    codes.dedup();
+
+    assert_eq!(codes.len(), reasons.len());
+    assert!(!codes.contains(&1));
+  }
+
+  #[test]
+  fn hip_without_script_uses_the_installed_csd_wrapper() {
+    assert_eq!(determine_hip_mode(Some(""), None), HipMode::Wrapper(String::new()));
+    assert_eq!(determine_hip_mode(None, None), HipMode::Disabled);
+  }
+
+  #[test]
+  fn native_hip_submission_is_explicit() {
+    assert_eq!(determine_hip_mode(Some("native"), None), HipMode::Native);
+    assert_eq!(
+      determine_hip_mode(Some("native"), Some("/tmp/legacy-hip.sh")),
+      HipMode::Native
+    );
+  }
+
+  #[test]
+  fn native_hip_falls_back_to_the_given_csd_wrapper() {
+    let fallback = wrapper_hip_fallback(Some("/tmp/legacy-hip.sh"), 1000, ClientOs::Windows).unwrap();
+
+    assert_eq!(fallback.wrapper(), "/tmp/legacy-hip.sh");
+  }
+
+  #[test]
+  fn hip_script_falls_back_to_csd_wrapper() {
+    assert_eq!(
+      determine_hip_mode(Some("/usr/libexec/gpclient/hipreport.sh"), None),
+      HipMode::Wrapper("/usr/libexec/gpclient/hipreport.sh".to_string())
+    );
+    assert_eq!(
+      determine_hip_mode(None, Some("/tmp/legacy-hip.sh")),
+      HipMode::Wrapper("/tmp/legacy-hip.sh".to_string())
+    );
+  }
+
   #[test]
   fn direct_gateway_recommendation_uses_gateway_server() {
     assert_eq!(
@@ -1,37 +1,46 @@
 mod args;
+mod client_version;
 mod credential;
 mod gateway;
 
-use std::cell::RefCell;
+use std::{cell::RefCell, path::PathBuf};
 
 use anyhow::bail;
 use gpapi::{
   clap::report,
+  credential::Credential,
   error::PortalError,
   gateway::{GatewayLoginContext, GatewaySelection},
-  gp_params::GpParams,
+  gp_params::{CscMode, GpParams},
   os_profile::OsProfile,
-  portal::{PreloginOptions, prelogin, retrieve_config},
+  portal::{CscAnswers, PreloginOptions, gateway_cache, prelogin, retrieve_config, retrieve_csc_checks},
   utils::request::RequestIdentityError,
 };
 use inquire::{Password, PasswordDisplayMode, Select};
 use log::{Level, info, warn};
 
-use crate::cli::SharedArgs;
+use crate::{cli::SharedArgs, hip::HipProfile, netns::NetnsGuard};
 
 pub(crate) use args::ConnectArgs;
-use args::{build_os_profile, build_os_profile_with_host_id, warn_deprecated_connect_args};
+use args::{build_os_profile, build_os_profile_with_host_id, identity_store_path, warn_deprecated_connect_args};
+use client_version::apply_remembered_version;
 use credential::CleanAuthState;
 use gateway::GatewayConnectError;
+pub(crate) use gateway::TunnelDisconnected;
 
 pub(crate) struct ConnectHandler<'a> {
   args: &'a ConnectArgs,
   shared_args: &'a SharedArgs<'a>,
   os_profile: RefCell<OsProfile>,
+  identity_store: Option<PathBuf>,
+  negotiated_client_version: RefCell<Option<String>>,
   latest_key_password: RefCell<Option<String>>,
   password_from_stdin: RefCell<Option<String>>,
   cookie_from_stdin: RefCell<Option<String>>,
   clean_auth_state: RefCell<CleanAuthState>,
+  hip_report_interval: RefCell<Option<u64>>,
+  portal_cookie_lifetime: RefCell<Option<u64>>,
+  csc_answers: RefCell<Option<CscAnswers>>,
 }
 
 impl<'a> ConnectHandler<'a> {
@@ -43,21 +52,36 @@ impl<'a> ConnectHandler<'a> {
     #[cfg(not(feature = "webview-auth"))]
     let clean_auth = false;
 
+    let identity_store = identity_store_path(args);
+    let os_profile = apply_remembered_version(args, build_os_profile(args, identity_store.as_deref()));
+
     Self {
       args,
       shared_args,
-      os_profile: RefCell::new(build_os_profile(args)),
+      os_profile: RefCell::new(os_profile),
+      identity_store,
+      negotiated_client_version: Default::default(),
       latest_key_password: Default::default(),
       password_from_stdin: Default::default(),
       cookie_from_stdin: Default::default(),
       clean_auth_state: RefCell::new(CleanAuthState::new(clean_auth)),
+      hip_report_interval: Default::default(),
+      portal_cookie_lifetime: Default::default(),
+      csc_answers: Default::default(),
     }
   }
 
   fn build_gp_params(&self) -> GpParams {
     let mut builder = GpParams::builder(self.os_profile.borrow().clone());
+    // The portal only sends its checks to a client that supports them
+    let csc_mode = if self.args.csc_dump {
+      CscMode::Yes
+    } else {
+      self.args.csc
+    };
     builder
-      .csc_mode(self.args.csc)
+      .csc_mode(csc_mode)
+      .csc_answers(self.csc_answers.borrow().clone())
       .ignore_tls_errors(self.shared_args.ignore_tls_errors)
       .certificate(self.args.certificate.clone())
       .sslkey(self.args.sslkey.clone())
@@ -70,6 +94,7 @@ impl<'a> ConnectHandler<'a> {
     PreloginOptions::default()
       .external_browser_requested(self.external_browser_requested())
       .gateway_external_browser_allowed(gateway_browser_auth_allowed)
+      .kerberos(!self.args.no_kerberos)
   }
 
   pub(super) fn direct_gateway_prelogin_options(&self) -> PreloginOptions {
@@ -100,6 +125,18 @@ impl<'a> ConnectHandler<'a> {
 
     self.latest_key_password.replace(self.args.key_password.clone());
 
+    // Fail before authenticating rather than when the gateway first asks for the report
+    if let Some(hip_profile) = self.args.hip_profile.as_deref() {
+      HipProfile::load(hip_profile)?;
+    }
+
+    if let Some(csc_answers) = self.args.csc_answers.as_deref() {
+      self.csc_answers.replace(Some(CscAnswers::load(csc_answers)?));
+    }
+
+    // Keep the namespace for the whole connection, it is cleaned up when the tunnel goes away
+    let _netns = self.args.netns.as_deref().map(NetnsGuard::prepare).transpose()?;
+
     loop {
       let Err(err) = self.handle_impl().await else {
         return Ok(());
@@ -133,6 +170,15 @@ impl<'a> ConnectHandler<'a> {
 
           self.latest_key_password.replace(Some(password));
         }
+        RequestIdentityError::NoPin(token) | RequestIdentityError::IncorrectPin(token) => {
+          let message = format!("Enter the PIN for token {}:", token);
+          let pin = Password::new(&message)
+            .without_confirmation()
+            .with_display_mode(PasswordDisplayMode::Masked)
+            .prompt()?;
+
+          self.latest_key_password.replace(Some(pin));
+        }
       }
     }
   }
@@ -148,10 +194,11 @@ impl<'a> ConnectHandler<'a> {
       return self.connect_gateway_with_prelogin(server, server, false, None).await;
     }
 
-    if !self.args.cookie_on_stdin {
-      if let Some(()) = self.try_cached_cookie(server).await {
-        return Ok(());
-      }
+    if !self.args.cookie_on_stdin
+      && !self.args.csc_dump
+      && let Some(()) = self.try_cached_cookie(server).await
+    {
+      return Ok(());
     }
 
     let Err(err) = self.connect_portal_with_prelogin(server).await else {
@@ -159,7 +206,15 @@ impl<'a> ConnectHandler<'a> {
     };
 
     warn!("Failed to connect portal with prelogin: {}", err);
-    if err.root_cause().downcast_ref::<PortalError>().is_some() {
+    if is_portal_network_error(&err)
+      && !self.args.csc_dump
+      && let Some(cached) = gateway_cache::load(&gateway_cache::cache_path(None), server)
+    {
+      info!("Trying the gateways cached from the last portal config...");
+      return self.connect_cached_gateways(server, &cached).await;
+    }
+
+    if err.root_cause().downcast_ref::<PortalError>().is_some() && !self.args.csc_dump {
       info!("Trying the gateway authentication workflow...");
       self.connect_gateway_with_prelogin(server, server, false, None).await?;
 
@@ -181,13 +236,45 @@ impl<'a> ConnectHandler<'a> {
     }
   }
 
-  async fn connect_portal_with_prelogin(&self, portal: &str) -> anyhow::Result<()> {
-    let gp_params = self.build_gp_params();
+  async fn dump_csc_checks(&self, portal: &str, cred: &Credential, gp_params: &GpParams) -> anyhow::Result<()> {
+    match retrieve_csc_checks(portal, cred, gp_params).await? {
+      Some(checks) if !checks.is_empty() => print!("{}", checks.answers_template()?),
+      _ => report(
+        self.shared_args.log_format,
+        Level::Info,
+        "The portal did not request any client-side checks",
+      ),
+    }
 
-    let prelogin = prelogin(portal, &gp_params, self.prelogin_options(false)).await?;
+    Ok(())
+  }
+
+  async fn connect_portal_with_prelogin(&self, portal: &str) -> anyhow::Result<()> {
+    let prelogin = loop {
+      match prelogin(portal, &self.build_gp_params(), self.prelogin_options(false)).await {
+        Ok(prelogin) => break prelogin,
+        Err(err) => self.negotiate_client_version(portal, err)?,
+      }
+    };
 
     let cred = self.obtain_credential(&prelogin, portal, false).await?;
-    let mut portal_config = retrieve_config(portal, &cred, &gp_params).await?;
+    if self.args.csc_dump {
+      return self.dump_csc_checks(portal, &cred, &self.build_gp_params()).await;
+    }
+
+    let mut portal_config = loop {
+      match retrieve_config(portal, &cred, &self.build_gp_params()).await {
+        Ok(portal_config) => break portal_config,
+        Err(err) => self.negotiate_client_version(portal, err)?,
+      }
+    };
+    self.remember_client_version(portal, portal_config.version());
+
+    let cached = gateway_cache::CachedGateways::new(&portal_config, Some(prelogin.region()));
+    let cache_path = gateway_cache::cache_path(None);
+    if let Err(err) = gateway_cache::save(&cache_path, &cached) {
+      warn!("Failed to save the gateways to {}: {}", cache_path.display(), err);
+    }
 
     portal_config.sort_gateways(prelogin.region());
 
@@ -198,6 +285,8 @@ impl<'a> ConnectHandler<'a> {
     let allow_extend_session = portal_config.allow_extend_session().unwrap_or(false);
     let portal_config_default_browser = portal_config.default_browser().unwrap_or(false);
     info!("Portal config default-browser: {}", portal_config_default_browser);
+    self.hip_report_interval.replace(portal_config.hip_report_interval());
+    self.portal_cookie_lifetime.replace(portal_config.cookie_lifetime());
 
     if self.args.auto_gateway {
       let gateways = portal_config.gateways();
@@ -298,9 +387,12 @@ impl<'a> ConnectHandler<'a> {
     let Some(host_id) = host_id else {
       return;
     };
-    self
-      .os_profile
-      .replace(build_os_profile_with_host_id(self.args, Some(host_id)));
+    // Keep the client version negotiated with the portal
+    let client_version = self.os_profile.borrow().client_version().to_string();
+    self.os_profile.replace(
+      build_os_profile_with_host_id(self.args, self.identity_store.as_deref(), Some(host_id))
+        .with_client_version(client_version),
+    );
     info!(
       "connect profile host-id: {}",
       self.os_profile.borrow().host_identity().host_id()
@@ -308,13 +400,20 @@ impl<'a> ConnectHandler<'a> {
   }
 }
 
+/// Whether the portal could not be reached at all, not rejected the request
+fn is_portal_network_error(err: &anyhow::Error) -> bool {
+  err
+    .chain()
+    .any(|e| matches!(e.downcast_ref::<PortalError>(), Some(PortalError::NetworkError(_))))
+}
+
 fn gateway_browser_auth_allowed(portal_config_default_browser: bool, external_browser_requested: bool) -> bool {
   portal_config_default_browser || external_browser_requested
 }
 
 #[cfg(test)]
 mod tests {
-  use super::gateway_browser_auth_allowed;
+  use super::{PortalError, gateway_browser_auth_allowed, is_portal_network_error};
 
   #[test]
   fn gateway_browser_auth_is_allowed_by_portal_config() {
@@ -330,4 +429,17 @@ mod tests {
   fn gateway_browser_auth_is_disabled_without_portal_config_or_user_request() {
     assert!(!gateway_browser_auth_allowed(false, false));
   }
+
+  #[test]
+  fn only_network_errors_use_the_cached_gateways() {
+    let network_err = reqwest::Client::new().get("not a url").build().unwrap_err();
+    let err = anyhow::anyhow!(PortalError::NetworkError(network_err)).context("Failed to retrieve the portal config");
+    assert!(is_portal_network_error(&err));
+
+    assert!(!is_portal_network_error(&anyhow::anyhow!(PortalError::TlsError)));
+    assert!(!is_portal_network_error(&anyhow::anyhow!(PortalError::ConfigError(
+      "invalid".to_string()
+    ))));
+    assert!(!is_portal_network_error(&anyhow::anyhow!("unexpected")));
+  }
 }
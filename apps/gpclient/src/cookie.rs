@@ -0,0 +1,148 @@
+use chrono::{DateTime, Local};
+use clap::{Args, Subcommand};
+use gpapi::{cookie_store, log_format::LogFormat};
+use serde::Serialize;
+
+use crate::cli::SharedArgs;
+
+#[derive(Args)]
+pub(crate) struct CookieArgs {
+  #[command(subcommand)]
+  command: CookieCommand,
+
+  #[arg(
+    long,
+    global = true,
+    help = "The cookie cache file, defaults to the one `connect --cookie-cache` uses"
+  )]
+  path: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum CookieCommand {
+  #[command(about = "List the cached portal cookies, without the cookie values")]
+  List {
+    #[arg(long, help = "Only list the cookies of this portal")]
+    server: Option<String>,
+  },
+  #[command(about = "Remove the cached portal cookies")]
+  Clear {
+    #[arg(long, help = "Only remove the cookies of this portal")]
+    server: Option<String>,
+  },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CookieSummary<'a> {
+  server: &'a str,
+  username: &'a str,
+  host_id: &'a str,
+  last_gateway: &'a str,
+  saved_at: u64,
+  expires_at: Option<u64>,
+  gateway_cookie: bool,
+  gateway_cookie_expires_at: Option<u64>,
+}
+
+pub(crate) struct CookieHandler<'a> {
+  args: &'a CookieArgs,
+  shared_args: &'a SharedArgs<'a>,
+}
+
+impl<'a> CookieHandler<'a> {
+  pub(crate) fn new(args: &'a CookieArgs, shared_args: &'a SharedArgs) -> Self {
+    Self { args, shared_args }
+  }
+
+  pub(crate) async fn handle(&self) -> anyhow::Result<()> {
+    let path = cookie_store::cookie_path(self.args.path.as_deref());
+
+    match &self.args.command {
+      CookieCommand::List { server } => {
+        let entries = cookie_store::list(&path);
+        let summaries = entries
+          .iter()
+          .filter(|stored| server.as_deref().is_none_or(|server| stored.server == server))
+          .map(|stored| CookieSummary {
+            server: &stored.server,
+            username: &stored.username,
+            host_id: &stored.host_id,
+            last_gateway: &stored.last_gateway,
+            saved_at: stored.saved_at,
+            expires_at: stored.expires_at,
+            gateway_cookie: stored.gateway_cookie().is_some(),
+            gateway_cookie_expires_at: stored.gateway_cookie_expires_at,
+          })
+          .collect::<Vec<_>>();
+
+        if self.shared_args.log_format == LogFormat::Json {
+          println!("{}", serde_json::to_string_pretty(&summaries)?);
+        } else if summaries.is_empty() {
+          println!("No cached cookies in {}", path.display());
+        } else {
+          summaries
+            .iter()
+            .for_each(|summary| println!("{}", format_summary(summary)));
+        }
+      }
+      CookieCommand::Clear { server } => {
+        let removed = cookie_store::clear(&path, server.as_deref())?;
+        println!("Removed {} cached cookie(s) from {}", removed, path.display());
+      }
+    }
+
+    Ok(())
+  }
+}
+
+fn format_summary(summary: &CookieSummary) -> String {
+  let expires = summary.expires_at.map_or_else(|| "unknown".to_string(), format_time);
+  let gateway_cookie = match (summary.gateway_cookie, summary.gateway_cookie_expires_at) {
+    (false, _) => "none".to_string(),
+    (true, Some(expires_at)) => format!("expires {}", format_time(expires_at)),
+    (true, None) => "expiry unknown".to_string(),
+  };
+
+  format!(
+    "{}\n  user: {}\n  host ID: {}\n  gateway: {}\n  saved: {}\n  expires: {}\n  gateway cookie: {}",
+    summary.server,
+    summary.username,
+    summary.host_id,
+    summary.last_gateway,
+    format_time(summary.saved_at),
+    expires,
+    gateway_cookie
+  )
+}
+
+pub(crate) fn format_time(secs: u64) -> String {
+  DateTime::from_timestamp(secs as i64, 0)
+    .map(|time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+    .unwrap_or_else(|| secs.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn summary_shows_unknown_expiry() {
+    let summary = CookieSummary {
+      server: "vpn.example.com",
+      username: "alice",
+      host_id: "host-1",
+      last_gateway: "gw1.example.com",
+      saved_at: 0,
+      expires_at: None,
+      gateway_cookie: false,
+      gateway_cookie_expires_at: None,
+    };
+
+    let text = format_summary(&summary);
+    assert!(text.starts_with("vpn.example.com\n"));
+    assert!(text.contains("user: alice"));
+    assert!(text.contains("expires: unknown"));
+    assert!(text.contains("gateway cookie: none"));
+  }
+}
@@ -0,0 +1,116 @@
+use anyhow::bail;
+use clap::Args;
+use gpapi::process::users::{get_current_user, get_non_root_user};
+use log::info;
+use tokio::process::Command;
+use uzers::User;
+
+use crate::netns::{check_netns_name, netns_exists};
+
+#[derive(Args)]
+pub(crate) struct ExecArgs {
+  #[arg(
+    long,
+    help = "The network namespace the VPN was connected with `gpclient connect --netns`"
+  )]
+  netns: String,
+
+  #[arg(
+    required = true,
+    trailing_var_arg = true,
+    allow_hyphen_values = true,
+    help = "The command to run, e.g., `gpclient exec --netns corp -- ssh host`"
+  )]
+  command: Vec<String>,
+}
+
+pub(crate) struct ExecHandler<'a> {
+  args: &'a ExecArgs,
+}
+
+impl<'a> ExecHandler<'a> {
+  pub(crate) fn new(args: &'a ExecArgs) -> Self {
+    Self { args }
+  }
+
+  pub(crate) async fn handle(&self) -> anyhow::Result<()> {
+    let netns = self.args.netns.as_str();
+    check_netns_name(netns)?;
+
+    if get_current_user()?.uid() != 0 {
+      bail!("Entering a network namespace requires root, run it with sudo");
+    }
+
+    if !netns_exists(netns) {
+      bail!("Network namespace `{}` does not exist", netns);
+    }
+
+    // Enter the namespace as root, then drop to the user who invoked sudo/pkexec
+    let mut cmd = Command::new("ip");
+    cmd.args(["netns", "exec", netns]);
+
+    match get_non_root_user() {
+      Ok(user) => {
+        info!(
+          "Running {:?} in {} as {}",
+          self.args.command,
+          netns,
+          user.name().to_string_lossy()
+        );
+        apply_user(&mut cmd, &user);
+      }
+      Err(_) => info!("Running {:?} in {} as root", self.args.command, netns),
+    }
+
+    let status = cmd.args(&self.args.command).status().await?;
+    if !status.success() {
+      std::process::exit(status.code().unwrap_or(1));
+    }
+
+    Ok(())
+  }
+}
+
+fn apply_user(cmd: &mut Command, user: &User) {
+  use uzers::os::unix::UserExt;
+
+  cmd
+    .arg("setpriv")
+    .arg(format!("--reuid={}", user.uid()))
+    .arg(format!("--regid={}", user.primary_group_id()))
+    .arg("--init-groups")
+    .arg("--")
+    .env("HOME", user.home_dir())
+    .env("USER", user.name())
+    .env("LOGNAME", user.name())
+    .env("USERNAME", user.name());
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(clap::Parser)]
+  struct ExecArgsTestCli {
+    #[command(flatten)]
+    args: ExecArgs,
+  }
+
+  #[test]
+  fn keeps_the_command_arguments_verbatim() {
+    use clap::Parser;
+
+    let cli = ExecArgsTestCli::try_parse_from(["test", "--netns", "corp", "--", "curl", "-v", "https://intranet"])
+      .expect("exec args should parse");
+
+    assert_eq!(cli.args.netns, "corp");
+    assert_eq!(cli.args.command, ["curl", "-v", "https://intranet"]);
+  }
+
+  #[test]
+  fn requires_a_command() {
+    use clap::Parser;
+
+    assert!(ExecArgsTestCli::try_parse_from(["test", "--netns", "corp"]).is_err());
+  }
+}
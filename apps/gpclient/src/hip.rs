@@ -1,15 +1,51 @@
+use anyhow::{Context, bail};
 use askama::Template;
-use clap::Args;
+use clap::{Args, ValueEnum};
 use gpapi::{
   clap::args::Os,
+  gateway::{
+    HipCheckOutcome, HipReportContext, HipReportGenerator, HipReportInput, check_and_submit_hip_report, hip_md5,
+  },
   os_profile::{ClientOs, OsProfile, OsProfileBuilder},
 };
-use log::debug;
+use log::{debug, info, warn};
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 use xmltree::Element;
 
+mod inspect;
+mod posture;
+mod profile;
+
+pub(crate) use profile::HipProfile;
+use profile::ReportDate;
+
+/// Read-only access to the machine, so that the posture collectors can run against fixtures
+trait HostProbe {
+  fn read_file(&self, path: &str) -> Option<String>;
+
+  fn list_dir(&self, path: &str) -> Vec<String>;
+
+  /// Stdout of the command, `None` if it is not installed or exited with an error
+  fn command_output(&self, program: &str, args: &[&str]) -> Option<String>;
+
+  /// The executable names of the running processes
+  fn process_names(&self) -> Vec<String>;
+}
+
+/// Discovers the state of a HIP category on the local machine. A collector leaves the
+/// category unset when it finds nothing to report, so the template default is kept.
+trait PostureCollector {
+  fn collect(&self, host: &dyn HostProbe, posture: &mut HipProfile);
+}
+
 #[derive(Template)]
 #[template(path = "hip_report.xml")]
 struct HipReportTemplate<'a> {
@@ -141,8 +177,45 @@ pub(crate) struct HipArgs {
   #[arg(long, help = "The client IPv6 address")]
   client_ipv6: Option<String>,
 
-  #[arg(long, help = "The MD5 digest to encode into the HIP report")]
-  md5: String,
+  #[arg(
+    long,
+    help = "The MD5 digest to encode into the HIP report, defaults to the digest of the cookie"
+  )]
+  md5: Option<String>,
+
+  #[arg(
+    long,
+    help = "TOML file declaring the HIP categories to report, overriding the detected ones"
+  )]
+  hip_profile: Option<PathBuf>,
+
+  #[arg(
+    long,
+    help = "Check the report structure and its md5 against the cookie, without submitting anything"
+  )]
+  validate: bool,
+
+  #[arg(
+    long,
+    value_name = "REPORT",
+    help = "Compare the report category by category with a saved one, e.g., rendered for another --client-os"
+  )]
+  diff: Option<PathBuf>,
+
+  #[arg(long, value_enum, default_value_t = ReportFormat::Xml, help = "The output format")]
+  format: ReportFormat,
+
+  #[arg(
+    long,
+    help = "Mask the user, host and network identifiers, e.g., to attach the output to a bug report"
+  )]
+  redact: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReportFormat {
+  Xml,
+  Json,
 }
 
 pub(crate) struct HipHandler<'a> {
@@ -161,7 +234,75 @@ impl<'a> HipHandler<'a> {
     let report = self.generate_hip_report(&cookie_params)?;
 
     debug!("Generated HIP report:\n{}", report);
-    println!("{}", report);
+
+    if !self.args.validate && self.args.diff.is_none() {
+      println!("{}", self.render_output(&report)?);
+      return Ok(());
+    }
+
+    if self.args.validate {
+      self.validate(&report)?;
+    }
+
+    if let Some(other) = self.args.diff.as_deref() {
+      self.diff(&report, other)?;
+    }
+
+    Ok(())
+  }
+
+  fn md5(&self) -> String {
+    self.args.md5.clone().unwrap_or_else(|| hip_md5(&self.args.cookie))
+  }
+
+  fn render_output(&self, report: &str) -> anyhow::Result<String> {
+    let mut xml = Element::parse(report.as_bytes())?;
+    if self.args.redact {
+      inspect::redact_report(&mut xml);
+    }
+
+    match self.args.format {
+      ReportFormat::Xml => emit_xml(&xml),
+      ReportFormat::Json => Ok(serde_json::to_string_pretty(&inspect::report_to_json(&xml))?),
+    }
+  }
+
+  fn validate(&self, report: &str) -> anyhow::Result<()> {
+    let xml = Element::parse(report.as_bytes())?;
+    let problems = inspect::validate_report(&xml, &hip_md5(&self.args.cookie));
+
+    match self.args.format {
+      ReportFormat::Xml if problems.is_empty() => println!("The HIP report is valid"),
+      ReportFormat::Xml => problems.iter().for_each(|problem| println!("{}", problem)),
+      ReportFormat::Json => println!(
+        "{}",
+        serde_json::json!({ "valid": problems.is_empty(), "problems": problems })
+      ),
+    }
+
+    if !problems.is_empty() {
+      bail!("The HIP report has {} problem(s)", problems.len());
+    }
+
+    Ok(())
+  }
+
+  fn diff(&self, report: &str, other: &Path) -> anyhow::Result<()> {
+    let other = fs::read_to_string(other).with_context(|| format!("Failed to read {}", other.display()))?;
+    let mut baseline =
+      Element::parse(other.as_bytes()).with_context(|| "The report to compare with is not valid XML".to_string())?;
+    let mut current = Element::parse(report.as_bytes())?;
+
+    if self.args.redact {
+      inspect::redact_report(&mut baseline);
+      inspect::redact_report(&mut current);
+    }
+
+    let diffs = inspect::diff_reports(&baseline, &current);
+    match self.args.format {
+      ReportFormat::Xml => print!("{}", inspect::format_diff(&diffs)),
+      ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&diffs)?),
+    }
 
     Ok(())
   }
@@ -174,22 +315,46 @@ impl<'a> HipHandler<'a> {
   /// Generate the complete HIP report XML
   fn generate_hip_report(&self, cookie_params: &HashMap<String, String>) -> anyhow::Result<String> {
     let (generate_time, day, month, year) = get_current_time_components();
+    let md5 = self.md5();
     let user_name = cookie_params.get("user").map(|s| s.as_str()).unwrap_or("");
     let host_info = self.collect_host_info(cookie_params);
 
+    let report_date = ReportDate {
+      day: &day,
+      month: &month,
+      year: &year,
+    };
+    let hip_profile = self.args.hip_profile.as_deref().map(HipProfile::load).transpose()?;
+
     let template = HipReportTemplate {
       client_version: &self.args.client_version,
       generate_time,
-      day,
-      month,
-      year,
+      day: day.clone(),
+      month: month.clone(),
+      year: year.clone(),
       user_name,
       host_info,
-      md5: &self.args.md5,
+      md5: &md5,
     };
 
     let report = template.render()?;
-    format_xml(&report)
+    let mut xml = Element::parse(report.as_bytes())?;
+    if let Some(posture) = self.collect_posture() {
+      posture.apply(&mut xml, &report_date)?;
+    }
+    // The admin-declared categories take precedence over the discovered ones
+    if let Some(hip_profile) = hip_profile {
+      hip_profile.apply(&mut xml, &report_date)?;
+    }
+
+    emit_xml(&xml)
+  }
+
+  /// Posture is only discovered when reporting the machine's own OS, an emulated OS keeps
+  /// the template defaults.
+  fn collect_posture(&self) -> Option<HipProfile> {
+    (self.profile.client_os() == ClientOs::Linux && self.profile.is_native())
+      .then(|| posture::collect_linux_posture(&posture::LiveHost))
   }
 
   /// Collect host information using the configured OsProfile.
@@ -198,6 +363,150 @@ impl<'a> HipHandler<'a> {
   }
 }
 
+// ============================================================================
+// Native HIP submission
+// ============================================================================
+
+/// Renders the HIP report in-process for the native `hipreportcheck.esp`/`hipreport.esp`
+/// exchange, with the same inputs the CSD wrapper would receive on its command line.
+pub(crate) struct NativeHipReportGenerator {
+  client_os: Os,
+  client_version: String,
+  host_id: String,
+  hip_profile: Option<PathBuf>,
+}
+
+impl NativeHipReportGenerator {
+  pub(crate) fn new(client_os: Os, client_version: String, host_id: String) -> Self {
+    Self {
+      client_os,
+      client_version,
+      host_id,
+      hip_profile: None,
+    }
+  }
+
+  pub(crate) fn with_hip_profile(mut self, hip_profile: Option<PathBuf>) -> Self {
+    self.hip_profile = hip_profile;
+    self
+  }
+}
+
+impl HipReportGenerator for NativeHipReportGenerator {
+  fn generate(&self, input: &HipReportInput) -> anyhow::Result<String> {
+    let args = HipArgs {
+      client_version: self.client_version.clone(),
+      client_os: self.client_os,
+      os_version: None,
+      host_id: Some(self.host_id.clone()),
+      cookie: input.cookie.to_string(),
+      client_ip: Some(input.client_ip.to_string()),
+      client_ipv6: input.client_ipv6.map(ToOwned::to_owned),
+      md5: Some(input.md5.to_string()),
+      hip_profile: self.hip_profile.clone(),
+      validate: false,
+      diff: None,
+      format: ReportFormat::Xml,
+      redact: false,
+    };
+    let handler = HipHandler::new(&args);
+
+    handler.generate_hip_report(&handler.parse_cookie())
+  }
+}
+
+/// Runs the CSD wrapper with the arguments openconnect passes it, for the native submission to
+/// fall back to the report of the wrapper
+pub(crate) struct WrapperHipReportGenerator {
+  wrapper: String,
+  uid: u32,
+  /// The primary group of the user, not the one of root
+  gid: Option<u32>,
+  client_os: String,
+}
+
+impl WrapperHipReportGenerator {
+  pub(crate) fn new(wrapper: String, uid: u32, client_os: String) -> Self {
+    Self {
+      wrapper,
+      uid,
+      gid: uzers::get_user_by_uid(uid).map(|user| user.primary_group_id()),
+      client_os,
+    }
+  }
+
+  pub(crate) fn wrapper(&self) -> &str {
+    &self.wrapper
+  }
+}
+
+impl HipReportGenerator for WrapperHipReportGenerator {
+  fn generate(&self, input: &HipReportInput) -> anyhow::Result<String> {
+    let mut command = Command::new(&self.wrapper);
+    command
+      .args(["--cookie", input.cookie, "--client-ip", input.client_ip])
+      .args(input.client_ipv6.iter().flat_map(|ipv6| ["--client-ipv6", *ipv6]))
+      .args(["--md5", input.md5, "--client-os", &self.client_os])
+      .uid(self.uid);
+    if let Some(gid) = self.gid {
+      command.gid(gid);
+    }
+
+    let output = command
+      .output()
+      .with_context(|| format!("Failed to run the CSD wrapper {}", self.wrapper))?;
+    if !output.status.success() {
+      bail!("The CSD wrapper {} exited with {}", self.wrapper, output.status);
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+  }
+}
+
+pub(crate) fn spawn_hip_runtime(
+  ctx: HipReportContext,
+  generator: NativeHipReportGenerator,
+  fallback: Option<WrapperHipReportGenerator>,
+  interval: Duration,
+) -> JoinHandle<()> {
+  tokio::spawn(run_hip_runtime(ctx, generator, fallback, interval))
+}
+
+/// Check right after the tunnel is up, then again on the portal's HIP re-check interval. The CSD
+/// wrapper generates the report of a check whose native submission failed, the next check tries
+/// the native one again.
+async fn run_hip_runtime(
+  ctx: HipReportContext,
+  generator: NativeHipReportGenerator,
+  fallback: Option<WrapperHipReportGenerator>,
+  interval: Duration,
+) {
+  let generator: Arc<dyn HipReportGenerator> = Arc::new(generator);
+  let fallback = fallback.map(Arc::new);
+  loop {
+    let mut result = check_and_submit_hip_report(&ctx, Arc::clone(&generator)).await;
+    if let Err(err) = &result
+      && let Some(wrapper) = &fallback
+    {
+      warn!(
+        "Native HIP submission failed, falling back to the CSD wrapper {}: {}",
+        wrapper.wrapper(),
+        err
+      );
+      result = check_and_submit_hip_report(&ctx, Arc::clone(wrapper) as Arc<dyn HipReportGenerator>).await;
+    }
+
+    match result {
+      Ok(HipCheckOutcome::Submitted) => info!("HIP report accepted by {}", ctx.server()),
+      Ok(HipCheckOutcome::NotNeeded) => {}
+      Err(err) => warn!("HIP check failed: {}", err),
+    }
+
+    info!("Next HIP check in {}s", interval.as_secs());
+    tokio::time::sleep(interval).await;
+  }
+}
+
 /// Construct an `OsProfile` from CLI args, falling back to per-OS defaults
 /// for any value not supplied on the command line.
 fn build_os_profile(args: &HipArgs) -> OsProfile {
@@ -313,14 +622,14 @@ impl<'p, 'a> HostInfoCollector<'p, 'a> {
   /// emits the software loopback interface in HIP reports.
   fn extra_interfaces(&self, primary: &NetworkInterface) -> Vec<NetworkInterface> {
     match self.profile.client_os() {
-      ClientOs::Windows => vec![NetworkInterface {
+      ClientOs::Windows | ClientOs::WindowsArm => vec![NetworkInterface {
         name: derive_windows_network_name(self.profile.host_id(), primary),
         description: "Software Loopback Interface 1".to_string(),
         mac_address: Some(String::new()),
         ipv4: Some("127.0.0.1".to_string()),
         ipv6: Some("::1".to_string()),
       }],
-      ClientOs::Linux | ClientOs::Mac => vec![],
+      ClientOs::Linux | ClientOs::Mac | ClientOs::MacArm | ClientOs::Ios | ClientOs::Android => vec![],
     }
   }
 
@@ -329,14 +638,26 @@ impl<'p, 'a> HostInfoCollector<'p, 'a> {
   fn domain_for_profile(&self) -> String {
     match self.profile.client_os() {
       ClientOs::Linux => String::new(),
-      ClientOs::Mac | ClientOs::Windows => self.get_domain(),
+      ClientOs::Mac
+      | ClientOs::MacArm
+      | ClientOs::Windows
+      | ClientOs::WindowsArm
+      | ClientOs::Ios
+      | ClientOs::Android => self.get_domain(),
     }
   }
 
   fn defender_for_profile(&self) -> Option<DefenderInfo> {
     match self.profile.client_os() {
+      // The posture collectors report Defender along with the other agents
+      ClientOs::Linux if self.profile.is_native() => None,
       ClientOs::Linux => detect_microsoft_defender_blocking(),
-      ClientOs::Mac | ClientOs::Windows => None,
+      ClientOs::Mac
+      | ClientOs::MacArm
+      | ClientOs::Windows
+      | ClientOs::WindowsArm
+      | ClientOs::Ios
+      | ClientOs::Android => None,
     }
   }
 }
@@ -374,8 +695,9 @@ fn parse_defender_info(json: &str) -> Option<DefenderInfo> {
 fn placeholder_interface_for(profile: &OsProfile, runtime: &NetworkInterface) -> (String, String) {
   match profile.client_os() {
     ClientOs::Linux => ("enp1s0f0".to_string(), "enp1s0f0".to_string()),
-    ClientOs::Mac => ("en0".to_string(), "en0".to_string()),
-    ClientOs::Windows => (
+    ClientOs::Mac | ClientOs::MacArm | ClientOs::Ios => ("en0".to_string(), "en0".to_string()),
+    ClientOs::Android => ("wlan0".to_string(), "wlan0".to_string()),
+    ClientOs::Windows | ClientOs::WindowsArm => (
       derive_windows_network_name(profile.host_id(), runtime),
       "PANGP Virtual Ethernet Adapter Secure".to_string(),
     ),
@@ -384,11 +706,13 @@ fn placeholder_interface_for(profile: &OsProfile, runtime: &NetworkInterface) ->
 
 /// MAC address formatting per target OS. Windows uses hyphen separators;
 /// Linux and macOS preserve the colon-separated form returned by the
-/// platform.
+/// platform. iOS and Android hide the MAC address from apps, so the fixed
+/// profile value stands in for it.
 fn format_mac_for(profile: &OsProfile, mac: Option<String>) -> Option<String> {
   match profile.client_os() {
-    ClientOs::Windows => mac.map(|m| m.replace(':', "-")),
-    ClientOs::Linux | ClientOs::Mac => mac,
+    ClientOs::Windows | ClientOs::WindowsArm => mac.map(|m| m.replace(':', "-")),
+    ClientOs::Linux | ClientOs::Mac | ClientOs::MacArm => mac,
+    ClientOs::Ios | ClientOs::Android => mac.map(|_| profile.mac_addr().to_string()),
   }
 }
 
@@ -408,9 +732,7 @@ fn get_current_time_components() -> (String, String, String, String) {
 }
 
 /// Format XML string with proper indentation
-fn format_xml(xml_str: &str) -> anyhow::Result<String> {
-  let xml = Element::parse(xml_str.as_bytes())?;
-
+fn emit_xml(xml: &Element) -> anyhow::Result<String> {
   let config = xmltree::EmitterConfig::new().perform_indent(true);
   let mut xml_buf = Vec::new();
   xml.write_with_config(&mut xml_buf, config)?;
@@ -439,6 +761,42 @@ mod tests {
   use super::*;
   use gpapi::os_profile::runtime_client_os;
 
+  #[test]
+  fn wrapper_generator_passes_the_openconnect_arguments() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let wrapper = dir.path().join("hipreport.sh");
+    fs::write(
+      &wrapper,
+      "#!/bin/sh\necho \"<hip-report gid=\\\"$(id -g)\\\">$*</hip-report>\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let generator = WrapperHipReportGenerator::new(
+      wrapper.display().to_string(),
+      uzers::get_current_uid(),
+      "Windows".to_string(),
+    );
+    let report = generator
+      .generate(&HipReportInput {
+        cookie: "authcookie=AUTH",
+        client_ip: "10.0.0.2",
+        client_ipv6: None,
+        md5: "abc",
+      })
+      .unwrap();
+
+    assert_eq!(
+      report.trim(),
+      format!(
+        "<hip-report gid=\"{}\">--cookie authcookie=AUTH --client-ip 10.0.0.2 --md5 abc --client-os Windows</hip-report>",
+        uzers::get_current_gid()
+      )
+    );
+  }
+
   fn make_hip_args(client_os: Os) -> HipArgs {
     HipArgs {
       client_version: "6.2.4-49".to_string(),
@@ -448,7 +806,12 @@ mod tests {
       cookie: String::new(),
       client_ip: None,
       client_ipv6: None,
-      md5: "deadbeef".to_string(),
+      md5: Some("deadbeef".to_string()),
+      hip_profile: None,
+      validate: false,
+      diff: None,
+      format: ReportFormat::Xml,
+      redact: false,
     }
   }
 
@@ -489,6 +852,53 @@ mod tests {
     assert_eq!(info.os_vendor, "Microsoft");
   }
 
+  #[test]
+  fn host_info_os_vendor_for_arm_and_mobile_profiles() {
+    for (client_os, os_arg, vendor) in [
+      (ClientOs::MacArm, Os::MacArm, "Apple"),
+      (ClientOs::WindowsArm, Os::WindowsArm, "Microsoft"),
+      (ClientOs::Ios, Os::Ios, "Apple"),
+      (ClientOs::Android, Os::Android, "Google"),
+    ] {
+      let args = make_hip_args(os_arg);
+      let profile = make_profile(client_os);
+      let cookie_params: HashMap<String, String> = HashMap::new();
+
+      let info = HostInfoCollector::new(&profile, &args, &cookie_params).collect();
+
+      assert_eq!(info.os_vendor, vendor, "{client_os:?}");
+      assert_eq!(info.os_version, profile.os_version());
+    }
+  }
+
+  #[test]
+  fn mobile_interfaces_report_the_fixed_profile_mac() {
+    let runtime = NetworkInterface {
+      name: "eth0".to_string(),
+      description: "eth0".to_string(),
+      mac_address: Some("aa:bb:cc:dd:ee:ff".to_string()),
+      ipv4: None,
+      ipv6: None,
+    };
+
+    for (client_os, name) in [(ClientOs::Ios, "en0"), (ClientOs::Android, "wlan0")] {
+      let profile = make_profile(client_os);
+
+      assert_eq!(placeholder_interface_for(&profile, &runtime).0, name);
+      assert_eq!(
+        format_mac_for(&profile, runtime.mac_address.clone()).as_deref(),
+        Some("02:00:00:00:00:00")
+      );
+      assert_eq!(format_mac_for(&profile, None), None);
+    }
+
+    let windows_arm = make_profile(ClientOs::WindowsArm);
+    assert_eq!(
+      format_mac_for(&windows_arm, runtime.mac_address.clone()).as_deref(),
+      Some("aa-bb-cc-dd-ee-ff")
+    );
+  }
+
   #[test]
   fn host_info_host_id_comes_from_os_profile() {
     let args = make_hip_args(Os::Linux);
@@ -555,6 +965,102 @@ mod tests {
     assert_eq!(info.host_id, profile.host_id());
   }
 
+  #[test]
+  fn native_generator_renders_report_with_session_inputs() {
+    let generator = NativeHipReportGenerator::new(Os::Linux, "6.2.4-49".to_string(), "runtime-host-id".to_string());
+
+    let report = generator
+      .generate(&HipReportInput {
+        cookie: "authcookie=AUTH&user=alice&domain=corp",
+        client_ip: "10.0.0.10",
+        client_ipv6: None,
+        md5: "0123456789abcdef",
+      })
+      .expect("report should render");
+    let root = Element::parse(report.as_bytes()).expect("report should be XML");
+
+    assert_eq!(
+      root.get_child("md5-sum").and_then(|e| e.get_text()).as_deref(),
+      Some("0123456789abcdef")
+    );
+    assert_eq!(
+      root.get_child("user-name").and_then(|e| e.get_text()).as_deref(),
+      Some("alice")
+    );
+  }
+
+  #[test]
+  fn native_generator_applies_hip_profile() {
+    let mut profile = tempfile::NamedTempFile::new().unwrap();
+    std::io::Write::write_all(
+      &mut profile,
+      br#"
+        [[firewall.products]]
+        vendor = "The Netfilter Project"
+        name = "nftables"
+        enabled = true
+      "#,
+    )
+    .unwrap();
+    let generator = NativeHipReportGenerator::new(Os::Linux, "6.2.4-49".to_string(), "runtime-host-id".to_string())
+      .with_hip_profile(Some(profile.path().to_path_buf()));
+
+    let report = generator
+      .generate(&HipReportInput {
+        cookie: "user=alice",
+        client_ip: "10.0.0.10",
+        client_ipv6: None,
+        md5: "0123456789abcdef",
+      })
+      .expect("report should render");
+
+    assert!(report.contains(r#"name="nftables""#), "{report}");
+    assert!(!report.contains("IPTables"), "{report}");
+  }
+
+  #[test]
+  fn rendered_reports_validate_for_every_os() {
+    for os in [
+      Os::Linux,
+      Os::Windows,
+      Os::Mac,
+      Os::MacArm,
+      Os::WindowsArm,
+      Os::Ios,
+      Os::Android,
+    ] {
+      let mut args = make_hip_args(os);
+      args.cookie = "authcookie=AUTH&user=alice&domain=corp&computer=host".to_string();
+      args.md5 = None;
+      let handler = HipHandler::new(&args);
+
+      let report = handler.generate_hip_report(&handler.parse_cookie()).unwrap();
+      let xml = Element::parse(report.as_bytes()).unwrap();
+
+      assert_eq!(
+        inspect::validate_report(&xml, &hip_md5(&args.cookie)),
+        Vec::<String>::new(),
+        "{os:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn diffs_reports_rendered_for_different_os() {
+    let render = |os| {
+      let args = make_hip_args(os);
+      let handler = HipHandler::new(&args);
+      let report = handler.generate_hip_report(&handler.parse_cookie()).unwrap();
+      Element::parse(report.as_bytes()).unwrap()
+    };
+
+    let diffs = inspect::diff_reports(&render(Os::Windows), &render(Os::Mac));
+    let sections = diffs.iter().map(|d| d.section.as_str()).collect::<Vec<_>>();
+
+    assert!(sections.contains(&"host-info"), "{sections:?}");
+    assert!(sections.contains(&"antivirus"), "{sections:?}");
+  }
+
   #[test]
   fn parses_microsoft_defender_health_json() {
     let defender = parse_defender_info(
@@ -0,0 +1,489 @@
+//! Tooling to inspect a HIP report without submitting it: structural validation, a
+//! category-by-category diff, redaction and a JSON rendering for bug reports.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use gpapi::utils::redact::redact_value;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use xmltree::{Element, XMLNode};
+
+/// The top-level fields every gateway expects before `<categories>`
+const REQUIRED_FIELDS: &[&str] = &[
+  "md5-sum",
+  "user-name",
+  "host-name",
+  "host-id",
+  "generate-time",
+  "hip-report-version",
+  "categories",
+];
+
+const KNOWN_CATEGORIES: &[&str] = &[
+  "host-info",
+  "antivirus",
+  "anti-spyware",
+  "anti-malware",
+  "disk-backup",
+  "disk-encryption",
+  "firewall",
+  "patch-management",
+  "data-loss-prevention",
+];
+
+/// Elements identifying the user or the machine, masked by `--redact`
+const SENSITIVE_FIELDS: &[&str] = &[
+  "md5-sum",
+  "user-name",
+  "domain",
+  "host-name",
+  "host-id",
+  "ip-address",
+  "ipv6-address",
+  "mac-address",
+  "network-interface",
+];
+
+/// Fields expected to differ between two renderings of the same host, left out of the diff
+const VOLATILE_FIELDS: &[&str] = &["generate-time", "last-full-scan-time"];
+const VOLATILE_ATTRIBUTES: &[&str] = &["dateday", "datemon", "dateyear"];
+
+/// The section holding the fields outside of `<categories>`
+const REPORT_SECTION: &str = "report";
+
+/// Check the report against what the gateway parses, returning every problem found
+pub(super) fn validate_report(report: &Element, expected_md5: &str) -> Vec<String> {
+  let mut problems = Vec::new();
+
+  if report.name != "hip-report" {
+    problems.push(format!("root element is <{}>, expected <hip-report>", report.name));
+  }
+
+  for field in REQUIRED_FIELDS {
+    if report.get_child(*field).is_none() {
+      problems.push(format!("missing <{field}>"));
+    }
+  }
+
+  match report.get_child("md5-sum").and_then(|e| e.get_text()) {
+    Some(md5) if md5.trim() != expected_md5 => problems.push(format!(
+      "<md5-sum> is {}, the cookie digest is {}",
+      md5.trim(),
+      expected_md5
+    )),
+    Some(md5) if !is_md5(md5.trim()) => problems.push(format!("<md5-sum> `{}` is not an md5 digest", md5.trim())),
+    None if report.get_child("md5-sum").is_some() => problems.push("<md5-sum> is empty".to_string()),
+    _ => {}
+  }
+
+  if let Some(categories) = report.get_child("categories") {
+    validate_categories(categories, &mut problems);
+  }
+
+  problems
+}
+
+fn validate_categories(categories: &Element, problems: &mut Vec<String>) {
+  let mut seen = BTreeSet::new();
+
+  for entry in child_elements(categories) {
+    let Some(name) = entry.attributes.get("name") else {
+      problems.push(format!("<categories> contains <{}> without a name", entry.name));
+      continue;
+    };
+
+    if entry.name != "entry" {
+      problems.push(format!("category {name} is a <{}>, expected <entry>", entry.name));
+    }
+    if !seen.insert(name.as_str()) {
+      problems.push(format!("category {name} appears more than once"));
+    }
+    if !KNOWN_CATEGORIES.contains(&name.as_str()) {
+      problems.push(format!("unknown category {name}"));
+    }
+    if name == "host-info" {
+      continue;
+    }
+
+    let Some(list) = entry.get_child("list") else {
+      problems.push(format!("category {name} has no <list>"));
+      continue;
+    };
+
+    for (i, product) in child_elements(list).enumerate() {
+      let prod = product.get_child("ProductInfo").and_then(|info| info.get_child("Prod"));
+      match prod {
+        Some(prod) => {
+          for attr in ["name", "vendor"] {
+            if prod.attributes.get(attr).is_none_or(|v| v.is_empty()) {
+              problems.push(format!("{name} product #{i} has no {attr}"));
+            }
+          }
+        }
+        None => problems.push(format!("{name} product #{i} has no <ProductInfo><Prod>")),
+      }
+    }
+  }
+
+  if !seen.contains("host-info") {
+    problems.push("missing the host-info category".to_string());
+  }
+}
+
+fn is_md5(value: &str) -> bool {
+  value.len() == 32 && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// What changed in one section between two reports
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(super) struct SectionDiff {
+  pub(super) section: String,
+  pub(super) removed: Vec<String>,
+  pub(super) added: Vec<String>,
+}
+
+/// Compare two reports category by category, `left` is the baseline
+pub(super) fn diff_reports(left: &Element, right: &Element) -> Vec<SectionDiff> {
+  let left = flatten_report(left);
+  let right = flatten_report(right);
+  let sections = left.keys().chain(right.keys()).collect::<BTreeSet<_>>();
+
+  sections
+    .into_iter()
+    .filter_map(|section| {
+      let empty = BTreeSet::new();
+      let before = left.get(section).unwrap_or(&empty);
+      let after = right.get(section).unwrap_or(&empty);
+
+      let removed = before.difference(after).cloned().collect::<Vec<_>>();
+      let added = after.difference(before).cloned().collect::<Vec<_>>();
+      (!removed.is_empty() || !added.is_empty()).then(|| SectionDiff {
+        section: section.clone(),
+        removed,
+        added,
+      })
+    })
+    .collect()
+}
+
+pub(super) fn format_diff(diffs: &[SectionDiff]) -> String {
+  if diffs.is_empty() {
+    return "The reports are identical\n".to_string();
+  }
+
+  let mut output = String::new();
+  for diff in diffs {
+    output.push_str(&format!("[{}]\n", diff.section));
+    for line in &diff.removed {
+      output.push_str(&format!("- {line}\n"));
+    }
+    for line in &diff.added {
+      output.push_str(&format!("+ {line}\n"));
+    }
+  }
+  output
+}
+
+/// Flatten each section to `path = value` leaves, so that products are compared by their
+/// `Prod` name rather than by their position in the list
+fn flatten_report(report: &Element) -> BTreeMap<String, BTreeSet<String>> {
+  let mut sections = BTreeMap::new();
+
+  for child in child_elements(report) {
+    if child.name == "categories" {
+      for category in child_elements(child) {
+        let name = category
+          .attributes
+          .get("name")
+          .cloned()
+          .unwrap_or_else(|| category.name.clone());
+        let leaves = sections.entry(name).or_insert_with(BTreeSet::new);
+        for grandchild in child_elements(category) {
+          flatten_element(grandchild, &grandchild.name, leaves);
+        }
+      }
+    } else {
+      let leaves = sections.entry(REPORT_SECTION.to_string()).or_insert_with(BTreeSet::new);
+      flatten_element(child, &child.name, leaves);
+    }
+  }
+
+  sections
+}
+
+fn flatten_element(element: &Element, path: &str, leaves: &mut BTreeSet<String>) {
+  if VOLATILE_FIELDS.contains(&element.name.as_str()) {
+    return;
+  }
+
+  for (attr, value) in &element.attributes {
+    if !VOLATILE_ATTRIBUTES.contains(&attr.as_str()) {
+      leaves.insert(format!("{path}@{attr} = {value}"));
+    }
+  }
+
+  let text = element.get_text().filter(|t| !t.trim().is_empty());
+  if let Some(text) = &text {
+    leaves.insert(format!("{path} = {}", text.trim()));
+  }
+
+  // Keep empty elements such as `<list/>`, so that an added empty category still shows up
+  if element.attributes.is_empty() && text.is_none() && element.children.iter().all(|n| n.as_element().is_none()) {
+    leaves.insert(path.to_string());
+  }
+
+  for (i, child) in child_elements(element).enumerate() {
+    let child_path = format!("{path}/{}", element_key(child, i));
+    flatten_element(child, &child_path, leaves);
+  }
+}
+
+/// `entry[eth0]` for named entries, `entry[ClamAV]` for product list entries
+fn element_key(element: &Element, index: usize) -> String {
+  if element.name != "entry" {
+    return element.name.clone();
+  }
+
+  let key = element.attributes.get("name").or_else(|| {
+    element
+      .get_child("ProductInfo")
+      .and_then(|info| info.get_child("Prod"))
+      .and_then(|prod| prod.attributes.get("name"))
+  });
+
+  match key {
+    Some(key) => format!("entry[{key}]"),
+    None => format!("entry[{index}]"),
+  }
+}
+
+/// Mask the user and machine identifiers, keeping the first and last character
+pub(super) fn redact_report(element: &mut Element) {
+  for child in child_elements_mut(element) {
+    if SENSITIVE_FIELDS.contains(&child.name.as_str()) {
+      redact_all(child);
+    } else {
+      redact_report(child);
+    }
+  }
+}
+
+fn redact_all(element: &mut Element) {
+  if let Some(name) = element.attributes.get_mut("name") {
+    *name = redact_value(name);
+  }
+
+  for node in element.children.iter_mut() {
+    match node {
+      XMLNode::Text(text) if !text.trim().is_empty() => *text = redact_value(text.trim()),
+      XMLNode::Element(child) => redact_all(child),
+      _ => {}
+    }
+  }
+}
+
+/// Render the report as JSON: attributes become `@name` keys, repeated elements become
+/// arrays, and text-only elements become strings
+pub(super) fn report_to_json(report: &Element) -> Value {
+  let mut root = Map::new();
+  root.insert(report.name.clone(), element_to_json(report));
+  Value::Object(root)
+}
+
+fn element_to_json(element: &Element) -> Value {
+  let children = child_elements(element).collect::<Vec<_>>();
+  let text = element
+    .get_text()
+    .map(|t| t.trim().to_string())
+    .filter(|t| !t.is_empty());
+
+  if element.attributes.is_empty() && children.is_empty() {
+    return Value::String(text.unwrap_or_default());
+  }
+
+  let mut object = Map::new();
+  for (attr, value) in &element.attributes {
+    object.insert(format!("@{attr}"), Value::String(value.clone()));
+  }
+  if let Some(text) = text {
+    object.insert("#text".to_string(), Value::String(text));
+  }
+
+  for child in children {
+    let value = element_to_json(child);
+    match object.get_mut(&child.name) {
+      Some(Value::Array(items)) => items.push(value),
+      Some(existing) => {
+        let first = existing.take();
+        *existing = Value::Array(vec![first, value]);
+      }
+      None => {
+        object.insert(child.name.clone(), value);
+      }
+    }
+  }
+
+  Value::Object(object)
+}
+
+fn child_elements(element: &Element) -> impl Iterator<Item = &Element> {
+  element.children.iter().filter_map(|node| node.as_element())
+}
+
+fn child_elements_mut(element: &mut Element) -> impl Iterator<Item = &mut Element> {
+  element.children.iter_mut().filter_map(|node| match node {
+    XMLNode::Element(e) => Some(e),
+    _ => None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MD5: &str = "0123456789abcdef0123456789abcdef";
+
+  fn report(categories: &str) -> Element {
+    Element::parse(
+      format!(
+        r#"<hip-report name="hip-report">
+          <md5-sum>{MD5}</md5-sum>
+          <user-name>alice</user-name>
+          <domain>corp.internal</domain>
+          <host-name>alice-laptop</host-name>
+          <host-id>3f1c0000-0000-0000-0000-000000000000</host-id>
+          <ip-address>10.0.0.10</ip-address>
+          <generate-time>10/18/2026 10:00:00</generate-time>
+          <hip-report-version>4</hip-report-version>
+          <categories>
+            <entry name="host-info">
+              <os>Linux Fedora 40</os>
+              <network-interface>
+                <entry name="eth0"><mac-address>00:11:22:33:44:55</mac-address></entry>
+              </network-interface>
+            </entry>
+            {categories}
+          </categories>
+        </hip-report>"#
+      )
+      .as_bytes(),
+    )
+    .unwrap()
+  }
+
+  fn product(name: &str, vendor: &str, enabled: &str) -> String {
+    format!(
+      r#"<entry><ProductInfo><Prod name="{name}" version="1.0" vendor="{vendor}"/><is-enabled>{enabled}</is-enabled></ProductInfo></entry>"#
+    )
+  }
+
+  #[test]
+  fn accepts_a_well_formed_report() {
+    let report = report(&format!(
+      r#"<entry name="firewall"><list>{}</list></entry>"#,
+      product("nftables", "The Netfilter Project", "yes")
+    ));
+
+    assert_eq!(validate_report(&report, MD5), Vec::<String>::new());
+  }
+
+  #[test]
+  fn reports_md5_mismatch_and_structural_problems() {
+    let report = report(&format!(
+      r#"<entry name="firewall"><list>{}</list></entry><entry name="firewall"/><entry name="antispam"><list/></entry>"#,
+      product("nftables", "", "yes")
+    ));
+
+    let problems = validate_report(&report, "ffffffffffffffffffffffffffffffff");
+
+    assert_eq!(
+      problems,
+      [
+        format!("<md5-sum> is {MD5}, the cookie digest is ffffffffffffffffffffffffffffffff"),
+        "firewall product #0 has no vendor".to_string(),
+        "category firewall appears more than once".to_string(),
+        "category firewall has no <list>".to_string(),
+        "unknown category antispam".to_string(),
+      ]
+    );
+  }
+
+  #[test]
+  fn reports_missing_fields() {
+    let report = Element::parse(r#"<hip-report><md5-sum/><categories/></hip-report>"#.as_bytes()).unwrap();
+
+    let problems = validate_report(&report, MD5);
+
+    assert!(problems.contains(&"missing <user-name>".to_string()), "{problems:?}");
+    assert!(problems.contains(&"<md5-sum> is empty".to_string()), "{problems:?}");
+    assert!(
+      problems.contains(&"missing the host-info category".to_string()),
+      "{problems:?}"
+    );
+  }
+
+  #[test]
+  fn diffs_reports_by_category_and_product() {
+    let linux = report(&format!(
+      r#"<entry name="firewall"><list>{}{}</list></entry>"#,
+      product("IPTables", "IPTables", "no"),
+      product("nftables", "The Netfilter Project", "yes")
+    ));
+    let mut windows = report(&format!(
+      r#"<entry name="firewall"><list>{}{}</list></entry><entry name="antivirus"><list/></entry>"#,
+      product("nftables", "The Netfilter Project", "no"),
+      product("IPTables", "IPTables", "no")
+    ));
+    windows.get_mut_child("generate-time").unwrap().children = vec![XMLNode::Text("01/01/2027 00:00:00".into())];
+
+    let diffs = diff_reports(&linux, &windows);
+
+    assert_eq!(
+      diffs,
+      [
+        SectionDiff {
+          section: "antivirus".to_string(),
+          removed: vec![],
+          added: vec!["list".to_string()],
+        },
+        SectionDiff {
+          section: "firewall".to_string(),
+          removed: vec!["list/entry[nftables]/ProductInfo/is-enabled = yes".to_string()],
+          added: vec!["list/entry[nftables]/ProductInfo/is-enabled = no".to_string()],
+        }
+      ]
+    );
+    assert_eq!(
+      format_diff(&diffs),
+      "[antivirus]\n+ list\n[firewall]\n- list/entry[nftables]/ProductInfo/is-enabled = yes\n+ list/entry[nftables]/ProductInfo/is-enabled = no\n"
+    );
+  }
+
+  #[test]
+  fn redacts_user_and_machine_identifiers() {
+    let mut report = report("");
+
+    redact_report(&mut report);
+    let json = report_to_json(&report);
+
+    let root = &json["hip-report"];
+    assert_eq!(root["user-name"], "a**********e");
+    assert_eq!(root["md5-sum"], "0**********f");
+    assert_eq!(root["hip-report-version"], "4");
+    let host_info = &root["categories"]["entry"];
+    assert_eq!(host_info["@name"], "host-info");
+    assert_eq!(host_info["os"], "Linux Fedora 40");
+    assert_eq!(host_info["network-interface"]["entry"]["@name"], "e**********0");
+    assert_eq!(host_info["network-interface"]["entry"]["mac-address"], "0**********5");
+  }
+
+  #[test]
+  fn repeated_elements_become_arrays() {
+    let report = Element::parse(r#"<r><entry name="a"/><entry name="b"/></r>"#.as_bytes()).unwrap();
+
+    let json = report_to_json(&report);
+
+    assert_eq!(json["r"]["entry"][0]["@name"], "a");
+    assert_eq!(json["r"]["entry"][1]["@name"], "b");
+  }
+}
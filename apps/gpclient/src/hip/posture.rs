@@ -0,0 +1,858 @@
+//! Linux posture collectors, discovering the HIP categories from the running machine.
+//!
+//! The collectors only talk to the machine through [`HostProbe`], the live implementation
+//! reads `/proc`, `/sys` and runs the distro tools, the tests feed captured outputs instead.
+
+use std::{fs, process::Command};
+
+use chrono::NaiveDateTime;
+use log::debug;
+use serde::Deserialize;
+
+use super::{
+  HostProbe, PostureCollector, parse_defender_info,
+  profile::{
+    AntiMalwareCategory, AntiMalwareProduct, DiskEncryptionCategory, DiskEncryptionProduct, EncryptedDrive,
+    EncryptionState, HipProfile, MissingPatch, PatchManagementCategory, ToggleCategory, ToggleProduct,
+  },
+};
+
+/// `/proc/<pid>/comm` holds at most 15 bytes of the executable name
+const PROC_COMM_MAX_LEN: usize = 15;
+
+/// Run every Linux collector against `host`
+pub(super) fn collect_linux_posture(host: &dyn HostProbe) -> HipProfile {
+  let collectors: [&dyn PostureCollector; 4] = [
+    &AntiMalwareCollector,
+    &DiskEncryptionCollector,
+    &FirewallCollector,
+    &PatchManagementCollector,
+  ];
+
+  let mut posture = HipProfile::default();
+  for collector in collectors {
+    collector.collect(host, &mut posture);
+  }
+
+  debug!("Collected HIP posture: {:?}", posture);
+  posture
+}
+
+/// The machine as seen by the collectors at HIP report time
+pub(super) struct LiveHost;
+
+impl HostProbe for LiveHost {
+  fn read_file(&self, path: &str) -> Option<String> {
+    fs::read_to_string(path).ok()
+  }
+
+  fn list_dir(&self, path: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(path) else {
+      return Vec::new();
+    };
+
+    entries
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.file_name().to_string_lossy().into_owned())
+      .collect()
+  }
+
+  fn command_output(&self, program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+
+    if !output.status.success() {
+      debug!("`{} {}` failed with {}", program, args.join(" "), output.status);
+      return None;
+    }
+
+    String::from_utf8(output.stdout).ok()
+  }
+
+  fn process_names(&self) -> Vec<String> {
+    self
+      .list_dir("/proc")
+      .into_iter()
+      .filter(|pid| pid.bytes().all(|b| b.is_ascii_digit()))
+      .filter_map(|pid| self.read_file(&format!("/proc/{pid}/comm")))
+      .map(|comm| comm.trim_end().to_string())
+      .collect()
+  }
+}
+
+// ============================================================================
+// Anti-malware
+// ============================================================================
+
+/// Endpoint agents recognized by their package and daemon names
+struct EndpointAgent {
+  vendor: &'static str,
+  name: &'static str,
+  packages: &'static [&'static str],
+  /// Any of these running means real-time protection is on
+  processes: &'static [&'static str],
+}
+
+const ENDPOINT_AGENTS: &[EndpointAgent] = &[
+  EndpointAgent {
+    vendor: "Cisco Systems, Inc.",
+    name: "ClamAV",
+    packages: &["clamav", "clamav-daemon"],
+    processes: &["clamd", "clamonacc"],
+  },
+  EndpointAgent {
+    vendor: "CrowdStrike, Inc.",
+    name: "CrowdStrike Falcon Sensor",
+    packages: &["falcon-sensor"],
+    processes: &["falcon-sensor"],
+  },
+  EndpointAgent {
+    vendor: "SentinelOne",
+    name: "SentinelOne Agent",
+    packages: &["sentinelagent", "SentinelAgent"],
+    processes: &["sentinelone-agent", "s1-agent"],
+  },
+];
+
+struct AntiMalwareCollector;
+
+impl PostureCollector for AntiMalwareCollector {
+  fn collect(&self, host: &dyn HostProbe, posture: &mut HipProfile) {
+    let processes = host.process_names();
+    let mut products = Vec::new();
+
+    for agent in ENDPOINT_AGENTS {
+      let Some(version) = agent
+        .packages
+        .iter()
+        .find_map(|pkg| installed_package_version(host, pkg))
+      else {
+        continue;
+      };
+
+      let mut product = AntiMalwareProduct {
+        vendor: agent.vendor.to_string(),
+        name: agent.name.to_string(),
+        version,
+        engine_version: String::new(),
+        definitions_version: String::new(),
+        definitions_date: None,
+        real_time_protection: agent
+          .processes
+          .iter()
+          .any(|name| processes.iter().any(|comm| process_matches(comm, name))),
+        last_full_scan_time: None,
+      };
+
+      if agent.name == "ClamAV"
+        && let Some(info) = host
+          .command_output("clamscan", &["--version"])
+          .as_deref()
+          .and_then(parse_clamav_version)
+      {
+        product.engine_version = info.engine_version;
+        product.definitions_version = info.definitions_version;
+        product.definitions_date = info.definitions_date;
+      }
+
+      products.push(product);
+    }
+
+    if let Some(defender) = host
+      .command_output("mdatp", &["health", "--output", "json"])
+      .as_deref()
+      .and_then(parse_defender_info)
+    {
+      products.push(AntiMalwareProduct {
+        vendor: "Microsoft Corporation".to_string(),
+        name: "Microsoft Defender ATP".to_string(),
+        version: defender.app_version,
+        engine_version: defender.engine_version,
+        definitions_version: defender.definitions_version,
+        definitions_date: None,
+        real_time_protection: defender.real_time_protection_enabled.value,
+        last_full_scan_time: None,
+      });
+    }
+
+    if !products.is_empty() {
+      posture.anti_malware = Some(AntiMalwareCategory { products });
+    }
+  }
+}
+
+struct ClamavVersion {
+  engine_version: String,
+  definitions_version: String,
+  definitions_date: Option<String>,
+}
+
+/// Parse `ClamAV 1.0.7/27412/Mon Sep 30 08:35:52 2024`, the definitions part is missing
+/// when no database has been downloaded yet
+fn parse_clamav_version(output: &str) -> Option<ClamavVersion> {
+  let line = output.lines().next()?.trim().strip_prefix("ClamAV ")?;
+  let mut parts = line.splitn(3, '/');
+
+  let engine_version = parts.next()?.trim().to_string();
+  let definitions_version = parts.next().unwrap_or_default().trim().to_string();
+  let definitions_date = parts
+    .next()
+    .and_then(|date| NaiveDateTime::parse_from_str(date.trim(), "%a %b %e %H:%M:%S %Y").ok())
+    .map(|date| date.format("%Y-%m-%d").to_string());
+
+  Some(ClamavVersion {
+    engine_version,
+    definitions_version,
+    definitions_date,
+  })
+}
+
+fn process_matches(comm: &str, name: &str) -> bool {
+  comm == name || (comm.len() == PROC_COMM_MAX_LEN && name.starts_with(comm))
+}
+
+/// The installed version from the dpkg or rpm database
+fn installed_package_version(host: &dyn HostProbe, package: &str) -> Option<String> {
+  let dpkg = host
+    .command_output("dpkg-query", &["-W", "-f=${Status}\t${Version}", package])
+    .and_then(|output| parse_dpkg_query(&output));
+
+  dpkg.or_else(|| {
+    host
+      .command_output("rpm", &["-q", "--qf", "%{VERSION}-%{RELEASE}", package])
+      .map(|output| output.trim().to_string())
+      .filter(|version| !version.is_empty())
+  })
+}
+
+/// `install ok installed\t1.0.7+dfsg-1`, removed packages keep a `config-files` status
+fn parse_dpkg_query(output: &str) -> Option<String> {
+  let (status, version) = output.trim().split_once('\t')?;
+
+  (status.split_whitespace().last() == Some("installed")).then(|| version.to_string())
+}
+
+// ============================================================================
+// Disk encryption
+// ============================================================================
+
+#[derive(Debug, Deserialize)]
+struct LsblkOutput {
+  blockdevices: Vec<LsblkDevice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LsblkDevice {
+  name: String,
+  #[serde(rename = "type")]
+  kind: String,
+  fstype: Option<String>,
+  mountpoint: Option<String>,
+  #[serde(default)]
+  children: Vec<LsblkDevice>,
+}
+
+struct DiskEncryptionCollector;
+
+impl PostureCollector for DiskEncryptionCollector {
+  fn collect(&self, host: &dyn HostProbe, posture: &mut HipProfile) {
+    let drives = host
+      .command_output("lsblk", &["-J", "-o", "NAME,TYPE,FSTYPE,MOUNTPOINT"])
+      .and_then(|output| drives_from_lsblk(&output))
+      .unwrap_or_else(|| drives_from_sysfs(host));
+
+    if drives.is_empty() {
+      return;
+    }
+
+    let version = host
+      .command_output("cryptsetup", &["--version"])
+      .and_then(|output| output.split_whitespace().nth(1).map(ToOwned::to_owned))
+      .unwrap_or_default();
+
+    posture.disk_encryption = Some(DiskEncryptionCategory {
+      products: vec![DiskEncryptionProduct {
+        vendor: "GitLab Inc.".to_string(),
+        name: "cryptsetup".to_string(),
+        version,
+        drives,
+      }],
+    });
+  }
+}
+
+fn drives_from_lsblk(output: &str) -> Option<Vec<EncryptedDrive>> {
+  let lsblk: LsblkOutput = serde_json::from_str(output).ok()?;
+  let mut drives = Vec::new();
+
+  fn walk(device: &LsblkDevice, encrypted: bool, drives: &mut Vec<EncryptedDrive>) {
+    if device.kind == "loop" {
+      return;
+    }
+
+    let encrypted = encrypted || device.kind == "crypt" || device.fstype.as_deref() == Some("crypto_LUKS");
+    if let Some(mountpoint) = device.mountpoint.as_deref().filter(|m| m.starts_with('/')) {
+      debug!("{} mounted at {}, encrypted: {}", device.name, mountpoint, encrypted);
+      drives.push(drive(mountpoint, encrypted));
+    }
+
+    for child in &device.children {
+      walk(child, encrypted, drives);
+    }
+  }
+
+  for device in &lsblk.blockdevices {
+    walk(device, false, &mut drives);
+  }
+
+  Some(drives)
+}
+
+/// Without lsblk, LUKS mappings are found by their `CRYPT-LUKS` device-mapper UUID and
+/// matched against the mount table
+fn drives_from_sysfs(host: &dyn HostProbe) -> Vec<EncryptedDrive> {
+  let crypt_devices = host
+    .list_dir("/sys/block")
+    .into_iter()
+    .filter(|dev| dev.starts_with("dm-"))
+    .filter(|dev| {
+      host
+        .read_file(&format!("/sys/block/{dev}/dm/uuid"))
+        .is_some_and(|uuid| uuid.starts_with("CRYPT-LUKS"))
+    })
+    .flat_map(|dev| {
+      let name = host
+        .read_file(&format!("/sys/block/{dev}/dm/name"))
+        .map(|name| format!("/dev/mapper/{}", name.trim()));
+      [Some(format!("/dev/{dev}")), name].into_iter().flatten()
+    })
+    .collect::<Vec<_>>();
+
+  let Some(mounts) = host.read_file("/proc/self/mounts") else {
+    return Vec::new();
+  };
+
+  mounts
+    .lines()
+    .filter_map(|line| {
+      let mut fields = line.split_whitespace();
+      Some((fields.next()?, fields.next()?))
+    })
+    .filter(|(device, _)| device.starts_with("/dev/") && !device.starts_with("/dev/loop"))
+    .map(|(device, mountpoint)| drive(mountpoint, crypt_devices.iter().any(|dev| dev == device)))
+    .collect()
+}
+
+fn drive(mountpoint: &str, encrypted: bool) -> EncryptedDrive {
+  EncryptedDrive {
+    name: mountpoint.to_string(),
+    state: if encrypted {
+      EncryptionState::Encrypted
+    } else {
+      EncryptionState::Unencrypted
+    },
+  }
+}
+
+// ============================================================================
+// Firewall
+// ============================================================================
+
+struct FirewallCollector;
+
+impl PostureCollector for FirewallCollector {
+  fn collect(&self, host: &dyn HostProbe, posture: &mut HipProfile) {
+    let mut products = Vec::new();
+
+    if let Some(version) = host.command_output("ufw", &["version"]) {
+      let enabled = host
+        .read_file("/etc/ufw/ufw.conf")
+        .is_some_and(|conf| parse_shell_var(&conf, "ENABLED").as_deref() == Some("yes"));
+      products.push(toggle(
+        "Canonical Ltd.",
+        "UFW",
+        last_word(first_line(&version)),
+        enabled,
+      ));
+    }
+
+    if let Some(version) = host.command_output("firewall-cmd", &["--version"]) {
+      let enabled = host
+        .command_output("firewall-cmd", &["--state"])
+        .is_some_and(|state| state.trim() == "running");
+      products.push(toggle("Red Hat, Inc.", "firewalld", first_line(&version), enabled));
+    }
+
+    if let Some(version) = host.command_output("nft", &["--version"]) {
+      // `nftables v1.0.9 (Old Doc Yak #3)`
+      let version = version
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .trim_start_matches('v');
+      let enabled = host
+        .command_output("nft", &["list", "ruleset"])
+        .is_some_and(|ruleset| ruleset.contains("hook input"));
+      products.push(toggle("The Netfilter Project", "nftables", version, enabled));
+    }
+
+    if !products.is_empty() {
+      posture.firewall = Some(ToggleCategory { products });
+    }
+  }
+}
+
+/// Read `KEY=value` from a shell-style config file such as `/etc/ufw/ufw.conf`
+fn parse_shell_var(content: &str, key: &str) -> Option<String> {
+  content.lines().find_map(|line| {
+    let (k, v) = line.trim().split_once('=')?;
+    (k.trim() == key).then(|| v.trim().trim_matches(|c| c == '"' || c == '\'').to_string())
+  })
+}
+
+// ============================================================================
+// Patch management
+// ============================================================================
+
+const APT_AUTO_UPGRADES: &str = "/etc/apt/apt.conf.d/20auto-upgrades";
+const SYSTEMD_TIMERS_DIR: &str = "/etc/systemd/system/timers.target.wants";
+
+struct PatchManagementCollector;
+
+impl PostureCollector for PatchManagementCollector {
+  fn collect(&self, host: &dyn HostProbe, posture: &mut HipProfile) {
+    let mut products = Vec::new();
+    let mut missing_patches = Vec::new();
+
+    // `apt 2.7.14 (amd64)`
+    if let Some(version) = host.command_output("apt-get", &["--version"]) {
+      let version = first_line(&version).split_whitespace().nth(1).unwrap_or_default();
+      let enabled = host
+        .read_file(APT_AUTO_UPGRADES)
+        .is_some_and(|conf| conf.contains(r#"APT::Periodic::Unattended-Upgrade "1""#));
+      products.push(toggle("Debian", "APT", version, enabled));
+
+      // The simulation only reads the local package lists, it neither locks nor downloads
+      if let Some(output) = host.command_output("apt-get", &["-s", "-o", "Debug::NoLocking=true", "upgrade"]) {
+        missing_patches.extend(parse_apt_security_upgrades(&output));
+      }
+    }
+
+    // dnf4 prints `4.19.2`, dnf5 prints `dnf5 version 5.1.17`
+    if let Some(version) = host.command_output("dnf", &["--version"]) {
+      let enabled = host
+        .list_dir(SYSTEMD_TIMERS_DIR)
+        .iter()
+        .any(|timer| timer.starts_with("dnf-automatic") || timer.starts_with("dnf5-automatic"));
+      products.push(toggle(
+        "Red Hat, Inc.",
+        "Dandified Yum",
+        last_word(first_line(&version)),
+        enabled,
+      ));
+
+      if let Some(output) = host.command_output("dnf", &["-q", "-C", "updateinfo", "list", "--security"]) {
+        missing_patches.extend(parse_dnf_security_updates(&output));
+      }
+    }
+
+    if !products.is_empty() {
+      posture.patch_management = Some(PatchManagementCategory {
+        products,
+        missing_patches,
+      });
+    }
+  }
+}
+
+/// Pick the security upgrades from `apt-get -s upgrade`, e.g.
+/// `Inst openssl [3.0.13-0ubuntu3.1] (3.0.13-0ubuntu3.4 Ubuntu:24.04/noble-security [amd64])`
+fn parse_apt_security_upgrades(output: &str) -> Vec<MissingPatch> {
+  output
+    .lines()
+    .filter_map(|line| line.strip_prefix("Inst "))
+    .filter_map(|line| {
+      let package = line.split_whitespace().next()?;
+      let (_, candidate) = line.split_once('(')?;
+      if !candidate.contains("-security") {
+        return None;
+      }
+
+      let version = candidate.split_whitespace().next().unwrap_or_default();
+      let vendor = candidate
+        .split_whitespace()
+        .nth(1)
+        .and_then(|origin| origin.split(':').next());
+
+      Some(MissingPatch {
+        title: format!("{package} {version}"),
+        product: package.to_string(),
+        vendor: vendor.unwrap_or_default().to_string(),
+        category: "Security Updates".to_string(),
+        severity: 0,
+      })
+    })
+    .collect()
+}
+
+/// Parse `dnf updateinfo list --security`, e.g.
+/// `FEDORA-2024-1a2b3c4d5e Important/Sec. openssl-1:3.2.2-3.fc40.x86_64`
+fn parse_dnf_security_updates(output: &str) -> Vec<MissingPatch> {
+  output
+    .lines()
+    .filter_map(|line| {
+      let fields = line.split_whitespace().collect::<Vec<_>>();
+      let (advisory, package) = (fields.first()?, fields.last()?);
+      if fields.len() < 3 || !advisory.contains('-') || *advisory == "Name" {
+        return None;
+      }
+
+      let severity = fields[1..fields.len() - 1]
+        .iter()
+        .find_map(|field| dnf_severity(field))
+        .unwrap_or(0);
+
+      Some(MissingPatch {
+        title: format!("{advisory} {package}"),
+        product: package.to_string(),
+        vendor: String::new(),
+        category: "Security Updates".to_string(),
+        severity,
+      })
+    })
+    .collect()
+}
+
+fn dnf_severity(field: &str) -> Option<u8> {
+  match field.split('/').next()? {
+    "Critical" => Some(4),
+    "Important" => Some(3),
+    "Moderate" => Some(2),
+    "Low" => Some(1),
+    _ => None,
+  }
+}
+
+fn toggle(vendor: &str, name: &str, version: &str, enabled: bool) -> ToggleProduct {
+  ToggleProduct {
+    vendor: vendor.to_string(),
+    name: name.to_string(),
+    version: version.trim().to_string(),
+    enabled,
+  }
+}
+
+fn first_line(output: &str) -> &str {
+  output.lines().next().unwrap_or_default().trim()
+}
+
+fn last_word(line: &str) -> &str {
+  line.split_whitespace().last().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+
+  /// A machine described by captured file contents and command outputs
+  #[derive(Default)]
+  struct FixtureHost {
+    files: HashMap<String, String>,
+    dirs: HashMap<String, Vec<String>>,
+    commands: HashMap<String, String>,
+    processes: Vec<String>,
+  }
+
+  impl FixtureHost {
+    fn file(mut self, path: &str, content: &str) -> Self {
+      self.files.insert(path.to_string(), content.to_string());
+      self
+    }
+
+    fn dir(mut self, path: &str, entries: &[&str]) -> Self {
+      self
+        .dirs
+        .insert(path.to_string(), entries.iter().map(ToString::to_string).collect());
+      self
+    }
+
+    fn command(mut self, cmdline: &str, output: &str) -> Self {
+      self.commands.insert(cmdline.to_string(), output.to_string());
+      self
+    }
+
+    fn process(mut self, comm: &str) -> Self {
+      self.processes.push(comm.to_string());
+      self
+    }
+  }
+
+  impl HostProbe for FixtureHost {
+    fn read_file(&self, path: &str) -> Option<String> {
+      self.files.get(path).cloned()
+    }
+
+    fn list_dir(&self, path: &str) -> Vec<String> {
+      self.dirs.get(path).cloned().unwrap_or_default()
+    }
+
+    fn command_output(&self, program: &str, args: &[&str]) -> Option<String> {
+      let cmdline = std::iter::once(program).chain(args.iter().copied()).collect::<Vec<_>>();
+      self.commands.get(&cmdline.join(" ")).cloned()
+    }
+
+    fn process_names(&self) -> Vec<String> {
+      self.processes.clone()
+    }
+  }
+
+  const LSBLK_LUKS_ROOT: &str = r#"{
+    "blockdevices": [
+      {"name": "loop0", "type": "loop", "fstype": "squashfs", "mountpoint": "/snap/core22/1380"},
+      {"name": "nvme0n1", "type": "disk", "fstype": null, "mountpoint": null,
+        "children": [
+          {"name": "nvme0n1p1", "type": "part", "fstype": "vfat", "mountpoint": "/boot/efi"},
+          {"name": "nvme0n1p2", "type": "part", "fstype": "crypto_LUKS", "mountpoint": null,
+            "children": [
+              {"name": "luks-3f1c", "type": "crypt", "fstype": "ext4", "mountpoint": "/"}
+            ]
+          },
+          {"name": "nvme0n1p3", "type": "part", "fstype": "swap", "mountpoint": "[SWAP]"}
+        ]
+      }
+    ]
+  }"#;
+
+  const APT_GET_UPGRADE: &str = "\
+NOTE: This is only a simulation!
+Reading package lists...
+The following packages will be upgraded:
+  libssl3t64 openssl tzdata
+Inst libssl3t64 [3.0.13-0ubuntu3.1] (3.0.13-0ubuntu3.4 Ubuntu:24.04/noble-updates, Ubuntu:24.04/noble-security [amd64])
+Inst openssl [3.0.13-0ubuntu3.1] (3.0.13-0ubuntu3.4 Ubuntu:24.04/noble-updates, Ubuntu:24.04/noble-security [amd64])
+Inst tzdata [2024a-2ubuntu1] (2024a-3ubuntu1.1 Ubuntu:24.04/noble-updates [all])
+Conf libssl3t64 (3.0.13-0ubuntu3.4 Ubuntu:24.04/noble-updates, Ubuntu:24.04/noble-security [amd64])
+";
+
+  const DNF_UPDATEINFO: &str = "\
+FEDORA-2024-1a2b3c4d5e Important/Sec. openssl-libs-1:3.2.2-3.fc40.x86_64
+FEDORA-2024-6f7a8b9c0d Moderate/Sec.  curl-8.6.0-10.fc40.x86_64
+";
+
+  #[test]
+  fn detects_luks_root_from_lsblk() {
+    let host = FixtureHost::default()
+      .command("lsblk -J -o NAME,TYPE,FSTYPE,MOUNTPOINT", LSBLK_LUKS_ROOT)
+      .command("cryptsetup --version", "cryptsetup 2.7.0 flags: UDEV BLKID KEYRING\n");
+
+    let posture = collect_linux_posture(&host);
+
+    let product = &posture.disk_encryption.unwrap().products[0];
+    assert_eq!(product.version, "2.7.0");
+    let drives = product
+      .drives
+      .iter()
+      .map(|d| (d.name.as_str(), d.state))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      drives,
+      [
+        ("/boot/efi", EncryptionState::Unencrypted),
+        ("/", EncryptionState::Encrypted)
+      ]
+    );
+  }
+
+  #[test]
+  fn falls_back_to_sysfs_without_lsblk() {
+    let host = FixtureHost::default()
+      .dir("/sys/block", &["nvme0n1", "dm-0", "dm-1"])
+      .file("/sys/block/dm-0/dm/uuid", "CRYPT-LUKS2-3f1c-luks-3f1c\n")
+      .file("/sys/block/dm-0/dm/name", "luks-3f1c\n")
+      .file("/sys/block/dm-1/dm/uuid", "LVM-abcdef\n")
+      .file(
+        "/proc/self/mounts",
+        "/dev/mapper/luks-3f1c / ext4 rw,relatime 0 0\n\
+         /dev/dm-1 /data xfs rw 0 0\n\
+         /dev/nvme0n1p1 /boot/efi vfat rw 0 0\n\
+         /dev/loop0 /snap/core22/1380 squashfs ro 0 0\n\
+         proc /proc proc rw 0 0\n",
+      );
+
+    let posture = collect_linux_posture(&host);
+
+    let drives = posture.disk_encryption.unwrap().products[0]
+      .drives
+      .iter()
+      .map(|d| (d.name.clone(), d.state))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      drives,
+      [
+        ("/".to_string(), EncryptionState::Encrypted),
+        ("/data".to_string(), EncryptionState::Unencrypted),
+        ("/boot/efi".to_string(), EncryptionState::Unencrypted),
+      ]
+    );
+  }
+
+  #[test]
+  fn reports_each_installed_firewall() {
+    let host = FixtureHost::default()
+      .command("ufw version", "ufw 0.36.2\nCopyright 2008-2023 Canonical Ltd.\n")
+      .file("/etc/ufw/ufw.conf", "# comment\nENABLED=yes\nLOGLEVEL=low\n")
+      .command("firewall-cmd --version", "2.1.1\n")
+      .command("nft --version", "nftables v1.0.9 (Old Doc Yak #3)\n")
+      .command(
+        "nft list ruleset",
+        "table inet filter {\n\tchain input {\n\t\ttype filter hook input priority filter; policy drop;\n\t}\n}\n",
+      );
+
+    let posture = collect_linux_posture(&host);
+
+    let products = posture
+      .firewall
+      .unwrap()
+      .products
+      .into_iter()
+      .map(|p| (p.name, p.version, p.enabled))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      products,
+      [
+        ("UFW".to_string(), "0.36.2".to_string(), true),
+        // `firewall-cmd --state` fails when the daemon is not running
+        ("firewalld".to_string(), "2.1.1".to_string(), false),
+        ("nftables".to_string(), "1.0.9".to_string(), true),
+      ]
+    );
+  }
+
+  #[test]
+  fn leaves_firewall_untouched_when_nothing_is_installed() {
+    let posture = collect_linux_posture(&FixtureHost::default());
+
+    assert!(posture.firewall.is_none());
+    assert!(posture.disk_encryption.is_none());
+    assert!(posture.patch_management.is_none());
+    assert!(posture.anti_malware.is_none());
+  }
+
+  #[test]
+  fn detects_endpoint_agents_by_package_and_process() {
+    let host = FixtureHost::default()
+      .command(
+        "dpkg-query -W -f=${Status}\t${Version} clamav",
+        "install ok installed\t1.0.7+dfsg-1\n",
+      )
+      .command("clamscan --version", "ClamAV 1.0.7/27412/Mon Sep 30 08:35:52 2024\n")
+      .command(
+        "dpkg-query -W -f=${Status}\t${Version} falcon-sensor",
+        "deinstall ok config-files\t7.10.0\n",
+      )
+      .command("rpm -q --qf %{VERSION}-%{RELEASE} SentinelAgent", "24.1.2.6-1")
+      .process("systemd")
+      .process("clamd")
+      .process("sentinelone-age");
+
+    let posture = collect_linux_posture(&host);
+
+    let products = posture.anti_malware.unwrap().products;
+    assert_eq!(products.len(), 2, "{products:?}");
+
+    assert_eq!(products[0].name, "ClamAV");
+    assert_eq!(products[0].version, "1.0.7+dfsg-1");
+    assert_eq!(products[0].engine_version, "1.0.7");
+    assert_eq!(products[0].definitions_version, "27412");
+    assert_eq!(products[0].definitions_date.as_deref(), Some("2024-09-30"));
+    assert!(products[0].real_time_protection);
+
+    assert_eq!(products[1].name, "SentinelOne Agent");
+    assert_eq!(products[1].version, "24.1.2.6-1");
+    assert!(products[1].real_time_protection);
+  }
+
+  #[test]
+  fn reports_microsoft_defender_from_mdatp() {
+    let host = FixtureHost::default().command(
+      "mdatp health --output json",
+      r#"{"appVersion": "101.25042.0000", "engineVersion": "1.1.25040.2",
+          "definitionsVersion": "1.429.201.0", "realTimeProtectionEnabled": {"value": false}}"#,
+    );
+
+    let products = collect_linux_posture(&host).anti_malware.unwrap().products;
+
+    assert_eq!(products[0].name, "Microsoft Defender ATP");
+    assert_eq!(products[0].definitions_version, "1.429.201.0");
+    assert!(!products[0].real_time_protection);
+  }
+
+  #[test]
+  fn clamav_without_database_has_no_definitions() {
+    let info = parse_clamav_version("ClamAV 1.3.1\n").unwrap();
+
+    assert_eq!(info.engine_version, "1.3.1");
+    assert!(info.definitions_version.is_empty());
+    assert!(info.definitions_date.is_none());
+  }
+
+  #[test]
+  fn collects_pending_apt_security_updates() {
+    let host = FixtureHost::default()
+      .command("apt-get --version", "apt 2.7.14 (amd64)\nSupported modules:\n")
+      .command("apt-get -s -o Debug::NoLocking=true upgrade", APT_GET_UPGRADE)
+      .file(
+        APT_AUTO_UPGRADES,
+        "APT::Periodic::Update-Package-Lists \"1\";\nAPT::Periodic::Unattended-Upgrade \"1\";\n",
+      );
+
+    let category = collect_linux_posture(&host).patch_management.unwrap();
+
+    assert_eq!(category.products[0].name, "APT");
+    assert_eq!(category.products[0].version, "2.7.14");
+    assert!(category.products[0].enabled);
+
+    let titles = category
+      .missing_patches
+      .iter()
+      .map(|p| p.title.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(titles, ["libssl3t64 3.0.13-0ubuntu3.4", "openssl 3.0.13-0ubuntu3.4"]);
+    assert_eq!(category.missing_patches[0].vendor, "Ubuntu");
+  }
+
+  #[test]
+  fn collects_pending_dnf_security_updates() {
+    let host = FixtureHost::default()
+      .command("dnf --version", "dnf5 version 5.1.17\ndnf5 plugin API version 2.0\n")
+      .command("dnf -q -C updateinfo list --security", DNF_UPDATEINFO)
+      .dir(SYSTEMD_TIMERS_DIR, &["dnf-automatic-install.timer"]);
+
+    let category = collect_linux_posture(&host).patch_management.unwrap();
+
+    assert_eq!(category.products[0].name, "Dandified Yum");
+    assert_eq!(category.products[0].version, "5.1.17");
+    assert!(category.products[0].enabled);
+
+    let patches = category
+      .missing_patches
+      .iter()
+      .map(|p| (p.product.as_str(), p.severity))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      patches,
+      [
+        ("openssl-libs-1:3.2.2-3.fc40.x86_64", 3),
+        ("curl-8.6.0-10.fc40.x86_64", 2)
+      ]
+    );
+  }
+
+  #[test]
+  fn matches_truncated_process_names() {
+    assert!(process_matches("falcon-sensor", "falcon-sensor"));
+    assert!(process_matches("sentinelone-age", "sentinelone-agent"));
+    assert!(!process_matches("clam", "clamd"));
+  }
+}
@@ -0,0 +1,646 @@
+//! Admin-declared HIP host state, loaded from `--hip-profile <file.toml>`.
+//!
+//! Each category present in the file replaces the category the generator would otherwise
+//! report, an absent category is left untouched. For example:
+//!
+//! ```toml
+//! [[anti-malware.products]]
+//! vendor = "Cisco Systems, Inc."
+//! name = "ClamAV"
+//! version = "1.0.7"
+//! definitions-version = "27412"
+//! definitions-date = "2024-09-30"
+//! real-time-protection = true
+//!
+//! [[disk-encryption.products]]
+//! vendor = "GitLab Inc."
+//! name = "cryptsetup"
+//! version = "2.7.0"
+//! drives = [{ name = "/", state = "encrypted" }]
+//!
+//! [[firewall.products]]
+//! vendor = "The Netfilter Project"
+//! name = "nftables"
+//! version = "1.0.9"
+//! enabled = true
+//! ```
+
+use std::{fmt::Write, fs, path::Path};
+
+use anyhow::{Context, bail};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use xmltree::{Element, XMLNode};
+
+/// `prodType`/`osType` values the official Linux agent reports for anti-malware products
+const ANTI_MALWARE_PROD_TYPE: &str = "3";
+const ANTI_MALWARE_OS_TYPE: &str = "1";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct HipProfile {
+  pub(super) anti_malware: Option<AntiMalwareCategory>,
+  pub(super) disk_encryption: Option<DiskEncryptionCategory>,
+  pub(super) firewall: Option<ToggleCategory>,
+  pub(super) patch_management: Option<PatchManagementCategory>,
+  pub(super) data_loss_prevention: Option<ToggleCategory>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct AntiMalwareCategory {
+  #[serde(default)]
+  pub(super) products: Vec<AntiMalwareProduct>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct AntiMalwareProduct {
+  pub(super) vendor: String,
+  pub(super) name: String,
+  #[serde(default)]
+  pub(super) version: String,
+  #[serde(default)]
+  pub(super) engine_version: String,
+  #[serde(default)]
+  pub(super) definitions_version: String,
+  /// `YYYY-MM-DD`, the report date is used when absent
+  pub(super) definitions_date: Option<String>,
+  #[serde(default)]
+  pub(super) real_time_protection: bool,
+  pub(super) last_full_scan_time: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct DiskEncryptionCategory {
+  #[serde(default)]
+  pub(super) products: Vec<DiskEncryptionProduct>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct DiskEncryptionProduct {
+  pub(super) vendor: String,
+  pub(super) name: String,
+  #[serde(default)]
+  pub(super) version: String,
+  #[serde(default)]
+  pub(super) drives: Vec<EncryptedDrive>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct EncryptedDrive {
+  pub(super) name: String,
+  pub(super) state: EncryptionState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(super) enum EncryptionState {
+  Encrypted,
+  Unencrypted,
+  Partial,
+  Unknown,
+}
+
+impl EncryptionState {
+  fn as_str(self) -> &'static str {
+    match self {
+      EncryptionState::Encrypted => "encrypted",
+      EncryptionState::Unencrypted => "unencrypted",
+      EncryptionState::Partial => "partial",
+      EncryptionState::Unknown => "unknown",
+    }
+  }
+}
+
+/// Categories whose products only report whether they are enabled
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct ToggleCategory {
+  #[serde(default)]
+  pub(super) products: Vec<ToggleProduct>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct ToggleProduct {
+  pub(super) vendor: String,
+  pub(super) name: String,
+  #[serde(default)]
+  pub(super) version: String,
+  pub(super) enabled: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct PatchManagementCategory {
+  #[serde(default)]
+  pub(super) products: Vec<ToggleProduct>,
+  #[serde(default)]
+  pub(super) missing_patches: Vec<MissingPatch>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct MissingPatch {
+  pub(super) title: String,
+  #[serde(default)]
+  pub(super) product: String,
+  #[serde(default)]
+  pub(super) vendor: String,
+  #[serde(default)]
+  pub(super) category: String,
+  /// 0 (unknown) to 4 (critical), as in the official agent reports
+  #[serde(default)]
+  pub(super) severity: u8,
+}
+
+/// Report date used for anti-malware products without a `definitions-date`
+pub(crate) struct ReportDate<'a> {
+  pub(crate) day: &'a str,
+  pub(crate) month: &'a str,
+  pub(crate) year: &'a str,
+}
+
+impl HipProfile {
+  pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read HIP profile {}", path.display()))?;
+
+    Self::parse(&content).with_context(|| format!("Invalid HIP profile {}", path.display()))
+  }
+
+  fn parse(content: &str) -> anyhow::Result<Self> {
+    let profile: HipProfile = toml::from_str(content).map_err(|err| anyhow::anyhow!("{}", err.to_string().trim()))?;
+    profile.validate()?;
+
+    Ok(profile)
+  }
+
+  /// Checks the rules the TOML schema alone cannot express, reporting every problem at once
+  fn validate(&self) -> anyhow::Result<()> {
+    let mut errors = Vec::new();
+
+    if let Some(category) = &self.anti_malware {
+      for (i, product) in category.products.iter().enumerate() {
+        let at = format!("anti-malware.products[{i}]");
+        check_product(&mut errors, &at, &product.vendor, &product.name);
+        if let Some(date) = &product.definitions_date
+          && parse_date(date).is_none()
+        {
+          errors.push(format!("{at}.definitions-date: expected YYYY-MM-DD, got `{date}`"));
+        }
+      }
+    }
+
+    if let Some(category) = &self.disk_encryption {
+      for (i, product) in category.products.iter().enumerate() {
+        let at = format!("disk-encryption.products[{i}]");
+        check_product(&mut errors, &at, &product.vendor, &product.name);
+        for (j, drive) in product.drives.iter().enumerate() {
+          if drive.name.trim().is_empty() {
+            errors.push(format!("{at}.drives[{j}].name: must not be empty"));
+          }
+        }
+      }
+    }
+
+    for (key, category) in [
+      ("firewall", &self.firewall),
+      ("data-loss-prevention", &self.data_loss_prevention),
+    ] {
+      if let Some(category) = category {
+        for (i, product) in category.products.iter().enumerate() {
+          check_product(
+            &mut errors,
+            &format!("{key}.products[{i}]"),
+            &product.vendor,
+            &product.name,
+          );
+        }
+      }
+    }
+
+    if let Some(category) = &self.patch_management {
+      for (i, product) in category.products.iter().enumerate() {
+        check_product(
+          &mut errors,
+          &format!("patch-management.products[{i}]"),
+          &product.vendor,
+          &product.name,
+        );
+      }
+      for (i, patch) in category.missing_patches.iter().enumerate() {
+        let at = format!("patch-management.missing-patches[{i}]");
+        if patch.title.trim().is_empty() {
+          errors.push(format!("{at}.title: must not be empty"));
+        }
+        if patch.severity > 4 {
+          errors.push(format!("{at}.severity: expected 0-4, got {}", patch.severity));
+        }
+      }
+    }
+
+    if errors.is_empty() {
+      return Ok(());
+    }
+
+    let mut message = String::from("schema validation failed:");
+    for error in errors {
+      write!(&mut message, "\n  - {error}").expect("writing to String should not fail");
+    }
+    bail!(message)
+  }
+
+  /// Replace the declared categories in a rendered `<hip-report>`
+  pub(crate) fn apply(&self, report: &mut Element, date: &ReportDate) -> anyhow::Result<()> {
+    let Some(categories) = report.get_mut_child("categories") else {
+      bail!("HIP report has no <categories> element");
+    };
+
+    if let Some(category) = &self.anti_malware {
+      let entries = category.products.iter().map(|p| anti_malware_entry(p, date)).collect();
+      replace_category(categories, category_entry("anti-malware", entries, None));
+    }
+
+    if let Some(category) = &self.disk_encryption {
+      let entries = category.products.iter().map(disk_encryption_entry).collect();
+      replace_category(categories, category_entry("disk-encryption", entries, None));
+    }
+
+    if let Some(category) = &self.firewall {
+      let entries = category.products.iter().map(toggle_entry).collect();
+      replace_category(categories, category_entry("firewall", entries, None));
+    }
+
+    if let Some(category) = &self.patch_management {
+      let entries = category.products.iter().map(toggle_entry).collect();
+      let missing_patches = missing_patches_element(&category.missing_patches);
+      replace_category(
+        categories,
+        category_entry("patch-management", entries, Some(missing_patches)),
+      );
+    }
+
+    if let Some(category) = &self.data_loss_prevention {
+      let entries = category.products.iter().map(toggle_entry).collect();
+      replace_category(categories, category_entry("data-loss-prevention", entries, None));
+    }
+
+    Ok(())
+  }
+}
+
+fn check_product(errors: &mut Vec<String>, at: &str, vendor: &str, name: &str) {
+  if vendor.trim().is_empty() {
+    errors.push(format!("{at}.vendor: must not be empty"));
+  }
+  if name.trim().is_empty() {
+    errors.push(format!("{at}.name: must not be empty"));
+  }
+}
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+  NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+fn replace_category(categories: &mut Element, entry: Element) {
+  let name = entry.attributes.get("name").cloned();
+  let existing = categories.children.iter().position(
+    |node| matches!(node, XMLNode::Element(e) if e.name == "entry" && e.attributes.get("name") == name.as_ref()),
+  );
+
+  match existing {
+    Some(index) => categories.children[index] = XMLNode::Element(entry),
+    None => categories.children.push(XMLNode::Element(entry)),
+  }
+}
+
+fn category_entry(name: &str, products: Vec<Element>, trailer: Option<Element>) -> Element {
+  let mut list = Element::new("list");
+  for product in products {
+    let mut entry = Element::new("entry");
+    entry.children.push(XMLNode::Element(product));
+    list.children.push(XMLNode::Element(entry));
+  }
+
+  let mut entry = Element::new("entry");
+  entry.attributes.insert("name".to_string(), name.to_string());
+  entry.children.push(XMLNode::Element(list));
+  if let Some(trailer) = trailer {
+    entry.children.push(XMLNode::Element(trailer));
+  }
+  entry
+}
+
+fn product_info(prod: Element, fields: &[(&str, &str)]) -> Element {
+  let mut info = Element::new("ProductInfo");
+  info.children.push(XMLNode::Element(prod));
+  for (name, value) in fields {
+    info.children.push(XMLNode::Element(text_element(name, value)));
+  }
+  info
+}
+
+fn prod(vendor: &str, name: &str, version: &str) -> Element {
+  let mut prod = Element::new("Prod");
+  for (key, value) in [("vendor", vendor), ("name", name), ("version", version)] {
+    prod.attributes.insert(key.to_string(), value.to_string());
+  }
+  prod
+}
+
+fn text_element(name: &str, text: &str) -> Element {
+  let mut element = Element::new(name);
+  element.children.push(XMLNode::Text(text.to_string()));
+  element
+}
+
+fn yes_no(value: bool) -> &'static str {
+  if value { "yes" } else { "no" }
+}
+
+fn anti_malware_entry(product: &AntiMalwareProduct, date: &ReportDate) -> Element {
+  let definitions_date = product.definitions_date.as_deref().and_then(parse_date);
+  let (day, month, year) = match definitions_date {
+    Some(d) => (
+      d.format("%d").to_string(),
+      d.format("%m").to_string(),
+      d.format("%Y").to_string(),
+    ),
+    None => (date.day.to_string(), date.month.to_string(), date.year.to_string()),
+  };
+
+  let mut prod = prod(&product.vendor, &product.name, &product.version);
+  for (key, value) in [
+    ("defver", product.definitions_version.as_str()),
+    ("engver", product.engine_version.as_str()),
+    ("prodType", ANTI_MALWARE_PROD_TYPE),
+    ("osType", ANTI_MALWARE_OS_TYPE),
+    ("dateday", &day),
+    ("datemon", &month),
+    ("dateyear", &year),
+  ] {
+    prod.attributes.insert(key.to_string(), value.to_string());
+  }
+
+  product_info(
+    prod,
+    &[
+      ("real-time-protection", yes_no(product.real_time_protection)),
+      (
+        "last-full-scan-time",
+        product.last_full_scan_time.as_deref().unwrap_or("n/a"),
+      ),
+    ],
+  )
+}
+
+fn disk_encryption_entry(product: &DiskEncryptionProduct) -> Element {
+  let mut info = product_info(prod(&product.vendor, &product.name, &product.version), &[]);
+
+  let mut drives = Element::new("drives");
+  for drive in &product.drives {
+    let mut entry = Element::new("entry");
+    entry
+      .children
+      .push(XMLNode::Element(text_element("drive-name", &drive.name)));
+    entry
+      .children
+      .push(XMLNode::Element(text_element("enc-state", drive.state.as_str())));
+    drives.children.push(XMLNode::Element(entry));
+  }
+  info.children.push(XMLNode::Element(drives));
+
+  info
+}
+
+fn toggle_entry(product: &ToggleProduct) -> Element {
+  product_info(
+    prod(&product.vendor, &product.name, &product.version),
+    &[("is-enabled", yes_no(product.enabled))],
+  )
+}
+
+fn missing_patches_element(patches: &[MissingPatch]) -> Element {
+  let mut missing = Element::new("missing-patches");
+  for patch in patches {
+    let mut entry = Element::new("entry");
+    let severity = patch.severity.to_string();
+    for (name, value) in [
+      ("title", patch.title.as_str()),
+      ("product", &patch.product),
+      ("vendor", &patch.vendor),
+      ("category", &patch.category),
+      ("severity", &severity),
+      ("is-installed", "no"),
+    ] {
+      entry.children.push(XMLNode::Element(text_element(name, value)));
+    }
+    missing.children.push(XMLNode::Element(entry));
+  }
+  missing
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DATE: ReportDate = ReportDate {
+    day: "01",
+    month: "02",
+    year: "2025",
+  };
+
+  fn report_with_categories(categories: &str) -> Element {
+    Element::parse(format!("<hip-report><categories>{categories}</categories></hip-report>").as_bytes()).unwrap()
+  }
+
+  fn category<'a>(report: &'a Element, name: &str) -> Option<&'a Element> {
+    report
+      .get_child("categories")?
+      .children
+      .iter()
+      .find_map(|node| match node {
+        XMLNode::Element(e) if e.attributes.get("name").map(String::as_str) == Some(name) => Some(e),
+        _ => None,
+      })
+  }
+
+  #[test]
+  fn parses_every_category() {
+    let profile = HipProfile::parse(
+      r#"
+        [[anti-malware.products]]
+        vendor = "Cisco Systems, Inc."
+        name = "ClamAV"
+        definitions-date = "2024-09-30"
+        real-time-protection = true
+
+        [[disk-encryption.products]]
+        vendor = "GitLab Inc."
+        name = "cryptsetup"
+        drives = [{ name = "/", state = "encrypted" }]
+
+        [[firewall.products]]
+        vendor = "The Netfilter Project"
+        name = "nftables"
+        enabled = true
+
+        [patch-management]
+        products = [{ vendor = "Debian", name = "APT", enabled = true }]
+        missing-patches = [{ title = "openssl", severity = 3 }]
+
+        [data-loss-prevention]
+        products = []
+      "#,
+    )
+    .expect("profile should parse");
+
+    assert_eq!(profile.anti_malware.unwrap().products[0].name, "ClamAV");
+    assert_eq!(
+      profile.disk_encryption.unwrap().products[0].drives[0].state,
+      EncryptionState::Encrypted
+    );
+    assert!(profile.firewall.unwrap().products[0].enabled);
+    assert_eq!(profile.patch_management.unwrap().missing_patches[0].severity, 3);
+    assert!(profile.data_loss_prevention.unwrap().products.is_empty());
+  }
+
+  #[test]
+  fn rejects_unknown_categories_and_fields() {
+    let err = HipProfile::parse("[antivirus]\nproducts = []").unwrap_err().to_string();
+    assert!(err.contains("unknown field `antivirus`"), "{err}");
+
+    let err = HipProfile::parse("[[firewall.products]]\nvendor = \"a\"\nname = \"b\"\nenabled = true\nstate = 1")
+      .unwrap_err()
+      .to_string();
+    assert!(err.contains("unknown field `state`"), "{err}");
+  }
+
+  #[test]
+  fn rejects_invalid_encryption_state() {
+    let err = HipProfile::parse(
+      r#"
+        [[disk-encryption.products]]
+        vendor = "GitLab Inc."
+        name = "cryptsetup"
+        drives = [{ name = "/", state = "yes" }]
+      "#,
+    )
+    .unwrap_err()
+    .to_string();
+
+    assert!(err.contains("unknown variant `yes`"), "{err}");
+  }
+
+  #[test]
+  fn reports_all_semantic_errors_together() {
+    let err = HipProfile::parse(
+      r#"
+        [[anti-malware.products]]
+        vendor = ""
+        name = "ClamAV"
+        definitions-date = "30/09/2024"
+
+        [patch-management]
+        missing-patches = [{ title = "openssl", severity = 9 }]
+      "#,
+    )
+    .unwrap_err()
+    .to_string();
+
+    assert!(
+      err.contains("anti-malware.products[0].vendor: must not be empty"),
+      "{err}"
+    );
+    assert!(
+      err.contains("anti-malware.products[0].definitions-date: expected YYYY-MM-DD, got `30/09/2024`"),
+      "{err}"
+    );
+    assert!(
+      err.contains("patch-management.missing-patches[0].severity: expected 0-4, got 9"),
+      "{err}"
+    );
+  }
+
+  #[test]
+  fn replaces_declared_categories_and_keeps_the_rest() {
+    let profile = HipProfile::parse(
+      r#"
+        [[firewall.products]]
+        vendor = "The Netfilter Project"
+        name = "nftables"
+        version = "1.0.9"
+        enabled = true
+      "#,
+    )
+    .unwrap();
+    let mut report = report_with_categories(
+      r#"<entry name="host-info"/><entry name="firewall"><list><entry><ProductInfo><Prod name="IPTables"/></ProductInfo></entry></list></entry>"#,
+    );
+
+    profile.apply(&mut report, &DATE).unwrap();
+
+    let firewall = category(&report, "firewall").unwrap();
+    let prod = firewall
+      .get_child("list")
+      .and_then(|list| list.get_child("entry"))
+      .and_then(|entry| entry.get_child("ProductInfo"))
+      .and_then(|info| info.get_child("Prod"))
+      .unwrap();
+    assert_eq!(prod.attributes.get("name").map(String::as_str), Some("nftables"));
+    assert!(category(&report, "host-info").is_some());
+  }
+
+  #[test]
+  fn uses_definitions_date_for_anti_malware() {
+    let profile = HipProfile::parse(
+      r#"
+        [[anti-malware.products]]
+        vendor = "Cisco Systems, Inc."
+        name = "ClamAV"
+        definitions-date = "2024-09-30"
+      "#,
+    )
+    .unwrap();
+    let mut report = report_with_categories("");
+
+    profile.apply(&mut report, &DATE).unwrap();
+
+    let prod = category(&report, "anti-malware")
+      .and_then(|c| c.get_child("list"))
+      .and_then(|list| list.get_child("entry"))
+      .and_then(|entry| entry.get_child("ProductInfo"))
+      .and_then(|info| info.get_child("Prod"))
+      .unwrap();
+    assert_eq!(prod.attributes.get("dateday").map(String::as_str), Some("30"));
+    assert_eq!(prod.attributes.get("datemon").map(String::as_str), Some("09"));
+    assert_eq!(prod.attributes.get("dateyear").map(String::as_str), Some("2024"));
+  }
+
+  #[test]
+  fn adds_missing_patches_to_patch_management() {
+    let profile = HipProfile::parse(
+      r#"
+        [patch-management]
+        missing-patches = [{ title = "openssl security update", severity = 4 }]
+      "#,
+    )
+    .unwrap();
+    let mut report = report_with_categories("");
+
+    profile.apply(&mut report, &DATE).unwrap();
+
+    let patch = category(&report, "patch-management")
+      .and_then(|c| c.get_child("missing-patches"))
+      .and_then(|m| m.get_child("entry"))
+      .unwrap();
+    assert_eq!(
+      patch.get_child("title").and_then(|e| e.get_text()).as_deref(),
+      Some("openssl security update")
+    );
+  }
+}
@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use gpapi::{
+  clap::args::Os,
+  log_format::LogFormat,
+  os_profile::{ClientOs, identity_store},
+};
+use serde::Serialize;
+
+use crate::{cli::SharedArgs, cookie::format_time};
+
+#[derive(Args)]
+pub(crate) struct IdentityArgs {
+  #[command(subcommand)]
+  command: IdentityCommand,
+
+  #[arg(
+    long,
+    global = true,
+    help = "The identity file, defaults to /var/lib/gpclient/identity.toml for root and ~/.config/gpclient/identity.toml otherwise"
+  )]
+  path: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum IdentityCommand {
+  #[command(about = "Show the persisted host identities, one per client OS")]
+  Show {
+    #[arg(long, help = "Only show the identity of this client OS")]
+    os: Option<Os>,
+  },
+  #[command(about = "Remove the persisted host identities, a new one is generated on the next connection")]
+  Reset {
+    #[arg(long, help = "Only remove the identity of this client OS")]
+    os: Option<Os>,
+  },
+  #[command(about = "Import the host identities from another identity file, e.g., copied from another machine")]
+  Import {
+    #[arg(help = "The identity file, or a file with a single identity when --os is specified")]
+    file: PathBuf,
+
+    #[arg(long, help = "Only import the identity of this client OS")]
+    os: Option<Os>,
+  },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IdentitySummary<'a> {
+  os: &'static str,
+  computer: &'a str,
+  host_id: &'a str,
+  serialno: &'a str,
+  mac_addr: &'a str,
+  runtime_host_id: Option<&'a str>,
+  created_at: u64,
+}
+
+pub(crate) struct IdentityHandler<'a> {
+  args: &'a IdentityArgs,
+  shared_args: &'a SharedArgs<'a>,
+}
+
+impl<'a> IdentityHandler<'a> {
+  pub(crate) fn new(args: &'a IdentityArgs, shared_args: &'a SharedArgs) -> Self {
+    Self { args, shared_args }
+  }
+
+  pub(crate) async fn handle(&self) -> anyhow::Result<()> {
+    let path = identity_store::identity_path(self.args.path.as_deref());
+
+    match &self.args.command {
+      IdentityCommand::Show { os } => {
+        let client_os = os.map(ClientOs::from);
+        let entries = identity_store::list(&path);
+        let summaries = entries
+          .iter()
+          .filter(|(os, _)| client_os.is_none_or(|client_os| *os == client_os))
+          .map(|(os, stored)| IdentitySummary {
+            os: os.as_str(),
+            computer: &stored.computer,
+            host_id: &stored.host_id,
+            serialno: &stored.serialno,
+            mac_addr: &stored.mac_addr,
+            runtime_host_id: stored.source.as_ref().map(|source| source.host_id.as_str()),
+            created_at: stored.created_at,
+          })
+          .collect::<Vec<_>>();
+
+        if self.shared_args.log_format == LogFormat::Json {
+          println!("{}", serde_json::to_string_pretty(&summaries)?);
+        } else if summaries.is_empty() {
+          println!("No persisted host identities in {}", path.display());
+        } else {
+          summaries
+            .iter()
+            .for_each(|summary| println!("{}", format_summary(summary)));
+        }
+      }
+      IdentityCommand::Reset { os } => {
+        let removed = identity_store::reset(&path, os.map(ClientOs::from))?;
+        println!("Removed {} host identity(ies) from {}", removed, path.display());
+      }
+      IdentityCommand::Import { file, os } => {
+        let imported = identity_store::import(&path, file, os.map(ClientOs::from))?;
+        println!("Imported {} host identity(ies) into {}", imported, path.display());
+      }
+    }
+
+    Ok(())
+  }
+}
+
+fn format_summary(summary: &IdentitySummary) -> String {
+  let runtime_host_id = summary.runtime_host_id.unwrap_or("none, imported");
+
+  format!(
+    "{}\n  computer: {}\n  host ID: {}\n  serial number: {}\n  MAC address: {}\n  runtime host ID: {}\n  created: {}",
+    summary.os,
+    summary.computer,
+    summary.host_id,
+    summary.serialno,
+    summary.mac_addr,
+    runtime_host_id,
+    format_time(summary.created_at)
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn summary_shows_imported_identity() {
+    let summary = IdentitySummary {
+      os: "Windows",
+      computer: "DESKTOP-1",
+      host_id: "host-1",
+      serialno: "SERIAL-1",
+      mac_addr: "aa-bb-cc-dd-ee-ff",
+      runtime_host_id: None,
+      created_at: 0,
+    };
+
+    let text = format_summary(&summary);
+    assert!(text.starts_with("Windows\n"));
+    assert!(text.contains("host ID: host-1"));
+    assert!(text.contains("MAC address: aa-bb-cc-dd-ee-ff"));
+    assert!(text.contains("runtime host ID: none, imported"));
+  }
+}
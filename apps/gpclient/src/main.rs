@@ -1,8 +1,12 @@
 mod cli;
 mod connect;
+mod cookie;
 mod disconnect;
+mod exec;
 mod hip;
+mod identity;
 mod launch_gui;
+mod netns;
 mod session;
 
 pub(crate) const GP_CLIENT_LOCK_FILE: &str = "/var/run/gpclient.lock";
@@ -0,0 +1,153 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  process::Command,
+};
+
+use anyhow::{Context, bail};
+use log::{info, warn};
+use openconnect::{is_valid_netns_name, netns_resolv_conf};
+
+const NETNS_RUN_DIR: &str = "/run/netns";
+
+pub(crate) fn netns_exists(name: &str) -> bool {
+  Path::new(NETNS_RUN_DIR).join(name).exists()
+}
+
+pub(crate) fn check_netns_name(name: &str) -> anyhow::Result<()> {
+  if !is_valid_netns_name(name) {
+    bail!(
+      "Invalid network namespace name `{}`, only letters, digits, '-', '_' and '.' are allowed",
+      name
+    );
+  }
+
+  Ok(())
+}
+
+/// A network namespace prepared for the tunnel, restored to its previous state when dropped.
+///
+/// The namespace is created if missing, and `/etc/netns/<name>/resolv.conf` is created so that
+/// `ip netns exec` shows the VPN DNS servers instead of the host ones.
+pub(crate) struct NetnsGuard {
+  name: String,
+  created: bool,
+  resolv_conf: PathBuf,
+  original_resolv_conf: Option<Vec<u8>>,
+  created_etc_dir: bool,
+}
+
+impl NetnsGuard {
+  pub(crate) fn prepare(name: &str) -> anyhow::Result<Self> {
+    check_netns_name(name)?;
+
+    let created = if netns_exists(name) {
+      info!("Using existing network namespace: {}", name);
+      false
+    } else {
+      info!("Creating network namespace: {}", name);
+      run_ip(&["netns", "add", name])?;
+      true
+    };
+
+    let mut guard = Self {
+      name: name.to_string(),
+      created,
+      resolv_conf: PathBuf::from(netns_resolv_conf(name)),
+      original_resolv_conf: None,
+      created_etc_dir: false,
+    };
+
+    run_ip(&["-n", name, "link", "set", "lo", "up"])?;
+    guard.prepare_resolv_conf()?;
+
+    Ok(guard)
+  }
+
+  fn prepare_resolv_conf(&mut self) -> anyhow::Result<()> {
+    let Some(etc_dir) = self.resolv_conf.parent() else {
+      bail!("Invalid resolv.conf path: {}", self.resolv_conf.display());
+    };
+
+    if !etc_dir.exists() {
+      fs::create_dir_all(etc_dir).with_context(|| format!("Failed to create {}", etc_dir.display()))?;
+      self.created_etc_dir = true;
+    }
+
+    match fs::read(&self.resolv_conf) {
+      Ok(content) => self.original_resolv_conf = Some(content),
+      // An empty file is enough for `ip netns exec` to bind-mount it, the vpnc-script wrapper
+      // fills it once the gateway pushed the DNS settings
+      Err(_) => {
+        fs::write(&self.resolv_conf, "").with_context(|| format!("Failed to create {}", self.resolv_conf.display()))?
+      }
+    }
+
+    Ok(())
+  }
+
+  fn cleanup(&mut self) {
+    let result = match self.original_resolv_conf.take() {
+      Some(content) => fs::write(&self.resolv_conf, content),
+      None => fs::remove_file(&self.resolv_conf),
+    };
+
+    if let Err(err) = result {
+      warn!("Failed to restore {}: {}", self.resolv_conf.display(), err);
+    }
+
+    if self.created_etc_dir
+      && let Some(etc_dir) = self.resolv_conf.parent()
+      && let Err(err) = fs::remove_dir(etc_dir)
+    {
+      warn!("Failed to remove {}: {}", etc_dir.display(), err);
+    }
+
+    if self.created {
+      info!("Deleting network namespace: {}", self.name);
+      if let Err(err) = run_ip(&["netns", "delete", &self.name]) {
+        warn!("Failed to delete network namespace {}: {}", self.name, err);
+      }
+    }
+  }
+}
+
+impl Drop for NetnsGuard {
+  fn drop(&mut self) {
+    self.cleanup();
+  }
+}
+
+fn run_ip(args: &[&str]) -> anyhow::Result<()> {
+  let output = Command::new("ip")
+    .args(args)
+    .output()
+    .context("Failed to run the `ip` command, is iproute2 installed?")?;
+
+  if !output.status.success() {
+    bail!(
+      "`ip {}` failed: {}",
+      args.join(" "),
+      String::from_utf8_lossy(&output.stderr).trim()
+    );
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rejects_names_unsafe_for_the_script_wrapper() {
+    assert!(check_netns_name("corp").is_ok());
+    assert!(check_netns_name("../corp").is_err());
+    assert!(check_netns_name("corp vpn").is_err());
+  }
+
+  #[test]
+  fn resolv_conf_lives_under_etc_netns() {
+    assert_eq!(netns_resolv_conf("corp"), "/etc/netns/corp/resolv.conf");
+  }
+}
@@ -1,22 +1,14 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-
 use gpapi::{
-  clap::report,
-  gateway::{SessionContext, SessionExtensionAuth, extend_session},
+  clap::{report, report_data},
+  gateway::{SessionContext, SessionEvent, SessionExtensionAuth, spawn_session_scheduler},
   log_format::LogFormat,
   os_profile::OsProfile,
-  session::{SessionInfo, SessionRequestArgs, SessionWarning},
+  service::vpn_state::{DisconnectReason, TunnelInfo},
+  session::{SessionInfo, SessionRequestArgs},
 };
-use log::{Level, info, warn};
-use openconnect::VpnSessionInfo;
-use tokio::{runtime::Handle, task::JoinHandle};
-
-#[derive(Debug, PartialEq, Eq)]
-struct SessionWarningSchedule {
-  delay: Duration,
-  message: String,
-  should_auto_extend: bool,
-}
+use log::Level;
+use openconnect::VpnExitReason;
+use tokio::task::JoinHandle;
 
 pub(crate) struct SessionContextInput {
   pub(crate) portal: String,
@@ -27,6 +19,7 @@ pub(crate) struct SessionContextInput {
   pub(crate) sslkey: Option<String>,
   pub(crate) key_password: Option<String>,
   pub(crate) disable_ipv6: bool,
+  pub(crate) ignore_tls_errors: bool,
   pub(crate) extension_auth: Option<SessionExtensionAuth>,
 }
 
@@ -36,7 +29,8 @@ pub(crate) fn build_session_context(input: SessionContextInput) -> SessionContex
     .with_certificate(input.certificate)
     .with_sslkey(input.sslkey)
     .with_key_password(input.key_password)
-    .with_disable_ipv6(input.disable_ipv6);
+    .with_disable_ipv6(input.disable_ipv6)
+    .with_ignore_tls_errors(input.ignore_tls_errors);
 
   let ctx = SessionContext::new(input.gateway, input.portal, session_args);
   match input.extension_auth {
@@ -45,139 +39,67 @@ pub(crate) fn build_session_context(input: SessionContextInput) -> SessionContex
   }
 }
 
-pub(crate) fn session_info_from_vpn(vpn_session_info: VpnSessionInfo, allow_extend_session: bool) -> SessionInfo {
-  SessionInfo::from_vpn_session_fields(
-    vpn_session_info.lifetime_secs,
-    vpn_session_info.user_expires,
-    vpn_session_info.lifetime_warning.map(|warning| SessionWarning {
-      prior_secs: warning.prior_secs,
-      message: warning.message,
+/// Show the tunnel configuration, with the fields as data in the JSON format
+pub(crate) fn report_tunnel_info(log_format: LogFormat, tunnel_info: &TunnelInfo) {
+  let data = serde_json::to_value(tunnel_info).unwrap_or_default();
+  report_data(
+    log_format,
+    Level::Info,
+    &format!("Tunnel info: {}", tunnel_info.log_summary()),
+    "tunnel",
+    data,
+  );
+}
+
+/// Show why the tunnel ended, with the reason and the openconnect exit code as data in the JSON format
+pub(crate) fn report_disconnect(log_format: LogFormat, reason: VpnExitReason, exit_code: i32) {
+  let level = match reason {
+    VpnExitReason::Requested => Level::Info,
+    _ => Level::Warn,
+  };
+
+  report_data(
+    log_format,
+    level,
+    &format!("VPN disconnected: {}", reason),
+    "disconnect",
+    serde_json::json!({
+      "reason": DisconnectReason::from(reason),
+      "exitCode": exit_code,
     }),
-    allow_extend_session,
-  )
+  );
 }
 
+/// Warn before the session ends and extend it when allowed, reporting each step to the user
 pub(crate) fn spawn_session_runtime_with_info(
-  handle: &Handle,
   session_ctx: SessionContext,
   session_info: SessionInfo,
   log_format: LogFormat,
 ) -> JoinHandle<()> {
-  handle.spawn(run_session_runtime(session_ctx, session_info, log_format))
-}
-
-async fn run_session_runtime(session_ctx: SessionContext, mut session_info: SessionInfo, log_format: LogFormat) {
-  loop {
-    let Some(schedule) = build_session_warning_schedule(&session_info) else {
-      info!("No session warning schedule provided by the gateway");
-      return;
-    };
-
-    tokio::time::sleep(schedule.delay).await;
-
-    report(log_format, Level::Warn, &format!("\nWARNING: {}", schedule.message));
-
-    if !schedule.should_auto_extend {
-      info!("Session extension is not allowed by the gateway");
-      return;
-    }
-
-    info!("Attempting to extend the session");
-    match extend_session(&session_ctx).await {
-      Ok(()) => {
-        let Some(next_session_info) = session_info.rescheduled_after_extension() else {
-          info!("Session extended, but no lifetime is available to schedule another warning");
-          return;
-        };
-
-        report(log_format, Level::Info, "Session extended.");
-        session_info = next_session_info;
-      }
-      Err(err) => {
-        warn!("Failed to extend session: {}", err);
-        report(
-          log_format,
-          Level::Warn,
-          &format!("WARNING: Failed to extend session: {}", err),
-        );
-        return;
-      }
-    }
-  }
-}
-
-fn build_session_warning_schedule(session_info: &SessionInfo) -> Option<SessionWarningSchedule> {
-  let warning = session_info.lifetime_warning.as_ref()?;
-  let warning_secs = if let Some(user_expires) = session_info.user_expires {
-    let now = unix_timestamp();
-    user_expires.saturating_sub(warning.prior_secs).saturating_sub(now)
-  } else if let Some(lifetime_secs) = session_info.lifetime_secs {
-    lifetime_secs.saturating_sub(warning.prior_secs)
-  } else {
-    return None;
-  };
-
-  Some(SessionWarningSchedule {
-    delay: Duration::from_secs(warning_secs as u64),
-    message: warning.message.clone(),
-    should_auto_extend: session_info.allow_extend_session,
+  spawn_session_scheduler(session_ctx, session_info, move |event| match event {
+    SessionEvent::Expiring { message, .. } => report(log_format, Level::Warn, &format!("\nWARNING: {}", message)),
+    SessionEvent::Extended(_) => report(log_format, Level::Info, "Session extended."),
+    SessionEvent::ExtensionFailed(err) => report(
+      log_format,
+      Level::Warn,
+      &format!("WARNING: Failed to extend session: {}", err),
+    ),
   })
 }
 
-fn unix_timestamp() -> u32 {
-  SystemTime::now()
-    .duration_since(UNIX_EPOCH)
-    .unwrap_or_default()
-    .as_secs() as u32
-}
-
 #[cfg(test)]
 mod tests {
+  use openconnect::VpnSessionInfo;
+
   use super::*;
 
-  fn sample_session_info(user_expires: Option<u32>, allow_extend_session: bool) -> SessionInfo {
+  fn session_info_with_extension(vpn_session_info: VpnSessionInfo, allow_extend_session: bool) -> SessionInfo {
     SessionInfo {
-      user_expires,
-      lifetime_warning: Some(SessionWarning {
-        prior_secs: 1800,
-        message: "Session expires soon".to_string(),
-      }),
       allow_extend_session,
-      ..Default::default()
+      ..vpn_session_info.into()
     }
   }
 
-  #[test]
-  fn builds_warning_schedule_for_auto_extend() {
-    let session_info = sample_session_info(Some(unix_timestamp() + 1830), true);
-
-    let schedule = build_session_warning_schedule(&session_info).unwrap();
-
-    assert_eq!(schedule.message, "Session expires soon");
-    assert!(schedule.delay <= Duration::from_secs(30));
-    assert!(schedule.should_auto_extend);
-  }
-
-  #[test]
-  fn builds_warning_schedule_without_auto_extend() {
-    let session_info = sample_session_info(Some(unix_timestamp() + 1830), false);
-
-    let schedule = build_session_warning_schedule(&session_info).unwrap();
-
-    assert!(!schedule.should_auto_extend);
-  }
-
-  #[test]
-  fn schedules_next_warning_after_extension_from_lifetime() {
-    let mut session_info = sample_session_info(Some(unix_timestamp() + 1830), true);
-    session_info.lifetime_secs = Some(7200);
-
-    let next_session_info = session_info.rescheduled_after_extension().unwrap();
-    let schedule = build_session_warning_schedule(&next_session_info).unwrap();
-
-    assert!(schedule.delay <= Duration::from_secs(5400));
-  }
-
   #[test]
   fn builds_session_context_from_os_profile() {
     let profile = OsProfile::builder(gpapi::os_profile::ClientOs::Mac)
@@ -194,6 +116,7 @@ mod tests {
       sslkey: Some("/tmp/client.key".to_string()),
       key_password: Some("secret".to_string()),
       disable_ipv6: true,
+      ignore_tls_errors: true,
       extension_auth: None,
     });
 
@@ -208,11 +131,12 @@ mod tests {
     assert_eq!(ctx.session_args().sslkey().as_deref(), Some("/tmp/client.key"));
     assert_eq!(ctx.session_args().key_password().as_deref(), Some("secret"));
     assert!(ctx.session_args().disable_ipv6());
+    assert!(ctx.session_args().ignore_tls_errors());
   }
 
   #[test]
   fn maps_openconnect_session_metadata_to_runtime_session_info() {
-    let info = session_info_from_vpn(
+    let info = session_info_with_extension(
       VpnSessionInfo {
         lifetime_secs: Some(43_200),
         user_expires: Some(1_776_828_409),
@@ -220,6 +144,7 @@ mod tests {
           prior_secs: 1_800,
           message: "Session expires soon".to_string(),
         }),
+        ..Default::default()
       },
       true,
     );
@@ -232,7 +157,7 @@ mod tests {
 
   #[test]
   fn direct_gateway_session_info_does_not_allow_extension_without_portal_policy() {
-    let info = session_info_from_vpn(
+    let info = session_info_with_extension(
       VpnSessionInfo {
         lifetime_secs: Some(43_200),
         user_expires: Some(1_776_828_409),
@@ -240,6 +165,7 @@ mod tests {
           prior_secs: 1_800,
           message: "Session expires soon".to_string(),
         }),
+        ..Default::default()
       },
       false,
     );
@@ -0,0 +1,499 @@
+use std::{sync::Arc, time::Duration};
+
+use gpapi::{
+  cookie_store::{self, StoredCookie},
+  credential::Credential,
+  error::PortalError,
+  gateway::{Gateway, GatewayLogin, gateway_login},
+  gp_params::GpParams,
+  os_profile::{OsProfile, identity_store, runtime_client_os},
+  portal::{AgentSettings, gateway_cache},
+  service::{
+    always_on::{AlwaysOnConfig, AlwaysOnMode, Backoff, same_server},
+    request::ConnectRequest,
+    vpn_state::{ConnectInfo, DisconnectReason, VpnConnections, VpnState},
+  },
+};
+use log::{info, warn};
+use openconnect::find_vpnc_script;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::{vpn_task::VpnTaskContext, ws_server::WsServerContext};
+
+/// The portal to keep connected and the gateways to use, in priority order
+struct Target {
+  portal: String,
+  gateways: Vec<Gateway>,
+  agent_settings: AgentSettings,
+}
+
+enum Attempt {
+  /// The connection has been started, its state tells whether the tunnel comes up
+  Started,
+  /// The cached credentials are missing or rejected, the user has to authenticate
+  AuthRequired,
+  Failed(anyhow::Error),
+}
+
+/// How the default connection went down
+#[derive(Debug, PartialEq, Eq)]
+struct Down {
+  was_up: bool,
+  /// `None` when the connection was already down
+  reason: Option<DisconnectReason>,
+}
+
+/// What to do once the default connection is down
+#[derive(Debug, PartialEq, Eq)]
+enum NextStep {
+  /// The user disconnected, wait for the next connection
+  Pause,
+  /// The tunnel did not come up, try again after the delay
+  Retry(Duration),
+  Connect,
+}
+
+/// Keeps the default connection up for an always-on portal, with the credentials gpclient cached
+pub(crate) struct AlwaysOn {
+  config: AlwaysOnConfig,
+  vpn_ctx: Arc<VpnTaskContext>,
+  ws_ctx: Arc<WsServerContext>,
+  vpn_state_rx: watch::Receiver<VpnConnections>,
+}
+
+impl AlwaysOn {
+  pub fn new(
+    config: AlwaysOnConfig,
+    vpn_ctx: Arc<VpnTaskContext>,
+    ws_ctx: Arc<WsServerContext>,
+    vpn_state_rx: watch::Receiver<VpnConnections>,
+  ) -> Self {
+    Self {
+      config,
+      vpn_ctx,
+      ws_ctx,
+      vpn_state_rx,
+    }
+  }
+
+  pub async fn run(mut self, cancel_token: CancellationToken) {
+    if self.config.mode == AlwaysOnMode::Off {
+      info!("Always-on is disabled by the local override");
+      return;
+    }
+
+    tokio::select! {
+      _ = self.keep_connected() => {}
+      _ = cancel_token.cancelled() => {
+        info!("Always-on task cancelled");
+      }
+    }
+  }
+
+  async fn keep_connected(&mut self) {
+    let mut backoff = self.config.backoff();
+    let mut started = false;
+
+    loop {
+      // A connection started by the GUI, or by the last attempt, is left alone until it ends
+      let down = wait_until_down(&mut self.vpn_state_rx).await;
+      match next_step(&down, std::mem::take(&mut started), &mut backoff) {
+        NextStep::Pause => {
+          info!("Always-on paused after the user disconnected, until the next connection");
+          wait_until_up(&mut self.vpn_state_rx).await;
+          continue;
+        }
+        NextStep::Retry(delay) => {
+          let reason = down
+            .reason
+            .map_or_else(|| "unknown reason".to_string(), |reason| reason.to_string());
+          info!(
+            "Always-on: the tunnel did not come up ({}), retrying in {:?}",
+            reason, delay
+          );
+          tokio::time::sleep(delay).await;
+        }
+        NextStep::Connect => {
+          if let Some(reason) = down.reason {
+            info!("Always-on: the connection ended ({}), reconnecting", reason);
+          }
+        }
+      }
+
+      let Some(target) = self.target() else {
+        info!("Always-on is not enabled for any known portal");
+        tokio::time::sleep(backoff.max()).await;
+        continue;
+      };
+
+      match self.attempt(&target, down.reason).await {
+        Attempt::Started => started = true,
+        Attempt::AuthRequired => {
+          info!(
+            "Always-on: authentication to {} required, asking the GUI",
+            target.portal
+          );
+          self.ws_ctx.request_auth(&target.portal).await;
+          // Check the cached credentials again later, e.g., after `gpclient connect` refreshed them
+          let _ = tokio::time::timeout(backoff.max(), wait_until_up(&mut self.vpn_state_rx)).await;
+          self.ws_ctx.clear_auth_request().await;
+        }
+        Attempt::Failed(err) => {
+          let delay = backoff.next_delay();
+          warn!(
+            "Always-on: failed to connect {}: {}, retrying in {:?}",
+            target.portal, err, delay
+          );
+          tokio::time::sleep(delay).await;
+        }
+      }
+    }
+  }
+
+  fn target(&self) -> Option<Target> {
+    let cached = gateway_cache::list(&gateway_cache::cache_path(None));
+    if let Some(entry) = self.config.select_portal(&cached) {
+      return Some(Target {
+        portal: entry.portal().to_string(),
+        gateways: entry.gateways_by_priority().into_iter().cloned().collect(),
+        agent_settings: entry.agent_settings().clone(),
+      });
+    }
+
+    // Without a cached portal config, the override alone keeps the portal connected
+    match (self.config.mode, &self.config.portal) {
+      (AlwaysOnMode::On, Some(portal)) => Some(Target {
+        portal: portal.clone(),
+        gateways: vec![],
+        agent_settings: AgentSettings::default(),
+      }),
+      _ => None,
+    }
+  }
+
+  async fn attempt(&self, target: &Target, last_reason: Option<DisconnectReason>) -> Attempt {
+    let client_os = self.config.os.unwrap_or_else(runtime_client_os);
+    let os_profile = OsProfile::builder(client_os)
+      .identity_store(identity_store::identity_path(None))
+      .build();
+
+    let path = cookie_store::cookie_path(None);
+    let Some(stored) = select_cookie(
+      cookie_store::list(&path),
+      &target.portal,
+      os_profile.host_identity().host_id(),
+      self.config.username.as_deref(),
+    ) else {
+      return Attempt::AuthRequired;
+    };
+
+    // The gateway ended the session of the cached gateway cookie, log in again
+    let session_ended = matches!(
+      last_reason,
+      Some(DisconnectReason::AuthFailed | DisconnectReason::SessionExpired | DisconnectReason::GatewayLogout)
+    );
+    if session_ended && stored.gateway_cookie().is_some() {
+      cookie_store::remove_gateway_cookie(&path, &stored);
+    }
+
+    let cookie = match stored.gateway_cookie().filter(|_| !session_ended) {
+      Some(cookie) => {
+        info!(
+          "Always-on: reconnecting to {} with the cached gateway cookie",
+          stored.last_gateway
+        );
+        cookie.to_string()
+      }
+      None => {
+        if !stored.auth_cookie.can_authenticate_gateway() {
+          return Attempt::AuthRequired;
+        }
+
+        info!(
+          "Always-on: logging in to {} with the cached portal cookie",
+          stored.last_gateway
+        );
+        let cred: Credential = (&stored.auth_cookie).into();
+        let gp_params = GpParams::builder(os_profile.clone()).is_gateway(true).build();
+        match gateway_login(&stored.last_gateway, &cred, &gp_params).await {
+          Ok(GatewayLogin::Cookie(cookie)) => cookie,
+          Ok(GatewayLogin::Mfa(..)) => return Attempt::AuthRequired,
+          Err(err) if is_network_error(&err) => return Attempt::Failed(err),
+          Err(err) => {
+            warn!("Always-on: the cached portal cookie was rejected: {}", err);
+            cookie_store::remove(&path, &stored);
+            return Attempt::AuthRequired;
+          }
+        }
+      }
+    };
+
+    let req = connect_request(target, &stored, &os_profile, cookie);
+    self.vpn_ctx.connect(req).await;
+
+    Attempt::Started
+  }
+}
+
+/// The most recently saved cookie of the portal for this host, of the user if given
+fn select_cookie(
+  entries: Vec<StoredCookie>,
+  portal: &str,
+  host_id: &str,
+  username: Option<&str>,
+) -> Option<StoredCookie> {
+  entries
+    .into_iter()
+    .filter(|stored| same_server(&stored.server, portal) && stored.host_id == host_id)
+    .filter(|stored| username.is_none_or(|username| stored.username == username))
+    .max_by_key(|stored| stored.saved_at)
+}
+
+/// The request to connect to the gateway of the cookie. The session is extended with the cached
+/// portal cookie, so that the connection is not cut off at the end of the session lifetime.
+fn connect_request(target: &Target, stored: &StoredCookie, os_profile: &OsProfile, cookie: String) -> ConnectRequest {
+  let gateway = target
+    .gateways
+    .iter()
+    .find(|gateway| same_server(gateway.server(), &stored.last_gateway))
+    .cloned()
+    .unwrap_or_else(|| Gateway::new(stored.last_gateway.clone(), stored.last_gateway.clone()));
+  let gateways = if target.gateways.is_empty() {
+    vec![gateway.clone()]
+  } else {
+    target.gateways.clone()
+  };
+
+  let info =
+    ConnectInfo::new(target.portal.clone(), gateway, gateways).with_agent_settings(target.agent_settings.clone());
+  let can_extend = stored.auth_cookie.can_authenticate_gateway();
+  ConnectRequest::new(info, cookie)
+    .with_os_profile(os_profile)
+    .with_vpnc_script(find_vpnc_script().map(String::from))
+    .with_extension_auth_cookie(can_extend.then(|| stored.auth_cookie.clone()))
+    .with_allow_extend_session(can_extend)
+}
+
+/// `attempted` tells the connection that went down was started by the last attempt. It failed
+/// when it never came up, even without a reason, e.g., the VPN could not be created at all and
+/// was down again before the state was watched.
+fn next_step(down: &Down, attempted: bool, backoff: &mut Backoff) -> NextStep {
+  if down.was_up {
+    backoff.reset();
+  }
+
+  match down.reason {
+    Some(DisconnectReason::UserRequested) => NextStep::Pause,
+    reason if !down.was_up && (attempted || reason.is_some()) => NextStep::Retry(backoff.next_delay()),
+    _ => NextStep::Connect,
+  }
+}
+
+fn is_network_error(err: &anyhow::Error) -> bool {
+  err
+    .chain()
+    .any(|e| matches!(e.downcast_ref::<PortalError>(), Some(PortalError::NetworkError(_))))
+}
+
+/// Wait until the default connection is down
+async fn wait_until_down(vpn_state_rx: &mut watch::Receiver<VpnConnections>) -> Down {
+  let mut was_up = false;
+  let mut changed = false;
+
+  loop {
+    match vpn_state_rx.borrow_and_update().primary() {
+      VpnState::Disconnected(reason) => {
+        return Down {
+          was_up,
+          reason: reason.filter(|_| changed),
+        };
+      }
+      VpnState::Connected(_) => was_up = true,
+      VpnState::Connecting(_) | VpnState::Disconnecting => {}
+    }
+
+    if vpn_state_rx.changed().await.is_err() {
+      std::future::pending::<()>().await;
+    }
+    changed = true;
+  }
+}
+
+/// Wait until the default connection is started, e.g., by the GUI
+async fn wait_until_up(vpn_state_rx: &mut watch::Receiver<VpnConnections>) {
+  loop {
+    if matches!(
+      vpn_state_rx.borrow_and_update().primary(),
+      VpnState::Connecting(_) | VpnState::Connected(_)
+    ) {
+      return;
+    }
+
+    if vpn_state_rx.changed().await.is_err() {
+      std::future::pending::<()>().await;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use gpapi::{credential::AuthCookieCredential, os_profile::ClientOs, service::vpn_state::ConnectedInfo};
+
+  use super::*;
+  use crate::vpn_task::extends_session;
+
+  fn stored(server: &str, username: &str, host_id: &str, saved_at: u64) -> StoredCookie {
+    let auth_cookie = AuthCookieCredential::new(username, "user-cookie", "prelogon-cookie");
+    let mut stored = StoredCookie::new(
+      server.to_string(),
+      username.to_string(),
+      host_id.to_string(),
+      "gw.example.com".to_string(),
+      auth_cookie,
+    );
+    stored.saved_at = saved_at;
+    stored
+  }
+
+  fn connect_info() -> ConnectInfo {
+    let gateway = Gateway::new("vpn".to_string(), "vpn.example.com".to_string());
+    ConnectInfo::new("vpn.example.com".to_string(), gateway.clone(), vec![gateway])
+  }
+
+  fn set_primary(vpn_state_tx: &watch::Sender<VpnConnections>, state: VpnState) {
+    vpn_state_tx.send_modify(|connections| connections.set("default", state));
+  }
+
+  fn set_primary_disconnected(vpn_state_tx: &watch::Sender<VpnConnections>, reason: DisconnectReason) {
+    set_primary(vpn_state_tx, VpnState::Disconnected(Some(reason)));
+  }
+
+  #[test]
+  fn selects_the_latest_cookie_of_the_portal_and_host() {
+    let entries = vec![
+      stored("https://vpn.example.com", "alice", "host-1", 1),
+      stored("vpn.example.com", "bob", "host-1", 2),
+      stored("vpn.example.com", "carol", "host-2", 3),
+      stored("other.example.com", "dave", "host-1", 4),
+    ];
+
+    let selected = select_cookie(entries.clone(), "vpn.example.com", "host-1", None).unwrap();
+    assert_eq!(selected.username, "bob");
+
+    let selected = select_cookie(entries.clone(), "vpn.example.com", "host-1", Some("alice")).unwrap();
+    assert_eq!(selected.saved_at, 1);
+
+    assert!(select_cookie(entries, "vpn.example.com", "host-3", None).is_none());
+  }
+
+  #[tokio::test]
+  async fn an_already_down_connection_has_no_reason() {
+    let (vpn_state_tx, mut vpn_state_rx) = watch::channel(VpnConnections::default());
+    set_primary_disconnected(&vpn_state_tx, DisconnectReason::ConnectFailed);
+
+    let down = wait_until_down(&mut vpn_state_rx).await;
+    assert_eq!(
+      down,
+      Down {
+        was_up: false,
+        reason: None
+      }
+    );
+  }
+
+  fn target() -> Target {
+    Target {
+      portal: "vpn.example.com".to_string(),
+      gateways: vec![],
+      agent_settings: AgentSettings::default(),
+    }
+  }
+
+  #[test]
+  fn service_connection_extends_the_session_with_the_portal_cookie() {
+    let os_profile = OsProfile::builder(ClientOs::Linux).build();
+    let stored = stored("vpn.example.com", "alice", "host-1", 1);
+
+    let req = connect_request(&target(), &stored, &os_profile, "gateway-cookie".to_string());
+    assert!(extends_session(&req));
+    let auth_cookie = req.args().extension_auth_cookie().unwrap();
+    assert_eq!(auth_cookie.username(), "alice");
+    assert_eq!(auth_cookie.user_auth_cookie(), "user-cookie");
+    assert_eq!(req.gateway().server(), "gw.example.com");
+  }
+
+  #[test]
+  fn service_connection_is_not_extended_without_a_gateway_auth_cookie() {
+    let os_profile = OsProfile::builder(ClientOs::Linux).build();
+    let mut stored = stored("vpn.example.com", "alice", "host-1", 1);
+    stored.auth_cookie = AuthCookieCredential::new("alice", "empty", "");
+
+    let req = connect_request(&target(), &stored, &os_profile, "gateway-cookie".to_string());
+    assert!(!extends_session(&req));
+  }
+
+  #[tokio::test]
+  async fn backs_off_when_the_started_connection_fails_right_away() {
+    let (vpn_state_tx, mut vpn_state_rx) = watch::channel(VpnConnections::default());
+    let mut backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(60));
+
+    // Nothing is connected at launch, connect right away
+    let down = wait_until_down(&mut vpn_state_rx).await;
+    assert_eq!(next_step(&down, false, &mut backoff), NextStep::Connect);
+
+    // The VPN could not be created, the connection is down before the state is watched again
+    set_primary_disconnected(&vpn_state_tx, DisconnectReason::ConnectFailed);
+    let down = wait_until_down(&mut vpn_state_rx).await;
+    assert_eq!(down.reason, None);
+    assert_eq!(
+      next_step(&down, true, &mut backoff),
+      NextStep::Retry(Duration::from_secs(5))
+    );
+
+    set_primary_disconnected(&vpn_state_tx, DisconnectReason::ConnectFailed);
+    let down = wait_until_down(&mut vpn_state_rx).await;
+    assert_eq!(
+      next_step(&down, true, &mut backoff),
+      NextStep::Retry(Duration::from_secs(10))
+    );
+  }
+
+  #[test]
+  fn reconnects_at_once_after_a_connection_that_was_up() {
+    let mut backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(60));
+    backoff.next_delay();
+
+    let down = Down {
+      was_up: true,
+      reason: Some(DisconnectReason::NetworkLost),
+    };
+    assert_eq!(next_step(&down, true, &mut backoff), NextStep::Connect);
+    assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+
+    let down = Down {
+      was_up: true,
+      reason: Some(DisconnectReason::UserRequested),
+    };
+    assert_eq!(next_step(&down, false, &mut backoff), NextStep::Pause);
+  }
+
+  #[tokio::test]
+  async fn reports_how_the_connection_went_down() {
+    let (vpn_state_tx, mut vpn_state_rx) = watch::channel(VpnConnections::default());
+    set_primary(&vpn_state_tx, VpnState::Connecting(Box::new(connect_info())));
+
+    let waiter = tokio::spawn(async move { wait_until_down(&mut vpn_state_rx).await });
+    tokio::task::yield_now().await;
+    set_primary(
+      &vpn_state_tx,
+      VpnState::Connected(Box::new(ConnectedInfo::new(connect_info(), None))),
+    );
+    tokio::task::yield_now().await;
+    set_primary_disconnected(&vpn_state_tx, DisconnectReason::NetworkLost);
+
+    let down = waiter.await.unwrap();
+    assert_eq!(down.reason, Some(DisconnectReason::NetworkLost));
+    assert!(down.was_up);
+  }
+}
@@ -8,13 +8,13 @@ use gpapi::clap::InfoLevelVerbosity;
 use gpapi::logger;
 use gpapi::{
   process::gui_launcher::GuiLauncher,
-  service::{request::WsRequest, vpn_state::VpnState},
+  service::{always_on, event::WsEvent, request::WsRequest, vpn_state::VpnConnections},
   utils::{crypto::generate_key, env_utils, lock_file::LockFile, redact::Redaction, shutdown_signal},
 };
 use log::{info, warn};
-use tokio::sync::{mpsc, watch};
+use tokio::sync::{broadcast, mpsc, watch};
 
-use crate::{vpn_task::VpnTask, ws_server::WsServer};
+use crate::{always_on::AlwaysOn, vpn_task::VpnTask, ws_server::WsServer};
 
 const VERSION: &str = concat!(
   env!("CARGO_PKG_VERSION"),
@@ -32,6 +32,9 @@ struct Cli {
   minimized: bool,
   #[clap(long)]
   env_file: Option<String>,
+  /// The always-on override, defaults to /etc/gpservice/always-on.toml
+  #[clap(long)]
+  always_on_config: Option<String>,
   #[cfg(debug_assertions)]
   #[clap(long)]
   no_gui: bool,
@@ -57,10 +60,20 @@ impl Cli {
     // Channel for sending requests to the VPN task
     let (ws_req_tx, ws_req_rx) = mpsc::channel::<WsRequest>(32);
     // Channel for receiving the VPN state from the VPN task
-    let (vpn_state_tx, vpn_state_rx) = watch::channel(VpnState::Disconnected);
-
-    let mut vpn_task = VpnTask::new(ws_req_rx, vpn_state_tx);
-    let ws_server = WsServer::new(api_key.clone(), ws_req_tx, vpn_state_rx, lock_file.clone(), redaction);
+    let (vpn_state_tx, vpn_state_rx) = watch::channel(VpnConnections::default());
+    // Channel for the events of the VPN task to the WS clients, e.g., the session is about to expire
+    let (ws_event_tx, _) = broadcast::channel::<WsEvent>(16);
+
+    let mut vpn_task = VpnTask::new(ws_req_rx, vpn_state_tx, ws_event_tx.clone());
+    let always_on_rx = vpn_state_rx.clone();
+    let ws_server = WsServer::new(
+      api_key.clone(),
+      ws_req_tx,
+      vpn_state_rx,
+      ws_event_tx,
+      lock_file.clone(),
+      redaction,
+    );
 
     let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(4);
     let shutdown_tx_clone = shutdown_tx.clone();
@@ -75,6 +88,11 @@ impl Cli {
       tokio::spawn(async move { signals::handle_signals(vpn_ctx, ws_ctx).await });
     }
 
+    // Bring up the always-on connection at launch, the GUI is only asked when the user has to authenticate
+    let always_on_config = always_on::load(&always_on::config_path(self.always_on_config.as_deref()));
+    let always_on = AlwaysOn::new(always_on_config, vpn_task.context(), ws_server.context(), always_on_rx);
+    tokio::spawn(always_on.run(vpn_task_cancel_token.clone()));
+
     let vpn_task_handle = tokio::spawn(async move { vpn_task.start(server_token).await });
     let ws_server_handle = tokio::spawn(async move { ws_server.start(shutdown_tx_clone).await });
 
@@ -184,7 +202,7 @@ mod signals {
       tokio::select! {
         _ = user_sig1.recv() => {
           info!("Received SIGUSR1 signal");
-          if vpn_ctx.disconnect().await {
+          if vpn_ctx.disconnect_all().await {
             // Write the PID to a dedicated file to indicate that the VPN task is disconnected via SIGUSR1
             let pid = std::process::id();
             if let Err(err) = tokio::fs::write(DISCONNECTED_PID_FILE, pid.to_string()).await {
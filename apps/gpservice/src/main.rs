@@ -1,3 +1,4 @@
+mod always_on;
 mod cli;
 mod handlers;
 mod routes;
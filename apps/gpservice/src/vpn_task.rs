@@ -1,47 +1,99 @@
-use std::{sync::Arc, thread};
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+  sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+  },
+};
 
+use futures::future::join_all;
 use gpapi::{
+  gateway::{SessionContext, SessionEvent, SessionExtensionAuth, spawn_session_scheduler},
+  gp_params::GpParams,
   logger,
+  os_profile::{OsProfile, identity_store, runtime_client_os},
+  portal::{AgentSettings, UserOverride, gateway_cache},
   service::{
+    event::WsEvent,
     request::{ConnectRequest, UpdateLogLevelRequest, WsRequest},
-    vpn_state::{ConnectedInfo, VpnState},
+    vpn_state::{
+      ConnectedInfo, DisconnectReason, TunnelInfo, VpnConnections, VpnState, default_interface,
+      is_valid_connection_name,
+    },
   },
-  session::{SessionInfo, SessionWarning},
+  session::{SessionInfo, SessionRequestArgs},
 };
 use log::{info, warn};
-use openconnect::Vpn;
-use tokio::sync::{RwLock, mpsc, oneshot, watch};
+use openconnect::{Vpn, VpnController, VpnEvent};
+use tokio::sync::{RwLock, broadcast, mpsc, watch};
 use tokio_util::sync::CancellationToken;
 
+/// A running connection, removed from the context once it is disconnected
+struct VpnConnection {
+  /// Tells a connection apart from a later one with the same name
+  id: u64,
+  controller: VpnController,
+  interface: Option<String>,
+  /// The portal policy on whether the user may disable the connection
+  agent_settings: AgentSettings,
+}
+
 pub(crate) struct VpnTaskContext {
-  vpn_handle: Arc<RwLock<Option<Vpn>>>,
-  vpn_state_tx: Arc<watch::Sender<VpnState>>,
-  disconnect_rx: RwLock<Option<oneshot::Receiver<()>>>,
+  connections: Arc<RwLock<HashMap<String, VpnConnection>>>,
+  next_id: AtomicU64,
+  vpn_state_tx: Arc<watch::Sender<VpnConnections>>,
+  /// The events of the connections for the WS clients, e.g., the session is about to expire
+  ws_event_tx: broadcast::Sender<WsEvent>,
+  /// The gateway cache of root, where the portal policy on disconnecting comes from
+  gateway_cache_path: PathBuf,
 }
 
 impl VpnTaskContext {
-  pub fn new(vpn_state_tx: watch::Sender<VpnState>) -> Self {
+  pub fn new(vpn_state_tx: watch::Sender<VpnConnections>, ws_event_tx: broadcast::Sender<WsEvent>) -> Self {
     Self {
-      vpn_handle: Default::default(),
+      connections: Default::default(),
+      next_id: Default::default(),
       vpn_state_tx: Arc::new(vpn_state_tx),
-      disconnect_rx: Default::default(),
+      ws_event_tx,
+      gateway_cache_path: gateway_cache::cache_path(None),
     }
   }
 
   pub async fn connect(&self, req: ConnectRequest) {
-    let vpn_state = self.vpn_state_tx.borrow().clone();
-    if !matches!(vpn_state, VpnState::Disconnected) {
-      info!("VPN is not disconnected, ignore the request");
+    let name = req.name().to_string();
+    if !is_valid_connection_name(&name) {
+      warn!("Invalid connection name: {}, ignore the request", name);
+      return;
+    }
+
+    let mut connections = self.connections.write().await;
+    if connections.contains_key(&name) {
+      info!("VPN connection {} is not disconnected, ignore the request", name);
       return;
     }
 
-    let vpn_state_tx = self.vpn_state_tx.clone();
-    let info = req.info().clone();
-    let vpn_handle = Arc::clone(&self.vpn_handle);
     let args = req.args();
-    let allow_extend_session = args.allow_extend_session();
+    let interface = args.interface().or_else(|| default_interface(&name));
+    if let Some(interface) = &interface
+      && interface_in_use(connections.values().map(|conn| conn.interface.as_deref()), interface)
+    {
+      warn!(
+        "Interface {} is used by another connection, ignore the request",
+        interface
+      );
+      return;
+    }
+
+    let vpn_state_tx = self.vpn_state_tx.clone();
+    let ws_event_tx = self.ws_event_tx.clone();
+    let agent_settings = cached_agent_settings(&self.gateway_cache_path, req.info().portal());
+    let info = req.info().clone().with_agent_settings(agent_settings.clone());
+    let session_ctx = session_context(&req);
+    let allow_extend_session = extends_session(&req);
     let vpn = match Vpn::builder(req.gateway().server(), args.cookie())
       .script(args.vpnc_script())
+      .interface(interface.clone())
       .user_agent(args.user_agent())
       .os(args.openconnect_os())
       .os_version(args.os_version())
@@ -64,70 +116,227 @@ impl VpnTaskContext {
     {
       Ok(vpn) => vpn,
       Err(err) => {
-        warn!("Failed to create VPN: {}", err);
-        vpn_state_tx.send(VpnState::Disconnected).ok();
+        warn!("Failed to create VPN {}: {}", name, err);
+        send_disconnected(&vpn_state_tx, &name, Some(DisconnectReason::ConnectFailed));
         return;
       }
     };
 
-    // Save the VPN handle
-    vpn_handle.write().await.replace(vpn);
-    let connect_info = Box::new(info.clone());
-    vpn_state_tx.send(VpnState::Connecting(connect_info)).ok();
-
-    let (disconnect_tx, disconnect_rx) = oneshot::channel::<()>();
-    self.disconnect_rx.write().await.replace(disconnect_rx);
-
-    // Spawn a new thread to process the VPN connection, cannot use tokio::spawn here.
-    // Otherwise, it will block the tokio runtime and cannot send the VPN state to the channel
-    thread::spawn(move || {
-      let vpn_state_tx_clone = vpn_state_tx.clone();
-
-      vpn_handle.blocking_read().as_ref().map(|vpn| {
-        vpn.connect(move |vpn_session_info| {
-          let session_info = SessionInfo::from_vpn_session_fields(
-            vpn_session_info.lifetime_secs,
-            vpn_session_info.user_expires,
-            vpn_session_info.lifetime_warning.map(|warning| SessionWarning {
-              prior_secs: warning.prior_secs,
-              message: warning.message,
-            }),
-            allow_extend_session,
-          );
-          info!("VPN session info: {}", session_info.log_summary());
-          let connected_info = Box::new(ConnectedInfo::new(info.clone(), Some(session_info)));
-          vpn_state_tx.send(VpnState::Connected(connected_info)).ok();
-        })
-      });
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let mut handle = vpn.start();
+    connections.insert(
+      name.clone(),
+      VpnConnection {
+        id,
+        controller: handle.controller(),
+        interface,
+        agent_settings,
+      },
+    );
+    drop(connections);
 
-      // Notify the VPN is disconnected
-      vpn_state_tx_clone.send(VpnState::Disconnected).ok();
-      // Remove the VPN handle
-      vpn_handle.blocking_write().take();
+    send_state(&vpn_state_tx, &name, VpnState::Connecting(Box::new(info.clone())));
 
-      disconnect_tx.send(()).ok();
+    let connections = Arc::clone(&self.connections);
+    tokio::spawn(async move {
+      let mut disconnect_reason = None;
+      let mut session_scheduler = None;
+      while let Some(event) = handle.next_event().await {
+        match event {
+          VpnEvent::TunnelUp(vpn_session_info) | VpnEvent::SessionInfoChanged(vpn_session_info) => {
+            let tunnel_info = TunnelInfo::from(&vpn_session_info.tunnel_info);
+            info!("VPN {} tunnel info: {}", name, tunnel_info.log_summary());
+            let session_info = SessionInfo {
+              allow_extend_session,
+              ..vpn_session_info.into()
+            };
+            info!("VPN {} session info: {}", name, session_info.log_summary());
+            // Warn and extend from the session of the tunnel that came up first, like gpclient does
+            if session_scheduler.is_none() {
+              session_scheduler = Some(spawn_session_scheduler(
+                session_ctx.clone(),
+                session_info.clone(),
+                session_event_handler(&name, &vpn_state_tx, &ws_event_tx),
+              ));
+            }
+            let connected_info =
+              Box::new(ConnectedInfo::new(info.clone(), Some(session_info)).with_tunnel_info(Some(tunnel_info)));
+            send_state(&vpn_state_tx, &name, VpnState::Connected(connected_info));
+          }
+          VpnEvent::Reconnecting => info!("VPN {} is reconnecting", name),
+          VpnEvent::Paused => info!("VPN {} is paused", name),
+          VpnEvent::Disconnected { reason, exit_code } => {
+            info!("VPN {} disconnected: {}, exit code: {}", name, reason, exit_code);
+            disconnect_reason = Some(reason.into());
+          }
+          VpnEvent::Connecting => {}
+        }
+      }
+
+      if let Some(session_scheduler) = session_scheduler {
+        session_scheduler.abort();
+      }
+      // Notify the VPN is disconnected
+      send_disconnected(&vpn_state_tx, &name, disconnect_reason);
+      // Remove the connection, unless the name has been reused by a new one
+      let mut connections = connections.write().await;
+      if connections.get(&name).is_some_and(|conn| conn.id == id) {
+        connections.remove(&name);
+      }
     });
   }
 
-  pub async fn disconnect(&self) -> bool {
-    if let Some(disconnect_rx) = self.disconnect_rx.write().await.take() {
-      info!("Disconnecting VPN...");
-      if let Some(vpn) = self.vpn_handle.read().await.as_ref() {
-        info!("VPN is connected, start disconnecting...");
-        self.vpn_state_tx.send(VpnState::Disconnecting).ok();
-        vpn.disconnect()
-      }
+  /// Disconnect on behalf of the user, if the portal lets them disable the connection, returns
+  /// whether it was connected
+  pub async fn disconnect(&self, name: &str, user_override: Option<&UserOverride>) -> bool {
+    let denied = self
+      .connections
+      .read()
+      .await
+      .get(name)
+      .and_then(|conn| conn.agent_settings.check_disable(user_override).err());
+
+    if let Some(err) = denied {
+      warn!("Refusing to disconnect VPN {}: {}", name, err);
+      // No WS client is listening when the GUI is not running
+      let _ = self.ws_event_tx.send(WsEvent::DisconnectRefused {
+        name: name.to_string(),
+        reason: err.to_string(),
+      });
+      return false;
+    }
+
+    self.stop(name).await
+  }
+
+  async fn stop(&self, name: &str) -> bool {
+    let controller = self
+      .connections
+      .read()
+      .await
+      .get(name)
+      .map(|conn| conn.controller.clone());
+
+    if let Some(controller) = controller {
+      info!("Disconnecting VPN {}...", name);
+      send_state(&self.vpn_state_tx, name, VpnState::Disconnecting);
       // Wait for the VPN to be disconnected
-      disconnect_rx.await.ok();
-      info!("VPN disconnected");
+      controller.disconnect().await;
+      info!("VPN {} disconnected", name);
 
       true
     } else {
-      info!("VPN is not connected, skip disconnect");
-      self.vpn_state_tx.send(VpnState::Disconnected).ok();
+      info!("VPN {} is not connected, skip disconnect", name);
+      send_disconnected(&self.vpn_state_tx, name, None);
       false
     }
   }
+
+  /// Disconnect every connection, returns whether any of them was connected. The portal policy
+  /// does not apply, the service is stopping or root asked for it.
+  pub async fn disconnect_all(&self) -> bool {
+    let names = self.connections.read().await.keys().cloned().collect::<Vec<_>>();
+    let results = join_all(names.iter().map(|name| self.stop(name))).await;
+
+    results.into_iter().any(|disconnected| disconnected)
+  }
+}
+
+/// The portal policy on disconnecting, from the gateway cache gpclient saved as root with the
+/// portal config. The settings in the request are up to the client, they are not trusted, and the
+/// user may disable a connection to a portal that is not cached.
+fn cached_agent_settings(cache_path: &Path, portal: &str) -> AgentSettings {
+  gateway_cache::load(cache_path, portal)
+    .map(|cached| cached.agent_settings().clone())
+    .unwrap_or_default()
+}
+
+/// Whether the session of the request is extended before it expires, it can only be by logging
+/// in to the gateway again
+pub(crate) fn extends_session(req: &ConnectRequest) -> bool {
+  let args = req.args();
+  args.allow_extend_session() && args.extension_auth_cookie().is_some()
+}
+
+/// The context to extend the session of the request with, the gateway is logged in to again with
+/// the portal auth cookie the client sent along
+fn session_context(req: &ConnectRequest) -> SessionContext {
+  let args = req.args();
+  let session_args = SessionRequestArgs::new(args.cookie().to_string())
+    .with_user_agent(args.user_agent())
+    .with_certificate(args.certificate())
+    .with_sslkey(args.sslkey())
+    .with_key_password(args.key_password())
+    .with_disable_ipv6(args.disable_ipv6());
+  let ctx = SessionContext::new(
+    req.gateway().server().to_string(),
+    req.info().portal().to_string(),
+    session_args,
+  );
+
+  let Some(auth_cookie) = args.extension_auth_cookie() else {
+    return ctx;
+  };
+
+  let mut profile =
+    OsProfile::builder(args.os().unwrap_or_else(runtime_client_os)).identity_store(identity_store::identity_path(None));
+  if let Some(host_id) = args.host_id() {
+    profile = profile.host_id_override(host_id);
+  }
+  if let Some(client_version) = args.client_version() {
+    profile = profile.client_version(client_version);
+  }
+  if let Some(user_agent) = args.user_agent() {
+    profile = profile.user_agent(user_agent);
+  }
+
+  let gp_params = GpParams::builder(profile.build())
+    .is_gateway(true)
+    .certificate(args.certificate())
+    .sslkey(args.sslkey())
+    .key_password(args.key_password())
+    .build();
+
+  ctx.with_extension_auth(SessionExtensionAuth::new(auth_cookie.into(), gp_params))
+}
+
+/// Tell the WS clients about the session of the named connection, and keep the connected state
+/// in sync with the extended lifetime
+fn session_event_handler(
+  name: &str,
+  vpn_state_tx: &Arc<watch::Sender<VpnConnections>>,
+  ws_event_tx: &broadcast::Sender<WsEvent>,
+) -> impl Fn(SessionEvent) + Send + 'static {
+  let name = name.to_string();
+  let vpn_state_tx = Arc::clone(vpn_state_tx);
+  let ws_event_tx = ws_event_tx.clone();
+
+  move |event| {
+    if let SessionEvent::Extended(session_info) = &event {
+      info!("VPN {} session extended: {}", name, session_info.log_summary());
+      vpn_state_tx.send_modify(|connections| {
+        if let VpnState::Connected(connected_info) = connections.get(&name) {
+          let connected_info = connected_info.with_session_info(Some(session_info.clone()));
+          connections.set(&name, VpnState::Connected(Box::new(connected_info)));
+        }
+      });
+    }
+
+    // No WS client is listening when the GUI is not running
+    let _ = ws_event_tx.send(WsEvent::session(&name, event));
+  }
+}
+
+fn interface_in_use<'a>(mut interfaces: impl Iterator<Item = Option<&'a str>>, interface: &str) -> bool {
+  interfaces.any(|used| used == Some(interface))
+}
+
+fn send_state(vpn_state_tx: &watch::Sender<VpnConnections>, name: &str, state: VpnState) {
+  vpn_state_tx.send_modify(|connections| connections.set(name, state));
+}
+
+fn send_disconnected(vpn_state_tx: &watch::Sender<VpnConnections>, name: &str, reason: Option<DisconnectReason>) {
+  send_state(vpn_state_tx, name, VpnState::Disconnected(reason));
 }
 
 pub(crate) struct VpnTask {
@@ -137,8 +346,12 @@ pub(crate) struct VpnTask {
 }
 
 impl VpnTask {
-  pub fn new(ws_req_rx: mpsc::Receiver<WsRequest>, vpn_state_tx: watch::Sender<VpnState>) -> Self {
-    let ctx = Arc::new(VpnTaskContext::new(vpn_state_tx));
+  pub fn new(
+    ws_req_rx: mpsc::Receiver<WsRequest>,
+    vpn_state_tx: watch::Sender<VpnConnections>,
+    ws_event_tx: broadcast::Sender<WsEvent>,
+  ) -> Self {
+    let ctx = Arc::new(VpnTaskContext::new(vpn_state_tx, ws_event_tx));
     let cancel_token = CancellationToken::new();
 
     Self {
@@ -161,7 +374,7 @@ impl VpnTask {
         }
         _ = cancel_token.cancelled() => {
             info!("VPN task cancelled");
-            self.ctx.disconnect().await;
+            self.ctx.disconnect_all().await;
         }
     }
 
@@ -184,8 +397,11 @@ async fn process_ws_req(req: WsRequest, ctx: Arc<VpnTaskContext>) {
     WsRequest::Connect(req) => {
       ctx.connect(*req).await;
     }
-    WsRequest::Disconnect(_) => {
-      ctx.disconnect().await;
+    WsRequest::Disconnect(req) => {
+      ctx.disconnect(req.name(), None).await;
+    }
+    WsRequest::OverrideDisconnect(req) => {
+      ctx.disconnect(req.name(), Some(req.user_override())).await;
     }
     WsRequest::UpdateLogLevel(UpdateLogLevelRequest(level)) => {
       let level = level.parse().unwrap_or_else(|_| log::Level::Info);
@@ -199,8 +415,74 @@ async fn process_ws_req(req: WsRequest, ctx: Arc<VpnTaskContext>) {
 
 #[cfg(test)]
 mod tests {
+  use gpapi::{gateway::Gateway, portal::DisableOverride, service::vpn_state::ConnectInfo, session::SessionWarning};
+
   use super::*;
 
+  fn connect_request(name: &str) -> ConnectRequest {
+    let gateway = Gateway::new("vpn".to_string(), "vpn.example.com".to_string());
+    let info = ConnectInfo::new("portal.example.com".to_string(), gateway.clone(), vec![gateway]);
+    ConnectRequest::new(info, "authcookie=AUTH".to_string()).with_name(name.to_string())
+  }
+
+  #[test]
+  fn rejects_an_interface_used_by_another_connection() {
+    let interfaces = [None, Some("gp-shared")];
+
+    assert!(interface_in_use(interfaces.into_iter(), "gp-shared"));
+    assert!(!interface_in_use(interfaces.into_iter(), "gp-partner"));
+  }
+
+  #[tokio::test]
+  async fn ignores_requests_with_an_invalid_name() {
+    let (vpn_state_tx, vpn_state_rx) = watch::channel(VpnConnections::default());
+    let (ws_event_tx, _) = broadcast::channel(4);
+    let ctx = VpnTaskContext::new(vpn_state_tx, ws_event_tx);
+
+    ctx.connect(connect_request("prod net")).await;
+
+    assert!(ctx.connections.read().await.is_empty());
+    assert!(vpn_state_rx.borrow().is_empty());
+  }
+
+  #[tokio::test]
+  async fn disconnecting_an_unknown_connection_is_a_no_op() {
+    let (vpn_state_tx, vpn_state_rx) = watch::channel(VpnConnections::default());
+    let (ws_event_tx, _) = broadcast::channel(4);
+    let ctx = VpnTaskContext::new(vpn_state_tx, ws_event_tx);
+
+    assert!(!ctx.disconnect("partner", None).await);
+    assert!(!ctx.disconnect_all().await);
+    assert!(vpn_state_rx.borrow().is_empty());
+  }
+
+  #[test]
+  fn disconnect_policy_comes_from_the_gateway_cache() {
+    let path = std::env::temp_dir().join(format!("gpservice-gateways-{}.json", std::process::id()));
+    let gateway = Gateway::new("vpn".to_string(), "vpn.example.com".to_string());
+    let cache = serde_json::json!({
+      "version": 1,
+      "entries": [{
+        "portal": "portal.example.com",
+        "gateways": [gateway],
+        "connectMethod": null,
+        "region": null,
+        "agentSettings": { "disableOverride": "disabled" },
+        "savedAt": 1,
+      }],
+    });
+    std::fs::write(&path, cache.to_string()).unwrap();
+
+    let settings = cached_agent_settings(&path, "portal.example.com");
+    assert_eq!(settings.disable_override, Some(DisableOverride::Disabled));
+    assert!(
+      cached_agent_settings(&path, "other.example.com")
+        .check_disable(None)
+        .is_ok()
+    );
+    std::fs::remove_file(&path).unwrap();
+  }
+
   #[test]
   fn maps_openconnect_session_metadata_to_service_session_info() {
     let info = SessionInfo::from_vpn_session_fields(
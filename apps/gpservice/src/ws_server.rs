@@ -3,12 +3,12 @@ use std::sync::Arc;
 use axum::extract::ws::Message;
 use common::binary_paths;
 use gpapi::{
-  os_profile::HostIdentity,
+  os_profile::{OsProfile, identity_store, runtime_client_os},
   service::{
     event::WsEvent,
     request::WsRequest,
     vpn_env::{HostInfo, VpnEnv},
-    vpn_state::VpnState,
+    vpn_state::VpnConnections,
   },
   utils::{crypto::Crypto, lock_file::LockFile, redact::Redaction},
 };
@@ -17,7 +17,7 @@ use openconnect::{find_csd_wrapper, find_vpnc_script};
 use serde::de::DeserializeOwned;
 use tokio::{
   net::TcpListener,
-  sync::{RwLock, mpsc, watch},
+  sync::{RwLock, broadcast, mpsc, watch},
 };
 use tokio_util::sync::CancellationToken;
 
@@ -26,16 +26,18 @@ use crate::{routes, ws_connection::WsConnection};
 pub(crate) struct WsServerContext {
   crypto: Arc<Crypto>,
   ws_req_tx: mpsc::Sender<WsRequest>,
-  vpn_state_rx: watch::Receiver<VpnState>,
+  vpn_state_rx: watch::Receiver<VpnConnections>,
   redaction: Arc<Redaction>,
   connections: RwLock<Vec<Arc<WsConnection>>>,
+  /// The portal the always-on connection needs the user to authenticate to, sent to new clients too
+  auth_request: RwLock<Option<String>>,
 }
 
 impl WsServerContext {
   pub fn new(
     api_key: Vec<u8>,
     ws_req_tx: mpsc::Sender<WsRequest>,
-    vpn_state_rx: watch::Receiver<VpnState>,
+    vpn_state_rx: watch::Receiver<VpnConnections>,
     redaction: Arc<Redaction>,
   ) -> Self {
     Self {
@@ -44,6 +46,7 @@ impl WsServerContext {
       vpn_state_rx,
       redaction,
       connections: Default::default(),
+      auth_request: Default::default(),
     }
   }
 
@@ -65,12 +68,18 @@ impl WsServerContext {
 
     // Send current VPN state to new client
     info!("Sending current environment to new client");
+    // The GUI seeds its profiles with the persisted identity, the same one gpclient uses
+    let os_profile = OsProfile::builder(runtime_client_os())
+      .identity_store(identity_store::identity_path(None))
+      .build();
     let host_info = HostInfo {
-      host_identity: HostIdentity::collect(),
+      host_identity: os_profile.host_identity().clone(),
     };
 
+    let connections = self.vpn_state_rx.borrow().clone();
     let vpn_env = VpnEnv {
-      vpn_state: self.vpn_state_rx.borrow().clone(),
+      vpn_state: connections.primary(),
+      connections,
       vpnc_script: find_vpnc_script().map(|s| s.to_owned()),
       csd_wrapper: find_csd_wrapper().map(|s| s.to_owned()),
       auth_executable: binary_paths::gpauth().to_string_lossy().into_owned(),
@@ -81,17 +90,33 @@ impl WsServerContext {
       warn!("Failed to send VPN state to new client: {}", err);
     }
 
+    if let Some(portal) = self.auth_request.read().await.clone()
+      && let Err(err) = conn.send_event(&WsEvent::AuthRequired(portal)).await
+    {
+      warn!("Failed to send the auth request to new client: {}", err);
+    }
+
     self.connections.write().await.push(Arc::clone(&conn));
 
     (conn, rx)
   }
 
+  /// Ask the GUI to authenticate to the portal, until the request is cleared
+  pub async fn request_auth(&self, portal: &str) {
+    self.auth_request.write().await.replace(portal.to_string());
+    self.send_event(WsEvent::AuthRequired(portal.to_string())).await;
+  }
+
+  pub async fn clear_auth_request(&self) {
+    self.auth_request.write().await.take();
+  }
+
   pub async fn remove_connection(&self, conn: Arc<WsConnection>) {
     let mut connections = self.connections.write().await;
     connections.retain(|c| !Arc::ptr_eq(c, &conn));
   }
 
-  fn vpn_state_rx(&self) -> watch::Receiver<VpnState> {
+  fn vpn_state_rx(&self) -> watch::Receiver<VpnConnections> {
     self.vpn_state_rx.clone()
   }
 
@@ -99,7 +124,18 @@ impl WsServerContext {
     if let WsRequest::Connect(ref req) = req {
       self
         .redaction
-        .add_values(&[req.gateway().server(), req.args().cookie()])?
+        .add_values(&[req.gateway().server(), req.args().cookie()])?;
+
+      if let Some(auth_cookie) = req.args().extension_auth_cookie() {
+        self
+          .redaction
+          .add_values(&[auth_cookie.user_auth_cookie(), auth_cookie.prelogon_user_auth_cookie()])?
+      }
+    }
+    if let WsRequest::OverrideDisconnect(ref req) = req
+      && let Some(passcode) = &req.user_override().passcode
+    {
+      self.redaction.add_values(&[passcode.as_str()])?
     }
 
     self.ws_req_tx.send(req).await?;
@@ -110,6 +146,7 @@ impl WsServerContext {
 
 pub(crate) struct WsServer {
   ctx: Arc<WsServerContext>,
+  ws_event_tx: broadcast::Sender<WsEvent>,
   cancel_token: CancellationToken,
   lock_file: Arc<LockFile>,
 }
@@ -118,7 +155,8 @@ impl WsServer {
   pub fn new(
     api_key: Vec<u8>,
     ws_req_tx: mpsc::Sender<WsRequest>,
-    vpn_state_rx: watch::Receiver<VpnState>,
+    vpn_state_rx: watch::Receiver<VpnConnections>,
+    ws_event_tx: broadcast::Sender<WsEvent>,
     lock_file: Arc<LockFile>,
     redaction: Arc<Redaction>,
   ) -> Self {
@@ -127,6 +165,7 @@ impl WsServer {
 
     Self {
       ctx,
+      ws_event_tx,
       cancel_token,
       lock_file,
     }
@@ -154,6 +193,9 @@ impl WsServer {
       _ = watch_vpn_state(self.ctx.vpn_state_rx(), Arc::clone(&self.ctx)) => {
         info!("VPN state watch task completed");
       }
+      _ = forward_ws_events(self.ws_event_tx.subscribe(), Arc::clone(&self.ctx)) => {
+        info!("WS event forward task completed");
+      }
       _ = start_server(listener, self.ctx.clone()) => {
           info!("WS server stopped");
       }
@@ -178,10 +220,22 @@ impl WsServer {
   }
 }
 
-async fn watch_vpn_state(mut vpn_state_rx: watch::Receiver<VpnState>, ctx: Arc<WsServerContext>) {
+async fn watch_vpn_state(mut vpn_state_rx: watch::Receiver<VpnConnections>, ctx: Arc<WsServerContext>) {
   while vpn_state_rx.changed().await.is_ok() {
-    let vpn_state = vpn_state_rx.borrow().clone();
-    ctx.send_event(WsEvent::VpnState(vpn_state)).await;
+    let connections = vpn_state_rx.borrow().clone();
+    // The GUI follows the default connection, other clients can track all of them
+    ctx.send_event(WsEvent::VpnState(connections.primary())).await;
+    ctx.send_event(WsEvent::VpnConnections(connections)).await;
+  }
+}
+
+async fn forward_ws_events(mut ws_event_rx: broadcast::Receiver<WsEvent>, ctx: Arc<WsServerContext>) {
+  loop {
+    match ws_event_rx.recv().await {
+      Ok(event) => ctx.send_event(event).await,
+      Err(broadcast::error::RecvError::Lagged(skipped)) => warn!("Dropped {} events for the WS clients", skipped),
+      Err(broadcast::error::RecvError::Closed) => return,
+    }
   }
 }
 
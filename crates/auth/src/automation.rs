@@ -0,0 +1,7 @@
+mod form;
+mod recipe;
+mod saml_automation;
+mod totp;
+
+pub use recipe::AutomationRecipe;
+pub use saml_automation::SamlAutomation;
@@ -0,0 +1,285 @@
+use anyhow::{Context, bail};
+use scraper::{ElementRef, Html, Selector};
+use url::Url;
+
+use super::recipe::{RecipeStep, parse_selector};
+
+/// The request a browser would send when the form is submitted
+#[derive(Debug)]
+pub(super) struct FormSubmission {
+  pub(super) post: bool,
+  pub(super) url: Url,
+  pub(super) fields: Vec<(String, String)>,
+}
+
+/// What to do with an HTML page on the way to the callback
+pub(super) enum PageAction<'a> {
+  /// Fill the form of a recipe step
+  Step(&'a RecipeStep, FormSubmission),
+  /// Submit a form that only relays the SAML request or response
+  AutoSubmit(FormSubmission),
+}
+
+pub(super) fn next_action<'a>(body: &str, page_url: &Url, steps: &'a [RecipeStep]) -> anyhow::Result<PageAction<'a>> {
+  let document = Html::parse_document(body);
+
+  // Relaying the SAML message never needs input, even when a broad step selector matches it
+  if let Some(form) = find_saml_relay_form(&document) {
+    return Ok(PageAction::AutoSubmit(collect_form(form, page_url)?));
+  }
+
+  for step in steps.iter().filter(|step| step.applies_to(page_url.as_str())) {
+    let selector = step.form_selector()?;
+    if let Some(form) = document.select(&selector).next() {
+      let submission = fill_form(form, page_url, step).with_context(|| format!("Step '{}'", step.label()))?;
+      return Ok(PageAction::Step(step, submission));
+    }
+  }
+
+  bail!("No recipe step matches the page {}", page_url)
+}
+
+fn fill_form(form: ElementRef, page_url: &Url, step: &RecipeStep) -> anyhow::Result<FormSubmission> {
+  let mut submission = collect_form(form, page_url)?;
+
+  for (selector, value) in step.fields() {
+    let name = field_name(form, &parse_selector(selector)?)
+      .with_context(|| format!("No input with a name matches '{}'", selector))?;
+    let value = value
+      .resolve()
+      .with_context(|| format!("Failed to resolve the value of '{}'", selector))?;
+
+    match submission.fields.iter_mut().find(|(field, _)| *field == name) {
+      Some((_, existing)) => *existing = value,
+      None => submission.fields.push((name, value)),
+    }
+  }
+
+  if let Some(selector) = step.submit_selector()? {
+    let button = form
+      .select(&selector)
+      .next()
+      .context("No submit button matches the selector")?;
+    if let Some(name) = button.attr("name") {
+      let value = button.attr("value").unwrap_or_default();
+      submission.fields.push((name.to_string(), value.to_string()));
+    }
+    if let Some(action) = button.attr("formaction") {
+      submission.url = page_url.join(action)?;
+    }
+  }
+
+  Ok(submission)
+}
+
+fn field_name(form: ElementRef, selector: &Selector) -> Option<String> {
+  form
+    .select(selector)
+    .next()
+    .and_then(|input| input.attr("name"))
+    .map(str::to_string)
+}
+
+/// Collect the successful controls of the form, as in the HTML form submission algorithm
+fn collect_form(form: ElementRef, page_url: &Url) -> anyhow::Result<FormSubmission> {
+  let action = form.attr("action").filter(|action| !action.trim().is_empty());
+  let url = match action {
+    Some(action) => page_url.join(action.trim())?,
+    None => page_url.clone(),
+  };
+  let post = form
+    .attr("method")
+    .is_some_and(|method| method.eq_ignore_ascii_case("post"));
+
+  let controls = parse_selector("input[name], select[name], textarea[name]")?;
+  let option = parse_selector("option")?;
+  let mut fields = Vec::new();
+
+  for control in form.select(&controls) {
+    let element = control.value();
+    let Some(name) = element.attr("name") else { continue };
+    if element.attr("disabled").is_some() {
+      continue;
+    }
+
+    let value = match element.name() {
+      "select" => {
+        let options: Vec<_> = control.select(&option).collect();
+        let selected = options.iter().find(|option| option.value().attr("selected").is_some());
+        match selected.or(options.first()) {
+          Some(option) => option_value(*option),
+          None => continue,
+        }
+      }
+      "textarea" => control.text().collect(),
+      _ => {
+        let kind = element.attr("type").unwrap_or("text").to_ascii_lowercase();
+        match kind.as_str() {
+          "submit" | "button" | "image" | "reset" | "file" => continue,
+          "checkbox" | "radio" if element.attr("checked").is_none() => continue,
+          "checkbox" | "radio" => element.attr("value").unwrap_or("on").to_string(),
+          _ => element.attr("value").unwrap_or_default().to_string(),
+        }
+      }
+    };
+
+    fields.push((name.to_string(), value));
+  }
+
+  Ok(FormSubmission { post, url, fields })
+}
+
+fn option_value(option: ElementRef) -> String {
+  option
+    .value()
+    .attr("value")
+    .map(str::to_string)
+    .unwrap_or_else(|| option.text().collect::<String>().trim().to_string())
+}
+
+/// The HTTP-POST binding pages carry the SAML message in a form submitted by JavaScript
+fn find_saml_relay_form(document: &Html) -> Option<ElementRef<'_>> {
+  let selector = parse_selector("form").ok()?;
+  let saml_input = parse_selector("input[name=SAMLResponse], input[name=SAMLRequest]").ok()?;
+
+  document
+    .select(&selector)
+    .find(|form| form.select(&saml_input).next().is_some())
+}
+
+/// The official client registers the `globalprotectcallback:` scheme, the last page points the
+/// browser to it with a redirect, a meta refresh or a link
+pub(super) fn find_gpcallback(body: &str) -> Option<String> {
+  let start = body.find("globalprotectcallback:")?;
+  let end = body[start..]
+    .find(|c: char| c == '"' || c == '\'' || c == '<' || c.is_whitespace())
+    .map_or(body.len(), |end| start + end);
+
+  Some(body[start..end].replace("&amp;", "&"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::automation::AutomationRecipe;
+
+  fn recipe(content: &str) -> AutomationRecipe {
+    AutomationRecipe::parse(content).unwrap()
+  }
+
+  fn page_url() -> Url {
+    Url::parse("https://idp.example.com/login?state=abc").unwrap()
+  }
+
+  const LOGIN_PAGE: &str = r#"
+<html><body>
+  <form id="search" action="/search"><input name="q"></form>
+  <form id="login" method="POST" action="/login/submit">
+    <input type="hidden" name="csrf" value="token-1">
+    <input type="text" id="user" name="username">
+    <input type="password" name="password" value="">
+    <input type="checkbox" name="remember" value="yes">
+    <select name="domain"><option value="corp">Corp</option><option value="lab" selected>Lab</option></select>
+    <input type="submit" name="cancel" value="Cancel">
+    <button type="submit" name="action" value="login">Sign in</button>
+  </form>
+</body></html>"#;
+
+  #[test]
+  fn fills_recipe_form() {
+    let recipe = recipe(
+      r##"
+[[step]]
+url = "idp.example.com"
+form = "form#login"
+submit = "button[name=action]"
+
+[step.fields]
+"#user" = { value = "alice" }
+"input[name=password]" = { value = "s3cret" }
+"##,
+    );
+
+    let PageAction::Step(step, submission) = next_action(LOGIN_PAGE, &page_url(), recipe.steps()).unwrap() else {
+      panic!("expected the recipe step to match");
+    };
+
+    assert_eq!(step.label(), "form#login");
+    assert!(submission.post);
+    assert_eq!(submission.url.as_str(), "https://idp.example.com/login/submit");
+    assert_eq!(
+      submission.fields,
+      [
+        ("csrf", "token-1"),
+        ("username", "alice"),
+        ("password", "s3cret"),
+        ("domain", "lab"),
+        ("action", "login"),
+      ]
+      .map(|(name, value)| (name.to_string(), value.to_string()))
+    );
+  }
+
+  #[test]
+  fn skips_steps_for_other_urls() {
+    let recipe = recipe(
+      r#"
+[[step]]
+url = "other.example.com"
+form = "form#login"
+"#,
+    );
+
+    let err = next_action(LOGIN_PAGE, &page_url(), recipe.steps()).err().unwrap();
+    assert!(err.to_string().contains("No recipe step matches"), "{err}");
+  }
+
+  #[test]
+  fn reports_missing_fields() {
+    let recipe = recipe(
+      r#"
+[[step]]
+form = "form#login"
+fields = { "input[name=otp]" = { value = "123456" } }
+"#,
+    );
+
+    let err = next_action(LOGIN_PAGE, &page_url(), recipe.steps()).err().unwrap();
+    assert!(
+      format!("{err:#}").contains("No input with a name matches 'input[name=otp]'"),
+      "{err:#}"
+    );
+  }
+
+  #[test]
+  fn auto_submits_saml_relay_form() {
+    let page = r#"
+<html><body onload="document.forms[0].submit()">
+  <form method="post" action="https://portal.example.com/SAML20/SP/ACS">
+    <input type="hidden" name="SAMLResponse" value="PHNhbWxwOlJlc3BvbnNlLz4=">
+    <input type="hidden" name="RelayState" value="relay">
+    <noscript><input type="submit" value="Continue"></noscript>
+  </form>
+</body></html>"#;
+
+    let PageAction::AutoSubmit(submission) = next_action(page, &page_url(), &[]).unwrap() else {
+      panic!("expected the SAML form to be submitted");
+    };
+
+    assert_eq!(submission.url.as_str(), "https://portal.example.com/SAML20/SP/ACS");
+    assert_eq!(submission.fields[0].0, "SAMLResponse");
+    assert_eq!(submission.fields[1], ("RelayState".to_string(), "relay".to_string()));
+  }
+
+  #[test]
+  fn finds_gpcallback() {
+    let page =
+      r#"<meta http-equiv="refresh" content="0; URL=globalprotectcallback:cas-as=1&amp;un=alice&amp;token=t">"#;
+
+    assert_eq!(
+      find_gpcallback(page).as_deref(),
+      Some("globalprotectcallback:cas-as=1&un=alice&token=t")
+    );
+    assert_eq!(find_gpcallback("<html></html>"), None);
+  }
+}
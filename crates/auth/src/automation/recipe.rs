@@ -0,0 +1,275 @@
+use std::{
+  collections::BTreeMap,
+  fs,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, bail};
+use scraper::Selector;
+use serde::Deserialize;
+
+use super::totp;
+
+const DEFAULT_MAX_STEPS: usize = 20;
+
+/// Describes how to get through the IdP pages of a SAML login without a browser, e.g.:
+///
+/// ```toml
+/// [[step]]
+/// name = "credentials"
+/// url = "login.example.com"
+/// form = "form#login"
+/// submit = "button[name=action]"
+///
+/// [step.fields]
+/// "input[name=username]" = { env = "GP_SAML_USERNAME" }
+/// "input[name=password]" = { file = "/run/secrets/vpn-password" }
+/// "input[name=otp]" = { totp = { env = "GP_SAML_TOTP_SECRET" } }
+/// ```
+///
+/// Forms that only carry the `SAMLRequest`/`SAMLResponse` back and forth are submitted
+/// without a step.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AutomationRecipe {
+  user_agent: Option<String>,
+  #[serde(default = "default_max_steps")]
+  max_steps: usize,
+  #[serde(default, rename = "step")]
+  steps: Vec<RecipeStep>,
+}
+
+fn default_max_steps() -> usize {
+  DEFAULT_MAX_STEPS
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct RecipeStep {
+  name: Option<String>,
+  /// Substring of the page URL the step applies to, any page when not set
+  url: Option<String>,
+  /// CSS selector of the form to fill and submit
+  form: String,
+  /// CSS selector of the button the form is submitted with, for IdPs that read its value
+  submit: Option<String>,
+  /// Values keyed by the CSS selector of the input they go into
+  #[serde(default)]
+  fields: BTreeMap<String, FieldValue>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(super) enum FieldValue {
+  Value(String),
+  Env(String),
+  File(PathBuf),
+  /// A TOTP code from a base32 secret or an `otpauth://` URI
+  Totp(Secret),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(super) enum Secret {
+  Value(String),
+  Env(String),
+  File(PathBuf),
+}
+
+impl AutomationRecipe {
+  pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    Self::parse(&content).with_context(|| format!("Invalid automation recipe {}", path.display()))
+  }
+
+  pub fn parse(content: &str) -> anyhow::Result<Self> {
+    let recipe: Self = toml::from_str(content)?;
+    recipe.validate()?;
+
+    Ok(recipe)
+  }
+
+  fn validate(&self) -> anyhow::Result<()> {
+    if self.max_steps == 0 {
+      bail!("max-steps must be greater than 0");
+    }
+
+    for step in &self.steps {
+      parse_selector(&step.form)?;
+      if let Some(submit) = &step.submit {
+        parse_selector(submit)?;
+      }
+      for selector in step.fields.keys() {
+        parse_selector(selector)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  pub(super) fn user_agent(&self) -> Option<&str> {
+    self.user_agent.as_deref()
+  }
+
+  pub(super) fn max_steps(&self) -> usize {
+    self.max_steps
+  }
+
+  pub(super) fn steps(&self) -> &[RecipeStep] {
+    &self.steps
+  }
+}
+
+impl RecipeStep {
+  pub(super) fn label(&self) -> &str {
+    self.name.as_deref().unwrap_or(&self.form)
+  }
+
+  pub(super) fn applies_to(&self, url: &str) -> bool {
+    self.url.as_deref().is_none_or(|pattern| url.contains(pattern))
+  }
+
+  pub(super) fn form_selector(&self) -> anyhow::Result<Selector> {
+    parse_selector(&self.form)
+  }
+
+  pub(super) fn submit_selector(&self) -> anyhow::Result<Option<Selector>> {
+    self.submit.as_deref().map(parse_selector).transpose()
+  }
+
+  pub(super) fn fields(&self) -> impl Iterator<Item = (&str, &FieldValue)> {
+    self.fields.iter().map(|(selector, value)| (selector.as_str(), value))
+  }
+}
+
+impl FieldValue {
+  pub(super) fn resolve(&self) -> anyhow::Result<String> {
+    match self {
+      FieldValue::Value(value) => Ok(value.clone()),
+      FieldValue::Env(name) => read_env(name),
+      FieldValue::File(path) => read_file(path),
+      FieldValue::Totp(secret) => {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        totp::generate(&secret.resolve()?, now)
+      }
+    }
+  }
+}
+
+impl Secret {
+  fn resolve(&self) -> anyhow::Result<String> {
+    match self {
+      Secret::Value(value) => Ok(value.clone()),
+      Secret::Env(name) => read_env(name),
+      Secret::File(path) => read_file(path),
+    }
+  }
+}
+
+pub(super) fn parse_selector(selector: &str) -> anyhow::Result<Selector> {
+  Selector::parse(selector).map_err(|err| anyhow::anyhow!("Invalid selector '{}': {}", selector, err))
+}
+
+fn read_env(name: &str) -> anyhow::Result<String> {
+  std::env::var(name).with_context(|| format!("Environment variable {} is not set", name))
+}
+
+/// Read a secret file, ignoring the trailing newline most editors and `echo` add
+fn read_file(path: &Path) -> anyhow::Result<String> {
+  let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+  Ok(content.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use super::*;
+
+  #[test]
+  fn parses_recipe_steps() {
+    let recipe = AutomationRecipe::parse(
+      r#"
+user-agent = "ci-agent"
+
+[[step]]
+name = "credentials"
+url = "idp.example.com/login"
+form = "form#login"
+submit = "button[name=action]"
+
+[step.fields]
+"input[name=username]" = { value = "alice" }
+"input[name=password]" = { env = "GP_TEST_PASSWORD" }
+"input[name=otp]" = { totp = { value = "GEZDGNBVGY3TQOJQ" } }
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(recipe.user_agent(), Some("ci-agent"));
+    assert_eq!(recipe.max_steps(), DEFAULT_MAX_STEPS);
+
+    let step = &recipe.steps()[0];
+    assert_eq!(step.label(), "credentials");
+    assert!(step.applies_to("https://idp.example.com/login?state=1"));
+    assert!(!step.applies_to("https://idp.example.com/mfa"));
+    assert!(matches!(
+      step.fields["input[name=otp]"],
+      FieldValue::Totp(Secret::Value(_))
+    ));
+  }
+
+  #[test]
+  fn rejects_invalid_selectors() {
+    let err = AutomationRecipe::parse(
+      r#"
+[[step]]
+form = "form[["
+"#,
+    )
+    .unwrap_err();
+
+    assert!(err.to_string().contains("Invalid selector 'form[['"), "{err}");
+  }
+
+  #[test]
+  fn rejects_unknown_keys() {
+    assert!(AutomationRecipe::parse("timeout = 10").is_err());
+    assert!(
+      AutomationRecipe::parse(
+        r#"
+[[step]]
+form = "form"
+fields = { "input" = { prompt = "x" } }
+"#
+      )
+      .is_err()
+    );
+  }
+
+  #[test]
+  fn resolves_field_values() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(file, "s3cret").unwrap();
+
+    let value = FieldValue::File(file.path().to_path_buf());
+    assert_eq!(value.resolve().unwrap(), "s3cret");
+
+    let value = FieldValue::Env("GP_AUTOMATION_TEST_UNSET".to_string());
+    assert!(
+      value
+        .resolve()
+        .unwrap_err()
+        .to_string()
+        .contains("GP_AUTOMATION_TEST_UNSET")
+    );
+
+    let value = FieldValue::Totp(Secret::Value("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".to_string()));
+    let code = value.resolve().unwrap();
+    assert_eq!(code.len(), 6);
+    assert!(code.chars().all(|c| c.is_ascii_digit()));
+  }
+}
@@ -0,0 +1,320 @@
+use anyhow::{Context, bail};
+use gpapi::{auth::SamlAuthData, error::AuthDataParseError, gp_params::GpParams};
+use log::{debug, info};
+use reqwest::{
+  Client, Response,
+  header::{HeaderMap, LOCATION},
+  redirect::Policy,
+};
+use url::Url;
+
+use super::{
+  form::{FormSubmission, PageAction, find_gpcallback, next_action},
+  recipe::AutomationRecipe,
+};
+
+/// The IdPs only serve their login pages to browsers
+const BROWSER_USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/605.1.15 (KHTML, like Gecko)";
+
+/// Drives the SAML login over plain HTTP according to an [`AutomationRecipe`], for IdPs
+/// that do not require JavaScript
+pub struct SamlAutomation<'a> {
+  server: &'a str,
+  auth_request: &'a str,
+  recipe: &'a AutomationRecipe,
+  user_agent: String,
+  ignore_tls_errors: bool,
+}
+
+impl<'a> SamlAutomation<'a> {
+  pub fn new(server: &'a str, auth_request: &'a str, recipe: &'a AutomationRecipe, gp_params: &GpParams) -> Self {
+    let user_agent = recipe
+      .user_agent()
+      .map(str::to_string)
+      .unwrap_or_else(|| format!("{} {}", gp_params.os_profile().user_agent(), BROWSER_USER_AGENT));
+
+    Self {
+      server,
+      auth_request,
+      recipe,
+      user_agent,
+      ignore_tls_errors: gp_params.ignore_tls_errors(),
+    }
+  }
+
+  pub async fn authenticate(&self) -> anyhow::Result<SamlAuthData> {
+    // Redirects are followed by hand, the last one may point to `globalprotectcallback:`
+    let client = Client::builder()
+      .cookie_store(true)
+      .redirect(Policy::none())
+      .danger_accept_invalid_certs(self.ignore_tls_errors)
+      .user_agent(&self.user_agent)
+      .build()?;
+
+    let mut submission = self.initial_request()?;
+    let mut last_step: Option<(&str, Url)> = None;
+
+    for _ in 0..self.recipe.max_steps() {
+      let res = send(&client, &submission).await?;
+      let page_url = res.url().clone();
+      debug!("SAML automation: {} {}", res.status(), page_url);
+
+      if let Some(auth_data) = auth_data_from_headers(res.headers())? {
+        return Ok(auth_data);
+      }
+
+      if res.status().is_redirection() {
+        let location = res
+          .headers()
+          .get(LOCATION)
+          .and_then(|location| location.to_str().ok())
+          .with_context(|| format!("Redirect without a location from {}", page_url))?;
+
+        if location.starts_with("globalprotectcallback:") {
+          info!("SAML automation: received the callback in a redirect");
+          return Ok(SamlAuthData::from_gpcallback(location)?);
+        }
+
+        submission = FormSubmission {
+          post: false,
+          url: page_url.join(location)?,
+          fields: Vec::new(),
+        };
+        continue;
+      }
+
+      let status = res.status();
+      let body = res.text().await?;
+      if !status.is_success() {
+        bail!("{} responded with {}", page_url, status);
+      }
+
+      if let Some(auth_data) = auth_data_from_body(&body)? {
+        return Ok(auth_data);
+      }
+
+      submission = match next_action(&body, &page_url, self.recipe.steps())? {
+        PageAction::Step(step, submission) => {
+          // The IdP showing the same form again means the submitted values were rejected
+          if last_step
+            .as_ref()
+            .is_some_and(|(label, url)| *label == step.label() && *url == page_url)
+          {
+            bail!(
+              "The page {} was shown again after step '{}', check the recipe values",
+              page_url,
+              step.label()
+            );
+          }
+
+          info!("SAML automation: step '{}'", step.label());
+          last_step = Some((step.label(), page_url));
+          submission
+        }
+        PageAction::AutoSubmit(submission) => {
+          info!("SAML automation: relaying the SAML message to {}", submission.url);
+          submission
+        }
+      };
+    }
+
+    bail!(
+      "SAML authentication did not finish within {} requests",
+      self.recipe.max_steps()
+    )
+  }
+
+  /// The SAML request is either the IdP URL (REDIRECT binding) or an HTML form (POST binding)
+  fn initial_request(&self) -> anyhow::Result<FormSubmission> {
+    if self.auth_request.starts_with("http") {
+      return Ok(FormSubmission {
+        post: false,
+        url: Url::parse(self.auth_request)?,
+        fields: Vec::new(),
+      });
+    }
+
+    let server_url = Url::parse(self.server)?;
+    match next_action(self.auth_request, &server_url, &[])? {
+      PageAction::AutoSubmit(submission) | PageAction::Step(_, submission) => Ok(submission),
+    }
+  }
+}
+
+async fn send(client: &Client, submission: &FormSubmission) -> anyhow::Result<Response> {
+  let request = if submission.post {
+    client.post(submission.url.clone()).form(&submission.fields)
+  } else if submission.fields.is_empty() {
+    client.get(submission.url.clone())
+  } else {
+    client.get(submission.url.clone()).query(&submission.fields)
+  };
+
+  request
+    .send()
+    .await
+    .with_context(|| format!("Failed to request {}", submission.url))
+}
+
+/// The portal returns the result in the `saml-*` headers of the assertion consumer response
+fn auth_data_from_headers(headers: &HeaderMap) -> anyhow::Result<Option<SamlAuthData>> {
+  let header = |name: &str| {
+    headers
+      .get(name)
+      .and_then(|value| value.to_str().ok())
+      .map(str::to_string)
+  };
+
+  match header("saml-auth-status").as_deref() {
+    Some("1") => {
+      info!("SAML automation: received the auth data in the headers");
+      let auth_data = SamlAuthData::new(
+        header("saml-username"),
+        header("prelogin-cookie"),
+        header("portal-userauthcookie"),
+      )?;
+      Ok(Some(auth_data))
+    }
+    Some(status) => bail!("SAML auth status: {}", status),
+    None => Ok(None),
+  }
+}
+
+/// Or in the body, as XML in an HTML comment, or as the callback URL
+fn auth_data_from_body(body: &str) -> anyhow::Result<Option<SamlAuthData>> {
+  match SamlAuthData::from_html(body) {
+    Ok(auth_data) => return Ok(Some(auth_data)),
+    Err(AuthDataParseError::Invalid(err)) => return Err(err),
+    Err(AuthDataParseError::NotFound) => {}
+  }
+
+  match find_gpcallback(body) {
+    Some(callback) => {
+      info!("SAML automation: received the callback in the page");
+      Ok(Some(SamlAuthData::from_gpcallback(&callback)?))
+    }
+    None => Ok(None),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::thread;
+
+  use gpapi::os_profile::{ClientOs, OsProfile};
+  use tiny_http::{Header, Response, Server};
+
+  use super::*;
+
+  const LOGIN_FORM: &str = r#"<form id="login" method="post" action="/login">
+  <input type="hidden" name="csrf" value="c1"><input name="username"><input type="password" name="password">
+</form>"#;
+
+  const RECIPE: &str = r#"
+[[step]]
+name = "credentials"
+form = "form#login"
+fields = { "input[name=username]" = { value = "alice" }, "input[name=password]" = { value = "s3cret" } }
+
+[[step]]
+name = "mfa"
+url = "/mfa"
+form = "form"
+fields = { "input[name=otp]" = { totp = { value = "GEZDGNBVGY3TQOJQ" } } }
+"#;
+
+  /// A fake IdP and portal: login form, redirect to an MFA form behind a session cookie,
+  /// then the SAMLResponse relay and the portal ACS
+  fn start_idp(password: &'static str) -> String {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", server.server_addr());
+
+    thread::spawn(move || {
+      for mut req in server.incoming_requests() {
+        let mut body = String::new();
+        req.as_reader().read_to_string(&mut body).unwrap();
+        let has_session = req
+          .headers()
+          .iter()
+          .any(|header| header.field.equiv("Cookie") && header.value.as_str().contains("session=1"));
+        let html = |content: &str| {
+          Response::from_string(content).with_header("content-type: text/html".parse::<Header>().unwrap())
+        };
+
+        let res = match req.url().split('?').next().unwrap() {
+          "/sso" => html(LOGIN_FORM),
+          "/login" if body == format!("csrf=c1&username=alice&password={password}") => Response::from_string("")
+            .with_status_code(302)
+            .with_header("location: /mfa".parse::<Header>().unwrap())
+            .with_header("set-cookie: session=1; Path=/".parse::<Header>().unwrap()),
+          "/login" => html(LOGIN_FORM),
+          "/mfa" if has_session && body.starts_with("otp=") && body.len() == 10 => html(
+            r#"<form method="post" action="/acs"><input type="hidden" name="SAMLResponse" value="PHNhbWw+"></form>"#,
+          ),
+          "/mfa" if has_session => html(r#"<form method="post"><input name="otp"></form>"#),
+          "/acs" if body == "SAMLResponse=PHNhbWw%2B" => html(
+            "<html><!-- <saml-auth-status>1</saml-auth-status><saml-username>alice</saml-username>\
+             <prelogin-cookie>prelogin-123</prelogin-cookie> --></html>",
+          ),
+          _ => Response::from_string("forbidden").with_status_code(403),
+        };
+        let _ = req.respond(res);
+      }
+    });
+
+    base
+  }
+
+  fn gp_params() -> GpParams {
+    GpParams::builder(OsProfile::builder(ClientOs::Linux).build()).build()
+  }
+
+  #[tokio::test]
+  async fn completes_login_with_recipe() {
+    let base = start_idp("s3cret");
+    let auth_request = format!("{base}/sso?SAMLRequest=abc");
+    let recipe = AutomationRecipe::parse(RECIPE).unwrap();
+    let gp_params = gp_params();
+
+    let auth_data = SamlAutomation::new(&base, &auth_request, &recipe, &gp_params)
+      .authenticate()
+      .await
+      .unwrap();
+
+    assert_eq!(auth_data.username(), "alice");
+    assert_eq!(auth_data.prelogin_cookie(), Some("prelogin-123"));
+  }
+
+  #[tokio::test]
+  async fn stops_when_credentials_are_rejected() {
+    let base = start_idp("another-password");
+    let auth_request = format!("{base}/sso?SAMLRequest=abc");
+    let recipe = AutomationRecipe::parse(RECIPE).unwrap();
+    let gp_params = gp_params();
+
+    let err = SamlAutomation::new(&base, &auth_request, &recipe, &gp_params)
+      .authenticate()
+      .await
+      .unwrap_err();
+
+    assert!(
+      err.to_string().contains("shown again after step 'credentials'"),
+      "{err}"
+    );
+  }
+
+  #[test]
+  fn reads_callback_from_headers() {
+    let mut headers = HeaderMap::new();
+    assert!(auth_data_from_headers(&headers).unwrap().is_none());
+
+    headers.insert("saml-auth-status", "1".parse().unwrap());
+    headers.insert("saml-username", "alice".parse().unwrap());
+    headers.insert("prelogin-cookie", "prelogin-123".parse().unwrap());
+    let auth_data = auth_data_from_headers(&headers).unwrap().unwrap();
+    assert_eq!(auth_data.username(), "alice");
+
+    headers.insert("saml-auth-status", "-1".parse().unwrap());
+    assert!(auth_data_from_headers(&headers).is_err());
+  }
+}
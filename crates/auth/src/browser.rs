@@ -1,4 +1,5 @@
 mod auth_server;
 mod browser_auth;
+mod self_signed;
 
-pub use browser_auth::BrowserAuthenticator;
+pub use browser_auth::{BrowserAuthenticator, DEFAULT_REMOTE_TIMEOUT};
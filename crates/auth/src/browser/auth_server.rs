@@ -1,24 +1,78 @@
-use std::io::Cursor;
+use std::{
+  io::{Cursor, Read},
+  net::SocketAddr,
+  sync::Arc,
+};
 
-use log::info;
-use tiny_http::{Header, Method, Response, Server};
+use gpapi::auth::SamlAuthData;
+use log::{info, warn};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tokio::sync::mpsc::UnboundedSender;
 use uuid::Uuid;
 
+use crate::browser::self_signed::SelfSignedCert;
+
+/// The largest callback body accepted, the SAML auth data is a few KB
+const MAX_CALLBACK_SIZE: u64 = 64 * 1024;
+
 pub(super) struct AuthServer {
-  server: Server,
+  server: Arc<Server>,
   auth_id: String,
+  base_url: String,
+}
+
+/// Stops the server from another thread, the serving loop returns right away
+pub(super) struct AuthServerStopper(Arc<Server>);
+
+impl AuthServerStopper {
+  pub fn stop(&self) {
+    self.0.unblock();
+  }
 }
 
 impl AuthServer {
   pub fn new(addr: &str) -> anyhow::Result<Self> {
     let server = Server::http(addr).map_err(|err| anyhow::anyhow!(err))?;
-    let auth_id = Uuid::new_v4().to_string();
+    let base_url = format!("http://{}", server.server_addr());
+
+    Ok(Self {
+      server: Arc::new(server),
+      auth_id: Uuid::new_v4().to_string(),
+      base_url,
+    })
+  }
 
-    Ok(Self { server, auth_id })
+  /// Serve over HTTPS for a browser on another machine, `public_addr` is how that machine
+  /// reaches the server when bound to the unspecified address
+  pub fn new_remote(bind_addr: SocketAddr, public_addr: SocketAddr, cert: &SelfSignedCert) -> anyhow::Result<Self> {
+    let server = Server::https(bind_addr, cert.ssl_config()).map_err(|err| anyhow::anyhow!(err))?;
+    let port = server
+      .server_addr()
+      .to_ip()
+      .map_or(public_addr.port(), |addr| addr.port());
+    let base_url = format!("https://{}", SocketAddr::new(public_addr.ip(), port));
+
+    Ok(Self {
+      server: Arc::new(server),
+      auth_id: Uuid::new_v4().to_string(),
+      base_url,
+    })
   }
 
   pub fn auth_url(&self) -> String {
-    format!("http://{}/{}", self.server.server_addr(), self.auth_id)
+    format!("{}/{}", self.base_url, self.auth_id)
+  }
+
+  pub fn stopper(&self) -> AuthServerStopper {
+    AuthServerStopper(Arc::clone(&self.server))
+  }
+
+  fn login_path(&self) -> String {
+    format!("/{}/login", self.auth_id)
+  }
+
+  fn callback_path(&self) -> String {
+    format!("/{}/callback", self.auth_id)
   }
 
   pub fn serve_request(&self, auth_request: &str) {
@@ -45,6 +99,83 @@ impl AuthServer {
       }
     }
   }
+
+  /// Serve the landing page, the SAML request, and the callback the landing page relays the
+  /// `globalprotectcallback:` data to, until valid auth data is received or the server is stopped
+  pub fn serve_remote(&self, auth_request: &str, auth_data_tx: UnboundedSender<SamlAuthData>) {
+    info!("remote auth server started at: {}", self.auth_url());
+
+    let landing_path = format!("/{}", self.auth_id);
+    for mut req in self.server.incoming_requests() {
+      info!("received request, method: {}, url: {}", req.method(), req.url());
+
+      let url = req.url().to_string();
+      let res = match (req.method(), url.as_str()) {
+        (Method::Head, path) if path == landing_path => Response::empty(200).boxed(),
+        (Method::Get, path) if path == landing_path => html_response(&self.landing_page(None)).boxed(),
+        (Method::Get, path) if path == self.login_path() => build_auth_response(auth_request).boxed(),
+        (Method::Post, path) if path == self.callback_path() => match read_callback(&mut req) {
+          Ok(auth_data) => {
+            let _ = req.respond(html_response(DONE_PAGE));
+            let _ = auth_data_tx.send(auth_data);
+            info!("received the authentication data, stop the auth server");
+            break;
+          }
+          Err(err) => {
+            warn!("invalid authentication data: {}", err);
+            html_response(&self.landing_page(Some(&err.to_string())))
+              .with_status_code(400)
+              .boxed()
+          }
+        },
+        _ => Response::from_string("forbidden").with_status_code(403).boxed(),
+      };
+
+      if let Err(err) = req.respond(res) {
+        info!("failed to respond to request: {}", err);
+      }
+    }
+  }
+
+  fn landing_page(&self, error: Option<&str>) -> String {
+    let error = error
+      .map(|err| format!(r#"<p class="error">{}</p>"#, escape_html(err)))
+      .unwrap_or_default();
+
+    LANDING_PAGE
+      .replace("{login_path}", &self.login_path())
+      .replace("{callback_path}", &self.callback_path())
+      .replace("{error}", &error)
+  }
+}
+
+fn read_callback(req: &mut Request) -> anyhow::Result<SamlAuthData> {
+  let mut body = String::new();
+  Read::take(req.as_reader(), MAX_CALLBACK_SIZE).read_to_string(&mut body)?;
+
+  let data = url::form_urlencoded::parse(body.as_bytes())
+    .find(|(key, _)| key == "data")
+    .map(|(_, value)| value.trim().to_string())
+    .unwrap_or_default();
+
+  if !data.starts_with("globalprotectcallback:") {
+    anyhow::bail!("The data should start with \"globalprotectcallback:\"");
+  }
+
+  Ok(SamlAuthData::from_gpcallback(&data)?)
+}
+
+fn html_response(html: &str) -> Response<Cursor<Vec<u8>>> {
+  let content_type: Header = "content-type: text/html; charset=utf-8".parse().unwrap();
+  Response::from_string(html).with_header(content_type)
+}
+
+fn escape_html(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
 }
 
 fn build_auth_response(auth_request: &str) -> Response<Cursor<Vec<u8>>> {
@@ -59,3 +190,151 @@ fn build_auth_response(auth_request: &str) -> Response<Cursor<Vec<u8>>> {
     Response::from_string(auth_request).with_header(content_type)
   }
 }
+
+const LANDING_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>GlobalProtect Authentication</title>
+<style>
+  body { font-family: sans-serif; max-width: 40em; margin: 2em auto; padding: 0 1em; line-height: 1.5; }
+  textarea { width: 100%; height: 8em; font-family: monospace; }
+  .error { color: #b00020; }
+</style>
+</head>
+<body>
+<h2>GlobalProtect Authentication</h2>
+<ol>
+  <li><a href="{login_path}" target="_blank" rel="noopener">Sign in</a> in a new tab.</li>
+  <li>When the sign-in finishes, the browser tries to open a <code>globalprotectcallback:</code> link.
+    Browsers do not hand that link to a web page, so copy it, e.g., from the prompt, the address
+    bar, or the page source.</li>
+  <li>Come back to this tab, the link is picked up from the clipboard and sent to the terminal.</li>
+</ol>
+{error}
+<form id="callback" method="post" action="{callback_path}">
+  <p>If the browser does not allow reading the clipboard, drop the link here:</p>
+  <textarea name="data" placeholder="globalprotectcallback:..." required></textarea>
+  <p><button type="submit">Send</button></p>
+</form>
+<script>
+  const form = document.getElementById("callback");
+  let sent = false;
+  function relay(text) {
+    text = (text || "").trim();
+    if (!sent && text.startsWith("globalprotectcallback:")) {
+      sent = true;
+      form.elements.data.value = text;
+      form.submit();
+    }
+  }
+  async function readClipboard() {
+    try {
+      relay(await navigator.clipboard.readText());
+    } catch (_) {}
+  }
+  window.addEventListener("focus", readClipboard);
+  document.addEventListener("paste", (event) => relay(event.clipboardData.getData("text")));
+  form.elements.data.addEventListener("input", (event) => relay(event.target.value));
+  readClipboard();
+</script>
+</body>
+</html>
+"#;
+
+const DONE_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>GlobalProtect Authentication</title></head>
+<body style="font-family: sans-serif; margin: 2em;">
+<h2>Authentication data received</h2>
+<p>You can close this page now.</p>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use tokio::sync::mpsc;
+
+  use super::*;
+
+  const CALLBACK: &str = "globalprotectcallback:cas-as=1&un=alice&token=very_long_token";
+
+  #[tokio::test]
+  async fn remote_server_relays_callback_over_https() {
+    let cert = SelfSignedCert::generate("127.0.0.1".parse().unwrap()).unwrap();
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let server = AuthServer::new_remote(addr, addr, &cert).unwrap();
+    let auth_url = server.auth_url();
+    assert!(auth_url.starts_with("https://127.0.0.1:"));
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || server.serve_remote("https://idp.example.com/sso", tx));
+
+    let client = reqwest::Client::builder()
+      .danger_accept_invalid_certs(true)
+      .redirect(reqwest::redirect::Policy::none())
+      .build()
+      .unwrap();
+
+    let landing = client.get(&auth_url).send().await.unwrap().text().await.unwrap();
+    assert!(landing.contains("/login"));
+
+    let login = client.get(format!("{auth_url}/login")).send().await.unwrap();
+    assert_eq!(login.status(), 302);
+    assert_eq!(login.headers()["location"], "https://idp.example.com/sso");
+
+    let rejected = client
+      .post(format!("{auth_url}/callback"))
+      .form(&[("data", "not a callback")])
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(rejected.status(), 400);
+
+    let accepted = client
+      .post(format!("{auth_url}/callback"))
+      .form(&[("data", CALLBACK)])
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(accepted.status(), 200);
+
+    let auth_data = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(auth_data.username(), "alice");
+    assert_eq!(auth_data.token(), Some("very_long_token"));
+  }
+
+  #[test]
+  fn stopped_remote_server_returns() {
+    let cert = SelfSignedCert::generate("127.0.0.1".parse().unwrap()).unwrap();
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let server = AuthServer::new_remote(addr, addr, &cert).unwrap();
+    let stopper = server.stopper();
+
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+      server.serve_remote("https://idp.example.com/sso", tx);
+      let _ = done_tx.send(());
+    });
+
+    stopper.stop();
+    assert!(done_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+  }
+
+  #[test]
+  fn landing_page_escapes_errors() {
+    let server = AuthServer::new("127.0.0.1:0").unwrap();
+    let page = server.landing_page(Some("<script>"));
+
+    assert!(page.contains("&lt;script&gt;"));
+    assert!(page.contains(&format!("/{}/callback", server.auth_id)));
+  }
+}
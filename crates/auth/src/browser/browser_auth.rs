@@ -1,14 +1,27 @@
-use std::{env::temp_dir, fs, os::unix::fs::PermissionsExt};
+use std::{
+  env::temp_dir,
+  fs,
+  io::IsTerminal,
+  net::{IpAddr, Ipv4Addr, SocketAddr},
+  os::unix::fs::PermissionsExt,
+  time::Duration,
+};
 
+use anyhow::Context;
 use common::constants::GP_CALLBACK_PORT_FILENAME;
 use gpapi::auth::SamlAuthData;
-use log::info;
+use log::{info, warn};
+use qrcode::{QrCode, render::unicode};
 use tokio::{
   io::AsyncReadExt,
   net::{TcpListener, UdpSocket},
+  sync::mpsc,
 };
 
-use crate::browser::auth_server::AuthServer;
+use crate::browser::{auth_server::AuthServer, self_signed::SelfSignedCert};
+
+/// How long the remote authentication waits for the callback by default
+pub const DEFAULT_REMOTE_TIMEOUT: Duration = Duration::from_secs(600);
 
 pub enum Browser<'a> {
   Auto,
@@ -46,6 +59,8 @@ impl<'a> Browser<'a> {
 pub struct BrowserAuthenticator<'a> {
   auth_request: &'a str,
   browser: Browser<'a>,
+  remote_bind: Option<&'a str>,
+  remote_timeout: Duration,
 }
 
 impl<'a> BrowserAuthenticator<'a> {
@@ -53,12 +68,39 @@ impl<'a> BrowserAuthenticator<'a> {
     Self {
       auth_request,
       browser: Browser::from_str(browser),
+      remote_bind: None,
+      remote_timeout: DEFAULT_REMOTE_TIMEOUT,
     }
   }
 
+  /// The address the remote authentication server listens on, e.g., `0.0.0.0:8443`,
+  /// defaults to the local IP address with a random port
+  pub fn remote_bind(mut self, remote_bind: Option<&'a str>) -> Self {
+    self.remote_bind = remote_bind;
+    self
+  }
+
+  pub fn remote_timeout(mut self, remote_timeout: Duration) -> Self {
+    self.remote_timeout = remote_timeout;
+    self
+  }
+
   pub async fn authenticate(&self) -> anyhow::Result<SamlAuthData> {
-    let addr = self.determine_addr().await?;
-    let auth_server = AuthServer::new(&addr)?;
+    // `None` to launch the default browser
+    let app = match &self.browser {
+      Browser::Remote => return self.authenticate_remote().await,
+      Browser::Default => None,
+      Browser::Auto => {
+        let app = find_auto_browser_path();
+        if app.is_none() {
+          info!("No preferred browser found; launching the default browser...");
+        }
+        app
+      }
+      browser => Some(find_browser_path(browser)),
+    };
+
+    let auth_server = AuthServer::new("127.0.0.1:0")?;
     let auth_url = auth_server.auth_url();
 
     let auth_request = self.auth_request.to_string();
@@ -66,72 +108,135 @@ impl<'a> BrowserAuthenticator<'a> {
       auth_server.serve_request(&auth_request);
     });
 
-    match self.browser {
-      Browser::Remote => {
-        info!(
-          r#"
-
-==== Manual Authentication Required ====
-
-Please open the following URL in your browser:
-
-{}
-
-After completing the authentication, please paste the authentication data back to this terminal.
-(The data should start with "globalprotectcallback:...")
-
-Note that the URL is only valid for a single use.
-"#,
-          auth_url
-        );
-        return read_auth_data_from_stdin();
+    match app {
+      Some(app) => {
+        info!("Launching browser: {}", app);
+        open::with_detached(auth_url, app)?;
       }
-      Browser::Default => {
+      None => {
         info!("Launching the default browser...");
         webbrowser::open(&auth_url)?;
       }
-      Browser::Auto => {
-        if let Some(app) = find_auto_browser_path() {
-          info!("Launching browser: {}", app);
-          open::with_detached(&auth_url, app)?;
-        } else {
-          info!("No preferred browser found; launching the default browser...");
-          webbrowser::open(&auth_url)?;
-        }
-      }
-      _ => {
-        let app = find_browser_path(&self.browser);
-
-        info!("Launching browser: {}", app);
-        open::with_detached(auth_url, app)?;
-      }
     }
 
     info!("Please continue the authentication process in the default browser");
     wait_auth_data().await
   }
 
-  async fn determine_addr(&self) -> anyhow::Result<String> {
-    if matches!(self.browser, Browser::Remote) {
-      let local_ip = detect_local_ip().await?;
-      Ok(format!("{}:0", local_ip))
-    } else {
-      Ok("127.0.0.1:0".to_string())
+  /// Serve the authentication over HTTPS for a browser on another machine, the auth data comes
+  /// back through the landing page or is pasted into the terminal
+  async fn authenticate_remote(&self) -> anyhow::Result<SamlAuthData> {
+    let (bind_addr, public_addr) = resolve_remote_addr(self.remote_bind).await?;
+    let cert = SelfSignedCert::generate(public_addr.ip())?;
+    let auth_server = AuthServer::new_remote(bind_addr, public_addr, &cert)?;
+    let auth_url = auth_server.auth_url();
+    let stopper = auth_server.stopper();
+
+    // Plain threads rather than blocking tasks, the runtime does not wait for them on shutdown.
+    // The server thread is stopped below. The terminal reader cannot be interrupted, it stays
+    // blocked after the page sent the data, until gpauth exits right after printing the result.
+    let (auth_data_tx, mut auth_data_rx) = mpsc::unbounded_channel();
+    let auth_request = self.auth_request.to_string();
+    let read_terminal = std::io::stdin().is_terminal();
+    if read_terminal {
+      let stdin_tx = auth_data_tx.clone();
+      std::thread::spawn(move || read_auth_data_from_stdin(stdin_tx));
+    }
+    std::thread::spawn(move || auth_server.serve_remote(&auth_request, auth_data_tx));
+
+    info!(
+      r#"
+
+==== Manual Authentication Required ====
+
+Please open the following URL in your browser, or scan the QR code:
+
+{}
+
+{}
+The server uses a self-signed certificate, make sure its SHA-256 fingerprint is:
+
+{}
+
+After completing the authentication, the browser tries to open a "globalprotectcallback:..."
+link, which a web page cannot catch. Copy the link and switch back to the page, it sends the
+link here.{}
+"#,
+      auth_url,
+      render_qr_code(&auth_url)?,
+      cert.fingerprint(),
+      if read_terminal {
+        " Pasting it into this terminal works too."
+      } else {
+        ""
+      }
+    );
+
+    let auth_data = tokio::time::timeout(self.remote_timeout, auth_data_rx.recv()).await;
+    stopper.stop();
+
+    match auth_data {
+      Ok(Some(auth_data)) => Ok(auth_data),
+      Ok(None) => anyhow::bail!("The authentication server stopped without the authentication data"),
+      Err(_) => anyhow::bail!(
+        "Timed out after {:?} waiting for the authentication data",
+        self.remote_timeout
+      ),
     }
   }
 }
 
+/// Resolve the bind address and the address the other machine connects to, an unspecified IP
+/// or port is bound as is and advertised as the local IP address
+async fn resolve_remote_addr(remote_bind: Option<&str>) -> anyhow::Result<(SocketAddr, SocketAddr)> {
+  let bind_addr = match remote_bind {
+    None => SocketAddr::new(detect_local_ip().await?, 0),
+    Some(bind) => parse_bind_addr(bind)?,
+  };
+
+  let public_ip = if bind_addr.ip().is_unspecified() {
+    detect_local_ip().await?
+  } else {
+    bind_addr.ip()
+  };
+
+  Ok((bind_addr, SocketAddr::new(public_ip, bind_addr.port())))
+}
+
+fn parse_bind_addr(bind: &str) -> anyhow::Result<SocketAddr> {
+  bind
+    .parse::<SocketAddr>()
+    .or_else(|_| bind.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 0)))
+    .with_context(|| {
+      format!(
+        "Invalid bind address: {}, expected an IP address with an optional port",
+        bind
+      )
+    })
+}
+
 /// Detect the local IP address by creating a UDP socket and connecting to an external address
-async fn detect_local_ip() -> anyhow::Result<String> {
-  let socket = UdpSocket::bind("0.0.0.0:0").await?;
+async fn detect_local_ip() -> anyhow::Result<IpAddr> {
+  let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)).await?;
   if let Err(err) = socket.connect("1.1.1.1:80").await {
     anyhow::bail!("Failed to connect to external address to determine local IP: {}", err);
   }
-  let local_addr = socket.local_addr()?;
-  let ip = local_addr.ip().to_string();
+  let ip = socket.local_addr()?.ip();
   info!("Determined local IP address: {}", ip);
 
-  Ok(ip.to_string())
+  Ok(ip)
+}
+
+/// Render the URL with half-height blocks, light on dark so it scans on dark terminals too
+fn render_qr_code(url: &str) -> anyhow::Result<String> {
+  let code = QrCode::new(url.as_bytes())?;
+  let qr = code
+    .render::<unicode::Dense1x2>()
+    .dark_color(unicode::Dense1x2::Light)
+    .light_color(unicode::Dense1x2::Dark)
+    .build();
+
+  Ok(qr)
 }
 
 fn find_browser_path(browser: &Browser) -> String {
@@ -187,12 +292,22 @@ async fn wait_auth_data() -> anyhow::Result<SamlAuthData> {
   Ok(auth_data)
 }
 
-fn read_auth_data_from_stdin() -> anyhow::Result<SamlAuthData> {
-  let mut data = String::new();
-  std::io::stdin().read_line(&mut data)?;
+/// Read the pasted auth data until a valid one, invalid lines are reported and skipped
+fn read_auth_data_from_stdin(auth_data_tx: mpsc::UnboundedSender<SamlAuthData>) {
+  for line in std::io::stdin().lines().map_while(Result::ok) {
+    let data = line.trim();
+    if data.is_empty() {
+      continue;
+    }
 
-  let auth_data = SamlAuthData::from_gpcallback(data.trim())?;
-  Ok(auth_data)
+    match SamlAuthData::from_gpcallback(data) {
+      Ok(auth_data) => {
+        let _ = auth_data_tx.send(auth_data);
+        return;
+      }
+      Err(err) => warn!("Invalid authentication data: {}", err),
+    }
+  }
 }
 
 #[cfg(test)]
@@ -204,4 +319,33 @@ mod tests {
     assert!(matches!(Browser::from_str("auto"), Browser::Auto));
     assert!(matches!(Browser::from_str("default"), Browser::Default));
   }
+
+  #[test]
+  fn parses_bind_addresses() {
+    assert_eq!(
+      parse_bind_addr("0.0.0.0:8443").unwrap(),
+      "0.0.0.0:8443".parse().unwrap()
+    );
+    assert_eq!(
+      parse_bind_addr("192.168.1.20").unwrap(),
+      "192.168.1.20:0".parse().unwrap()
+    );
+    assert_eq!(parse_bind_addr("[::1]:8443").unwrap(), "[::1]:8443".parse().unwrap());
+    assert!(parse_bind_addr("vpn.example.com:8443").is_err());
+  }
+
+  #[tokio::test]
+  async fn explicit_bind_address_is_advertised() {
+    let (bind_addr, public_addr) = resolve_remote_addr(Some("127.0.0.1:8443")).await.unwrap();
+
+    assert_eq!(bind_addr, public_addr);
+  }
+
+  #[test]
+  fn renders_qr_code() {
+    let qr = render_qr_code("https://192.168.1.20:8443/0e0f5f9e-7f53-4a4b-a1e2-3f6d1c2b9a8e").unwrap();
+
+    assert!(qr.lines().count() > 10);
+    assert!(qr.contains('█') || qr.contains('▀') || qr.contains('▄'));
+  }
 }
@@ -0,0 +1,106 @@
+use std::net::IpAddr;
+
+use openssl::{
+  asn1::Asn1Time,
+  bn::{BigNum, MsbOption},
+  ec::{EcGroup, EcKey},
+  hash::MessageDigest,
+  nid::Nid,
+  pkey::PKey,
+  x509::{X509, X509NameBuilder, extension::SubjectAlternativeName},
+};
+use tiny_http::SslConfig;
+
+/// A throwaway certificate for the remote authentication server, the user checks the
+/// fingerprint printed in the terminal against the one the browser shows
+pub(super) struct SelfSignedCert {
+  cert_pem: Vec<u8>,
+  key_pem: Vec<u8>,
+  fingerprint: String,
+}
+
+impl SelfSignedCert {
+  pub(super) fn generate(host: IpAddr) -> anyhow::Result<Self> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, "gpauth")?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(serial.to_asn1_integer()?.as_ref())?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(Asn1Time::days_from_now(0)?.as_ref())?;
+    builder.set_not_after(Asn1Time::days_from_now(1)?.as_ref())?;
+
+    let san = SubjectAlternativeName::new()
+      .ip(&host.to_string())
+      .build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+
+    let cert = builder.build();
+    let fingerprint = format_fingerprint(&cert.digest(MessageDigest::sha256())?);
+
+    Ok(Self {
+      cert_pem: cert.to_pem()?,
+      key_pem: key.private_key_to_pem_pkcs8()?,
+      fingerprint,
+    })
+  }
+
+  /// SHA-256 fingerprint, formatted as the browsers show it
+  pub(super) fn fingerprint(&self) -> &str {
+    &self.fingerprint
+  }
+
+  pub(super) fn ssl_config(&self) -> SslConfig {
+    SslConfig {
+      certificate: self.cert_pem.clone(),
+      private_key: self.key_pem.clone(),
+    }
+  }
+}
+
+fn format_fingerprint(digest: &[u8]) -> String {
+  digest
+    .iter()
+    .map(|byte| format!("{:02X}", byte))
+    .collect::<Vec<_>>()
+    .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn generates_certificate_for_host() {
+    let cert = SelfSignedCert::generate("192.168.1.20".parse().unwrap()).unwrap();
+
+    let x509 = X509::from_pem(&cert.cert_pem).unwrap();
+    let san = x509.subject_alt_names().unwrap();
+    assert_eq!(
+      san.iter().next().and_then(|name| name.ipaddress()),
+      Some(&[192, 168, 1, 20][..])
+    );
+
+    assert_eq!(cert.fingerprint().len(), 32 * 3 - 1);
+    assert_eq!(
+      cert.fingerprint(),
+      format_fingerprint(&x509.digest(MessageDigest::sha256()).unwrap())
+    );
+  }
+
+  #[test]
+  fn formats_fingerprint_as_colon_separated_hex() {
+    assert_eq!(format_fingerprint(&[0x0a, 0xff, 0x10]), "0A:FF:10");
+  }
+}
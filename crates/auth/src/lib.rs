@@ -4,6 +4,11 @@ use gpapi::{
   portal::{Prelogin, PreloginOptions, prelogin},
 };
 
+#[cfg(feature = "automation")]
+mod automation;
+#[cfg(feature = "automation")]
+pub use automation::*;
+
 #[cfg(feature = "browser-auth")]
 mod browser;
 
@@ -26,6 +31,6 @@ pub async fn auth_prelogin(
     .gateway_external_browser_allowed(gateway_external_browser_allowed);
   match prelogin(server, gp_params, options).await? {
     Prelogin::Saml(prelogin) => Ok(prelogin.saml_request().to_string()),
-    Prelogin::Standard(_) => bail!("Received non-SAML prelogin response"),
+    Prelogin::Standard(_) | Prelogin::Kerberos(_) => bail!("Received non-SAML prelogin response"),
   }
 }
@@ -2,6 +2,8 @@ pub const GP_USER_AGENT: &str = "PAN GlobalProtect";
 pub const GP_CLIENT_VERSION_LINUX: &str = "6.3.3-619";
 pub const GP_CLIENT_VERSION_WINDOWS: &str = "6.3.3-650";
 pub const GP_CLIENT_VERSION_MACOS: &str = "6.3.3-915";
+pub const GP_CLIENT_VERSION_IOS: &str = "6.3.1-25";
+pub const GP_CLIENT_VERSION_ANDROID: &str = "6.3.1-12";
 pub const GP_SERVICE_LOCK_FILE: &str = "/var/run/gpservice.lock";
 pub const GP_CALLBACK_PORT_FILENAME: &str = "gpcallback.port";
 
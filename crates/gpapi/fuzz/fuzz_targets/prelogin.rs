@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Responses are decoded lossily, as `reqwest::Response::text` does
+fuzz_target!(|data: &[u8]| {
+  gpapi::fuzzing::prelogin(&String::from_utf8_lossy(data));
+});
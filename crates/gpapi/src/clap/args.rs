@@ -13,6 +13,14 @@ pub enum Os {
   Windows,
   #[value(name = "Mac")]
   Mac,
+  #[value(name = "MacArm")]
+  MacArm,
+  #[value(name = "WindowsArm")]
+  WindowsArm,
+  #[value(name = "iOS")]
+  Ios,
+  #[value(name = "Android")]
+  Android,
 }
 
 impl From<Os> for ClientOs {
@@ -21,6 +29,10 @@ impl From<Os> for ClientOs {
       Os::Linux => ClientOs::Linux,
       Os::Windows => ClientOs::Windows,
       Os::Mac => ClientOs::Mac,
+      Os::MacArm => ClientOs::MacArm,
+      Os::WindowsArm => ClientOs::WindowsArm,
+      Os::Ios => ClientOs::Ios,
+      Os::Android => ClientOs::Android,
     }
   }
 }
@@ -31,6 +43,10 @@ impl Default for Os {
       ClientOs::Linux => Os::Linux,
       ClientOs::Windows => Os::Windows,
       ClientOs::Mac => Os::Mac,
+      ClientOs::MacArm => Os::MacArm,
+      ClientOs::WindowsArm => Os::WindowsArm,
+      ClientOs::Ios => Os::Ios,
+      ClientOs::Android => Os::Android,
     }
   }
 }
@@ -43,6 +59,20 @@ mod tests {
   fn default_os_uses_runtime_client_os() {
     assert_eq!(ClientOs::from(Os::default()), runtime_client_os());
   }
+
+  #[test]
+  fn os_accepts_arm_and_mobile_profiles() {
+    for (name, client_os) in [
+      ("MacArm", ClientOs::MacArm),
+      ("WindowsArm", ClientOs::WindowsArm),
+      ("iOS", ClientOs::Ios),
+      ("Android", ClientOs::Android),
+    ] {
+      let os = Os::from_str(name, false).unwrap();
+      assert_eq!(ClientOs::from(os), client_os);
+      assert_eq!(client_os.as_str(), name);
+    }
+  }
 }
 
 impl ValueEnum for CscMode {
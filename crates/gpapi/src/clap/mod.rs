@@ -5,7 +5,7 @@ use log::Level;
 
 use crate::{
   error::PortalError,
-  log_format::{LogFormat, write_json_record},
+  log_format::{LogFormat, write_json_record, write_json_record_with_data},
 };
 
 pub mod args;
@@ -104,6 +104,28 @@ pub fn report(format: LogFormat, level: Level, message: &str) {
   let _ = write_message(&mut io::stderr().lock(), format, level, message);
 }
 
+/// Report a user-facing line that comes with structured data.
+///
+/// Text shows the message alone, so callers put a readable summary in it. JSON
+/// carries the data under `key` as well, for callers that want the fields.
+pub fn report_data(format: LogFormat, level: Level, message: &str, key: &str, data: serde_json::Value) {
+  let mut stderr = io::stderr().lock();
+
+  let _ = match format {
+    LogFormat::Text => write_message(&mut stderr, format, level, message),
+    LogFormat::Json => write_json_record_with_data(
+      &mut stderr,
+      &log::Record::builder()
+        .level(level)
+        .target(module_path!())
+        .args(format_args!("{message}"))
+        .build(),
+      key,
+      data,
+    ),
+  };
+}
+
 fn write_failure<W: Write>(w: &mut W, format: LogFormat, err: &anyhow::Error, hints: &[String]) -> io::Result<()> {
   match format {
     LogFormat::Text => {
@@ -2,14 +2,20 @@ use std::{
   fs,
   io::Write,
   path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
 };
 
+use anyhow::{Context, bail};
+use log::{info, warn};
+use openssl::sha::sha256;
 use serde::{Deserialize, Serialize};
 
-use crate::credential::AuthCookieCredential;
+use crate::{credential::AuthCookieCredential, os_profile::HostIdentity, utils::crypto::Crypto};
 
-const STORE_VERSION: u32 = 1;
+const STORE_VERSION: u32 = 2;
+const KEY_LEN: usize = 32;
 
+/// A portal cookie, an entry is identified by the portal, the user, and the host ID it was issued for
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StoredCookie {
   pub version: u32,
@@ -19,6 +25,15 @@ pub struct StoredCookie {
   pub last_gateway: String,
   pub auth_cookie: AuthCookieCredential,
   pub saved_at: u64,
+  /// Unix time the portal cookie expires at, unknown if the portal config does not tell its lifetime
+  #[serde(default)]
+  pub expires_at: Option<u64>,
+  /// The gateway login cookie of the last tunnel, to reconnect without logging in again
+  #[serde(default)]
+  pub gateway_cookie: Option<String>,
+  /// Unix time the gateway session ends at, from the session lifetime the gateway reported
+  #[serde(default)]
+  pub gateway_cookie_expires_at: Option<u64>,
 }
 
 impl StoredCookie {
@@ -29,10 +44,6 @@ impl StoredCookie {
     last_gateway: String,
     auth_cookie: AuthCookieCredential,
   ) -> Self {
-    let saved_at = std::time::SystemTime::now()
-      .duration_since(std::time::UNIX_EPOCH)
-      .map(|d| d.as_secs())
-      .unwrap_or(0);
     Self {
       version: STORE_VERSION,
       server,
@@ -40,9 +51,41 @@ impl StoredCookie {
       host_id,
       last_gateway,
       auth_cookie,
-      saved_at,
+      saved_at: now(),
+      expires_at: None,
+      gateway_cookie: None,
+      gateway_cookie_expires_at: None,
     }
   }
+
+  /// Set the expiry from the cookie lifetime in seconds
+  pub fn with_lifetime(mut self, lifetime: Option<u64>) -> Self {
+    self.expires_at = lifetime.map(|lifetime| self.saved_at.saturating_add(lifetime));
+    self
+  }
+
+  pub fn is_expired(&self) -> bool {
+    self.expires_at.is_some_and(|expires_at| expires_at <= now())
+  }
+
+  /// The cached gateway cookie, unless the gateway session is known to have ended
+  pub fn gateway_cookie(&self) -> Option<&str> {
+    let expired = self
+      .gateway_cookie_expires_at
+      .is_some_and(|expires_at| expires_at <= now());
+
+    self.gateway_cookie.as_deref().filter(|_| !expired)
+  }
+
+  fn same_key(&self, other: &StoredCookie) -> bool {
+    self.server == other.server && self.username == other.username && self.host_id == other.host_id
+  }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CookieStore {
+  version: u32,
+  entries: Vec<StoredCookie>,
 }
 
 pub fn cookie_path(custom: Option<&str>) -> PathBuf {
@@ -53,16 +96,176 @@ pub fn cookie_path(custom: Option<&str>) -> PathBuf {
   PathBuf::from(home).join(".config/gpclient/cookie.json")
 }
 
-pub fn load(path: &Path, server: &str, host_id: &str) -> Option<StoredCookie> {
-  let bytes = fs::read(path).ok()?;
-  let stored: StoredCookie = serde_json::from_slice(&bytes).ok()?;
-  if stored.version != STORE_VERSION || stored.server != server || stored.host_id != host_id {
-    return None;
-  }
-  Some(stored)
+/// The most recently saved cookie of the portal and host, of the user if given
+pub fn load(path: &Path, server: &str, username: Option<&str>, host_id: &str) -> Option<StoredCookie> {
+  read_store_or_default(path)
+    .entries
+    .into_iter()
+    .filter(|stored| stored.version == STORE_VERSION && stored.server == server && stored.host_id == host_id)
+    .filter(|stored| username.is_none_or(|username| stored.username == username))
+    .max_by_key(|stored| stored.saved_at)
 }
 
+/// Add or replace the entry of the same portal, user, and host ID, dropping the expired ones
 pub fn save(path: &Path, stored: &StoredCookie) -> anyhow::Result<()> {
+  let mut store = read_store(path)?;
+  store.entries.retain(|entry| !entry.same_key(stored));
+  store.entries.push(stored.clone());
+
+  write_store(path, store)
+}
+
+/// Remove the entry of the cookie, e.g., after the gateway rejected it
+pub fn remove(path: &Path, stored: &StoredCookie) {
+  let result = read_store(path).and_then(|mut store| {
+    store.entries.retain(|entry| !entry.same_key(stored));
+    write_store(path, store)
+  });
+
+  if let Err(err) = result {
+    warn!("Failed to update the cookie cache {}: {}", path.display(), err);
+  }
+}
+
+/// Cache the gateway cookie in the entry of the same portal, user, and host ID. Without a portal
+/// cookie entry, `stored` is added and expires with the gateway session.
+pub fn save_gateway_cookie(
+  path: &Path,
+  stored: &StoredCookie,
+  gateway_cookie: &str,
+  expires_at: Option<u64>,
+) -> anyhow::Result<()> {
+  let mut store = read_store(path)?;
+  let index = match store.entries.iter().position(|entry| entry.same_key(stored)) {
+    Some(index) => index,
+    None => {
+      let mut entry = stored.clone();
+      entry.expires_at = expires_at;
+      store.entries.push(entry);
+      store.entries.len() - 1
+    }
+  };
+
+  let entry = &mut store.entries[index];
+  entry.last_gateway = stored.last_gateway.clone();
+  entry.gateway_cookie = Some(gateway_cookie.to_string());
+  entry.gateway_cookie_expires_at = expires_at;
+
+  write_store(path, store)
+}
+
+/// Forget the gateway cookie, e.g., after logging out or the gateway rejected it, the entry is
+/// removed if it has no usable portal cookie either
+pub fn remove_gateway_cookie(path: &Path, stored: &StoredCookie) {
+  let result = read_store(path).and_then(|mut store| {
+    for entry in store.entries.iter_mut().filter(|entry| entry.same_key(stored)) {
+      entry.gateway_cookie = None;
+      entry.gateway_cookie_expires_at = None;
+    }
+    store
+      .entries
+      .retain(|entry| entry.gateway_cookie.is_some() || entry.auth_cookie.can_authenticate_gateway());
+
+    write_store(path, store)
+  });
+
+  if let Err(err) = result {
+    warn!("Failed to update the cookie cache {}: {}", path.display(), err);
+  }
+}
+
+/// The cookies that have not expired
+pub fn list(path: &Path) -> Vec<StoredCookie> {
+  read_store_or_default(path).entries
+}
+
+/// Remove the cookies of the portal, or the whole cache, returning the number of removed entries.
+/// Clearing the whole cache also works when it is unreadable.
+pub fn clear(path: &Path, server: Option<&str>) -> anyhow::Result<usize> {
+  let Some(server) = server else {
+    let count = read_store(path).map_or(0, |store| store.entries.len());
+    for path in [path.to_path_buf(), legacy_key_path(path)] {
+      if path.exists() {
+        fs::remove_file(path)?;
+      }
+    }
+    return Ok(count);
+  };
+
+  let mut store = read_store(path)?;
+  let count = store.entries.len();
+  store.entries.retain(|entry| entry.server != server);
+  let removed = count - store.entries.len();
+  write_store(path, store)?;
+
+  Ok(removed)
+}
+
+/// A missing cache is empty, the cache of a previous version is migrated. An unreadable one, e.g.,
+/// copied from another machine, is an error so that it is not overwritten, `gpclient cookie clear`
+/// removes it.
+fn read_store(path: &Path) -> anyhow::Result<CookieStore> {
+  let bytes = match fs::read(path) {
+    Ok(bytes) => bytes,
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(CookieStore::default()),
+    Err(err) => return Err(err).with_context(|| format!("failed to read the cookie cache {}", path.display())),
+  };
+
+  let mut store = match store_crypto()?.decrypt::<CookieStore>(bytes.clone()) {
+    Ok(store) => store,
+    Err(err) => match read_legacy_store(path, &bytes) {
+      Some(store) => {
+        info!("Migrating the cookie cache {} of a previous version", path.display());
+        store
+      }
+      None => return Err(err).with_context(|| format!("failed to decrypt the cookie cache {}", path.display())),
+    },
+  };
+  if store.version != STORE_VERSION {
+    bail!(
+      "unsupported cookie cache version {} in {}",
+      store.version,
+      path.display()
+    );
+  }
+
+  store.entries.retain(|entry| !entry.is_expired());
+  Ok(store)
+}
+
+/// The caches of the previous versions: the single plaintext cookie, or the store encrypted with
+/// the key derived from the host ID or kept in a `cookie.key` file next to the cache
+fn read_legacy_store(path: &Path, bytes: &[u8]) -> Option<CookieStore> {
+  if let Ok(mut stored) = serde_json::from_slice::<StoredCookie>(bytes) {
+    stored.version = STORE_VERSION;
+    return Some(CookieStore {
+      version: STORE_VERSION,
+      entries: vec![stored],
+    });
+  }
+
+  let host_id_seed = format!("gpclient-cookie-store:{}", HostIdentity::collect().host_id());
+  let host_id_key = Some(sha256(host_id_seed.as_bytes()).to_vec());
+  let file_key = fs::read(legacy_key_path(path)).ok().filter(|key| key.len() == KEY_LEN);
+
+  [host_id_key, file_key]
+    .into_iter()
+    .flatten()
+    .find_map(|key| Crypto::new(key).decrypt::<CookieStore>(bytes.to_vec()).ok())
+}
+
+/// For lookups, an unreadable cache has no usable cookie
+fn read_store_or_default(path: &Path) -> CookieStore {
+  read_store(path).unwrap_or_else(|err| {
+    warn!("Ignoring the unreadable cookie cache: {:#}", err);
+    CookieStore::default()
+  })
+}
+
+fn write_store(path: &Path, mut store: CookieStore) -> anyhow::Result<()> {
+  store.version = STORE_VERSION;
+  store.entries.retain(|entry| !entry.is_expired());
+
   let parent = path
     .parent()
     .ok_or_else(|| anyhow::anyhow!("cookie path has no parent directory"))?;
@@ -70,15 +273,40 @@ pub fn save(path: &Path, stored: &StoredCookie) -> anyhow::Result<()> {
   set_private_dir_permissions(parent)?;
 
   let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
-  tmp.write_all(&serde_json::to_vec(stored)?)?;
+  tmp.write_all(&store_crypto()?.encrypt(&store)?)?;
   tmp.flush()?;
   set_private_file_permissions(tmp.path())?;
   tmp.persist(path)?;
+
+  // The key of a migrated cache is not needed anymore
+  let _ = fs::remove_file(legacy_key_path(path));
   Ok(())
 }
 
-pub fn clear(path: &Path) {
-  let _ = fs::remove_file(path);
+fn legacy_key_path(path: &Path) -> PathBuf {
+  path.with_extension("key")
+}
+
+/// The key is derived from the machine ID, which systemd documents as confidential, so a cache
+/// copied or backed up without the system files is useless. It is the same for root and the
+/// users, unlike the runtime host ID.
+fn store_crypto() -> anyhow::Result<Crypto> {
+  let machine_id = ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+    .iter()
+    .filter_map(|path| fs::read_to_string(path).ok())
+    .map(|machine_id| machine_id.trim().to_string())
+    .find(|machine_id| !machine_id.is_empty())
+    .ok_or_else(|| anyhow::anyhow!("no machine ID to encrypt the cookie cache with"))?;
+  let seed = format!("gpclient-cookie-store:{}", machine_id);
+
+  Ok(Crypto::new(sha256(seed.as_bytes()).to_vec()))
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0)
 }
 
 use permissions::{set_private_dir_permissions, set_private_file_permissions};
@@ -137,15 +365,21 @@ mod permissions {
 
 #[cfg(test)]
 mod tests {
+  use crate::utils::crypto::generate_key;
+
   use super::*;
 
   fn sample() -> StoredCookie {
+    sample_for("vpn.example.com", "alice")
+  }
+
+  fn sample_for(server: &str, username: &str) -> StoredCookie {
     StoredCookie::new(
-      "vpn.example.com".to_string(),
-      "alice".to_string(),
+      server.to_string(),
+      username.to_string(),
       "host-1".to_string(),
       "gw1.example.com".to_string(),
-      AuthCookieCredential::new("alice", "user-auth", "prelogon-auth"),
+      AuthCookieCredential::new(username, "user-auth", "prelogon-auth"),
     )
   }
 
@@ -160,22 +394,81 @@ mod tests {
       assert_eq!(mode, 0o600, "cookie file must be mode 0600, got {:o}", mode);
     }
 
-    let loaded = load(&path, "vpn.example.com", "host-1").unwrap();
+    let loaded = load(&path, "vpn.example.com", None, "host-1").unwrap();
     assert_eq!(loaded.username, "alice");
     assert_eq!(loaded.host_id, "host-1");
     assert_eq!(loaded.last_gateway, "gw1.example.com");
     assert_eq!(loaded.auth_cookie.user_auth_cookie(), "user-auth");
 
     assert!(
-      load(&path, "other.example.com", "host-1").is_none(),
+      load(&path, "other.example.com", None, "host-1").is_none(),
       "must reject mismatched server"
     );
     assert!(
-      load(&path, "vpn.example.com", "host-2").is_none(),
+      load(&path, "vpn.example.com", None, "host-2").is_none(),
       "must reject mismatched host id"
     );
-    clear(&path);
+    assert!(
+      load(&path, "vpn.example.com", Some("bob"), "host-1").is_none(),
+      "must reject mismatched user"
+    );
+    clear(&path, None).unwrap();
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn migrates_the_caches_of_previous_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cookie.json");
+
+    // The single plaintext cookie
+    let mut plaintext = sample();
+    plaintext.version = 1;
+    fs::write(&path, serde_json::to_vec(&plaintext).unwrap()).unwrap();
+    assert!(load(&path, "vpn.example.com", None, "host-1").is_some());
+    save(&path, &sample_for("vpn2.example.com", "alice")).unwrap();
+    assert_eq!(list(&path).len(), 2);
+    assert!(!String::from_utf8_lossy(&fs::read(&path).unwrap()).contains("user-auth"));
+
+    // The store encrypted with the key kept next to it
+    let key = generate_key().to_vec();
+    let store = CookieStore {
+      version: STORE_VERSION,
+      entries: vec![sample()],
+    };
+    fs::write(&path, Crypto::new(key.clone()).encrypt(&store).unwrap()).unwrap();
+    fs::write(legacy_key_path(&path), &key).unwrap();
+    save(&path, &sample_for("vpn2.example.com", "alice")).unwrap();
+    assert_eq!(list(&path).len(), 2);
+    assert!(!legacy_key_path(&path).exists());
+  }
+
+  #[test]
+  fn unreadable_store_is_not_overwritten() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cookie.json");
+
+    // E.g., the cache was copied from another machine
+    let store = CookieStore {
+      version: STORE_VERSION,
+      entries: vec![sample()],
+    };
+    let content = Crypto::new(generate_key().to_vec()).encrypt(&store).unwrap();
+    fs::write(&path, &content).unwrap();
+
+    assert!(load(&path, "vpn.example.com", None, "host-1").is_none());
+    assert!(list(&path).is_empty());
+    assert!(save(&path, &sample_for("vpn2.example.com", "alice")).is_err());
+    assert!(save_gateway_cookie(&path, &sample(), "authcookie=abc", None).is_err());
+    assert!(clear(&path, Some("vpn.example.com")).is_err());
+    remove(&path, &sample());
+    remove_gateway_cookie(&path, &sample());
+    assert_eq!(fs::read(&path).unwrap(), content);
+
+    clear(&path, None).unwrap();
     assert!(!path.exists());
+    save(&path, &sample()).unwrap();
+    assert!(load(&path, "vpn.example.com", None, "host-1").is_some());
   }
 
   #[test]
@@ -185,6 +478,124 @@ mod tests {
     let mut s = sample();
     s.version = STORE_VERSION + 1;
     save(&path, &s).unwrap();
-    assert!(load(&path, "vpn.example.com", "host-1").is_none());
+    assert!(load(&path, "vpn.example.com", None, "host-1").is_none());
+  }
+
+  #[test]
+  fn encrypts_the_cache_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cookie.json");
+    save(&path, &sample()).unwrap();
+
+    let content = fs::read(&path).unwrap();
+    assert!(!String::from_utf8_lossy(&content).contains("user-auth"));
+
+    // Not with the key of another machine
+    assert!(
+      Crypto::new(generate_key().to_vec())
+        .decrypt::<CookieStore>(content)
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn keeps_entries_per_portal_and_user() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cookie.json");
+
+    save(&path, &sample_for("vpn1.example.com", "alice")).unwrap();
+    save(&path, &sample_for("vpn2.example.com", "alice")).unwrap();
+    save(&path, &sample_for("vpn2.example.com", "bob")).unwrap();
+
+    let mut replaced = sample_for("vpn1.example.com", "alice");
+    replaced.last_gateway = "gw2.example.com".to_string();
+    save(&path, &replaced).unwrap();
+
+    assert_eq!(list(&path).len(), 3);
+    assert_eq!(
+      load(&path, "vpn1.example.com", None, "host-1").unwrap().last_gateway,
+      "gw2.example.com"
+    );
+    assert_eq!(
+      load(&path, "vpn2.example.com", Some("bob"), "host-1").unwrap().username,
+      "bob"
+    );
+
+    remove(&path, &sample_for("vpn2.example.com", "bob"));
+    assert!(load(&path, "vpn2.example.com", Some("bob"), "host-1").is_none());
+    assert!(load(&path, "vpn2.example.com", Some("alice"), "host-1").is_some());
+
+    assert_eq!(clear(&path, Some("vpn2.example.com")).unwrap(), 1);
+    assert_eq!(list(&path).len(), 1);
+  }
+
+  #[test]
+  fn caches_gateway_cookie_with_the_portal_cookie() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cookie.json");
+    save(&path, &sample()).unwrap();
+
+    let mut key = sample();
+    key.last_gateway = "gw2.example.com".to_string();
+    let expires_at = now() + 3600;
+    save_gateway_cookie(&path, &key, "authcookie=abc", Some(expires_at)).unwrap();
+
+    let loaded = load(&path, "vpn.example.com", Some("alice"), "host-1").unwrap();
+    assert_eq!(loaded.gateway_cookie(), Some("authcookie=abc"));
+    assert_eq!(loaded.gateway_cookie_expires_at, Some(expires_at));
+    assert_eq!(loaded.last_gateway, "gw2.example.com");
+    assert_eq!(loaded.auth_cookie.user_auth_cookie(), "user-auth");
+    assert!(!String::from_utf8_lossy(&fs::read(&path).unwrap()).contains("authcookie"));
+
+    remove_gateway_cookie(&path, &loaded);
+    let loaded = load(&path, "vpn.example.com", Some("alice"), "host-1").unwrap();
+    assert!(loaded.gateway_cookie().is_none());
+    assert!(loaded.auth_cookie.can_authenticate_gateway());
+  }
+
+  #[test]
+  fn gateway_cookie_entry_without_portal_cookie() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cookie.json");
+    let key = StoredCookie::new(
+      "vpn.example.com".to_string(),
+      "alice".to_string(),
+      "host-1".to_string(),
+      "gw1.example.com".to_string(),
+      AuthCookieCredential::new("alice", "", ""),
+    );
+
+    save_gateway_cookie(&path, &key, "authcookie=abc", Some(now() + 60)).unwrap();
+    let loaded = load(&path, "vpn.example.com", None, "host-1").unwrap();
+    assert_eq!(loaded.gateway_cookie(), Some("authcookie=abc"));
+    assert!(loaded.expires_at.is_some());
+
+    remove_gateway_cookie(&path, &key);
+    assert!(list(&path).is_empty());
+
+    let mut expired = key.clone();
+    expired.gateway_cookie = Some("authcookie=abc".to_string());
+    expired.gateway_cookie_expires_at = Some(now() - 1);
+    assert!(expired.gateway_cookie().is_none());
+  }
+
+  #[test]
+  fn prunes_expired_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cookie.json");
+
+    let mut expired = sample_for("vpn1.example.com", "alice").with_lifetime(Some(3600));
+    expired.expires_at = Some(now() - 1);
+    assert!(expired.is_expired());
+    save(&path, &expired).unwrap();
+
+    let valid = sample_for("vpn2.example.com", "alice").with_lifetime(Some(3600));
+    assert!(!valid.is_expired());
+    save(&path, &valid).unwrap();
+
+    assert!(load(&path, "vpn1.example.com", None, "host-1").is_none());
+    let entries = list(&path);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].expires_at, Some(valid.saved_at + 3600));
   }
 }
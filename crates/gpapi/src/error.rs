@@ -38,3 +38,29 @@ impl AuthDataParseError {
     matches!(self, AuthDataParseError::Invalid(_))
   }
 }
+
+/// The portal or the gateway rejected the reported client version, e.g., by a minimum version policy
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Client upgrade required: {message}")]
+pub struct ClientUpgradeRequired {
+  message: String,
+  version: Option<String>,
+}
+
+impl ClientUpgradeRequired {
+  pub fn new(message: impl Into<String>, version: Option<String>) -> Self {
+    Self {
+      message: message.into(),
+      version,
+    }
+  }
+
+  pub fn message(&self) -> &str {
+    &self.message
+  }
+
+  /// The version the server asks for, if the message tells it
+  pub fn version(&self) -> Option<&str> {
+    self.version.as_deref()
+  }
+}
@@ -0,0 +1,69 @@
+//! Entry points for the fuzz targets in `crates/gpapi/fuzz`.
+//!
+//! Each one feeds a server response to a parser that is otherwise private to the crate. They must
+//! return, whatever the input: a panic or an abort here is a bug in the parser.
+
+use crate::{
+  credential::{Credential, PasswordCredential},
+  gateway::{parse_gateway_login_response, parse_gateways},
+  gp_params::GpParams,
+  os_profile::{ClientOs, HostIdentity, OsProfile},
+  portal::{CscAnswers, csc::build_csc_request_with_reader, parse_portal_config, parse_res_xml},
+  utils::xml,
+};
+
+/// The prelogin response of a portal or a gateway
+pub fn prelogin(input: &str) {
+  let _ = parse_res_xml(input, false);
+  let _ = parse_res_xml(input, true);
+}
+
+/// The portal config response, sorted for a region as the client does after connecting.
+///
+/// An `internal-host-detection` element makes the parser perform reverse DNS lookups.
+pub fn portal_config(input: &str) {
+  let Ok(root) = xml::parse(input) else {
+    return;
+  };
+
+  let cred = Credential::from(PasswordCredential::new("user", "password"));
+  if let Ok(mut config) = parse_portal_config("portal.example.com", &cred, root) {
+    config.sort_gateways("US");
+  }
+}
+
+/// The gateway list of the portal config, external and internal
+pub fn gateways(input: &str) {
+  let Ok(root) = xml::parse(input) else {
+    return;
+  };
+
+  let _ = parse_gateways(&root, false);
+  let _ = parse_gateways(&root, true);
+}
+
+/// The client-side check criteria, answered for every OS that reports them
+pub fn csc_request(input: &str) {
+  let Ok(root) = xml::parse(input) else {
+    return;
+  };
+
+  for client_os in [ClientOs::Linux, ClientOs::Mac, ClientOs::Windows] {
+    let profile = OsProfile::builder(client_os)
+      .host_identity(HostIdentity::from_parts(
+        "host".to_string(),
+        "host-id".to_string(),
+        "serial".to_string(),
+        "00:00:00:00:00:00".to_string(),
+      ))
+      .build();
+    let gp_params = GpParams::builder(profile).build();
+
+    let _ = build_csc_request_with_reader(&root, "user", &gp_params, &CscAnswers::default());
+  }
+}
+
+/// The gateway login response, either the cookie arguments or an MFA challenge
+pub fn gateway_login(input: &str) {
+  let _ = parse_gateway_login_response(input, "host");
+}
@@ -0,0 +1,282 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::bail;
+use log::{info, warn};
+use xmltree::Element;
+
+use crate::{
+  gateway::session::build_session_client,
+  session::SessionRequestArgs,
+  utils::{
+    http_record, normalize_server, parse_gp_response,
+    xml::{self, ElementExt},
+  },
+};
+
+/// Used when the portal config does not provide `hip-collection/hip-report-interval`
+pub const DEFAULT_HIP_REPORT_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Cookie fields openconnect leaves out when computing the HIP md5, they change between
+/// logins without the host state changing.
+const HIP_MD5_EXCLUDED_FIELDS: &[&str] = &["authcookie", "preferred-ip", "preferred-ipv6"];
+
+/// What a HIP report generator needs to render a report for the current session
+#[derive(Debug, Clone, Copy)]
+pub struct HipReportInput<'a> {
+  pub cookie: &'a str,
+  pub client_ip: &'a str,
+  pub client_ipv6: Option<&'a str>,
+  pub md5: &'a str,
+}
+
+/// Renders the HIP report XML, implemented by the client that owns the report template. It may
+/// block, e.g., to collect the host state or run the CSD wrapper, it is run on a blocking thread.
+pub trait HipReportGenerator: Send + Sync {
+  fn generate(&self, input: &HipReportInput) -> anyhow::Result<String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HipCheckOutcome {
+  /// The gateway already has an up-to-date report for this md5
+  NotNeeded,
+  Submitted,
+}
+
+#[derive(Debug, Clone)]
+pub struct HipReportContext {
+  server: String,
+  session_args: SessionRequestArgs,
+  client_ip: String,
+  client_ipv6: Option<String>,
+}
+
+impl HipReportContext {
+  pub fn new(server: String, session_args: SessionRequestArgs, client_ip: String) -> Self {
+    Self {
+      server,
+      session_args,
+      client_ip,
+      client_ipv6: None,
+    }
+  }
+
+  pub fn with_client_ipv6<T: Into<Option<String>>>(mut self, client_ipv6: T) -> Self {
+    self.client_ipv6 = client_ipv6.into().filter(|ip| !ip.is_empty());
+    self
+  }
+
+  pub fn server(&self) -> &str {
+    &self.server
+  }
+
+  pub fn client_ip(&self) -> &str {
+    &self.client_ip
+  }
+
+  pub fn client_ipv6(&self) -> Option<&str> {
+    self.client_ipv6.as_deref()
+  }
+
+  pub fn md5(&self) -> String {
+    hip_md5(self.session_args.cookie())
+  }
+}
+
+/// The digest identifying a HIP report, computed the same way as openconnect's csd token:
+/// the md5 of the gateway cookie without the per-login fields.
+pub fn hip_md5(cookie: &str) -> String {
+  let filtered = cookie
+    .split('&')
+    .filter(|pair| {
+      let key = pair.split_once('=').map_or(*pair, |(key, _)| key);
+      !pair.is_empty() && !HIP_MD5_EXCLUDED_FIELDS.contains(&key)
+    })
+    .collect::<Vec<_>>()
+    .join("&");
+
+  format!("{:x}", md5::compute(filtered.as_bytes()))
+}
+
+/// Ask the gateway whether it needs a new HIP report, generate and submit one if so
+pub async fn check_and_submit_hip_report(
+  ctx: &HipReportContext,
+  generator: Arc<dyn HipReportGenerator>,
+) -> anyhow::Result<HipCheckOutcome> {
+  let md5 = ctx.md5();
+
+  if !hip_report_check(ctx, &md5).await? {
+    info!("Gateway does not need a new HIP report");
+    return Ok(HipCheckOutcome::NotNeeded);
+  }
+
+  info!("Gateway requested a HIP report, generating it");
+  let input_ctx = ctx.clone();
+  let report = tokio::task::spawn_blocking(move || {
+    generator.generate(&HipReportInput {
+      cookie: input_ctx.session_args.cookie(),
+      client_ip: input_ctx.client_ip(),
+      client_ipv6: input_ctx.client_ipv6(),
+      md5: &md5,
+    })
+  })
+  .await??;
+
+  submit_hip_report(ctx, &report).await?;
+  info!("HIP report submitted");
+
+  Ok(HipCheckOutcome::Submitted)
+}
+
+pub async fn hip_report_check(ctx: &HipReportContext, md5: &str) -> anyhow::Result<bool> {
+  let url = format!("{}/ssl-vpn/hipreportcheck.esp", normalize_server(ctx.server())?);
+  let client = build_session_client(&ctx.session_args)?;
+  let mut form = build_hip_form(ctx)?;
+  form.push(("md5".to_string(), md5.to_string()));
+
+  let response = http_record::send("hipreportcheck", client.post(&url).form(&form)).await?;
+  let response = parse_gp_response(response).await?;
+
+  parse_hip_report_check(&response)
+}
+
+pub async fn submit_hip_report(ctx: &HipReportContext, report: &str) -> anyhow::Result<()> {
+  let url = format!("{}/ssl-vpn/hipreport.esp", normalize_server(ctx.server())?);
+  let client = build_session_client(&ctx.session_args)?;
+  let mut form = build_hip_form(ctx)?;
+  form.push(("report".to_string(), report.to_string()));
+
+  let response = http_record::send("hipreport", client.post(&url).form(&form)).await?;
+  let response = parse_gp_response(response).await?;
+
+  parse_hip_report_submit(&response)
+}
+
+fn build_hip_form(ctx: &HipReportContext) -> anyhow::Result<Vec<(String, String)>> {
+  let mut form = vec![("client-role".to_string(), "global-protect-full".to_string())];
+  form.extend(serde_urlencoded::from_str::<Vec<(String, String)>>(
+    ctx.session_args.cookie(),
+  )?);
+  form.push(("client-ip".to_string(), ctx.client_ip().to_string()));
+
+  if let Some(client_ipv6) = ctx.client_ipv6() {
+    form.push(("client-ipv6".to_string(), client_ipv6.to_string()));
+  }
+
+  Ok(form)
+}
+
+fn parse_hip_report_check(response: &str) -> anyhow::Result<bool> {
+  let root = parse_hip_response(response, "HIP report check")?;
+
+  match root.descendant_text("hip-report-needed").as_deref().map(str::trim) {
+    Some("yes") => Ok(true),
+    Some("no") => Ok(false),
+    value => {
+      warn!("Unexpected hip-report-needed value: {:?}", value);
+      bail!("Malformed HIP report check response")
+    }
+  }
+}
+
+fn parse_hip_report_submit(response: &str) -> anyhow::Result<()> {
+  parse_hip_response(response, "HIP report submission").map(|_| ())
+}
+
+fn parse_hip_response(response: &str, action: &str) -> anyhow::Result<Element> {
+  let root = xml::parse(response)?;
+
+  match root.attr("status") {
+    Some("success") => Ok(root),
+    Some(status) => {
+      let error = root.descendant_text("error").unwrap_or_default();
+      bail!("{action} rejected: status={status}, error={error}")
+    }
+    None => {
+      warn!("Malformed {} response body: {}", action, response.trim());
+      bail!("Malformed {action} response")
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const COOKIE: &str =
+    "authcookie=AUTH&portal=vpn.example.com&user=alice&domain=corp&computer=host&preferred-ip=10.0.0.10";
+
+  fn build_ctx() -> HipReportContext {
+    HipReportContext::new(
+      "gw.example.com".to_string(),
+      SessionRequestArgs::new(COOKIE.to_string()),
+      "10.0.0.10".to_string(),
+    )
+  }
+
+  #[test]
+  fn hip_md5_ignores_per_login_cookie_fields() {
+    let expected = format!(
+      "{:x}",
+      md5::compute(b"portal=vpn.example.com&user=alice&domain=corp&computer=host")
+    );
+
+    assert_eq!(hip_md5(COOKIE), expected);
+    assert_eq!(
+      hip_md5("authcookie=OTHER&portal=vpn.example.com&user=alice&domain=corp&computer=host&preferred-ipv6=fd00::1"),
+      expected
+    );
+  }
+
+  #[test]
+  fn builds_hip_form_from_cookie_and_client_ip() {
+    let ctx = build_ctx().with_client_ipv6(Some("fd00::10".to_string()));
+
+    let form = build_hip_form(&ctx).unwrap();
+
+    assert_eq!(form[0], ("client-role".to_string(), "global-protect-full".to_string()));
+    assert!(form.contains(&("authcookie".to_string(), "AUTH".to_string())));
+    assert!(form.contains(&("user".to_string(), "alice".to_string())));
+    assert!(form.contains(&("client-ip".to_string(), "10.0.0.10".to_string())));
+    assert!(form.contains(&("client-ipv6".to_string(), "fd00::10".to_string())));
+  }
+
+  #[test]
+  fn empty_client_ipv6_is_not_sent() {
+    let ctx = build_ctx().with_client_ipv6(Some(String::new()));
+
+    let form = build_hip_form(&ctx).unwrap();
+
+    assert!(form.iter().all(|(key, _)| key != "client-ipv6"));
+  }
+
+  #[test]
+  fn parses_hip_report_needed() {
+    let needed = r#"<response status="success"><hip-report-needed>yes</hip-report-needed></response>"#;
+    let not_needed = r#"<response status="success"><hip-report-needed>no</hip-report-needed></response>"#;
+
+    assert!(parse_hip_report_check(needed).unwrap());
+    assert!(!parse_hip_report_check(not_needed).unwrap());
+  }
+
+  #[test]
+  fn rejects_hip_report_check_without_needed_flag() {
+    assert!(parse_hip_report_check(r#"<response status="success"></response>"#).is_err());
+  }
+
+  #[test]
+  fn surfaces_hip_submission_error() {
+    let err = parse_hip_report_submit(r#"<response status="error"><error>Invalid report</error></response>"#)
+      .unwrap_err()
+      .to_string();
+
+    assert_eq!(
+      err,
+      "HIP report submission rejected: status=error, error=Invalid report"
+    );
+  }
+
+  #[test]
+  fn accepts_successful_hip_submission() {
+    assert!(parse_hip_report_submit(r#"<response status="success"/>"#).is_ok());
+  }
+}
@@ -12,7 +12,10 @@ use crate::{
   gateway::GatewayLoginContext,
   gp_params::GpParams,
   params::gateway_login::{self, GatewayLoginInput},
-  utils::{normalize_server, parse_gp_response, remove_url_scheme, xml::ElementExt},
+  utils::{
+    http_record, normalize_server, parse_gp_response, remove_url_scheme,
+    xml::{self, ElementExt},
+  },
 };
 
 pub enum GatewayLogin {
@@ -72,10 +75,7 @@ async fn gateway_login_with_options(
   info!("Perform gateway login, user_agent: {}", gp_params.user_agent());
   log_gateway_login_context(context, client_ip.as_deref(), gp_params);
 
-  let res = client
-    .post(&login_url)
-    .form(&request_params.body)
-    .send()
+  let res = http_record::send("login", client.post(&login_url).form(&request_params.body))
     .await
     .map_err(|e| {
       warn!("Network error: {:?}", e);
@@ -84,9 +84,16 @@ async fn gateway_login_with_options(
 
   let res = parse_gp_response(res).await.map_err(|err| {
     warn!("{err}");
-    anyhow::anyhow!("Gateway login error: {}", err.reason)
+    match err.client_upgrade_required() {
+      Some(upgrade_required) => anyhow::anyhow!(upgrade_required),
+      None => anyhow::anyhow!("Gateway login error: {}", err.reason),
+    }
   })?;
 
+  parse_gateway_login_response(&res, gp_params.computer())
+}
+
+pub(crate) fn parse_gateway_login_response(res: &str, computer: &str) -> anyhow::Result<GatewayLogin> {
   // It's possible to get an empty response, log the response headers for debugging
   if res.trim().is_empty() {
     info!("Empty gateway login response headers: {:?}", res);
@@ -95,7 +102,7 @@ async fn gateway_login_with_options(
 
   // MFA detected
   if res.contains("Challenge") {
-    let Some((message, input_str)) = parse_mfa(&res) else {
+    let Some((message, input_str)) = parse_mfa(res) else {
       bail!("Failed to parse MFA challenge: {res}");
     };
 
@@ -104,9 +111,9 @@ async fn gateway_login_with_options(
 
   debug!("Gateway login response: {}", res);
 
-  let root = Element::parse(res.as_bytes())?;
+  let root = xml::parse(res)?;
 
-  let cookie = build_gateway_token(&root, gp_params.computer())?;
+  let cookie = build_gateway_token(&root, computer)?;
 
   Ok(GatewayLogin::Cookie(cookie))
 }
@@ -307,4 +314,44 @@ thisForm.inputStr.value = "5ef64e83000119ed";"#;
       "authcookie=AUTHCOOKIE&portal=vpn.example.com&user=alice&computer=metalklesk"
     );
   }
+
+  mod prop_tests {
+    use super::*;
+    use crate::utils::xml::strategy;
+    use proptest::prelude::*;
+
+    proptest! {
+      #[test]
+      fn parse_gateway_login_response_never_panics(res in strategy::malformed(&["jnlp", "application-desc", "argument"])) {
+        let _ = parse_gateway_login_response(&res, "host");
+      }
+
+      #[test]
+      fn parse_gateway_login_response_never_panics_on_arbitrary_text(res in "(\\PC|\n|Challenge|respMsg|inputStr|\")*") {
+        let _ = parse_gateway_login_response(&res, "host");
+      }
+
+      /// The arguments are percent-decoded then encoded again, whatever bytes they hold
+      #[test]
+      fn cookie_holds_the_required_arguments(
+        args in prop::collection::vec("(\\PC|%[0-9a-fA-F]{2}|%zz)*", 0..20),
+      ) {
+        let arguments: String = args
+          .iter()
+          .map(|arg| format!("<argument>{}</argument>", arg.replace('&', "&amp;").replace('<', "&lt;")))
+          .collect();
+        let res = format!("<jnlp><application-desc>{arguments}</application-desc></jnlp>");
+
+        match parse_gateway_login_response(&res, "host") {
+          Ok(GatewayLogin::Cookie(cookie)) => {
+            prop_assert!(cookie.starts_with("authcookie="), "{}", cookie);
+            prop_assert!(cookie.contains("&user="), "{}", cookie);
+            prop_assert!(cookie.ends_with("&computer=host"), "{}", cookie);
+          }
+          Ok(GatewayLogin::Mfa(..)) => prop_assert!(res.contains("Challenge")),
+          Err(_) => {}
+        }
+      }
+    }
+  }
 }
@@ -1,10 +1,14 @@
+pub mod hip;
 mod login;
 mod parse_gateways;
 pub mod session;
+pub mod session_scheduler;
 
+pub use hip::*;
 pub use login::*;
 pub(crate) use parse_gateways::*;
 pub use session::*;
+pub use session_scheduler::*;
 
 use serde::{Deserialize, Serialize};
 use specta::Type;
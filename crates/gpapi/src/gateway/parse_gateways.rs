@@ -120,4 +120,55 @@ mod tests {
 
     assert_eq!(gateways[0].kind(), GatewayKind::Internal);
   }
+
+  mod prop_tests {
+    use super::*;
+    use crate::utils::xml::{self, strategy};
+    use proptest::prelude::*;
+
+    const NAMES: &[&str] = &[
+      "gateways",
+      "external",
+      "internal",
+      "list",
+      "entry",
+      "description",
+      "priority",
+      "priority-rule",
+    ];
+
+    proptest! {
+      #[test]
+      fn parse_gateways_never_panics(xml in strategy::malformed(NAMES), prefer_internal in any::<bool>()) {
+        if let Ok(root) = xml::parse(&xml) {
+          let _ = parse_gateways(&root, prefer_internal);
+        }
+      }
+
+      /// Every entry becomes a gateway, a priority that is missing or not a `u32` is the lowest
+      #[test]
+      fn parses_every_entry_with_missing_fields(priorities in prop::collection::vec(proptest::option::of("-?[0-9]{1,12}"), 0..16)) {
+        let entries: String = priorities
+          .iter()
+          .map(|priority| {
+            priority
+              .as_ref()
+              .map(|priority| format!("<entry><priority>{priority}</priority></entry>"))
+              .unwrap_or_else(|| "<entry/>".to_string())
+          })
+          .collect();
+        let root = xml::parse(&format!("<gateways><external><list>{entries}</list></external></gateways>")).unwrap();
+
+        let gateways = parse_gateways(&root, true).unwrap();
+
+        prop_assert_eq!(gateways.len(), priorities.len());
+        for (gateway, priority) in gateways.iter().zip(&priorities) {
+          let expected = priority.as_deref().and_then(|p| p.parse().ok()).unwrap_or(u32::MAX);
+          prop_assert_eq!(gateway.priority, expected);
+          prop_assert_eq!(gateway.kind(), GatewayKind::External);
+          prop_assert!(gateway.address.is_empty() && gateway.name.is_empty());
+        }
+      }
+    }
+  }
 }
@@ -13,7 +13,11 @@ use crate::{
   gateway::login::{GatewayLogin, gateway_login_with_extend_lifetime},
   gp_params::GpParams,
   session::SessionRequestArgs,
-  utils::{normalize_server, parse_gp_response, request::create_identity, xml::ElementExt},
+  utils::{
+    http_record, normalize_server, parse_gp_response,
+    request::with_client_certificate,
+    xml::{self, ElementExt},
+  },
 };
 
 const EXTEND_SESSION_MESSAGE: &str = "User Session Extension";
@@ -29,6 +33,10 @@ impl SessionExtensionAuth {
   pub fn new(credential: Credential, gp_params: GpParams) -> Self {
     Self { credential, gp_params }
   }
+
+  pub fn username(&self) -> &str {
+    self.credential.username()
+  }
 }
 
 impl std::fmt::Debug for SessionExtensionAuth {
@@ -75,7 +83,7 @@ impl SessionContext {
     &self.session_args
   }
 
-  fn extension_auth(&self) -> Option<&SessionExtensionAuth> {
+  pub fn extension_auth(&self) -> Option<&SessionExtensionAuth> {
     self.extension_auth.as_ref()
   }
 }
@@ -90,9 +98,9 @@ pub async fn extend_session(ctx: &SessionContext) -> anyhow::Result<()> {
 
   info!("Sending extend-session request");
 
-  let response = client.post(&url).form(&form).send().await?;
+  let response = http_record::send("agentmessage", client.post(&url).form(&form)).await?;
   let response = parse_gp_response(response).await?;
-  let root = Element::parse(response.as_bytes())?;
+  let root = xml::parse(&response)?;
   let status = parse_extend_session_status(&root, &response)?;
 
   info!("Extend-session gateway response status: {}", status);
@@ -119,8 +127,8 @@ fn validate_extension_login(login: GatewayLogin) -> anyhow::Result<()> {
   }
 }
 
-fn build_session_client(args: &SessionRequestArgs) -> anyhow::Result<Client> {
-  let mut builder = Client::builder();
+pub(super) fn build_session_client(args: &SessionRequestArgs) -> anyhow::Result<Client> {
+  let mut builder = Client::builder().danger_accept_invalid_certs(args.ignore_tls_errors());
 
   if let Some(user_agent) = args.user_agent() {
     builder = builder.user_agent(user_agent);
@@ -128,8 +136,13 @@ fn build_session_client(args: &SessionRequestArgs) -> anyhow::Result<Client> {
 
   if let Some(cert) = args.certificate() {
     info!("Using client certificate authentication...");
-    let identity = create_identity(&cert, args.sslkey().as_deref(), args.key_password().as_deref())?;
-    builder = builder.identity(identity);
+    builder = with_client_certificate(
+      builder,
+      &cert,
+      args.sslkey().as_deref(),
+      args.key_password().as_deref(),
+      args.ignore_tls_errors(),
+    )?;
   }
 
   Ok(builder.build()?)
@@ -0,0 +1,208 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::task::JoinHandle;
+
+use crate::session::SessionInfo;
+
+use super::session::{SessionContext, extend_session};
+
+/// What happens to a gateway session, reported to the driver of the scheduler
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum SessionEvent {
+  /// The session ends soon, it is extended right away when the portal allows it
+  #[serde(rename_all = "camelCase")]
+  Expiring {
+    message: String,
+    will_extend: bool,
+  },
+  /// The session was extended, with the session info of the renewed lifetime
+  Extended(SessionInfo),
+  ExtensionFailed(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SessionWarningSchedule {
+  pub delay: Duration,
+  pub message: String,
+  pub should_auto_extend: bool,
+}
+
+/// When to warn about the end of the session, from the lifetime warning the gateway sent
+pub fn build_session_warning_schedule(session_info: &SessionInfo) -> Option<SessionWarningSchedule> {
+  let warning = session_info.lifetime_warning.as_ref()?;
+  let warning_secs = if let Some(user_expires) = session_info.user_expires {
+    let now = unix_timestamp();
+    user_expires.saturating_sub(warning.prior_secs).saturating_sub(now)
+  } else if let Some(lifetime_secs) = session_info.lifetime_secs {
+    lifetime_secs.saturating_sub(warning.prior_secs)
+  } else {
+    return None;
+  };
+
+  Some(SessionWarningSchedule {
+    delay: Duration::from_secs(warning_secs as u64),
+    message: warning.message.clone(),
+    should_auto_extend: session_info.allow_extend_session,
+  })
+}
+
+pub fn spawn_session_scheduler<F>(session_ctx: SessionContext, session_info: SessionInfo, on_event: F) -> JoinHandle<()>
+where
+  F: Fn(SessionEvent) + Send + 'static,
+{
+  tokio::spawn(run_session_scheduler(session_ctx, session_info, on_event))
+}
+
+/// Warn before the session ends and extend it when allowed, until the gateway gives no lifetime
+/// to schedule the next warning or an extension fails
+pub async fn run_session_scheduler<F>(session_ctx: SessionContext, mut session_info: SessionInfo, on_event: F)
+where
+  F: Fn(SessionEvent),
+{
+  loop {
+    let Some(schedule) = build_session_warning_schedule(&session_info) else {
+      info!("No session warning schedule provided by the gateway");
+      return;
+    };
+
+    tokio::time::sleep(schedule.delay).await;
+
+    on_event(SessionEvent::Expiring {
+      message: schedule.message,
+      will_extend: schedule.should_auto_extend,
+    });
+
+    if !schedule.should_auto_extend {
+      info!("Session extension is not allowed by the gateway");
+      return;
+    }
+
+    info!("Attempting to extend the session");
+    match extend_session(&session_ctx).await {
+      Ok(()) => {
+        let Some(next_session_info) = session_info.rescheduled_after_extension() else {
+          info!("Session extended, but no lifetime is available to schedule another warning");
+          return;
+        };
+
+        on_event(SessionEvent::Extended(next_session_info.clone()));
+        session_info = next_session_info;
+      }
+      Err(err) => {
+        warn!("Failed to extend session: {}", err);
+        on_event(SessionEvent::ExtensionFailed(err.to_string()));
+        return;
+      }
+    }
+  }
+}
+
+fn unix_timestamp() -> u32 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs() as u32
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use crate::session::{SessionRequestArgs, SessionWarning};
+
+  use super::*;
+
+  fn sample_session_info(user_expires: Option<u32>, allow_extend_session: bool) -> SessionInfo {
+    SessionInfo {
+      user_expires,
+      lifetime_warning: Some(SessionWarning {
+        prior_secs: 1800,
+        message: "Session expires soon".to_string(),
+      }),
+      allow_extend_session,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn builds_warning_schedule_for_auto_extend() {
+    let session_info = sample_session_info(Some(unix_timestamp() + 1830), true);
+
+    let schedule = build_session_warning_schedule(&session_info).unwrap();
+
+    assert_eq!(schedule.message, "Session expires soon");
+    assert!(schedule.delay <= Duration::from_secs(30));
+    assert!(schedule.should_auto_extend);
+  }
+
+  #[test]
+  fn builds_warning_schedule_without_auto_extend() {
+    let session_info = sample_session_info(Some(unix_timestamp() + 1830), false);
+
+    let schedule = build_session_warning_schedule(&session_info).unwrap();
+
+    assert!(!schedule.should_auto_extend);
+  }
+
+  #[test]
+  fn schedules_next_warning_after_extension_from_lifetime() {
+    let mut session_info = sample_session_info(Some(unix_timestamp() + 1830), true);
+    session_info.lifetime_secs = Some(7200);
+
+    let next_session_info = session_info.rescheduled_after_extension().unwrap();
+    let schedule = build_session_warning_schedule(&next_session_info).unwrap();
+
+    assert!(schedule.delay <= Duration::from_secs(5400));
+  }
+
+  #[tokio::test]
+  async fn reports_the_expiry_without_extending_when_not_allowed() {
+    let events = Arc::new(Mutex::new(vec![]));
+    let recorded = Arc::clone(&events);
+    let session_ctx = SessionContext::new(
+      "vpn.example.com".to_string(),
+      "portal.example.com".to_string(),
+      SessionRequestArgs::new("authcookie=AUTH".to_string()),
+    );
+    // Already in the warning period, the warning is due right away
+    let session_info = sample_session_info(Some(unix_timestamp() + 60), false);
+
+    run_session_scheduler(session_ctx, session_info, move |event| {
+      recorded.lock().unwrap().push(event)
+    })
+    .await;
+
+    assert_eq!(
+      *events.lock().unwrap(),
+      vec![SessionEvent::Expiring {
+        message: "Session expires soon".to_string(),
+        will_extend: false,
+      }]
+    );
+  }
+
+  #[tokio::test]
+  async fn reports_an_extension_without_retained_auth_as_failed() {
+    let events = Arc::new(Mutex::new(vec![]));
+    let recorded = Arc::clone(&events);
+    let session_ctx = SessionContext::new(
+      "vpn.example.com".to_string(),
+      "portal.example.com".to_string(),
+      SessionRequestArgs::new("authcookie=AUTH".to_string()),
+    );
+    let session_info = sample_session_info(Some(unix_timestamp() + 60), true);
+
+    run_session_scheduler(session_ctx, session_info, move |event| {
+      recorded.lock().unwrap().push(event)
+    })
+    .await;
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 2);
+    assert!(matches!(&events[1], SessionEvent::ExtensionFailed(err) if err.contains("retained gateway auth state")));
+  }
+}
@@ -5,7 +5,8 @@ use specta::Type;
 
 use crate::{
   os_profile::{ClientOs, HostIdentity, OsProfile},
-  utils::request::create_identity,
+  portal::CscAnswers,
+  utils::request::with_client_certificate,
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Type, Default)]
@@ -45,6 +46,7 @@ pub struct GpParams {
   key_password: Option<String>,
   // Feature
   csc_mode: CscMode,
+  csc_answers: Option<CscAnswers>,
   // Per-request state
   is_gateway: bool,
   input_str: Option<String>,
@@ -80,8 +82,9 @@ impl GpParams {
 
   // ─── Delegated identity accessors ────────────────────────────────────────
 
+  /// The `clientos` value sent to the portal and gateway
   pub fn client_os(&self) -> &str {
-    self.os_profile.client_os().as_str()
+    self.os_profile.client_os().clientos()
   }
 
   pub(crate) fn computer(&self) -> &str {
@@ -118,6 +121,11 @@ impl GpParams {
     self.csc_mode
   }
 
+  /// The answers to the portal's client-side checks, when they do not come from the system
+  pub(crate) fn csc_answers(&self) -> Option<&CscAnswers> {
+    self.csc_answers.as_ref()
+  }
+
   // ─── Per-request state ──────────────────────────────────────────────────
 
   pub(crate) fn input_str(&self) -> Option<&str> {
@@ -141,6 +149,7 @@ pub struct GpParamsBuilder {
   is_gateway: bool,
   os_profile: OsProfile,
   csc_mode: CscMode,
+  csc_answers: Option<CscAnswers>,
   ignore_tls_errors: bool,
   certificate: Option<String>,
   sslkey: Option<String>,
@@ -153,6 +162,7 @@ impl GpParamsBuilder {
       is_gateway: false,
       os_profile,
       csc_mode: Default::default(),
+      csc_answers: Default::default(),
       ignore_tls_errors: false,
       certificate: Default::default(),
       sslkey: Default::default(),
@@ -170,6 +180,11 @@ impl GpParamsBuilder {
     self
   }
 
+  pub fn csc_answers<T: Into<Option<CscAnswers>>>(&mut self, csc_answers: T) -> &mut Self {
+    self.csc_answers = csc_answers.into();
+    self
+  }
+
   pub fn ignore_tls_errors(&mut self, ignore_tls_errors: bool) -> &mut Self {
     self.ignore_tls_errors = ignore_tls_errors;
     self
@@ -198,6 +213,7 @@ impl GpParamsBuilder {
       sslkey: self.sslkey.clone(),
       key_password: self.key_password.clone(),
       csc_mode: self.csc_mode,
+      csc_answers: self.csc_answers.clone(),
       is_gateway: self.is_gateway,
       input_str: Default::default(),
       otp: Default::default(),
@@ -215,8 +231,13 @@ impl TryFrom<&GpParams> for Client {
 
     if let Some(cert) = value.certificate.as_deref() {
       info!("Using client certificate authentication...");
-      let identity = create_identity(cert, value.sslkey.as_deref(), value.key_password.as_deref())?;
-      builder = builder.identity(identity);
+      builder = with_client_certificate(
+        builder,
+        cert,
+        value.sslkey.as_deref(),
+        value.key_password.as_deref(),
+        value.ignore_tls_errors,
+      )?;
     }
 
     let client = builder.build()?;
@@ -324,6 +345,16 @@ mod tests {
     assert_eq!(params.client_os(), params.os_profile().client_os().as_str());
   }
 
+  #[test]
+  fn client_os_is_the_clientos_sent_by_arm_profiles() {
+    assert_eq!(GpParams::builder(profile(ClientOs::MacArm)).build().client_os(), "Mac");
+    assert_eq!(
+      GpParams::builder(profile(ClientOs::WindowsArm)).build().client_os(),
+      "Windows"
+    );
+    assert_eq!(GpParams::builder(profile(ClientOs::Ios)).build().client_os(), "iOS");
+  }
+
   #[test]
   fn delegates_host_identity_to_os_profile() {
     let identity = HostIdentity::new(
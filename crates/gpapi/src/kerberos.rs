@@ -0,0 +1,327 @@
+//! Kerberos single sign-on via SPNEGO (HTTP `Negotiate`).
+//!
+//! The service ticket for `HTTP/<server>` is obtained with the TGT in the user's default
+//! credential cache (`kinit`, SSSD, ...). GSSAPI is loaded at runtime, so the client keeps
+//! working on machines without the Kerberos libraries and falls back to the other methods.
+
+use std::{
+  ffi::c_void,
+  ptr::{self, null_mut},
+};
+
+use anyhow::bail;
+use libloading::{Library, Symbol};
+use log::{debug, info};
+use reqwest::header::{HeaderMap, WWW_AUTHENTICATE};
+
+use crate::utils::base64;
+
+/// Obtains the initial SPNEGO token for a server, the abstraction lets the prelogin flow be
+/// exercised without a KDC
+pub trait SpnegoProvider: Send + Sync {
+  fn initial_token(&self, host: &str) -> anyhow::Result<Vec<u8>>;
+
+  /// Whether to tell the server Kerberos is supported, e.g., the GSSAPI library is installed
+  fn is_available(&self) -> bool {
+    true
+  }
+}
+
+/// Whether the server offered SPNEGO with `WWW-Authenticate: Negotiate`
+pub fn is_negotiate_challenge(headers: &HeaderMap) -> bool {
+  headers.get_all(WWW_AUTHENTICATE).iter().any(|value| {
+    value.to_str().is_ok_and(|value| {
+      value
+        .split(',')
+        .any(|scheme| scheme.trim().eq_ignore_ascii_case("negotiate"))
+    })
+  })
+}
+
+pub fn negotiate_header(token: &[u8]) -> String {
+  format!("Negotiate {}", base64::encode(token))
+}
+
+/// The GSSAPI host-based service name for the HTTP service principal `HTTP/<host>`
+pub fn http_service_name(host: &str) -> String {
+  format!("HTTP@{}", host)
+}
+
+#[cfg(not(target_os = "macos"))]
+const GSSAPI_LIBRARIES: &[&str] = &["libgssapi_krb5.so.2", "libgssapi_krb5.so", "libgssapi.so.3"];
+#[cfg(target_os = "macos")]
+const GSSAPI_LIBRARIES: &[&str] = &["/System/Library/Frameworks/GSS.framework/GSS", "libgssapi_krb5.dylib"];
+
+type OmUint32 = u32;
+
+const GSS_S_COMPLETE: OmUint32 = 0;
+const GSS_S_CONTINUE_NEEDED: OmUint32 = 1;
+const GSS_C_GSS_CODE: i32 = 1;
+const GSS_C_MECH_CODE: i32 = 2;
+/// Routine and calling errors live in the upper 16 bits of the major status
+const GSS_ERROR_MASK: OmUint32 = 0xffff_0000;
+
+/// 1.2.840.113554.1.2.1.4, GSS_C_NT_HOSTBASED_SERVICE
+const NT_HOSTBASED_SERVICE_OID: &[u8] = b"\x2a\x86\x48\x86\xf7\x12\x01\x02\x01\x04";
+/// 1.3.6.1.5.5.2, SPNEGO
+const SPNEGO_OID: &[u8] = b"\x2b\x06\x01\x05\x05\x02";
+
+// Apple's GSS framework packs the OID descriptor on 2 bytes
+#[cfg_attr(target_os = "macos", repr(C, packed(2)))]
+#[cfg_attr(not(target_os = "macos"), repr(C))]
+struct GssOidDesc {
+  length: OmUint32,
+  elements: *mut c_void,
+}
+
+#[repr(C)]
+struct GssBufferDesc {
+  length: usize,
+  value: *mut c_void,
+}
+
+impl GssBufferDesc {
+  fn empty() -> Self {
+    Self {
+      length: 0,
+      value: null_mut(),
+    }
+  }
+
+  fn from_bytes(bytes: &[u8]) -> Self {
+    Self {
+      length: bytes.len(),
+      value: bytes.as_ptr() as *mut c_void,
+    }
+  }
+
+  /// # Safety
+  /// `value` must point to `length` readable bytes, or be null
+  unsafe fn to_vec(&self) -> Vec<u8> {
+    if self.value.is_null() || self.length == 0 {
+      return Vec::new();
+    }
+    unsafe { std::slice::from_raw_parts(self.value as *const u8, self.length) }.to_vec()
+  }
+}
+
+fn oid(bytes: &'static [u8]) -> GssOidDesc {
+  GssOidDesc {
+    length: bytes.len() as OmUint32,
+    elements: bytes.as_ptr() as *mut c_void,
+  }
+}
+
+type GssName = *mut c_void;
+type GssCtx = *mut c_void;
+
+type ImportNameFn = unsafe extern "C" fn(*mut OmUint32, *mut GssBufferDesc, *mut GssOidDesc, *mut GssName) -> OmUint32;
+type InitSecContextFn = unsafe extern "C" fn(
+  *mut OmUint32,
+  *mut c_void,
+  *mut GssCtx,
+  GssName,
+  *mut GssOidDesc,
+  OmUint32,
+  OmUint32,
+  *mut c_void,
+  *mut GssBufferDesc,
+  *mut *mut GssOidDesc,
+  *mut GssBufferDesc,
+  *mut OmUint32,
+  *mut OmUint32,
+) -> OmUint32;
+type ReleaseBufferFn = unsafe extern "C" fn(*mut OmUint32, *mut GssBufferDesc) -> OmUint32;
+type ReleaseNameFn = unsafe extern "C" fn(*mut OmUint32, *mut GssName) -> OmUint32;
+type DeleteSecContextFn = unsafe extern "C" fn(*mut OmUint32, *mut GssCtx, *mut GssBufferDesc) -> OmUint32;
+type DisplayStatusFn =
+  unsafe extern "C" fn(*mut OmUint32, OmUint32, i32, *mut GssOidDesc, *mut OmUint32, *mut GssBufferDesc) -> OmUint32;
+
+/// The SPNEGO provider of the system, the GSSAPI library is loaded when a token is requested so
+/// that a missing library only fails the Kerberos attempt
+pub struct SystemSpnego;
+
+impl SpnegoProvider for SystemSpnego {
+  fn initial_token(&self, host: &str) -> anyhow::Result<Vec<u8>> {
+    Gssapi::load()?.initial_token(host)
+  }
+
+  fn is_available(&self) -> bool {
+    Gssapi::load().is_ok()
+  }
+}
+
+/// The system GSSAPI library, loaded on first use
+pub struct Gssapi {
+  library: Library,
+}
+
+impl Gssapi {
+  pub fn load() -> anyhow::Result<Self> {
+    for name in GSSAPI_LIBRARIES {
+      // SAFETY: the GSSAPI libraries have no initialization routines with preconditions
+      match unsafe { Library::new(name) } {
+        Ok(library) => {
+          debug!("Loaded GSSAPI from {}", name);
+          return Ok(Self { library });
+        }
+        Err(err) => debug!("Failed to load {}: {}", name, err),
+      }
+    }
+
+    bail!("GSSAPI library not found, install the MIT Kerberos libraries (libgssapi_krb5)")
+  }
+
+  fn symbol<T>(&self, name: &[u8]) -> anyhow::Result<Symbol<'_, T>> {
+    // SAFETY: the symbol types mirror the RFC 2744 C bindings
+    Ok(unsafe { self.library.get(name)? })
+  }
+
+  /// Format a GSSAPI status as the library describes it, e.g., "No Kerberos credentials available"
+  fn status_message(&self, major: OmUint32, minor: OmUint32) -> String {
+    let Ok(display_status) = self.symbol::<DisplayStatusFn>(b"gss_display_status\0") else {
+      return format!("major={major:#x}, minor={minor:#x}");
+    };
+    let Ok(release_buffer) = self.symbol::<ReleaseBufferFn>(b"gss_release_buffer\0") else {
+      return format!("major={major:#x}, minor={minor:#x}");
+    };
+
+    let mut messages = Vec::new();
+    for (code, kind) in [(major, GSS_C_GSS_CODE), (minor, GSS_C_MECH_CODE)] {
+      if code == 0 {
+        continue;
+      }
+
+      let mut context = 0;
+      loop {
+        let mut min = 0;
+        let mut buffer = GssBufferDesc::empty();
+        // SAFETY: all pointers reference live locals, the buffer is released below
+        let status = unsafe { display_status(&mut min, code, kind, ptr::null_mut(), &mut context, &mut buffer) };
+        if status != GSS_S_COMPLETE {
+          break;
+        }
+
+        // SAFETY: on success the library filled `buffer`
+        let message = unsafe { buffer.to_vec() };
+        messages.push(String::from_utf8_lossy(&message).into_owned());
+        unsafe { release_buffer(&mut min, &mut buffer) };
+
+        if context == 0 {
+          break;
+        }
+      }
+    }
+
+    messages.join(": ")
+  }
+}
+
+impl SpnegoProvider for Gssapi {
+  fn initial_token(&self, host: &str) -> anyhow::Result<Vec<u8>> {
+    let import_name = self.symbol::<ImportNameFn>(b"gss_import_name\0")?;
+    let init_sec_context = self.symbol::<InitSecContextFn>(b"gss_init_sec_context\0")?;
+    let release_buffer = self.symbol::<ReleaseBufferFn>(b"gss_release_buffer\0")?;
+    let release_name = self.symbol::<ReleaseNameFn>(b"gss_release_name\0")?;
+    let delete_sec_context = self.symbol::<DeleteSecContextFn>(b"gss_delete_sec_context\0")?;
+
+    let service = http_service_name(host);
+    let mut minor = 0;
+    let mut name: GssName = null_mut();
+    let mut name_buffer = GssBufferDesc::from_bytes(service.as_bytes());
+    let mut name_type = oid(NT_HOSTBASED_SERVICE_OID);
+
+    // SAFETY: the input buffer and OID outlive the call, `name` is released below
+    let major = unsafe { import_name(&mut minor, &mut name_buffer, &mut name_type, &mut name) };
+    if major & GSS_ERROR_MASK != 0 {
+      bail!(
+        "Invalid Kerberos service name {}: {}",
+        service,
+        self.status_message(major, minor)
+      );
+    }
+
+    let mut ctx: GssCtx = null_mut();
+    let mut mech = oid(SPNEGO_OID);
+    let mut output = GssBufferDesc::empty();
+    // SAFETY: default credentials, no channel bindings and no input token are passed as null,
+    // the context and output buffer are released below
+    let major = unsafe {
+      init_sec_context(
+        &mut minor,
+        null_mut(),
+        &mut ctx,
+        name,
+        &mut mech,
+        0,
+        0,
+        null_mut(),
+        null_mut(),
+        null_mut(),
+        &mut output,
+        null_mut(),
+        null_mut(),
+      )
+    };
+
+    let result = if major & GSS_ERROR_MASK != 0 {
+      Err(anyhow::anyhow!(
+        "Failed to get a Kerberos ticket for HTTP/{}: {}",
+        host,
+        self.status_message(major, minor)
+      ))
+    } else if major != GSS_S_COMPLETE && major != GSS_S_CONTINUE_NEEDED {
+      Err(anyhow::anyhow!("Unexpected GSSAPI status {:#x}", major))
+    } else {
+      // SAFETY: on success the library filled `output`
+      Ok(unsafe { output.to_vec() })
+    };
+
+    let mut ignored = 0;
+    // SAFETY: releasing the handles allocated above, null handles are accepted
+    unsafe {
+      release_buffer(&mut ignored, &mut output);
+      if !ctx.is_null() {
+        delete_sec_context(&mut ignored, &mut ctx, null_mut());
+      }
+      release_name(&mut ignored, &mut name);
+    }
+
+    let token = result?;
+    if token.is_empty() {
+      bail!("GSSAPI returned an empty token for HTTP/{}", host);
+    }
+
+    info!("Obtained a Kerberos service ticket for HTTP/{}", host);
+    Ok(token)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use reqwest::header::HeaderValue;
+
+  use super::*;
+
+  #[test]
+  fn detects_negotiate_challenge() {
+    let mut headers = HeaderMap::new();
+    assert!(!is_negotiate_challenge(&headers));
+
+    headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("Basic realm=\"portal\""));
+    assert!(!is_negotiate_challenge(&headers));
+
+    headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("negotiate"));
+    assert!(is_negotiate_challenge(&headers));
+  }
+
+  #[test]
+  fn builds_negotiate_header() {
+    assert_eq!(negotiate_header(b"\x60\x82token"), "Negotiate YIJ0b2tlbg==");
+  }
+
+  #[test]
+  fn service_name_is_host_based() {
+    assert_eq!(http_service_name("vpn.example.com"), "HTTP@vpn.example.com");
+  }
+}
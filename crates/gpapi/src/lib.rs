@@ -4,6 +4,7 @@ pub mod credential;
 pub mod error;
 pub mod gateway;
 pub mod gp_params;
+pub mod kerberos;
 pub mod log_format;
 pub mod os_profile;
 pub mod params;
@@ -19,5 +20,8 @@ pub mod logger;
 #[cfg(feature = "clap")]
 pub mod clap;
 
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+
 #[cfg(debug_assertions)]
 pub const GP_API_KEY: &[u8; 32] = &[0; 32];
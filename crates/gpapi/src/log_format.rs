@@ -49,16 +49,30 @@ impl LogFormat {
 /// env_logger::builder().format(write_json_record).init();
 /// ```
 pub fn write_json_record<W: Write>(w: &mut W, record: &log::Record) -> io::Result<()> {
-  writeln!(
-    w,
-    "{}",
-    json!({
-      "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
-      "level": record.level().as_str(),
-      "target": record.target(),
-      "message": record.args().to_string(),
-    })
-  )
+  writeln!(w, "{}", json_record(record))
+}
+
+/// Like [`write_json_record`], with structured data under `key` so consumers do
+/// not have to parse it back out of the message.
+pub fn write_json_record_with_data<W: Write>(
+  w: &mut W,
+  record: &log::Record,
+  key: &str,
+  data: serde_json::Value,
+) -> io::Result<()> {
+  let mut value = json_record(record);
+  value[key] = data;
+
+  writeln!(w, "{}", value)
+}
+
+fn json_record(record: &log::Record) -> serde_json::Value {
+  json!({
+    "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+    "level": record.level().as_str(),
+    "target": record.target(),
+    "message": record.args().to_string(),
+  })
 }
 
 #[cfg(test)]
@@ -143,6 +157,27 @@ mod tests {
     assert_eq!(parse(&out)["message"], "first\nsecond", "the newline must survive");
   }
 
+  /// Structured data sits next to the standard fields, it does not replace them.
+  #[test]
+  fn record_with_data_keeps_the_standard_fields() {
+    let mut buf = Vec::new();
+    write_json_record_with_data(
+      &mut buf,
+      &log::Record::builder()
+        .level(Level::Info)
+        .target("t")
+        .args(format_args!("tunnel up"))
+        .build(),
+      "tunnel",
+      json!({ "ipv4": "10.0.0.10" }),
+    )
+    .expect("writing to a Vec cannot fail");
+
+    let v = parse(&String::from_utf8(buf).unwrap());
+    assert_eq!(v["message"], "tunnel up");
+    assert_eq!(v["tunnel"]["ipv4"], "10.0.0.10");
+  }
+
   /// `as_str` is what gets passed to a child process on the command line, and
   /// clap parses that with the names from its own `ValueEnum` derive. If the
   /// two ever disagree, gpclient hands gpauth a value gpauth rejects.
@@ -174,7 +174,11 @@ mod platform {
 
   impl RuntimeNativeHostIdentity {
     pub(super) fn runtime_os() -> ClientOs {
-      ClientOs::Mac
+      if cfg!(target_arch = "aarch64") {
+        ClientOs::MacArm
+      } else {
+        ClientOs::Mac
+      }
     }
 
     pub(super) fn collect(host_id_override: Option<&str>) -> NativeHostIdentitySnapshot {
@@ -288,7 +292,11 @@ mod platform {
 
   impl RuntimeNativeHostIdentity {
     pub(super) fn runtime_os() -> ClientOs {
-      ClientOs::Windows
+      if cfg!(target_arch = "aarch64") {
+        ClientOs::WindowsArm
+      } else {
+        ClientOs::Windows
+      }
     }
 
     pub(super) fn collect(host_id_override: Option<&str>) -> NativeHostIdentitySnapshot {
@@ -0,0 +1,535 @@
+use std::{
+  collections::BTreeMap,
+  fs,
+  io::Write,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, bail};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{ClientOs, HostIdentity, ProfileHostIdentity};
+
+const STORE_VERSION: u32 = 1;
+
+/// The identity store of root, shared by gpservice and gpclient run with sudo
+pub const SYSTEM_IDENTITY_PATH: &str = "/var/lib/gpclient/identity.toml";
+
+/// The identity of a client OS projection, generated once and reused so the portal and the
+/// gateways see the same device on every run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct StoredIdentity {
+  pub computer: String,
+  pub host_id: String,
+  pub serialno: String,
+  pub mac_addr: String,
+  #[serde(default)]
+  pub created_at: u64,
+  /// The runtime identity the projection was derived from, the projected values are used as is for
+  /// an imported identity without one
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source: Option<StoredSource>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct StoredSource {
+  pub computer: String,
+  pub host_id: String,
+  pub serialno: String,
+  pub mac_addr: String,
+}
+
+impl StoredIdentity {
+  fn new(profile: &ProfileHostIdentity, source: &HostIdentity) -> Self {
+    Self {
+      computer: profile.computer().to_string(),
+      host_id: profile.host_id().to_string(),
+      serialno: profile.serialno().to_string(),
+      mac_addr: profile.mac_addr().to_string(),
+      created_at: now(),
+      source: Some(StoredSource {
+        computer: source.computer().to_string(),
+        host_id: source.host_id().to_string(),
+        serialno: source.serialno().to_string(),
+        mac_addr: source.mac_addr().to_string(),
+      }),
+    }
+  }
+
+  /// The runtime identity, i.e., the seed sent to gpauth and the key of the cookie cache
+  pub fn host_identity(&self) -> HostIdentity {
+    match &self.source {
+      Some(source) => HostIdentity::from_parts(
+        source.computer.clone(),
+        source.host_id.clone(),
+        source.serialno.clone(),
+        source.mac_addr.clone(),
+      ),
+      None => HostIdentity::from_parts(
+        self.computer.clone(),
+        self.host_id.clone(),
+        self.serialno.clone(),
+        self.mac_addr.clone(),
+      ),
+    }
+  }
+
+  pub(super) fn profile_identity(&self, computer_override: Option<String>) -> ProfileHostIdentity {
+    ProfileHostIdentity {
+      computer: computer_override
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| self.computer.clone()),
+      host_id: self.host_id.clone(),
+      serial_number: self.serialno.clone(),
+      mac_address: self.mac_addr.clone(),
+    }
+  }
+
+  fn validate(&self, client_os: ClientOs) -> anyhow::Result<()> {
+    let mut fields = vec![("computer", &self.computer), ("host-id", &self.host_id)];
+    // The mobile clients report no serial number
+    if !matches!(client_os, ClientOs::Ios | ClientOs::Android) {
+      fields.extend([("serialno", &self.serialno), ("mac-addr", &self.mac_addr)]);
+    }
+
+    if let Some((name, _)) = fields.iter().find(|(_, value)| value.trim().is_empty()) {
+      bail!("The identity has an empty `{}`", name);
+    }
+    Ok(())
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct IdentityStore {
+  version: u32,
+  #[serde(default)]
+  profiles: BTreeMap<String, StoredIdentity>,
+}
+
+impl Default for IdentityStore {
+  fn default() -> Self {
+    Self {
+      version: STORE_VERSION,
+      profiles: BTreeMap::new(),
+    }
+  }
+}
+
+/// The store of the current user, the system-wide one for root
+pub fn identity_path(custom: Option<&str>) -> PathBuf {
+  if let Some(p) = custom {
+    return PathBuf::from(p);
+  }
+  if uzers::get_effective_uid() == 0 {
+    return PathBuf::from(SYSTEM_IDENTITY_PATH);
+  }
+  let home = std::env::var("HOME").unwrap_or_else(|_| "/root".to_string());
+  PathBuf::from(home).join(".config/gpclient/identity.toml")
+}
+
+pub fn load(path: &Path, client_os: ClientOs) -> Option<StoredIdentity> {
+  read_store_or_default(path).profiles.remove(client_os.as_str())
+}
+
+/// The stored identities, ordered by client OS
+pub fn list(path: &Path) -> Vec<(ClientOs, StoredIdentity)> {
+  read_store_or_default(path)
+    .profiles
+    .into_iter()
+    .filter_map(|(os, stored)| match client_os_of(&os) {
+      Ok(client_os) => Some((client_os, stored)),
+      Err(err) => {
+        warn!("Ignoring a stored identity: {}", err);
+        None
+      }
+    })
+    .collect()
+}
+
+/// Save the identity of the client OS, an unreadable store is left as is for the user to fix or reset
+pub fn save(path: &Path, client_os: ClientOs, stored: &StoredIdentity) -> anyhow::Result<()> {
+  let mut store = read_store(path)?;
+  store.profiles.insert(client_os.as_str().to_string(), stored.clone());
+
+  write_store(path, &store)
+}
+
+/// Remove the identity of the client OS, or all of them, returning the number of removed entries.
+/// A new identity is generated on the next run.
+pub fn reset(path: &Path, client_os: Option<ClientOs>) -> anyhow::Result<usize> {
+  let Some(client_os) = client_os else {
+    // Also the way out of an unreadable store
+    let count = read_store(path).map_or(0, |store| store.profiles.len());
+    if path.exists() {
+      fs::remove_file(path)?;
+    }
+    return Ok(count);
+  };
+
+  let mut store = read_store(path)?;
+  let removed = store.profiles.remove(client_os.as_str()).map_or(0, |_| 1);
+  write_store(path, &store)?;
+
+  Ok(removed)
+}
+
+/// Import the identities of another store file, e.g., one exported from another machine, or a
+/// single identity table for `client_os`. Returns the number of imported entries.
+pub fn import(path: &Path, from: &Path, client_os: Option<ClientOs>) -> anyhow::Result<usize> {
+  let content = fs::read_to_string(from).with_context(|| format!("Failed to read {}", from.display()))?;
+  let value: toml::Table = toml::from_str(&content).with_context(|| format!("Failed to parse {}", from.display()))?;
+
+  let imported = if value.contains_key("profiles") {
+    let file: IdentityStore = value.try_into()?;
+    let mut imported = Vec::new();
+    for (os, stored) in file.profiles {
+      let os = client_os_of(&os).with_context(|| format!("Failed to import {}", from.display()))?;
+      if client_os.is_none_or(|client_os| os == client_os) {
+        imported.push((os, stored));
+      }
+    }
+    imported
+  } else {
+    let Some(client_os) = client_os else {
+      bail!(
+        "{} has a single identity, specify the client OS to import it for",
+        from.display()
+      );
+    };
+    vec![(client_os, value.try_into::<StoredIdentity>()?)]
+  };
+
+  for (os, stored) in &imported {
+    stored.validate(*os)?;
+  }
+
+  let mut store = read_store(path)?;
+  for (os, mut stored) in imported.iter().cloned() {
+    if stored.created_at == 0 {
+      stored.created_at = now();
+    }
+    store.profiles.insert(os.as_str().to_string(), stored);
+  }
+  write_store(path, &store)?;
+
+  Ok(imported.len())
+}
+
+/// The stored identity of the client OS, generated from the live host identity and saved on the
+/// first run. A host ID override that does not match the stored identity wins over it, e.g., the
+/// host ID gpauth reports after being started by another identity.
+pub(super) fn resolve(path: &Path, client_os: ClientOs, host_id_override: Option<&str>) -> Option<StoredIdentity> {
+  if let Some(stored) = load(path, client_os) {
+    let matches = host_id_override.is_none_or(|host_id| host_id == stored.host_identity().host_id());
+    return matches.then_some(stored);
+  }
+
+  // Only an identity collected from this host is worth persisting
+  if host_id_override.is_some() {
+    return None;
+  }
+
+  let host_identity = HostIdentity::collect();
+  let profile = ProfileHostIdentity::for_client_os(client_os, &host_identity, None);
+  let stored = StoredIdentity::new(&profile, &host_identity);
+
+  match save(path, client_os, &stored) {
+    Ok(()) => info!("Saved the {} host identity to {}", client_os.as_str(), path.display()),
+    Err(err) => warn!("Failed to save the host identity to {}: {}", path.display(), err),
+  }
+
+  Some(stored)
+}
+
+/// The client OS of a store key, the keys are written by `ClientOs::as_str`
+fn client_os_of(key: &str) -> anyhow::Result<ClientOs> {
+  let client_os = ClientOs::from(key);
+  if client_os.as_str() != key {
+    bail!("Unknown client OS `{}`", key);
+  }
+  Ok(client_os)
+}
+
+/// A missing store is empty, an unreadable one is an error so that it is not overwritten
+fn read_store(path: &Path) -> anyhow::Result<IdentityStore> {
+  let Ok(content) = fs::read_to_string(path) else {
+    return Ok(IdentityStore::default());
+  };
+
+  let unreadable = || format!("The identity store {} is unreadable, fix or reset it", path.display());
+  let store = toml::from_str::<IdentityStore>(&content).with_context(unreadable)?;
+  if store.version != STORE_VERSION {
+    bail!("{}: unsupported version {}", unreadable(), store.version);
+  }
+  Ok(store)
+}
+
+fn read_store_or_default(path: &Path) -> IdentityStore {
+  read_store(path).unwrap_or_else(|err| {
+    warn!("Ignoring the identity store: {:#}", err);
+    IdentityStore::default()
+  })
+}
+
+fn write_store(path: &Path, store: &IdentityStore) -> anyhow::Result<()> {
+  let parent = path
+    .parent()
+    .ok_or_else(|| anyhow::anyhow!("identity path has no parent directory"))?;
+  fs::create_dir_all(parent)?;
+
+  let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+  tmp.write_all(toml::to_string(store)?.as_bytes())?;
+  tmp.flush()?;
+  tmp.persist(path)?;
+  Ok(())
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn stored(host_id: &str) -> StoredIdentity {
+    StoredIdentity {
+      computer: "DESKTOP-TEST".to_string(),
+      host_id: host_id.to_string(),
+      serialno: "SERIAL-1".to_string(),
+      mac_addr: "aa-bb-cc-dd-ee-ff".to_string(),
+      created_at: 1,
+      source: Some(StoredSource {
+        computer: "test-host".to_string(),
+        host_id: "source-host-id".to_string(),
+        serialno: "source-serial".to_string(),
+        mac_addr: "01:02:03:04:05:06".to_string(),
+      }),
+    }
+  }
+
+  #[test]
+  fn save_and_load_round_trip_per_client_os() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+
+    save(&path, ClientOs::Windows, &stored("windows-host-id")).unwrap();
+    save(&path, ClientOs::Mac, &stored("mac-host-id")).unwrap();
+
+    assert_eq!(load(&path, ClientOs::Windows), Some(stored("windows-host-id")));
+    assert_eq!(load(&path, ClientOs::Mac).unwrap().host_id, "mac-host-id");
+    assert!(load(&path, ClientOs::Linux).is_none());
+
+    let listed = list(&path).into_iter().map(|(os, _)| os).collect::<Vec<_>>();
+    assert_eq!(listed, vec![ClientOs::Mac, ClientOs::Windows]);
+  }
+
+  #[test]
+  fn store_is_human_readable_toml() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+
+    save(&path, ClientOs::Windows, &stored("windows-host-id")).unwrap();
+
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(content.contains("[profiles.Windows]"));
+    assert!(content.contains("host-id = \"windows-host-id\""));
+    assert!(content.contains("[profiles.Windows.source]"));
+  }
+
+  #[test]
+  fn unreadable_store_is_ignored() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    fs::write(&path, "not = [valid").unwrap();
+
+    assert!(load(&path, ClientOs::Linux).is_none());
+    assert!(list(&path).is_empty());
+  }
+
+  #[test]
+  fn unreadable_store_is_not_overwritten() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    fs::write(&path, "not = [valid").unwrap();
+
+    assert!(save(&path, ClientOs::Windows, &stored("windows-host-id")).is_err());
+    assert!(reset(&path, Some(ClientOs::Windows)).is_err());
+    assert!(resolve(&path, ClientOs::Windows, None).is_some());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "not = [valid");
+
+    assert_eq!(reset(&path, None).unwrap(), 0);
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn resolve_generates_the_identity_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nested/identity.toml");
+
+    let first = resolve(&path, ClientOs::Windows, None).unwrap();
+    let saved = load(&path, ClientOs::Windows).unwrap();
+    assert_eq!(first, saved);
+
+    // A different identity in the store is reused as is
+    save(&path, ClientOs::Windows, &stored("windows-host-id")).unwrap();
+    let second = resolve(&path, ClientOs::Windows, None).unwrap();
+    assert_eq!(second.host_id, "windows-host-id");
+  }
+
+  #[test]
+  fn resolve_keeps_the_identity_for_its_own_host_id() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    save(&path, ClientOs::Windows, &stored("windows-host-id")).unwrap();
+
+    let same = resolve(&path, ClientOs::Windows, Some("source-host-id"));
+    assert_eq!(same.unwrap().host_id, "windows-host-id");
+
+    assert!(resolve(&path, ClientOs::Windows, Some("other-host-id")).is_none());
+  }
+
+  #[test]
+  fn resolve_does_not_persist_a_host_id_override() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+
+    assert!(resolve(&path, ClientOs::Linux, Some("override-host-id")).is_none());
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn identity_without_source_uses_projected_values() {
+    let mut identity = stored("imported-host-id");
+    identity.source = None;
+
+    let host_identity = identity.host_identity();
+    assert_eq!(host_identity.host_id(), "imported-host-id");
+    assert_eq!(host_identity.computer(), "DESKTOP-TEST");
+    assert_eq!(host_identity.serialno(), "SERIAL-1");
+  }
+
+  #[test]
+  fn profile_identity_applies_computer_override() {
+    let identity = stored("windows-host-id");
+
+    assert_eq!(identity.profile_identity(None).computer(), "DESKTOP-TEST");
+    assert_eq!(
+      identity.profile_identity(Some("override".to_string())).computer(),
+      "override"
+    );
+    assert_eq!(
+      identity.profile_identity(Some(" ".to_string())).computer(),
+      "DESKTOP-TEST"
+    );
+  }
+
+  #[test]
+  fn reset_removes_one_or_all_identities() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    save(&path, ClientOs::Windows, &stored("windows-host-id")).unwrap();
+    save(&path, ClientOs::Mac, &stored("mac-host-id")).unwrap();
+
+    assert_eq!(reset(&path, Some(ClientOs::Linux)).unwrap(), 0);
+    assert_eq!(reset(&path, Some(ClientOs::Mac)).unwrap(), 1);
+    assert!(load(&path, ClientOs::Mac).is_none());
+    assert!(load(&path, ClientOs::Windows).is_some());
+
+    assert_eq!(reset(&path, None).unwrap(), 1);
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn import_merges_another_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    let other = dir.path().join("other.toml");
+    save(&path, ClientOs::Linux, &stored("linux-host-id")).unwrap();
+    save(&other, ClientOs::Windows, &stored("windows-host-id")).unwrap();
+    save(&other, ClientOs::Mac, &stored("mac-host-id")).unwrap();
+
+    assert_eq!(import(&path, &other, Some(ClientOs::Mac)).unwrap(), 1);
+    assert!(load(&path, ClientOs::Windows).is_none());
+
+    assert_eq!(import(&path, &other, None).unwrap(), 2);
+    assert_eq!(load(&path, ClientOs::Windows).unwrap().host_id, "windows-host-id");
+    assert_eq!(load(&path, ClientOs::Linux).unwrap().host_id, "linux-host-id");
+  }
+
+  #[test]
+  fn import_reads_a_single_identity_for_the_client_os() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    let single = dir.path().join("single.toml");
+    fs::write(
+      &single,
+      "computer = \"DESKTOP-1\"\nhost-id = \"imported\"\nserialno = \"S1\"\nmac-addr = \"aa-bb-cc-dd-ee-ff\"\n",
+    )
+    .unwrap();
+
+    assert!(import(&path, &single, None).is_err());
+    assert_eq!(import(&path, &single, Some(ClientOs::Windows)).unwrap(), 1);
+
+    let imported = load(&path, ClientOs::Windows).unwrap();
+    assert_eq!(imported.host_id, "imported");
+    assert!(imported.source.is_none());
+    assert!(imported.created_at > 0);
+  }
+
+  #[test]
+  fn import_rejects_empty_fields() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    let single = dir.path().join("single.toml");
+    fs::write(
+      &single,
+      "computer = \"DESKTOP-1\"\nhost-id = \"\"\nserialno = \"S1\"\nmac-addr = \"aa-bb-cc-dd-ee-ff\"\n",
+    )
+    .unwrap();
+
+    let err = import(&path, &single, Some(ClientOs::Windows)).unwrap_err();
+    assert!(err.to_string().contains("host-id"));
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn import_accepts_a_mobile_identity_without_serial_number() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    let single = dir.path().join("single.toml");
+    fs::write(
+      &single,
+      "computer = \"iPhone\"\nhost-id = \"imported\"\nserialno = \"\"\nmac-addr = \"02:00:00:00:00:00\"\n",
+    )
+    .unwrap();
+
+    assert!(import(&path, &single, Some(ClientOs::Windows)).is_err());
+    assert_eq!(import(&path, &single, Some(ClientOs::Ios)).unwrap(), 1);
+    assert_eq!(load(&path, ClientOs::Ios).unwrap().host_id, "imported");
+  }
+
+  #[test]
+  fn import_rejects_an_unknown_client_os() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    let other = dir.path().join("other.toml");
+    fs::write(
+      &other,
+      "version = 1\n\n[profiles.Solaris]\ncomputer = \"DESKTOP-1\"\nhost-id = \"imported\"\n\
+       serialno = \"S1\"\nmac-addr = \"aa-bb-cc-dd-ee-ff\"\n",
+    )
+    .unwrap();
+
+    let err = import(&path, &other, None).unwrap_err();
+    assert!(format!("{err:#}").contains("Solaris"));
+    assert!(!path.exists());
+  }
+}
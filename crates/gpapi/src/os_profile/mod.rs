@@ -1,21 +1,38 @@
 pub mod host_identity;
+pub mod identity_store;
 mod serial_number;
 
 pub use host_identity::HostIdentity;
 
-use common::constants::{GP_CLIENT_VERSION_LINUX, GP_CLIENT_VERSION_MACOS, GP_CLIENT_VERSION_WINDOWS, GP_USER_AGENT};
+use std::path::PathBuf;
+
+use common::constants::{
+  GP_CLIENT_VERSION_ANDROID, GP_CLIENT_VERSION_IOS, GP_CLIENT_VERSION_LINUX, GP_CLIENT_VERSION_MACOS,
+  GP_CLIENT_VERSION_WINDOWS, GP_USER_AGENT,
+};
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
 const DEFAULT_MACOS_VERSION: &str = "13.4.0";
+// Apple Silicon Macs have only shipped with macOS 11 or later
+const DEFAULT_MACOS_ARM_VERSION: &str = "14.6.1";
 const DEFAULT_LINUX_DISTRO: &str = "Ubuntu 24.04.3 LTS";
 const DEFAULT_WINDOWS_DISTRO: &str = "Windows 11 Pro";
 const DEFAULT_WINDOWS_VERSION: &str = "10.0.22631.0";
+// Windows on ARM is only supported by the official client on Windows 11 24H2
+const DEFAULT_WINDOWS_ARM_VERSION: &str = "10.0.26100.0";
+const DEFAULT_IOS_VERSION: &str = "17.5.1";
+const DEFAULT_ANDROID_VERSION: &str = "14";
 const DEFAULT_LINUX_WEBVIEW_USER_AGENT: &str =
   "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/602.1 (KHTML, like Gecko) PanGPUI Version/10.0 Safari/602.1";
 const DEFAULT_MACOS_WEBVIEW_USER_AGENT: &str =
   "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko)";
 const DEFAULT_WINDOWS_WEBVIEW_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/144.0.0.0 Safari/537.36 Edg/144.0.0.0";
+const DEFAULT_IOS_WEBVIEW_USER_AGENT: &str =
+  "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148";
+const DEFAULT_ANDROID_WEBVIEW_USER_AGENT: &str = "Mozilla/5.0 (Linux; Android 14; K; wv) AppleWebKit/537.36 (KHTML, like Gecko) Version/4.0 Chrome/124.0.6367.179 Mobile Safari/537.36";
+// Mobile clients report a fixed, locally administered MAC address, the OS hides the real one from apps
+const MOBILE_MAC_ADDRESS: &str = "02:00:00:00:00:00";
 
 // ─── ClientOs ────────────────────────────────────────────────────────────────
 
@@ -24,6 +41,12 @@ pub enum ClientOs {
   Linux,
   Windows,
   Mac,
+  /// macOS on Apple Silicon
+  MacArm,
+  /// Windows on ARM64
+  WindowsArm,
+  Ios,
+  Android,
 }
 
 impl From<&str> for ClientOs {
@@ -32,6 +55,10 @@ impl From<&str> for ClientOs {
       "Linux" => ClientOs::Linux,
       "Windows" => ClientOs::Windows,
       "Mac" => ClientOs::Mac,
+      "MacArm" => ClientOs::MacArm,
+      "WindowsArm" => ClientOs::WindowsArm,
+      "iOS" => ClientOs::Ios,
+      "Android" => ClientOs::Android,
       _ => ClientOs::Linux,
     }
   }
@@ -43,22 +70,43 @@ impl ClientOs {
       ClientOs::Linux => "Linux",
       ClientOs::Windows => "Windows",
       ClientOs::Mac => "Mac",
+      ClientOs::MacArm => "MacArm",
+      ClientOs::WindowsArm => "WindowsArm",
+      ClientOs::Ios => "iOS",
+      ClientOs::Android => "Android",
+    }
+  }
+
+  /// The `clientos` value sent to the portal and gateway, the official ARM
+  /// clients report the same value as their x86 counterparts.
+  pub fn clientos(&self) -> &'static str {
+    match self {
+      ClientOs::Linux => "Linux",
+      ClientOs::Windows | ClientOs::WindowsArm => "Windows",
+      ClientOs::Mac | ClientOs::MacArm => "Mac",
+      ClientOs::Ios => "iOS",
+      ClientOs::Android => "Android",
     }
   }
 
+  /// The OS reported by openconnect, which has no ARM variants
   pub fn to_openconnect_os(&self) -> &str {
     match self {
       ClientOs::Linux => "linux",
-      ClientOs::Windows => "win",
-      ClientOs::Mac => "mac-intel",
+      ClientOs::Windows | ClientOs::WindowsArm => "win",
+      ClientOs::Mac | ClientOs::MacArm => "mac-intel",
+      ClientOs::Ios => "apple-ios",
+      ClientOs::Android => "android",
     }
   }
 
   pub fn default_client_version(&self) -> &'static str {
     match self {
       ClientOs::Linux => GP_CLIENT_VERSION_LINUX,
-      ClientOs::Windows => GP_CLIENT_VERSION_WINDOWS,
-      ClientOs::Mac => GP_CLIENT_VERSION_MACOS,
+      ClientOs::Windows | ClientOs::WindowsArm => GP_CLIENT_VERSION_WINDOWS,
+      ClientOs::Mac | ClientOs::MacArm => GP_CLIENT_VERSION_MACOS,
+      ClientOs::Ios => GP_CLIENT_VERSION_IOS,
+      ClientOs::Android => GP_CLIENT_VERSION_ANDROID,
     }
   }
 
@@ -68,8 +116,17 @@ impl ClientOs {
 
   pub(crate) fn default_csc_support(&self) -> bool {
     match self {
-      ClientOs::Linux => false,
-      ClientOs::Mac | ClientOs::Windows => true,
+      ClientOs::Linux | ClientOs::Ios | ClientOs::Android => false,
+      ClientOs::Mac | ClientOs::MacArm | ClientOs::Windows | ClientOs::WindowsArm => true,
+    }
+  }
+
+  /// The desktop OS an ARM profile runs, the mobile profiles are their own
+  pub(crate) fn platform(&self) -> ClientOs {
+    match self {
+      ClientOs::MacArm => ClientOs::Mac,
+      ClientOs::WindowsArm => ClientOs::Windows,
+      client_os => *client_os,
     }
   }
 }
@@ -116,43 +173,59 @@ mod target {
   pub(super) fn os_version(client_os: ClientOs) -> String {
     match client_os {
       ClientOs::Linux => linux_os_string(),
-      ClientOs::Windows => windows_os_string(),
-      ClientOs::Mac => macos_os_string(),
+      ClientOs::Windows | ClientOs::WindowsArm => windows_os_string(),
+      ClientOs::Mac => macos_os_string(DEFAULT_MACOS_VERSION),
+      ClientOs::MacArm => macos_os_string(DEFAULT_MACOS_ARM_VERSION),
+      ClientOs::Ios => format!("Apple iOS {DEFAULT_IOS_VERSION}"),
+      ClientOs::Android => format!("Android {DEFAULT_ANDROID_VERSION}"),
     }
   }
 
   pub(super) fn software_version(client_os: ClientOs) -> String {
     match client_os {
       ClientOs::Linux => String::new(),
-      ClientOs::Windows => windows_version(),
-      ClientOs::Mac => macos_version(),
+      ClientOs::Windows => windows_version(DEFAULT_WINDOWS_VERSION),
+      ClientOs::WindowsArm => windows_version(DEFAULT_WINDOWS_ARM_VERSION),
+      ClientOs::Mac => macos_version(DEFAULT_MACOS_VERSION),
+      ClientOs::MacArm => macos_version(DEFAULT_MACOS_ARM_VERSION),
+      ClientOs::Ios => DEFAULT_IOS_VERSION.to_string(),
+      ClientOs::Android => DEFAULT_ANDROID_VERSION.to_string(),
     }
   }
 
   pub(super) fn prelogin_param_location(client_os: ClientOs) -> PreloginParamLocation {
     match client_os {
-      ClientOs::Linux | ClientOs::Mac => PreloginParamLocation::Body,
-      ClientOs::Windows => PreloginParamLocation::Query,
+      ClientOs::Linux | ClientOs::Mac | ClientOs::MacArm | ClientOs::Ios | ClientOs::Android => {
+        PreloginParamLocation::Body
+      }
+      ClientOs::Windows | ClientOs::WindowsArm => PreloginParamLocation::Query,
     }
   }
 
   pub(super) fn saml_password(client_os: ClientOs) -> &'static str {
     match client_os {
       ClientOs::Linux => "SAMLPASS",
-      ClientOs::Mac | ClientOs::Windows => "",
+      ClientOs::Mac
+      | ClientOs::MacArm
+      | ClientOs::Windows
+      | ClientOs::WindowsArm
+      | ClientOs::Ios
+      | ClientOs::Android => "",
     }
   }
 
   pub(super) fn external_browser_os_value(client_os: ClientOs) -> &'static str {
     match client_os {
       ClientOs::Linux => "4",
-      ClientOs::Mac => "3",
-      ClientOs::Windows => "2",
+      ClientOs::Mac | ClientOs::MacArm => "3",
+      ClientOs::Windows | ClientOs::WindowsArm => "2",
+      ClientOs::Ios => "5",
+      ClientOs::Android => "6",
     }
   }
 
   pub(super) fn supports_macos_plist_csc(client_os: ClientOs) -> bool {
-    matches!(client_os, ClientOs::Mac)
+    matches!(client_os, ClientOs::Mac | ClientOs::MacArm)
   }
 
   pub(super) fn supports_linux_process_csc(client_os: ClientOs) -> bool {
@@ -160,36 +233,47 @@ mod target {
   }
 
   pub(super) fn supports_windows_registry_csc(client_os: ClientOs) -> bool {
-    matches!(client_os, ClientOs::Windows)
+    matches!(client_os, ClientOs::Windows | ClientOs::WindowsArm)
   }
 
+  /// As the official clients send it, the prelogin adds it for Linux too when the GSSAPI library
+  /// is available, see `PreloginOptions::kerberos`
   pub(super) fn kerberos_support_in_query(client_os: ClientOs) -> bool {
     match client_os {
-      ClientOs::Linux => false,
-      ClientOs::Mac | ClientOs::Windows => true,
+      ClientOs::Linux | ClientOs::Ios | ClientOs::Android => false,
+      ClientOs::Mac | ClientOs::MacArm | ClientOs::Windows | ClientOs::WindowsArm => true,
     }
   }
 
   pub(super) fn os_vendor(client_os: ClientOs) -> &'static str {
     match client_os {
       ClientOs::Linux => "Linux",
-      ClientOs::Mac => "Apple",
-      ClientOs::Windows => "Microsoft",
+      ClientOs::Mac | ClientOs::MacArm | ClientOs::Ios => "Apple",
+      ClientOs::Windows | ClientOs::WindowsArm => "Microsoft",
+      ClientOs::Android => "Google",
     }
   }
 
   pub(super) fn webview_user_agent_default(client_os: ClientOs) -> &'static str {
     match client_os {
       ClientOs::Linux => DEFAULT_LINUX_WEBVIEW_USER_AGENT,
-      ClientOs::Mac => DEFAULT_MACOS_WEBVIEW_USER_AGENT,
-      ClientOs::Windows => DEFAULT_WINDOWS_WEBVIEW_USER_AGENT,
+      // Safari reports an Intel Mac on Apple Silicon too
+      ClientOs::Mac | ClientOs::MacArm => DEFAULT_MACOS_WEBVIEW_USER_AGENT,
+      ClientOs::Windows | ClientOs::WindowsArm => DEFAULT_WINDOWS_WEBVIEW_USER_AGENT,
+      ClientOs::Ios => DEFAULT_IOS_WEBVIEW_USER_AGENT,
+      ClientOs::Android => DEFAULT_ANDROID_WEBVIEW_USER_AGENT,
     }
   }
 
   pub(super) fn webview_user_agent_transform(client_os: ClientOs) -> WebviewUserAgentTransform {
     match client_os {
       ClientOs::Linux => WebviewUserAgentTransform::LinuxPanGpuiVersion,
-      ClientOs::Mac | ClientOs::Windows => WebviewUserAgentTransform::None,
+      ClientOs::Mac
+      | ClientOs::MacArm
+      | ClientOs::Windows
+      | ClientOs::WindowsArm
+      | ClientOs::Ios
+      | ClientOs::Android => WebviewUserAgentTransform::None,
     }
   }
 }
@@ -263,6 +347,17 @@ impl OsProfile {
     &self.client_version
   }
 
+  /// The profile reporting another client version, e.g., the one a portal with a minimum version
+  /// policy accepts. A custom user agent is kept.
+  pub fn with_client_version(mut self, version: impl Into<String>) -> Self {
+    let version = version.into();
+    if self.user_agent == default_user_agent(&self.client_version, &self.os_version) {
+      self.user_agent = default_user_agent(&version, &self.os_version);
+    }
+    self.client_version = version;
+    self
+  }
+
   pub fn host_identity(&self) -> &HostIdentity {
     &self.host_identity
   }
@@ -297,12 +392,13 @@ impl OsProfile {
     self.profile_identity.mac_addr()
   }
 
-  /// Returns true when the target client OS matches the runtime OS.
+  /// Returns true when the target client OS matches the runtime OS, an ARM
+  /// profile matches its x86 counterpart.
   ///
   /// Used to decide whether to consult real OS-level state (e.g. the actual
   /// network interface) versus simulated values for emulation.
   pub fn is_native(&self) -> bool {
-    runtime_client_os() == self.client_os
+    runtime_client_os().platform() == self.client_os.platform()
   }
 
   /// Default software version string for the target OS, matching the
@@ -369,6 +465,7 @@ pub struct OsProfileBuilder {
   computer_name_override: Option<String>,
   host_id_override: Option<String>,
   host_identity: Option<HostIdentity>,
+  identity_store: Option<PathBuf>,
   user_agent: Option<String>,
 }
 
@@ -380,6 +477,7 @@ impl OsProfileBuilder {
       computer_name_override: None,
       host_id_override: None,
       host_identity: None,
+      identity_store: None,
       user_agent: None,
     }
   }
@@ -399,6 +497,13 @@ impl OsProfileBuilder {
     self
   }
 
+  /// Reuse the identity persisted in the store, generating it on the first run, instead of
+  /// collecting the host identity every time
+  pub fn identity_store(mut self, path: impl Into<PathBuf>) -> Self {
+    self.identity_store = Some(path.into());
+    self
+  }
+
   pub fn host_id_override(mut self, host_id: impl Into<String>) -> Self {
     let host_id = host_id.into();
     self.host_id_override = if host_id.trim().is_empty() { None } else { Some(host_id) };
@@ -417,19 +522,32 @@ impl OsProfileBuilder {
       .filter(|v| !v.trim().is_empty())
       .unwrap_or_else(|| self.client_os.default_client_version().to_string());
 
-    let host_identity = match (self.host_identity, self.host_id_override) {
-      (Some(identity), _) => identity,
-      (None, host_id) => HostIdentity::collect_with_host_id(host_id.as_deref()),
+    let stored = match (&self.host_identity, &self.identity_store) {
+      (None, Some(path)) => identity_store::resolve(path, self.client_os, self.host_id_override.as_deref()),
+      _ => None,
+    };
+    let (host_identity, profile_identity) = match stored {
+      Some(stored) => (
+        stored.host_identity(),
+        stored.profile_identity(self.computer_name_override),
+      ),
+      None => {
+        let host_identity = match (self.host_identity, self.host_id_override) {
+          (Some(identity), _) => identity,
+          (None, host_id) => HostIdentity::collect_with_host_id(host_id.as_deref()),
+        };
+        let profile_identity =
+          ProfileHostIdentity::for_client_os(self.client_os, &host_identity, self.computer_name_override);
+        (host_identity, profile_identity)
+      }
     };
-    let profile_identity =
-      ProfileHostIdentity::for_client_os(self.client_os, &host_identity, self.computer_name_override);
     let os_version = target::os_version(self.client_os);
     let software_version = target::software_version(self.client_os);
     let os_vendor = target::os_vendor(self.client_os);
 
     let user_agent = self
       .user_agent
-      .unwrap_or_else(|| format!("{}/{} ({})", GP_USER_AGENT, client_version, os_version));
+      .unwrap_or_else(|| default_user_agent(&client_version, &os_version));
 
     OsProfile {
       client_os: self.client_os,
@@ -460,6 +578,10 @@ pub fn default_software_version(client_os: &ClientOs) -> String {
   target::software_version(*client_os)
 }
 
+fn default_user_agent(client_version: &str, os_version: &str) -> String {
+  format!("{}/{} ({})", GP_USER_AGENT, client_version, os_version)
+}
+
 pub fn runtime_client_os() -> ClientOs {
   HostIdentity::runtime_os()
 }
@@ -472,19 +594,19 @@ fn linux_os_string() -> String {
   }
 }
 
-fn windows_version() -> String {
-  if runtime_client_os() == ClientOs::Windows {
+fn windows_version(default_version: &str) -> String {
+  if runtime_client_os().platform() == ClientOs::Windows {
     match os_info::get().version() {
-      os_info::Version::Unknown => DEFAULT_WINDOWS_VERSION.to_string(),
+      os_info::Version::Unknown => default_version.to_string(),
       v => format!("{}.0", v),
     }
   } else {
-    DEFAULT_WINDOWS_VERSION.to_string()
+    default_version.to_string()
   }
 }
 
 fn windows_os_string() -> String {
-  if runtime_client_os() == ClientOs::Windows {
+  if runtime_client_os().platform() == ClientOs::Windows {
     let edition = os_info::get()
       .edition()
       .map(|e| e.to_string())
@@ -499,19 +621,19 @@ fn runtime_distro_or(fallback: &str) -> String {
   whoami::distro().unwrap_or_else(|_| fallback.to_string())
 }
 
-fn macos_version() -> String {
-  if runtime_client_os() == ClientOs::Mac {
+fn macos_version(default_version: &str) -> String {
+  if runtime_client_os().platform() == ClientOs::Mac {
     match os_info::get().version() {
-      os_info::Version::Unknown => DEFAULT_MACOS_VERSION.to_string(),
+      os_info::Version::Unknown => default_version.to_string(),
       v => v.to_string(),
     }
   } else {
-    DEFAULT_MACOS_VERSION.to_string()
+    default_version.to_string()
   }
 }
 
-fn macos_os_string() -> String {
-  format!("Apple Mac OS X {}", macos_version())
+fn macos_os_string(default_version: &str) -> String {
+  format!("Apple Mac OS X {}", macos_version(default_version))
 }
 
 fn derive_uuid_from_seed(seed: &str, parts: &[&str]) -> String {
@@ -534,8 +656,8 @@ fn derived_profile_mac(seed: &str, client_os: ClientOs) -> String {
     .collect::<Vec<_>>();
 
   match client_os {
-    ClientOs::Linux | ClientOs::Windows => bytes.join("-"),
-    ClientOs::Mac => bytes.join(":"),
+    ClientOs::Linux | ClientOs::Windows | ClientOs::WindowsArm => bytes.join("-"),
+    ClientOs::Mac | ClientOs::MacArm | ClientOs::Ios | ClientOs::Android => bytes.join(":"),
   }
 }
 
@@ -553,14 +675,16 @@ mod target_identity {
     host_identity: &HostIdentity,
     computer_override: Option<String>,
   ) -> ProfileHostIdentity {
-    if client_os == runtime_client_os() {
+    if client_os.platform() == runtime_client_os().platform() {
       return native::project(client_os, host_identity, computer_override);
     }
 
     match client_os {
       ClientOs::Linux => linux::project(host_identity, computer_override),
-      ClientOs::Windows => windows::project(host_identity, computer_override),
-      ClientOs::Mac => macos::project(host_identity, computer_override),
+      ClientOs::Windows | ClientOs::WindowsArm => windows::project(client_os, host_identity, computer_override),
+      ClientOs::Mac | ClientOs::MacArm => macos::project(client_os, host_identity, computer_override),
+      ClientOs::Ios => ios::project(host_identity, computer_override),
+      ClientOs::Android => android::project(host_identity, computer_override),
     }
   }
 
@@ -572,8 +696,8 @@ mod target_identity {
 
   fn profile_mac_for(client_os: ClientOs, mac_address: &str) -> String {
     match client_os {
-      ClientOs::Linux | ClientOs::Windows => mac_address.replace(':', "-"),
-      ClientOs::Mac => mac_address.replace('-', ":"),
+      ClientOs::Linux | ClientOs::Windows | ClientOs::WindowsArm => mac_address.replace(':', "-"),
+      ClientOs::Mac | ClientOs::MacArm | ClientOs::Ios | ClientOs::Android => mac_address.replace('-', ":"),
     }
   }
 
@@ -588,8 +712,10 @@ mod target_identity {
       ProfileHostIdentity {
         computer: computer(host_identity, computer_override),
         host_id: match client_os {
-          ClientOs::Mac => host_identity.host_id().replace('-', ":"),
-          ClientOs::Linux | ClientOs::Windows => host_identity.host_id().to_string(),
+          ClientOs::Mac | ClientOs::MacArm | ClientOs::Ios | ClientOs::Android => {
+            host_identity.host_id().replace('-', ":")
+          }
+          ClientOs::Linux | ClientOs::Windows | ClientOs::WindowsArm => host_identity.host_id().to_string(),
         },
         serial_number: host_identity.serialno().to_string(),
         mac_address: profile_mac_for(client_os, host_identity.mac_addr()),
@@ -615,14 +741,18 @@ mod target_identity {
   mod windows {
     use super::*;
 
-    pub(super) fn project(host_identity: &HostIdentity, computer_override: Option<String>) -> ProfileHostIdentity {
+    pub(super) fn project(
+      client_os: ClientOs,
+      host_identity: &HostIdentity,
+      computer_override: Option<String>,
+    ) -> ProfileHostIdentity {
       let seed = host_identity.host_id();
       ProfileHostIdentity {
         computer: computer(host_identity, computer_override),
-        host_id: derive_uuid_from_seed(seed, &["os-profile", ClientOs::Windows.as_str(), "host-id"]),
-        serial_number: serial_number::vmware_from_compact_hex(&compact_uuid(seed, ClientOs::Windows, "serialno"))
+        host_id: derive_uuid_from_seed(seed, &["os-profile", client_os.as_str(), "host-id"]),
+        serial_number: serial_number::vmware_from_compact_hex(&compact_uuid(seed, client_os, "serialno"))
           .expect("projected Windows serial seed should be compact hex"),
-        mac_address: derived_profile_mac(seed, ClientOs::Windows),
+        mac_address: derived_profile_mac(seed, client_os),
       }
     }
   }
@@ -630,18 +760,54 @@ mod target_identity {
   mod macos {
     use super::*;
 
-    pub(super) fn project(host_identity: &HostIdentity, computer_override: Option<String>) -> ProfileHostIdentity {
+    pub(super) fn project(
+      client_os: ClientOs,
+      host_identity: &HostIdentity,
+      computer_override: Option<String>,
+    ) -> ProfileHostIdentity {
       let seed = host_identity.host_id();
-      let mac_address = derived_profile_mac(seed, ClientOs::Mac);
+      let mac_address = derived_profile_mac(seed, client_os);
       ProfileHostIdentity {
         computer: computer(host_identity, computer_override),
         host_id: mac_address.clone(),
-        serial_number: compact_uuid(seed, ClientOs::Mac, "serialno").chars().take(10).collect(),
+        serial_number: compact_uuid(seed, client_os, "serialno").chars().take(10).collect(),
         mac_address,
       }
     }
   }
 
+  /// The app's identifier-for-vendor is the host ID, iOS exposes neither a
+  /// serial number nor the MAC address to apps
+  mod ios {
+    use super::*;
+
+    pub(super) fn project(host_identity: &HostIdentity, computer_override: Option<String>) -> ProfileHostIdentity {
+      let seed = host_identity.host_id();
+      ProfileHostIdentity {
+        computer: computer(host_identity, computer_override),
+        host_id: derive_uuid_from_seed(seed, &["os-profile", ClientOs::Ios.as_str(), "host-id"]).to_uppercase(),
+        serial_number: String::new(),
+        mac_address: MOBILE_MAC_ADDRESS.to_string(),
+      }
+    }
+  }
+
+  /// The 64-bit Android ID is the host ID, Android exposes neither a serial
+  /// number nor the MAC address to apps
+  mod android {
+    use super::*;
+
+    pub(super) fn project(host_identity: &HostIdentity, computer_override: Option<String>) -> ProfileHostIdentity {
+      let seed = host_identity.host_id();
+      ProfileHostIdentity {
+        computer: computer(host_identity, computer_override),
+        host_id: compact_uuid(seed, ClientOs::Android, "host-id").to_lowercase()[..16].to_string(),
+        serial_number: String::new(),
+        mac_address: MOBILE_MAC_ADDRESS.to_string(),
+      }
+    }
+  }
+
   #[cfg(test)]
   mod tests {
     use super::*;
@@ -908,6 +1074,187 @@ mod tests {
     );
   }
 
+  // ─── ARM and Mobile Profile Tests ───────────────────────────────────────────
+
+  fn emulated_profile(client_os: ClientOs) -> OsProfile {
+    OsProfileBuilder::new(client_os)
+      .host_identity(HostIdentity::new(
+        "real-computer".to_string(),
+        "real-host-id".to_string(),
+        "real-serial".to_string(),
+        "aa:bb:cc:dd:ee:ff".to_string(),
+      ))
+      .build()
+  }
+
+  #[test]
+  fn arm_profiles_report_their_desktop_os_on_the_wire() {
+    assert_eq!(ClientOs::MacArm.clientos(), "Mac");
+    assert_eq!(ClientOs::MacArm.to_openconnect_os(), "mac-intel");
+    assert_eq!(ClientOs::MacArm.default_client_version(), GP_CLIENT_VERSION_MACOS);
+    assert_eq!(ClientOs::MacArm.platform(), ClientOs::Mac);
+
+    assert_eq!(ClientOs::WindowsArm.clientos(), "Windows");
+    assert_eq!(ClientOs::WindowsArm.to_openconnect_os(), "win");
+    assert_eq!(ClientOs::WindowsArm.default_client_version(), GP_CLIENT_VERSION_WINDOWS);
+    assert_eq!(ClientOs::WindowsArm.platform(), ClientOs::Windows);
+  }
+
+  #[test]
+  fn mobile_profiles_report_their_own_os_on_the_wire() {
+    assert_eq!(ClientOs::Ios.clientos(), "iOS");
+    assert_eq!(ClientOs::Ios.to_openconnect_os(), "apple-ios");
+    assert_eq!(ClientOs::Ios.default_client_version(), GP_CLIENT_VERSION_IOS);
+    assert_eq!(ClientOs::Ios.platform(), ClientOs::Ios);
+
+    assert_eq!(ClientOs::Android.clientos(), "Android");
+    assert_eq!(ClientOs::Android.to_openconnect_os(), "android");
+    assert_eq!(ClientOs::Android.default_client_version(), GP_CLIENT_VERSION_ANDROID);
+    assert_eq!(ClientOs::Android.platform(), ClientOs::Android);
+  }
+
+  #[test]
+  fn client_os_names_round_trip() {
+    for client_os in [
+      ClientOs::Linux,
+      ClientOs::Windows,
+      ClientOs::Mac,
+      ClientOs::MacArm,
+      ClientOs::WindowsArm,
+      ClientOs::Ios,
+      ClientOs::Android,
+    ] {
+      assert_eq!(ClientOs::from(client_os.as_str()), client_os);
+    }
+  }
+
+  #[test]
+  fn arm_profiles_behave_like_their_desktop_os() {
+    let mac_arm = emulated_profile(ClientOs::MacArm);
+    assert_eq!(mac_arm.prelogin_param_location(), PreloginParamLocation::Body);
+    assert_eq!(mac_arm.portal_default_browser(PreloginBrowserMode::External), "3");
+    assert_eq!(mac_arm.saml_password(), "");
+    assert!(mac_arm.kerberos_support_in_query());
+    assert!(mac_arm.supports_macos_plist_csc());
+    assert!(ClientOs::MacArm.default_csc_support());
+    assert_eq!(mac_arm.os_vendor(), "Apple");
+
+    let windows_arm = emulated_profile(ClientOs::WindowsArm);
+    assert_eq!(windows_arm.prelogin_param_location(), PreloginParamLocation::Query);
+    assert_eq!(windows_arm.portal_default_browser(PreloginBrowserMode::External), "2");
+    assert_eq!(windows_arm.saml_password(), "");
+    assert!(windows_arm.kerberos_support_in_query());
+    assert!(windows_arm.supports_windows_registry_csc());
+    assert!(ClientOs::WindowsArm.default_csc_support());
+    assert_eq!(windows_arm.os_vendor(), "Microsoft");
+  }
+
+  #[test]
+  fn mobile_profiles_match_official_apps() {
+    for (client_os, vendor, external_browser) in [(ClientOs::Ios, "Apple", "5"), (ClientOs::Android, "Google", "6")] {
+      let profile = emulated_profile(client_os);
+
+      assert_eq!(profile.prelogin_param_location(), PreloginParamLocation::Body);
+      assert_eq!(
+        profile.gateway_default_browser(PreloginBrowserMode::External),
+        external_browser
+      );
+      assert_eq!(profile.saml_password(), "");
+      assert!(!profile.kerberos_support_in_query());
+      assert!(!profile.supports_macos_plist_csc());
+      assert!(!profile.supports_linux_process_csc());
+      assert!(!profile.supports_windows_registry_csc());
+      assert!(!client_os.default_csc_support());
+      assert_eq!(profile.os_vendor(), vendor);
+      assert!(!profile.is_native());
+    }
+  }
+
+  #[test]
+  fn arm_and_mobile_user_agents_match_official_clients() {
+    assert_eq!(
+      emulated_profile(ClientOs::Ios).user_agent(),
+      format!("PAN GlobalProtect/{GP_CLIENT_VERSION_IOS} (Apple iOS 17.5.1)")
+    );
+    assert_eq!(
+      emulated_profile(ClientOs::Android).user_agent(),
+      format!("PAN GlobalProtect/{GP_CLIENT_VERSION_ANDROID} (Android 14)")
+    );
+
+    if runtime_client_os().platform() != ClientOs::Mac {
+      assert_eq!(
+        emulated_profile(ClientOs::MacArm).user_agent(),
+        format!("PAN GlobalProtect/{GP_CLIENT_VERSION_MACOS} (Apple Mac OS X 14.6.1)")
+      );
+      assert_eq!(emulated_profile(ClientOs::MacArm).software_version(), "14.6.1");
+    }
+    if runtime_client_os().platform() != ClientOs::Windows {
+      assert_eq!(
+        emulated_profile(ClientOs::WindowsArm).user_agent(),
+        format!("PAN GlobalProtect/{GP_CLIENT_VERSION_WINDOWS} (Microsoft Windows 11 Pro , 64-bit)")
+      );
+      assert_eq!(
+        emulated_profile(ClientOs::WindowsArm).software_version(),
+        "10.0.26100.0"
+      );
+    }
+  }
+
+  #[test]
+  fn mobile_webview_user_agents_are_projected() {
+    assert_eq!(
+      emulated_profile(ClientOs::Ios).webview_user_agent(),
+      WebviewUserAgent::Projected {
+        prefix: emulated_profile(ClientOs::Ios).user_agent().to_string(),
+        default_user_agent: DEFAULT_IOS_WEBVIEW_USER_AGENT.to_string()
+      }
+    );
+    assert_eq!(
+      emulated_profile(ClientOs::Android).webview_user_agent(),
+      WebviewUserAgent::Projected {
+        prefix: emulated_profile(ClientOs::Android).user_agent().to_string(),
+        default_user_agent: DEFAULT_ANDROID_WEBVIEW_USER_AGENT.to_string()
+      }
+    );
+  }
+
+  #[test]
+  fn mobile_identities_hide_serial_and_mac() {
+    let ios = emulated_profile(ClientOs::Ios);
+    assert_eq!(ios.host_id().len(), 36);
+    assert_eq!(ios.host_id(), ios.host_id().to_uppercase());
+    assert_eq!(ios.serialno(), "");
+    assert_eq!(ios.mac_addr(), MOBILE_MAC_ADDRESS);
+
+    let android = emulated_profile(ClientOs::Android);
+    assert_eq!(android.host_id().len(), 16);
+    assert!(
+      android
+        .host_id()
+        .chars()
+        .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+    );
+    assert_eq!(android.serialno(), "");
+    assert_eq!(android.mac_addr(), MOBILE_MAC_ADDRESS);
+  }
+
+  #[test]
+  fn arm_identities_differ_from_their_desktop_os() {
+    if runtime_client_os().platform() != ClientOs::Mac {
+      let mac = emulated_profile(ClientOs::Mac);
+      let mac_arm = emulated_profile(ClientOs::MacArm);
+      assert_ne!(mac.host_id(), mac_arm.host_id());
+      assert_eq!(mac_arm.serialno().len(), 10);
+      assert!(mac_arm.mac_addr().contains(':'));
+    }
+    if runtime_client_os().platform() != ClientOs::Windows {
+      let windows = emulated_profile(ClientOs::Windows);
+      let windows_arm = emulated_profile(ClientOs::WindowsArm);
+      assert_ne!(windows.host_id(), windows_arm.host_id());
+      assert!(windows_arm.mac_addr().contains('-'));
+    }
+  }
+
   // ─── User Agent Tests ───────────────────────────────────────────────────────
 
   #[test]
@@ -958,7 +1305,15 @@ mod tests {
 
   #[test]
   fn user_agent_derived_is_non_empty_for_all_client_os_variants() {
-    for client_os in [ClientOs::Linux, ClientOs::Windows, ClientOs::Mac] {
+    for client_os in [
+      ClientOs::Linux,
+      ClientOs::Windows,
+      ClientOs::Mac,
+      ClientOs::MacArm,
+      ClientOs::WindowsArm,
+      ClientOs::Ios,
+      ClientOs::Android,
+    ] {
       let profile = OsProfileBuilder::new(client_os).build();
       assert!(
         !profile.user_agent().is_empty(),
@@ -1003,7 +1358,7 @@ mod tests {
 
   #[test]
   fn projected_macos_webview_user_agent_uses_macos_default() {
-    if runtime_client_os() == ClientOs::Mac {
+    if runtime_client_os().platform() == ClientOs::Mac {
       return;
     }
 
@@ -1022,7 +1377,7 @@ mod tests {
 
   #[test]
   fn projected_windows_webview_user_agent_uses_windows_default() {
-    if runtime_client_os() == ClientOs::Windows {
+    if runtime_client_os().platform() == ClientOs::Windows {
       return;
     }
 
@@ -1081,9 +1436,9 @@ mod tests {
 
   #[test]
   fn builder_host_id_override_is_used_as_projected_identity_seed() {
-    let client_os = match runtime_client_os() {
+    let client_os = match runtime_client_os().platform() {
       ClientOs::Linux => ClientOs::Mac,
-      ClientOs::Mac | ClientOs::Windows => ClientOs::Linux,
+      _ => ClientOs::Linux,
     };
 
     let first = OsProfileBuilder::new(client_os)
@@ -1098,6 +1453,87 @@ mod tests {
     assert_ne!(first.host_id(), "provided-host-id");
   }
 
+  #[test]
+  fn with_client_version_updates_the_default_user_agent() {
+    let profile = OsProfileBuilder::new(ClientOs::Windows)
+      .client_version("6.1.0-10")
+      .build()
+      .with_client_version("6.3.0-33");
+
+    assert_eq!(profile.client_version(), "6.3.0-33");
+    assert!(profile.user_agent().contains("/6.3.0-33 ("));
+
+    let custom = OsProfileBuilder::new(ClientOs::Windows)
+      .user_agent("Custom/1.0")
+      .build()
+      .with_client_version("6.3.0-33");
+    assert_eq!(custom.user_agent(), "Custom/1.0");
+  }
+
+  #[test]
+  fn builder_prefers_the_stored_identity() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    let stored = identity_store::StoredIdentity {
+      computer: "DESKTOP-STORED".to_string(),
+      host_id: "stored-host-id".to_string(),
+      serialno: "STORED-SERIAL".to_string(),
+      mac_addr: "aa-bb-cc-dd-ee-ff".to_string(),
+      created_at: 1,
+      source: None,
+    };
+    identity_store::save(&path, ClientOs::Windows, &stored).unwrap();
+
+    let profile = OsProfileBuilder::new(ClientOs::Windows).identity_store(&path).build();
+    assert_eq!(profile.computer(), "DESKTOP-STORED");
+    assert_eq!(profile.host_id(), "stored-host-id");
+    assert_eq!(profile.serialno(), "STORED-SERIAL");
+    assert_eq!(profile.mac_addr(), "aa-bb-cc-dd-ee-ff");
+    assert_eq!(profile.host_identity().host_id(), "stored-host-id");
+
+    // The host ID gpauth echoes back keeps the stored identity
+    let profile = OsProfileBuilder::new(ClientOs::Windows)
+      .identity_store(&path)
+      .host_id_override("stored-host-id")
+      .computer_name_override("override")
+      .build();
+    assert_eq!(profile.host_id(), "stored-host-id");
+    assert_eq!(profile.computer(), "override");
+  }
+
+  #[test]
+  fn builder_generates_the_stored_identity_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+
+    let first = OsProfileBuilder::new(ClientOs::Mac).identity_store(&path).build();
+    let second = OsProfileBuilder::new(ClientOs::Mac).identity_store(&path).build();
+
+    assert!(identity_store::load(&path, ClientOs::Mac).is_some());
+    assert_eq!(first.host_id(), second.host_id());
+    assert_eq!(first.host_identity(), second.host_identity());
+  }
+
+  #[test]
+  fn builder_explicit_host_identity_ignores_the_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.toml");
+    let identity = HostIdentity::new(
+      "identity-computer".to_string(),
+      "explicit-host-id".to_string(),
+      "serial".to_string(),
+      "aa:bb:cc:dd:ee:ff".to_string(),
+    );
+
+    let profile = OsProfileBuilder::new(ClientOs::Linux)
+      .identity_store(&path)
+      .host_identity(identity)
+      .build();
+
+    assert_eq!(profile.host_identity().host_id(), "explicit-host-id");
+    assert!(!path.exists());
+  }
+
   #[test]
   fn builder_uses_explicit_values_when_provided() {
     let identity = HostIdentity::new(
@@ -1230,9 +1666,9 @@ mod tests {
   }
 
   fn non_native_client_os() -> ClientOs {
-    match runtime_client_os() {
+    match runtime_client_os().platform() {
       ClientOs::Linux => ClientOs::Mac,
-      ClientOs::Mac | ClientOs::Windows => ClientOs::Linux,
+      _ => ClientOs::Linux,
     }
   }
 
@@ -32,7 +32,7 @@ pub(crate) fn build(input: &GatewayLoginInput) -> RequestParams {
   body.push(("direct".into(), "yes".into()));
   body.push(("ipv6-support".into(), "yes".into()));
   body.push(("clientVer".into(), "4100".into()));
-  body.push(("clientos".into(), profile.client_os().as_str().into()));
+  body.push(("clientos".into(), profile.client_os().clientos().into()));
   body.push(("computer".into(), profile.computer().into()));
   body.push((
     "inputStr".into(),
@@ -527,4 +527,36 @@ mod tests {
     // Falls back to the original gateway host when DNS resolution fails
     assert_eq!(server, "nonexistent.invalid.test");
   }
+
+  #[test]
+  fn arm_and_mobile_profiles_send_official_clientos_and_os_version() {
+    let cred = make_credential();
+
+    for (client_os, clientos, os_version) in [
+      (ClientOs::MacArm, "Mac", None),
+      (ClientOs::WindowsArm, "Windows", None),
+      (ClientOs::Ios, "iOS", Some("Apple iOS 17.5.1")),
+      (ClientOs::Android, "Android", Some("Android 14")),
+    ] {
+      let gp_params = GpParams::builder(test_profile(client_os)).build();
+      let input = GatewayLoginInput {
+        gp_params: &gp_params,
+        cred: &cred,
+        gateway_host: "vpn.example.com",
+        context: None,
+        client_ip: None,
+        extend_lifetime: false,
+      };
+
+      let result = build(&input);
+
+      let find = |key: &str| result.body.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+      assert_eq!(find("clientos"), Some(clientos), "{client_os:?}");
+      assert_eq!(
+        find("os-version"),
+        Some(os_version.unwrap_or(gp_params.os_profile().os_version()))
+      );
+      assert_eq!(find("host-id"), Some(gp_params.os_profile().host_id()));
+    }
+  }
 }
@@ -10,7 +10,7 @@ pub(crate) fn build(profile: &OsProfile, browser_mode: PreloginBrowserMode) -> R
   let params: Vec<(String, String)> = vec![
     ("tmp".into(), "tmp".into()),
     ("clientVer".into(), "4100".into()),
-    ("clientos".into(), profile.client_os().as_str().into()),
+    ("clientos".into(), profile.client_os().clientos().into()),
     ("os-version".into(), profile.os_version().into()),
     ("host-id".into(), profile.host_id().into()),
     ("ipv6-support".into(), "yes".into()),
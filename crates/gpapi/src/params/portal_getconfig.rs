@@ -25,7 +25,7 @@ pub(crate) fn build(cred: &Credential, gp_params: &GpParams, server: &str) -> Re
   body.push(("clientVer".into(), "4100".into()));
 
   // OS params
-  body.push(("clientos".into(), profile.client_os().as_str().into()));
+  body.push(("clientos".into(), profile.client_os().clientos().into()));
   body.push(("clientgpversion".into(), profile.client_version().into()));
   body.push(("computer".into(), profile.computer().into()));
   body.push(("os-version".into(), profile.os_version().into()));
@@ -14,7 +14,7 @@ pub(crate) fn build(profile: &OsProfile, browser_mode: PreloginBrowserMode) -> R
   let params: Vec<(String, String)> = vec![
     ("tmp".into(), "tmp".into()),
     ("clientVer".into(), "4100".into()),
-    ("clientos".into(), profile.client_os().as_str().into()),
+    ("clientos".into(), profile.client_os().clientos().into()),
     ("os-version".into(), profile.os_version().into()),
     ("host-id".into(), profile.host_id().into()),
     ("ipv6-support".into(), "yes".into()),
@@ -144,4 +144,47 @@ mod tests {
     let has_kerberos = result.query.iter().any(|(k, _)| k == "kerberos-support");
     assert!(has_kerberos);
   }
+
+  fn param<'a>(params: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+  }
+
+  #[test]
+  fn mac_arm_sends_mac_params_in_body_with_kerberos_query() {
+    let profile = OsProfile::builder(ClientOs::MacArm).build();
+    let result = build(&profile, PreloginBrowserMode::External);
+
+    assert_eq!(param(&result.body, "clientos"), Some("Mac"));
+    assert_eq!(param(&result.body, "os-version"), Some(profile.os_version()));
+    assert_eq!(param(&result.body, "default-browser"), Some("3"));
+    assert_eq!(result.query, vec![("kerberos-support".into(), "yes".into())]);
+  }
+
+  #[test]
+  fn windows_arm_sends_windows_params_in_query() {
+    let profile = OsProfile::builder(ClientOs::WindowsArm).build();
+    let result = build(&profile, PreloginBrowserMode::External);
+
+    assert!(result.body.is_empty());
+    assert_eq!(param(&result.query, "clientos"), Some("Windows"));
+    assert_eq!(param(&result.query, "default-browser"), Some("2"));
+    assert_eq!(param(&result.query, "kerberos-support"), Some("yes"));
+    assert_eq!(result.query.len(), 10);
+  }
+
+  #[test]
+  fn mobile_profiles_send_params_in_body_without_kerberos() {
+    for (client_os, clientos, os_version) in [
+      (ClientOs::Ios, "iOS", "Apple iOS 17.5.1"),
+      (ClientOs::Android, "Android", "Android 14"),
+    ] {
+      let profile = OsProfile::builder(client_os).build();
+      let result = build(&profile, PreloginBrowserMode::Embedded);
+
+      assert_eq!(param(&result.body, "clientos"), Some(clientos));
+      assert_eq!(param(&result.body, "os-version"), Some(os_version));
+      assert_eq!(param(&result.body, "default-browser"), Some("-10"));
+      assert!(result.query.is_empty());
+    }
+  }
 }
@@ -0,0 +1,268 @@
+use anyhow::bail;
+use openssl::memcmp;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use xmltree::Element;
+
+use crate::utils::xml::ElementExt;
+
+/// Whether and how the user may disable the VPN, the `agent-user-override` of the portal
+#[derive(Debug, Serialize, Deserialize, Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisableOverride {
+  Allowed,
+  WithComment,
+  WithPasscode,
+  WithTicket,
+  Disabled,
+}
+
+impl DisableOverride {
+  fn parse(value: &str) -> Option<Self> {
+    match value.trim() {
+      "allowed" => Some(Self::Allowed),
+      "with-comment" => Some(Self::WithComment),
+      "with-passcode" => Some(Self::WithPasscode),
+      "with-ticket" => Some(Self::WithTicket),
+      "disabled" => Some(Self::Disabled),
+      _ => None,
+    }
+  }
+}
+
+/// The client agent settings the portal pushes in `<agent-ui>`
+#[derive(Debug, Serialize, Deserialize, Type, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AgentSettings {
+  /// `None` when the portal does not tell, the user may disable the VPN then
+  pub disable_override: Option<DisableOverride>,
+  /// The hash of the passcode to disable the VPN with `with-passcode`, the passcodes themselves are
+  /// not kept as the settings are cached and sent to the WS clients
+  pub passcode_hash: Option<String>,
+  pub uninstall_passcode_hash: Option<String>,
+  /// How long the VPN may stay disabled, in minutes
+  pub override_timeout_mins: Option<u32>,
+  /// How many times a day the user may disable the VPN
+  pub max_overrides: Option<u32>,
+  /// The page to show after connecting, when the portal asks to display one
+  pub welcome_page: Option<String>,
+  pub help_pages: Vec<String>,
+  /// Whether the VPN is established before the user logs on
+  pub connect_before_logon: Option<bool>,
+}
+
+/// What the user gives to disable a VPN the portal does not let them disable freely
+#[derive(Debug, Serialize, Deserialize, Type, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UserOverride {
+  #[serde(default)]
+  pub comment: Option<String>,
+  #[serde(default)]
+  pub passcode: Option<String>,
+}
+
+impl AgentSettings {
+  pub(crate) fn parse(root: &Element) -> Self {
+    let connect_before_logon = parse_yes_no(root.descendant_text("connect-before-logon"));
+    let Some(agent_ui) = root.descendant("agent-ui") else {
+      return Self {
+        connect_before_logon,
+        ..Default::default()
+      };
+    };
+
+    let welcome_page = agent_ui.child("welcome-page").and_then(|welcome_page| {
+      let display = parse_yes_no(welcome_page.child_text("display"))?;
+      non_empty(welcome_page.child_text("page")).filter(|_| display)
+    });
+
+    Self {
+      disable_override: agent_ui
+        .child_text("agent-user-override")
+        .and_then(|value| DisableOverride::parse(&value)),
+      passcode_hash: non_empty(agent_ui.child_text("passcode")).map(|passcode| hash_passcode(&passcode)),
+      uninstall_passcode_hash: non_empty(agent_ui.child_text("uninstall-passwd"))
+        .map(|passcode| hash_passcode(&passcode)),
+      override_timeout_mins: positive(agent_ui.child_text("agent-user-override-timeout")),
+      max_overrides: positive(agent_ui.child_text("max-agent-user-overrides")),
+      welcome_page,
+      help_pages: ["help-page", "help-page-2"]
+        .iter()
+        .filter_map(|name| non_empty(agent_ui.child_text(name)))
+        .collect(),
+      connect_before_logon,
+    }
+  }
+
+  /// Whether the user may disable the VPN, as the official client asks for the comment or the
+  /// passcode the portal requires
+  pub fn check_disable(&self, user_override: Option<&UserOverride>) -> anyhow::Result<()> {
+    let comment = user_override
+      .and_then(|o| o.comment.as_deref())
+      .filter(|v| !v.trim().is_empty());
+    let passcode = user_override
+      .and_then(|o| o.passcode.as_deref())
+      .filter(|v| !v.is_empty());
+
+    match self.disable_override {
+      None | Some(DisableOverride::Allowed) => Ok(()),
+      Some(DisableOverride::WithComment) if comment.is_some() => Ok(()),
+      Some(DisableOverride::WithComment) => bail!("The portal requires a comment to disable the VPN"),
+      Some(DisableOverride::WithPasscode) if self.passcode_matches(passcode) => Ok(()),
+      Some(DisableOverride::WithPasscode) => bail!("The portal requires the correct passcode to disable the VPN"),
+      Some(DisableOverride::WithTicket) => {
+        bail!("The portal requires a ticket from the administrator to disable the VPN, which is not supported")
+      }
+      Some(DisableOverride::Disabled) => bail!("The portal does not allow disabling the VPN"),
+    }
+  }
+
+  /// The settings without the passcode hashes, for the WS clients
+  pub fn without_passcodes(&self) -> Self {
+    Self {
+      passcode_hash: None,
+      uninstall_passcode_hash: None,
+      ..self.clone()
+    }
+  }
+
+  fn passcode_matches(&self, passcode: Option<&str>) -> bool {
+    match (passcode, &self.passcode_hash) {
+      (Some(passcode), Some(expected)) => memcmp::eq(hash_passcode(passcode).as_bytes(), expected.as_bytes()),
+      _ => false,
+    }
+  }
+}
+
+fn hash_passcode(passcode: &str) -> String {
+  sha256::digest(format!("gp-agent-passcode:{}", passcode))
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+  value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn positive(value: Option<String>) -> Option<u32> {
+  value?.trim().parse().ok().filter(|value| *value > 0)
+}
+
+fn parse_yes_no(value: Option<String>) -> Option<bool> {
+  match value?.trim() {
+    "yes" => Some(true),
+    "no" => Some(false),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse_xml(xml: &str) -> Element {
+    Element::parse(xml.as_bytes()).unwrap()
+  }
+
+  fn with_override(disable_override: DisableOverride) -> AgentSettings {
+    AgentSettings {
+      disable_override: Some(disable_override),
+      passcode_hash: Some(hash_passcode("1234")),
+      ..Default::default()
+    }
+  }
+
+  fn user_override(comment: Option<&str>, passcode: Option<&str>) -> UserOverride {
+    UserOverride {
+      comment: comment.map(String::from),
+      passcode: passcode.map(String::from),
+    }
+  }
+
+  #[test]
+  fn parses_agent_ui_settings() {
+    let root = parse_xml(
+      r#"<policy>
+        <agent-ui>
+          <passcode>1234</passcode>
+          <uninstall-passwd></uninstall-passwd>
+          <agent-user-override-timeout>30</agent-user-override-timeout>
+          <max-agent-user-overrides>0</max-agent-user-overrides>
+          <help-page>help</help-page>
+          <help-page-2></help-page-2>
+          <welcome-page>
+            <display>yes</display>
+            <page>welcome</page>
+          </welcome-page>
+          <agent-user-override>with-passcode</agent-user-override>
+        </agent-ui>
+        <connect-before-logon>yes</connect-before-logon>
+      </policy>"#,
+    );
+
+    let settings = AgentSettings::parse(&root);
+    assert_eq!(settings.disable_override, Some(DisableOverride::WithPasscode));
+    assert_eq!(settings.passcode_hash, Some(hash_passcode("1234")));
+    assert_eq!(settings.uninstall_passcode_hash, None);
+    assert!(!serde_json::to_string(&settings).unwrap().contains("1234"));
+    assert_eq!(settings.without_passcodes().passcode_hash, None);
+    assert_eq!(settings.override_timeout_mins, Some(30));
+    assert_eq!(settings.max_overrides, None);
+    assert_eq!(settings.welcome_page.as_deref(), Some("welcome"));
+    assert_eq!(settings.help_pages, vec!["help"]);
+    assert_eq!(settings.connect_before_logon, Some(true));
+  }
+
+  #[test]
+  fn hidden_welcome_page_is_ignored() {
+    let root = parse_xml(
+      "<policy><agent-ui><welcome-page><display>no</display><page>welcome</page></welcome-page></agent-ui></policy>",
+    );
+
+    assert_eq!(AgentSettings::parse(&root).welcome_page, None);
+  }
+
+  #[test]
+  fn missing_agent_ui_allows_disabling() {
+    let settings = AgentSettings::parse(&parse_xml("<policy></policy>"));
+
+    assert_eq!(settings, AgentSettings::default());
+    assert!(settings.check_disable(None).is_ok());
+  }
+
+  #[test]
+  fn disabling_requires_what_the_portal_asks_for() {
+    assert!(with_override(DisableOverride::Allowed).check_disable(None).is_ok());
+
+    let with_comment = with_override(DisableOverride::WithComment);
+    assert!(with_comment.check_disable(None).is_err());
+    assert!(
+      with_comment
+        .check_disable(Some(&user_override(Some(" "), None)))
+        .is_err()
+    );
+    assert!(
+      with_comment
+        .check_disable(Some(&user_override(Some("travelling"), None)))
+        .is_ok()
+    );
+
+    let with_passcode = with_override(DisableOverride::WithPasscode);
+    assert!(with_passcode.check_disable(None).is_err());
+    assert!(
+      with_passcode
+        .check_disable(Some(&user_override(None, Some("0000"))))
+        .is_err()
+    );
+    assert!(
+      with_passcode
+        .check_disable(Some(&user_override(None, Some("1234"))))
+        .is_ok()
+    );
+
+    assert!(with_override(DisableOverride::WithTicket).check_disable(None).is_err());
+    assert!(
+      with_override(DisableOverride::Disabled)
+        .check_disable(Some(&user_override(Some("please"), Some("1234"))))
+        .is_err()
+    );
+  }
+}
@@ -0,0 +1,352 @@
+use std::{
+  cmp::Ordering,
+  collections::BTreeMap,
+  fs,
+  io::Write,
+  path::{Path, PathBuf},
+  sync::LazyLock,
+};
+
+use log::warn;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  error::ClientUpgradeRequired,
+  os_profile::ClientOs,
+  utils::{
+    normalize_server, remove_url_scheme,
+    xml::{self, ElementExt},
+  },
+};
+
+const STORE_VERSION: u32 = 1;
+
+static UPGRADE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\bupgrade\b").unwrap());
+static PRODUCT_RE: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"(?i)\b(?:client|globalprotect|agent|app)\b").unwrap());
+static VERSION_WORD_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\bversion\b").unwrap());
+static UNSUPPORTED_RE: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r"(?i)\b(?:not supported|unsupported|no longer supported|too old|outdated|minimum|not allowed)\b").unwrap()
+});
+/// A GlobalProtect version, e.g., `6.2.1` or `6.2.1-123`, an IP address has one component more
+static VERSION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\d+\.\d+(?:\.\d+)?(?:-\d+)?$").unwrap());
+
+/// Detect the "client upgrade required" error of a portal or a gateway with a minimum version
+/// policy, with the required version if the message tells it.
+///
+/// The message is the text of the GlobalProtect error header or of an XML error, see
+/// [`xml_error_message`], never a whole response body.
+pub fn detect_upgrade_required(message: &str) -> Option<ClientUpgradeRequired> {
+  let upgrade = UPGRADE_RE.is_match(message) && PRODUCT_RE.is_match(message);
+  let unsupported = VERSION_WORD_RE.is_match(message) && UNSUPPORTED_RE.is_match(message);
+
+  if !upgrade && !unsupported {
+    return None;
+  }
+
+  // "version 5.1.0 is lower than the minimum version 6.0.0", the newest one is the requirement
+  let version = told_versions(message)
+    .into_iter()
+    .max_by(|a, b| compare_versions(a, b))
+    .map(String::from);
+
+  Some(ClientUpgradeRequired::new(message.trim(), version))
+}
+
+/// The versions right after the word "version", e.g., "version 6.0.0" or "version is 6.0.0", so
+/// that an address or a library version elsewhere in the message is not taken for one
+fn told_versions(message: &str) -> Vec<&str> {
+  let words = message
+    .split(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | '(' | ')' | '[' | ']' | '"' | '\''))
+    .filter(|word| !word.is_empty())
+    .map(|word| word.trim_end_matches(['.', ':']))
+    .collect::<Vec<_>>();
+
+  words
+    .iter()
+    .enumerate()
+    .filter(|(i, word)| {
+      VERSION_RE.is_match(word)
+        && words[i.saturating_sub(2)..*i]
+          .iter()
+          .any(|word| word.eq_ignore_ascii_case("version"))
+    })
+    .map(|(_, word)| *word)
+    .collect()
+}
+
+/// The text of the `<error>` or `<msg>` element of an XML error response
+pub(crate) fn xml_error_message(body: &str) -> Option<String> {
+  let root = xml::parse(body).ok()?;
+
+  root
+    .descendant_text("error")
+    .or_else(|| root.descendant_text("msg"))
+    .filter(|message| !message.trim().is_empty())
+}
+
+/// Compare versions like `6.2.1-123` by their numeric components, a missing component is 0
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+  let parts = |version: &str| {
+    version
+      .split(['.', '-'])
+      .map(|part| part.trim().parse::<u64>().unwrap_or(0))
+      .collect::<Vec<_>>()
+  };
+  let (a, b) = (parts(a), parts(b));
+
+  (0..a.len().max(b.len()))
+    .map(|i| a.get(i).copied().unwrap_or(0).cmp(&b.get(i).copied().unwrap_or(0)))
+    .find(|ordering| ordering.is_ne())
+    .unwrap_or(Ordering::Equal)
+}
+
+/// The client versions known to work with a portal
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct KnownVersions {
+  /// The version the portal accepted after rejecting the reported one
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub negotiated: Option<String>,
+  /// The agent version the portal config advertises
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub portal: Option<String>,
+}
+
+impl KnownVersions {
+  /// The version to retry with after the server rejected `current`, `None` if no known version is
+  /// newer, so that negotiating always ends
+  pub fn next_version(&self, rejected: &ClientUpgradeRequired, current: &str) -> Option<String> {
+    [rejected.version(), self.negotiated.as_deref(), self.portal.as_deref()]
+      .into_iter()
+      .flatten()
+      .filter(|version| compare_versions(version, current).is_gt())
+      .max_by(|a, b| compare_versions(a, b))
+      .map(String::from)
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct VersionStore {
+  version: u32,
+  /// The known versions by portal, then by client OS
+  #[serde(default)]
+  portals: BTreeMap<String, BTreeMap<String, KnownVersions>>,
+}
+
+impl Default for VersionStore {
+  fn default() -> Self {
+    Self {
+      version: STORE_VERSION,
+      portals: BTreeMap::new(),
+    }
+  }
+}
+
+pub fn version_path(custom: Option<&str>) -> PathBuf {
+  if let Some(p) = custom {
+    return PathBuf::from(p);
+  }
+  let home = std::env::var("HOME").unwrap_or_else(|_| "/root".to_string());
+  PathBuf::from(home).join(".config/gpclient/client-version.toml")
+}
+
+pub fn load(path: &Path, portal: &str, client_os: ClientOs) -> KnownVersions {
+  read_store(path)
+    .portals
+    .remove(&portal_key(portal))
+    .and_then(|mut versions| versions.remove(client_os.as_str()))
+    .unwrap_or_default()
+}
+
+/// Remember the negotiated version and the version the portal advertises, the known ones are kept
+/// for a `None`
+pub fn remember(
+  path: &Path,
+  portal: &str,
+  client_os: ClientOs,
+  negotiated: Option<&str>,
+  portal_version: Option<&str>,
+) -> anyhow::Result<()> {
+  let mut store = read_store(path);
+  let known = store
+    .portals
+    .entry(portal_key(portal))
+    .or_default()
+    .entry(client_os.as_str().to_string())
+    .or_default();
+
+  let updated = KnownVersions {
+    negotiated: negotiated.map(String::from).or_else(|| known.negotiated.clone()),
+    portal: portal_version.map(String::from).or_else(|| known.portal.clone()),
+  };
+  if *known == updated {
+    return Ok(());
+  }
+  *known = updated;
+
+  write_store(path, &store)
+}
+
+fn portal_key(portal: &str) -> String {
+  normalize_server(portal)
+    .map(|portal| remove_url_scheme(&portal))
+    .unwrap_or_else(|_| portal.to_string())
+}
+
+fn read_store(path: &Path) -> VersionStore {
+  let Ok(content) = fs::read_to_string(path) else {
+    return VersionStore::default();
+  };
+
+  match toml::from_str::<VersionStore>(&content) {
+    Ok(store) if store.version == STORE_VERSION => store,
+    Ok(_) | Err(_) => {
+      warn!("Ignoring the unreadable client version store {}", path.display());
+      VersionStore::default()
+    }
+  }
+}
+
+fn write_store(path: &Path, store: &VersionStore) -> anyhow::Result<()> {
+  let parent = path
+    .parent()
+    .ok_or_else(|| anyhow::anyhow!("client version path has no parent directory"))?;
+  fs::create_dir_all(parent)?;
+
+  let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+  tmp.write_all(toml::to_string(store)?.as_bytes())?;
+  tmp.flush()?;
+  tmp.persist(path)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn detects_upgrade_required_messages() {
+    let required = detect_upgrade_required("Please upgrade your GlobalProtect app to version 6.2.0 or later").unwrap();
+    assert_eq!(required.version(), Some("6.2.0"));
+
+    let required = detect_upgrade_required("Client version 5.1.0-12 is lower than the minimum version 6.0.0").unwrap();
+    assert_eq!(required.version(), Some("6.0.0"));
+
+    let required = detect_upgrade_required("GlobalProtect client version is not supported").unwrap();
+    assert_eq!(required.version(), None);
+    assert_eq!(required.message(), "GlobalProtect client version is not supported");
+  }
+
+  #[test]
+  fn ignores_other_errors() {
+    assert!(detect_upgrade_required("Authentication failed: Invalid username or password").is_none());
+    assert!(detect_upgrade_required("Kerberos ticket validation failed").is_none());
+    assert!(detect_upgrade_required("auth-failed").is_none());
+  }
+
+  #[test]
+  fn matches_whole_words_only() {
+    assert!(detect_upgrade_required("The application upgrade window is closed").is_none());
+    assert!(detect_upgrade_required("TLS handshake failed: protocol version below the minimum_version").is_none());
+    assert!(detect_upgrade_required("Upgrades to the clientless VPN are pending").is_none());
+  }
+
+  #[test]
+  fn ignores_addresses_and_library_versions() {
+    let required =
+      detect_upgrade_required("Client version is not supported for 10.1.2.3 by OpenSSL 3.0.2, upgrade the app")
+        .unwrap();
+    assert_eq!(required.version(), None);
+
+    let required = detect_upgrade_required("The minimum version is 6.1.0-20. Upgrade from 10.0.0.1").unwrap();
+    assert_eq!(required.version(), Some("6.1.0-20"));
+  }
+
+  #[test]
+  fn reads_the_message_of_an_xml_error() {
+    assert_eq!(
+      xml_error_message("<response status=\"error\"><error>Client version is not supported</error></response>")
+        .as_deref(),
+      Some("Client version is not supported")
+    );
+    assert_eq!(
+      xml_error_message("<prelogin-response><status>Error</status><msg>Upgrade the app</msg></prelogin-response>")
+        .as_deref(),
+      Some("Upgrade the app")
+    );
+    assert!(xml_error_message("<html><body>Please upgrade your client version</body></html>").is_none());
+    assert!(xml_error_message("Please upgrade your client").is_none());
+  }
+
+  #[test]
+  fn compares_versions_by_component() {
+    assert!(compare_versions("6.2.1-123", "6.2.1-45").is_gt());
+    assert!(compare_versions("6.10.0", "6.9.9").is_gt());
+    assert!(compare_versions("6.2", "6.2.0-0").is_eq());
+    assert!(compare_versions("5.9.9-999", "6.0.0").is_lt());
+  }
+
+  #[test]
+  fn next_version_is_the_newest_known_version() {
+    let rejected = ClientUpgradeRequired::new("upgrade the client", Some("6.2.0".to_string()));
+    let known = KnownVersions {
+      negotiated: None,
+      portal: Some("6.3.0-33".to_string()),
+    };
+
+    assert_eq!(known.next_version(&rejected, "6.1.0-10").as_deref(), Some("6.3.0-33"));
+    assert_eq!(
+      KnownVersions::default().next_version(&rejected, "6.1.0-10").as_deref(),
+      Some("6.2.0")
+    );
+  }
+
+  #[test]
+  fn next_version_ends_without_a_newer_version() {
+    let rejected = ClientUpgradeRequired::new("upgrade the client", Some("6.2.0".to_string()));
+
+    assert!(KnownVersions::default().next_version(&rejected, "6.2.0").is_none());
+    assert!(
+      KnownVersions::default()
+        .next_version(&ClientUpgradeRequired::new("upgrade the client", None), "6.1.0")
+        .is_none()
+    );
+  }
+
+  #[test]
+  fn remembers_versions_per_portal_and_client_os() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("client-version.toml");
+
+    remember(
+      &path,
+      "https://vpn.example.com/",
+      ClientOs::Windows,
+      Some("6.2.0"),
+      None,
+    )
+    .unwrap();
+    remember(&path, "vpn.example.com", ClientOs::Windows, None, Some("6.3.0-33")).unwrap();
+
+    let known = load(&path, "vpn.example.com", ClientOs::Windows);
+    assert_eq!(known.negotiated.as_deref(), Some("6.2.0"));
+    assert_eq!(known.portal.as_deref(), Some("6.3.0-33"));
+
+    assert_eq!(load(&path, "vpn.example.com", ClientOs::Mac), KnownVersions::default());
+    assert_eq!(
+      load(&path, "other.example.com", ClientOs::Windows),
+      KnownVersions::default()
+    );
+  }
+
+  #[test]
+  fn remember_does_not_write_unchanged_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("client-version.toml");
+
+    remember(&path, "vpn.example.com", ClientOs::Linux, None, None).unwrap();
+    assert!(!path.exists());
+  }
+}
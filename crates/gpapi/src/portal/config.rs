@@ -10,14 +10,23 @@ use xmltree::Element;
 
 use crate::{
   credential::{AuthCookieCredential, Credential},
-  error::PortalError,
+  error::{ClientUpgradeRequired, PortalError},
   gateway::{Gateway, parse_gateways},
   gp_params::GpParams,
   params,
-  utils::{normalize_server, parse_gp_response, redact::redact_form_params, remove_url_scheme, xml::ElementExt},
+  utils::{
+    http_record, normalize_server, parse_gp_response,
+    redact::redact_form_params,
+    remove_url_scheme,
+    xml::{self, ElementExt},
+  },
 };
 
-use super::csc;
+use super::{
+  agent_settings::AgentSettings,
+  client_version::detect_upgrade_required,
+  csc::{self, CscChecks},
+};
 
 #[derive(Debug, Serialize, Type)]
 #[serde(rename_all = "camelCase")]
@@ -47,6 +56,18 @@ pub struct PortalConfig {
    * Whether the portal policy enables default-browser authentication.
    */
   default_browser: Option<bool>,
+  /**
+   * How often the gateway expects a HIP re-check, in seconds.
+   */
+  hip_report_interval: Option<u64>,
+  /**
+   * How long the portal auth cookie is accepted, in seconds.
+   */
+  cookie_lifetime: Option<u64>,
+  /**
+   * The client agent settings, e.g., whether the user may disable the VPN.
+   */
+  agent_settings: AgentSettings,
 }
 
 impl PortalConfig {
@@ -86,14 +107,28 @@ impl PortalConfig {
     self.default_browser
   }
 
+  pub fn hip_report_interval(&self) -> Option<u64> {
+    self.hip_report_interval
+  }
+
+  pub fn cookie_lifetime(&self) -> Option<u64> {
+    self.cookie_lifetime
+  }
+
+  pub fn agent_settings(&self) -> &AgentSettings {
+    &self.agent_settings
+  }
+
   /// In-place sort the gateways by region
   pub fn sort_gateways(&mut self, region: &str) {
-    let preferred_gateway = self.find_preferred_gateway(region);
+    let Some(preferred_gateway) = self.find_preferred_gateway(region) else {
+      return;
+    };
     let preferred_gateway_index = self
       .gateways()
       .iter()
       .position(|gateway| gateway.name == preferred_gateway.name)
-      .unwrap();
+      .unwrap_or_default();
 
     // Move the preferred gateway to the front of the list
     self.gateways.swap(0, preferred_gateway_index);
@@ -110,8 +145,9 @@ impl PortalConfig {
   /// Find the preferred gateway for the given region
   /// Iterates over the gateways and find the first one that
   /// has the lowest priority for the given region.
-  /// If no gateway is found, returns the gateway with the lowest priority.
-  pub fn find_preferred_gateway(&self, region: &str) -> &Gateway {
+  /// If no gateway is found, returns the gateway with the lowest priority,
+  /// or `None` if there are no gateways at all.
+  pub fn find_preferred_gateway(&self, region: &str) -> Option<&Gateway> {
     let mut preferred_gateway: Option<&Gateway> = None;
     let mut lowest_region_priority = u32::MAX;
 
@@ -125,11 +161,61 @@ impl PortalConfig {
     }
 
     // If no gateway is found, return the gateway with the lowest priority
-    preferred_gateway.unwrap_or_else(|| self.gateways.iter().min_by_key(|gateway| gateway.priority).unwrap())
+    preferred_gateway.or_else(|| self.gateways.iter().min_by_key(|gateway| gateway.priority))
   }
 }
 
+/// The portal config response, before the client-side checks are answered
+struct ConfigResponse {
+  client: Client,
+  portal: String,
+  server: String,
+  root: Element,
+}
+
 pub async fn retrieve_config(portal: &str, cred: &Credential, gp_params: &GpParams) -> anyhow::Result<PortalConfig> {
+  let ConfigResponse {
+    client,
+    portal,
+    server,
+    root,
+  } = request_config(portal, cred, gp_params).await?;
+
+  if csc::is_config_criteria(&root) {
+    info!("Portal returned CSC criteria: {}", csc_criteria_summary(&root));
+    if !gp_params.effective_csc_support() {
+      bail!(PortalError::ConfigError(
+        "Portal returned CSC criteria but CSC support is disabled".to_string()
+      ));
+    }
+    let csc_xml = retrieve_csc_config(&client, &portal, &root, cred.username(), gp_params).await?;
+    debug!("Portal CSC config response: {}", csc_xml);
+    let root = xml::parse(&csc_xml).map_err(|e| PortalError::ConfigError(e.to_string()))?;
+    return parse_portal_config(&server, cred, root);
+  }
+
+  info!("Portal did not return CSC criteria");
+  parse_portal_config(&server, cred, root)
+}
+
+/// The client-side checks the portal asks for, without answering them, `None` if it asks for none
+pub async fn retrieve_csc_checks(
+  portal: &str,
+  cred: &Credential,
+  gp_params: &GpParams,
+) -> anyhow::Result<Option<CscChecks>> {
+  let ConfigResponse { root, .. } = request_config(portal, cred, gp_params).await?;
+
+  if !csc::is_config_criteria(&root) {
+    info!("Portal did not return CSC criteria");
+    return Ok(None);
+  }
+
+  info!("Portal returned CSC criteria: {}", csc_criteria_summary(&root));
+  Ok(Some(CscChecks::from_criteria(&root)))
+}
+
+async fn request_config(portal: &str, cred: &Credential, gp_params: &GpParams) -> anyhow::Result<ConfigResponse> {
   let portal = normalize_server(portal)?;
   let server = remove_url_scheme(&portal);
 
@@ -146,12 +232,18 @@ pub async fn retrieve_config(portal: &str, cred: &Credential, gp_params: &GpPara
   info!("Retrieve the portal config, user_agent: {}", gp_params.user_agent());
   info!("Portal config request params: {}", redact_form_params(&body_pairs));
 
-  let res = client.post(&url).form(&request_params.body).send().await.map_err(|e| {
-    warn!("Network error: {:?}", e);
-    anyhow::anyhow!(PortalError::NetworkError(e))
-  })?;
+  let res = http_record::send("getconfig", client.post(&url).form(&request_params.body))
+    .await
+    .map_err(|e| {
+      warn!("Network error: {:?}", e);
+      anyhow::anyhow!(PortalError::NetworkError(e))
+    })?;
 
   let res_xml = parse_gp_response(res).await.or_else(|err| {
+    if let Some(upgrade_required) = err.client_upgrade_required() {
+      bail!(upgrade_required);
+    }
+
     if err.status == StatusCode::NOT_FOUND {
       bail!(PortalError::ConfigError("Config endpoint not found".to_string()));
     }
@@ -169,23 +261,17 @@ pub async fn retrieve_config(portal: &str, cred: &Credential, gp_params: &GpPara
   }
 
   debug!("Portal config response: {}", res_xml);
-  let root = Element::parse(res_xml.as_bytes()).map_err(|e| PortalError::ConfigError(e.to_string()))?;
-
-  if csc::is_config_criteria(&root) {
-    info!("Portal returned CSC criteria: {}", csc_criteria_summary(&root));
-    if !gp_params.effective_csc_support() {
-      bail!(PortalError::ConfigError(
-        "Portal returned CSC criteria but CSC support is disabled".to_string()
-      ));
-    }
-    let csc_xml = retrieve_csc_config(&client, &portal, &root, cred.username(), gp_params).await?;
-    debug!("Portal CSC config response: {}", csc_xml);
-    let root = Element::parse(csc_xml.as_bytes()).map_err(|e| PortalError::ConfigError(e.to_string()))?;
-    return parse_portal_config(&server, cred, root);
+  let root = xml::parse(&res_xml).map_err(|e| PortalError::ConfigError(e.to_string()))?;
+  if let Some(upgrade_required) = error_response_upgrade_required(&root) {
+    bail!(upgrade_required);
   }
 
-  info!("Portal did not return CSC criteria");
-  parse_portal_config(&server, cred, root)
+  Ok(ConfigResponse {
+    client,
+    portal,
+    server,
+    root,
+  })
 }
 
 async fn retrieve_csc_config(
@@ -203,10 +289,12 @@ async fn retrieve_csc_config(
   info!("Portal CSC config request summary: {}", csc_req.summary());
   info!("Portal CSC config request params: {}", redact_params(&params));
 
-  let res = client.post(&url).form(&params).send().await.map_err(|e| {
-    warn!("Network error: {:?}", e);
-    anyhow::anyhow!(PortalError::NetworkError(e))
-  })?;
+  let res = http_record::send("getconfig-csc", client.post(&url).form(&params))
+    .await
+    .map_err(|e| {
+      warn!("Network error: {:?}", e);
+      anyhow::anyhow!(PortalError::NetworkError(e))
+    })?;
 
   parse_gp_response(res).await.or_else(|err| {
     if err.status == StatusCode::NOT_FOUND {
@@ -222,8 +310,23 @@ async fn retrieve_csc_config(
   })
 }
 
+/// A `<response status="error">` rejecting the client version
+fn error_response_upgrade_required(root: &Element) -> Option<ClientUpgradeRequired> {
+  if root.name != "response" || root.attributes.get("status").map(String::as_str) != Some("error") {
+    return None;
+  }
+
+  let message = root
+    .descendant_text("error")
+    .or_else(|| root.descendant_text("msg"))
+    .unwrap_or_default();
+  detect_upgrade_required(&message)
+}
+
 fn redact_params(params: &HashMap<&str, &str>) -> String {
-  let params = params.iter().map(|(key, value)| (*key, *value)).collect::<Vec<_>>();
+  // Sorted for a stable log line, the map has no order
+  let mut params = params.iter().map(|(key, value)| (*key, *value)).collect::<Vec<_>>();
+  params.sort_by(|(left, _), (right, _)| left.cmp(right));
   redact_form_params(&params)
 }
 
@@ -245,7 +348,7 @@ fn present(value: Option<&str>) -> &'static str {
   }
 }
 
-fn parse_portal_config(server: &str, cred: &Credential, root: Element) -> anyhow::Result<PortalConfig> {
+pub(crate) fn parse_portal_config(server: &str, cred: &Credential, root: Element) -> anyhow::Result<PortalConfig> {
   let mut ihd_enabled = false;
   let mut prefer_internal = false;
   if let Some(ihd_node) = root.descendant("internal-host-detection") {
@@ -275,6 +378,9 @@ fn parse_portal_config(server: &str, cred: &Credential, root: Element) -> anyhow
   info!("Detected portal version: {:?}", version);
   let allow_extend_session = parse_allow_extend_session(&root);
   let default_browser = parse_default_browser(&root);
+  let hip_report_interval = parse_hip_report_interval(&root);
+  let cookie_lifetime = parse_cookie_lifetime(&root);
+  let agent_settings = AgentSettings::parse(&root);
 
   Ok(PortalConfig {
     portal: server.to_string(),
@@ -287,6 +393,9 @@ fn parse_portal_config(server: &str, cred: &Credential, root: Element) -> anyhow
     version,
     allow_extend_session,
     default_browser,
+    hip_report_interval,
+    cookie_lifetime,
+    agent_settings,
   })
 }
 
@@ -306,6 +415,32 @@ fn parse_allow_extend_session(root: &Element) -> Option<bool> {
   }
 }
 
+fn parse_hip_report_interval(root: &Element) -> Option<u64> {
+  root
+    .descendant("hip-collection")?
+    .child_text("hip-report-interval")?
+    .trim()
+    .parse()
+    .ok()
+    .filter(|interval| *interval > 0)
+}
+
+/// The lifetime of the cookie the portal generates, in days, hours, or minutes
+fn parse_cookie_lifetime(root: &Element) -> Option<u64> {
+  let lifetime = root.descendant("authentication-override")?.child("cookie-lifetime")?;
+  [
+    ("lifetime-in-days", 24 * 60 * 60),
+    ("lifetime-in-hours", 60 * 60),
+    ("lifetime-in-minutes", 60),
+  ]
+  .iter()
+  .find_map(|(name, unit)| {
+    let value = lifetime.child_text(name)?.trim().parse::<u64>().ok()?;
+    value.checked_mul(*unit)
+  })
+  .filter(|lifetime| *lifetime > 0)
+}
+
 fn parse_connect_method(root: &Element) -> Option<String> {
   root
     .descendant_text("connect-method")
@@ -356,6 +491,25 @@ mod tests {
     Element::parse(xml.as_bytes()).unwrap()
   }
 
+  #[test]
+  fn error_response_rejecting_the_client_version_is_detected() {
+    let root = parse_xml(
+      r#"<response status="error"><error>GlobalProtect app version 5.2.0 is not supported, the minimum version is 6.0.0</error></response>"#,
+    );
+
+    let upgrade_required = error_response_upgrade_required(&root).unwrap();
+    assert_eq!(upgrade_required.version(), Some("6.0.0"));
+  }
+
+  #[test]
+  fn other_responses_are_not_upgrade_requirements() {
+    let error = parse_xml(r#"<response status="error"><error>Invalid username or password</error></response>"#);
+    assert!(error_response_upgrade_required(&error).is_none());
+
+    let config = parse_xml("<policy><version>6.3.0-33</version><upgrade-client>yes</upgrade-client></policy>");
+    assert!(error_response_upgrade_required(&config).is_none());
+  }
+
   #[test]
   fn parses_allow_extend_session_yes() {
     let root = parse_xml("<response><allow-extend-session>yes</allow-extend-session></response>");
@@ -405,6 +559,70 @@ mod tests {
     assert_eq!(parse_default_browser(&root), None);
   }
 
+  #[test]
+  fn parses_hip_report_interval() {
+    let root =
+      parse_xml("<policy><hip-collection><hip-report-interval>3600</hip-report-interval></hip-collection></policy>");
+
+    assert_eq!(parse_hip_report_interval(&root), Some(3600));
+  }
+
+  #[test]
+  fn ignores_invalid_hip_report_interval() {
+    let root =
+      parse_xml("<policy><hip-collection><hip-report-interval>0</hip-report-interval></hip-collection></policy>");
+
+    assert_eq!(parse_hip_report_interval(&root), None);
+    assert_eq!(parse_hip_report_interval(&parse_xml("<policy/>")), None);
+  }
+
+  #[test]
+  fn parses_agent_settings() {
+    let xml = std::fs::read_to_string("tests/files/portal_config.xml").unwrap();
+    let cred = Credential::from(crate::credential::PasswordCredential::new("alice", "secret"));
+
+    let config = parse_portal_config("vpn.example.com", &cred, parse_xml(&xml)).unwrap();
+
+    let settings = config.agent_settings();
+    assert_eq!(settings.disable_override, Some(crate::portal::DisableOverride::Allowed));
+    assert_eq!(settings.welcome_page, None);
+    assert!(settings.help_pages.is_empty());
+  }
+
+  #[test]
+  fn parses_cookie_lifetime() {
+    let xml = std::fs::read_to_string("tests/files/portal_config.xml").unwrap();
+    assert_eq!(parse_cookie_lifetime(&parse_xml(&xml)), Some(365 * 24 * 60 * 60));
+
+    let root = parse_xml(
+      "<policy><authentication-override><cookie-lifetime><lifetime-in-hours>8</lifetime-in-hours></cookie-lifetime></authentication-override></policy>",
+    );
+    assert_eq!(parse_cookie_lifetime(&root), Some(8 * 60 * 60));
+    assert_eq!(parse_cookie_lifetime(&parse_xml("<policy/>")), None);
+  }
+
+  #[test]
+  fn ignores_cookie_lifetime_that_overflows() {
+    let root = parse_xml(&format!(
+      "<policy><authentication-override><cookie-lifetime><lifetime-in-days>{}</lifetime-in-days></cookie-lifetime></authentication-override></policy>",
+      u64::MAX
+    ));
+
+    assert_eq!(parse_cookie_lifetime(&root), None);
+  }
+
+  #[test]
+  fn sorting_no_gateways_does_nothing() {
+    let cred = Credential::from(crate::credential::PasswordCredential::new("alice", "secret"));
+    let mut config = parse_portal_config("vpn.example.com", &cred, parse_xml("<policy/>")).unwrap();
+    config.gateways.clear();
+
+    config.sort_gateways("US");
+
+    assert!(config.gateways().is_empty());
+    assert!(config.find_preferred_gateway("US").is_none());
+  }
+
   #[test]
   fn parses_connect_method() {
     let root = parse_xml("<policy><connect-method>on-demand</connect-method></policy>");
@@ -437,4 +655,110 @@ mod tests {
     assert_eq!(config.auth_cookie().prelogon_user_auth_cookie(), "prelogon-cookie");
     assert_eq!(config.gateways().len(), 1);
   }
+
+  mod prop_tests {
+    use super::*;
+    use crate::utils::xml::strategy;
+    use proptest::prelude::*;
+
+    // `internal-host-detection` is left out, it performs DNS lookups
+    const NAMES: &[&str] = &[
+      "policy",
+      "gateways",
+      "external",
+      "internal",
+      "list",
+      "entry",
+      "description",
+      "priority",
+      "priority-rule",
+      "portal-userauthcookie",
+      "portal-prelogonuserauthcookie",
+      "config-digest",
+      "connect-method",
+      "version",
+      "allow-extend-session",
+      "default-browser",
+      "hip-collection",
+      "hip-report-interval",
+      "authentication-override",
+      "cookie-lifetime",
+      "lifetime-in-days",
+      "lifetime-in-hours",
+      "lifetime-in-minutes",
+      "agent-ui",
+      "agent-user-override",
+      "passcode",
+      "welcome-page",
+      "display",
+      "page",
+      "help-page",
+      "connect-before-logon",
+    ];
+
+    fn cred() -> Credential {
+      Credential::from(crate::credential::PasswordCredential::new("alice", "secret"))
+    }
+
+    /// A gateway list with arbitrary priorities and region rules, any of which may be missing
+    fn gateway_list() -> impl Strategy<Value = String> {
+      let rule = (
+        prop::sample::select(vec!["Any", "US", "EU"]),
+        proptest::option::of(any::<u32>()),
+      );
+      let gateway = (proptest::option::of(any::<u32>()), prop::collection::vec(rule, 0..3));
+
+      prop::collection::vec(gateway, 0..8).prop_map(|gateways| {
+        let priority = |priority: Option<u32>| priority.map(|p| format!("<priority>{p}</priority>")).unwrap_or_default();
+        let entries: String = gateways
+          .into_iter()
+          .enumerate()
+          .map(|(i, (gateway_priority, rules))| {
+            let rules: String = rules
+              .into_iter()
+              .map(|(region, rule_priority)| format!(r#"<entry name="{region}">{}</entry>"#, priority(rule_priority)))
+              .collect();
+            format!(
+              r#"<entry name="gw{i}.example.com"><description>gw{i}</description>{}<priority-rule>{rules}</priority-rule></entry>"#,
+              priority(gateway_priority)
+            )
+          })
+          .collect();
+
+        format!("<policy><gateways><external><list>{entries}</list></external></gateways></policy>")
+      })
+    }
+
+    proptest! {
+      #[test]
+      fn parse_portal_config_never_panics(xml in strategy::malformed(NAMES)) {
+        let Ok(root) = xml::parse(&xml) else {
+          return Ok(());
+        };
+
+        let mut config = parse_portal_config("vpn.example.com", &cred(), root).unwrap();
+        prop_assert!(!config.gateways().is_empty());
+        config.sort_gateways("US");
+      }
+
+      /// Sorting moves the preferred gateway to the front and keeps every gateway
+      #[test]
+      fn sort_gateways_moves_the_preferred_gateway_first(
+        xml in gateway_list(),
+        region in prop::sample::select(vec!["US", "EU", "Unknown"]),
+      ) {
+        let mut config = parse_portal_config("vpn.example.com", &cred(), xml::parse(&xml).unwrap()).unwrap();
+        let preferred = config.find_preferred_gateway(region).cloned().unwrap();
+        let mut names: Vec<_> = config.gateways().iter().map(|gateway| gateway.name.clone()).collect();
+
+        config.sort_gateways(region);
+
+        prop_assert_eq!(&config.gateways()[0].name, &preferred.name);
+        let mut sorted: Vec<_> = config.gateways().iter().map(|gateway| gateway.name.clone()).collect();
+        names.sort();
+        sorted.sort();
+        prop_assert_eq!(names, sorted);
+      }
+    }
+  }
 }
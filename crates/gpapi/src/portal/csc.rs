@@ -1,18 +1,21 @@
 use std::{
-  collections::HashMap,
+  collections::{BTreeMap, HashMap},
   fmt::Write,
   fs,
+  path::Path,
   process::Command,
   time::{SystemTime, UNIX_EPOCH},
 };
 
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
 use xmltree::{Element, EmitterConfig, XMLNode};
 
 use crate::{gp_params::GpParams, utils::xml::ElementExt};
 
 const DIGEST_PLACEHOLDER: &str = "__#_PAN_CSC_DATA_DIGEST_#__";
 
-pub(super) struct CscRequest {
+pub(crate) struct CscRequest {
   pub(super) auth_cookie: String,
   pub(super) config_digest: String,
   pub(super) csc_digest: String,
@@ -25,7 +28,7 @@ pub(super) struct CscRequest {
 impl CscRequest {
   pub(super) fn summary(&self) -> String {
     format!(
-      "digest_len={}, xml_len={}, plist_entries={}, plist_present={}, plist_missing={}, process_entries={}, process_present={}, process_missing={}",
+      "digest_len={}, xml_len={}, plist_entries={}, plist_present={}, plist_missing={}, process_entries={}, process_present={}, process_missing={}, registry_entries={}, registry_present={}, registry_missing={}",
       self.csc_digest.len(),
       self.csc_data.len(),
       self.summary.plist_entries,
@@ -34,6 +37,9 @@ impl CscRequest {
       self.summary.process_entries,
       self.summary.process_present,
       self.summary.process_missing,
+      self.summary.registry_entries,
+      self.summary.registry_present,
+      self.summary.registry_missing,
     )
   }
 }
@@ -46,6 +52,9 @@ struct CscBuildSummary {
   process_entries: usize,
   process_present: usize,
   process_missing: usize,
+  registry_entries: usize,
+  registry_present: usize,
+  registry_missing: usize,
 }
 
 struct CscDataInput<'a> {
@@ -58,9 +67,18 @@ struct CscDataInput<'a> {
   csc_digest: &'a str,
 }
 
-pub(super) trait CscDataReader {
+pub(crate) trait CscDataReader {
   fn read_preference(&self, domain: &str, key: &str) -> Option<String>;
   fn process_exists(&self, name: &str) -> bool;
+
+  fn registry_key_exists(&self, _key: &str) -> bool {
+    false
+  }
+
+  /// The data of a registry value, `None` as the name reads the key's default value
+  fn read_registry_value(&self, _key: &str, _name: Option<&str>) -> Option<String> {
+    None
+  }
 }
 
 struct SystemCscDataReader;
@@ -75,6 +93,191 @@ impl CscDataReader for SystemCscDataReader {
   }
 }
 
+/// Answers to the portal's client-side checks, read from a TOML or YAML file instead of the system.
+///
+/// ```toml
+/// processes = ["falcon-sensor"]
+///
+/// [plist."com.example.settings"]
+/// ExampleKey = "ExampleValue"
+///
+/// [registry.'HKEY_LOCAL_MACHINE\SOFTWARE\Example']
+/// default = "installed"
+/// values = { Version = "1.2" }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CscAnswers {
+  /// Preference values by plist domain and key
+  plist: BTreeMap<String, BTreeMap<String, String>>,
+  /// The registry keys that exist, with their values
+  registry: BTreeMap<String, RegistryKeyAnswer>,
+  /// The running processes, the local process list is used when not set
+  #[serde(skip_serializing_if = "Option::is_none")]
+  processes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct RegistryKeyAnswer {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  default: Option<String>,
+  values: BTreeMap<String, String>,
+}
+
+impl CscAnswers {
+  /// Load the answers, a `.yaml` or `.yml` file is read as YAML, anything else as TOML
+  pub fn load(path: &Path) -> anyhow::Result<Self> {
+    let content =
+      fs::read_to_string(path).with_context(|| format!("Failed to read the CSC answer file {}", path.display()))?;
+    let is_yaml = path
+      .extension()
+      .and_then(|ext| ext.to_str())
+      .is_some_and(|ext| ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml"));
+
+    let answers = if is_yaml {
+      Self::from_yaml(&content)
+    } else {
+      Self::from_toml(&content)
+    };
+    answers.with_context(|| format!("Invalid CSC answer file {}", path.display()))
+  }
+
+  pub fn from_toml(content: &str) -> anyhow::Result<Self> {
+    toml::from_str(content).map_err(Into::into)
+  }
+
+  pub fn from_yaml(content: &str) -> anyhow::Result<Self> {
+    serde_yaml::from_str(content).map_err(Into::into)
+  }
+
+  fn registry_key(&self, key: &str) -> Option<&RegistryKeyAnswer> {
+    // Registry keys are case-insensitive
+    self
+      .registry
+      .iter()
+      .find(|(name, _)| name.eq_ignore_ascii_case(key))
+      .map(|(_, answer)| answer)
+  }
+}
+
+impl CscDataReader for CscAnswers {
+  fn read_preference(&self, domain: &str, key: &str) -> Option<String> {
+    self.plist.get(domain)?.get(key).cloned()
+  }
+
+  fn process_exists(&self, name: &str) -> bool {
+    match &self.processes {
+      Some(processes) => processes.iter().any(|process| process == name),
+      None => SystemCscDataReader.process_exists(name),
+    }
+  }
+
+  fn registry_key_exists(&self, key: &str) -> bool {
+    self.registry_key(key).is_some()
+  }
+
+  fn read_registry_value(&self, key: &str, name: Option<&str>) -> Option<String> {
+    let answer = self.registry_key(key)?;
+    match name {
+      Some(name) => answer.values.get(name).cloned(),
+      None => answer.default.clone(),
+    }
+  }
+}
+
+/// The client-side checks requested by the portal, regardless of the OS profile
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CscChecks {
+  plist: BTreeMap<String, Vec<String>>,
+  registry: BTreeMap<String, Vec<String>>,
+  processes: Vec<String>,
+}
+
+impl CscChecks {
+  pub(super) fn from_criteria(criteria: &Element) -> Self {
+    let mut checks = Self::default();
+    let Some(requested_checks) = criteria.descendant("custom-checks") else {
+      return checks;
+    };
+
+    if let Some(plist) = requested_checks
+      .child("mac-os")
+      .and_then(|mac_os| mac_os.child("plist"))
+    {
+      for entry in plist.children("entry") {
+        let domain = entry.attr("name").unwrap_or_default().to_string();
+        checks.plist.entry(domain).or_default().extend(plist_entry_keys(entry));
+      }
+    }
+
+    if let Some(process_list) = requested_checks
+      .child("linux")
+      .and_then(|linux| linux.child("process-list"))
+      .or_else(|| requested_checks.child("process-list"))
+    {
+      checks.processes = process_list_names(process_list);
+    }
+
+    if let Some(registry_key) = requested_checks
+      .child("windows")
+      .and_then(|windows| windows.child("registry-key"))
+      .or_else(|| requested_checks.child("registry-key"))
+    {
+      checks.registry = registry_key_requests(registry_key);
+    }
+
+    checks
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.plist.is_empty() && self.registry.is_empty() && self.processes.is_empty()
+  }
+
+  /// A TOML answer file for `--csc-answers` listing every requested check, with empty values to fill in
+  pub fn answers_template(&self) -> anyhow::Result<String> {
+    let answers = CscAnswers {
+      plist: self
+        .plist
+        .iter()
+        .map(|(domain, keys)| (domain.clone(), empty_values(keys)))
+        .collect(),
+      registry: self
+        .registry
+        .iter()
+        .map(|(key, names)| {
+          let answer = RegistryKeyAnswer {
+            default: Some(String::new()),
+            values: empty_values(names),
+          };
+          (key.clone(), answer)
+        })
+        .collect(),
+      processes: None,
+    };
+
+    let mut template = String::from(
+      "# Answers to the client-side checks requested by the portal, use it with `--csc-answers`.\n\
+       # plist: the preference values by domain and key, remove the ones that are not set.\n\
+       # registry: the registry keys that exist, with their default and named values.\n\
+       # processes: the processes reported as running, uncomment the ones that are.\n\n",
+    );
+    // Listed commented out, an unedited template reports none of them as running
+    template.push_str("processes = [\n");
+    for process in &self.processes {
+      template.push_str(&format!("  # {},\n", toml::Value::String(process.clone())));
+    }
+    template.push_str("]\n\n");
+    template.push_str(&toml::to_string(&answers)?);
+
+    Ok(template)
+  }
+}
+
+fn empty_values(names: &[String]) -> BTreeMap<String, String> {
+  names.iter().map(|name| (name.clone(), String::new())).collect()
+}
+
 pub(crate) fn swg_nonce() -> String {
   let millis = SystemTime::now()
     .duration_since(UNIX_EPOCH)
@@ -89,11 +292,13 @@ pub(super) fn is_config_criteria(root: &Element) -> bool {
 }
 
 pub(super) fn build_csc_request(root: &Element, username: &str, gp_params: &GpParams) -> anyhow::Result<CscRequest> {
-  let reader = SystemCscDataReader;
-  build_csc_request_with_reader(root, username, gp_params, &reader)
+  match gp_params.csc_answers() {
+    Some(answers) => build_csc_request_with_reader(root, username, gp_params, answers),
+    None => build_csc_request_with_reader(root, username, gp_params, &SystemCscDataReader),
+  }
 }
 
-pub(super) fn build_csc_request_with_reader(
+pub(crate) fn build_csc_request_with_reader(
   root: &Element,
   username: &str,
   gp_params: &GpParams,
@@ -235,9 +440,11 @@ fn build_custom_checks(
       .and_then(|windows| windows.child("registry-key"))
       .or_else(|| requested_checks.child("registry-key"))
   {
-    custom_checks
-      .children
-      .push(XMLNode::Element(build_registry_key_checks(registry_key)));
+    custom_checks.children.push(XMLNode::Element(build_registry_key_checks(
+      registry_key,
+      reader,
+      summary,
+    )));
   }
 
   custom_checks
@@ -349,40 +556,89 @@ fn process_list_names(process_list: &Element) -> Vec<String> {
   names
 }
 
-fn build_registry_key_checks(registry_key: &Element) -> Element {
+fn build_registry_key_checks(
+  registry_key: &Element,
+  reader: &dyn CscDataReader,
+  summary: &mut CscBuildSummary,
+) -> Element {
   let mut out = Element::new("registry-key");
 
-  for name in registry_key_names(registry_key) {
+  for (name, value_names) in registry_key_requests(registry_key) {
+    let exists = reader.registry_key_exists(&name);
+    summary.registry_entries += 1;
+    if exists {
+      summary.registry_present += 1;
+    } else {
+      summary.registry_missing += 1;
+    }
+
     let mut entry = Element::new("entry");
-    entry.attributes.insert("name".to_string(), name);
-    push_text(&mut entry, "exist", "no");
-    push_text(&mut entry, "value", "");
+    entry.attributes.insert("name".to_string(), name.clone());
+    push_text(&mut entry, "exist", yes_no(exists));
+    push_text(
+      &mut entry,
+      "value",
+      &reader.read_registry_value(&name, None).unwrap_or_default(),
+    );
+
+    if !value_names.is_empty() {
+      let mut registry_value = Element::new("registry-value");
+      for value_name in value_names {
+        let value = reader.read_registry_value(&name, Some(&value_name));
+        let mut value_entry = Element::new("entry");
+        value_entry.attributes.insert("name".to_string(), value_name);
+        push_text(&mut value_entry, "exist", yes_no(value.is_some()));
+        push_text(&mut value_entry, "value", value.as_deref().unwrap_or_default());
+        registry_value.children.push(XMLNode::Element(value_entry));
+      }
+      entry.children.push(XMLNode::Element(registry_value));
+    }
+
     out.children.push(XMLNode::Element(entry));
   }
 
   out
 }
 
-fn registry_key_names(registry_key: &Element) -> Vec<String> {
-  let mut names: Vec<String> = registry_key
+/// The requested registry keys, with the names of the values requested for each of them
+fn registry_key_requests(registry_key: &Element) -> BTreeMap<String, Vec<String>> {
+  let mut requests: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+  for name in registry_key
     .descendants("member")
     .into_iter()
     .filter_map(|element| element.get_text())
     .map(|text| text.trim().to_string())
     .filter(|text| !text.is_empty())
-    .collect();
+  {
+    requests.entry(name).or_default();
+  }
 
-  names.extend(
-    registry_key
-      .children("entry")
-      .into_iter()
-      .filter_map(|entry| entry.attr("name"))
-      .map(str::to_string),
-  );
+  for entry in registry_key.children("entry") {
+    let Some(name) = entry.attr("name") else {
+      continue;
+    };
 
-  names.sort();
-  names.dedup();
-  names
+    let value_names = entry
+      .child("registry-value")
+      .map(|registry_value| {
+        registry_value
+          .children("entry")
+          .into_iter()
+          .filter_map(|value| value.attr("name"))
+          .map(str::to_string)
+          .collect::<Vec<_>>()
+      })
+      .unwrap_or_default();
+    requests.entry(name.to_string()).or_default().extend(value_names);
+  }
+
+  for value_names in requests.values_mut() {
+    value_names.sort();
+    value_names.dedup();
+  }
+
+  requests
 }
 
 fn pan_md5_hex(value: &[u8]) -> String {
@@ -674,6 +930,144 @@ mod tests {
     assert!(req.csc_data.contains("<value />") || req.csc_data.contains("<value></value>"));
   }
 
+  const ANSWERS_TOML: &str = r#"
+processes = ["falcon-sensor"]
+
+[plist."com.example.settings"]
+ExampleKey = "ExampleValue"
+
+[registry.'HKEY_LOCAL_MACHINE\SOFTWARE\Example']
+default = "installed"
+values = { Version = "1.2" }
+"#;
+
+  #[test]
+  fn reads_answers_from_toml_and_yaml() {
+    let yaml = r#"
+processes: [falcon-sensor]
+plist:
+  com.example.settings:
+    ExampleKey: ExampleValue
+registry:
+  'HKEY_LOCAL_MACHINE\SOFTWARE\Example':
+    default: installed
+    values:
+      Version: "1.2"
+"#;
+    let answers = CscAnswers::from_toml(ANSWERS_TOML).unwrap();
+
+    assert_eq!(answers, CscAnswers::from_yaml(yaml).unwrap());
+    assert_eq!(
+      answers.read_preference("com.example.settings", "ExampleKey").as_deref(),
+      Some("ExampleValue")
+    );
+    assert!(answers.process_exists("falcon-sensor"));
+    assert!(!answers.process_exists("missing-process"));
+    assert!(answers.registry_key_exists(r"hkey_local_machine\software\example"));
+    assert_eq!(
+      answers
+        .read_registry_value(r"HKEY_LOCAL_MACHINE\SOFTWARE\Example", None)
+        .as_deref(),
+      Some("installed")
+    );
+    assert_eq!(
+      answers
+        .read_registry_value(r"HKEY_LOCAL_MACHINE\SOFTWARE\Example", Some("Version"))
+        .as_deref(),
+      Some("1.2")
+    );
+  }
+
+  #[test]
+  fn rejects_unreadable_answer_files() {
+    assert!(CscAnswers::from_toml("processes = 1").is_err());
+    assert!(CscAnswers::load(Path::new("/nonexistent/csc-answers.toml")).is_err());
+  }
+
+  #[test]
+  fn builds_windows_registry_key_report_from_answers() {
+    let root = parse_xml(
+      r#"<config-criteria>
+        <custom-checks>
+          <windows>
+            <registry-key>
+              <entry name="HKEY_LOCAL_MACHINE\SOFTWARE\Example">
+                <registry-value><entry name="Version" /><entry name="Missing" /></registry-value>
+              </entry>
+              <entry name="HKEY_LOCAL_MACHINE\SOFTWARE\Other" />
+            </registry-key>
+          </windows>
+        </custom-checks>
+        <portal-csc-auth-cookie>cookie</portal-csc-auth-cookie>
+      </config-criteria>"#,
+    );
+    let mut gp_params = GpParams::builder(gp_params(ClientOs::Windows).os_profile().clone());
+    let gp_params = gp_params
+      .csc_answers(CscAnswers::from_toml(ANSWERS_TOML).unwrap())
+      .build();
+
+    let req = build_csc_request(&root, "alice", &gp_params).unwrap();
+
+    assert!(req.csc_data.contains("<value>installed</value>"));
+    assert!(req.csc_data.contains("<registry-value>"));
+    assert!(req.csc_data.contains(r#"<entry name="Version">"#));
+    assert!(req.csc_data.contains("<value>1.2</value>"));
+    assert!(req.summary().contains("registry_entries=2"));
+    assert!(req.summary().contains("registry_present=1"));
+    assert!(req.summary().contains("registry_missing=1"));
+  }
+
+  #[test]
+  fn lists_requested_checks_as_an_answers_template() {
+    let root = parse_xml(
+      r#"<config-criteria>
+        <custom-checks>
+          <mac-os>
+            <plist>
+              <entry name="com.example.settings"><key><member>ExampleKey</member></key></entry>
+            </plist>
+          </mac-os>
+          <linux>
+            <process-list><entry name="falcon-sensor" /></process-list>
+          </linux>
+          <windows>
+            <registry-key>
+              <entry name="HKEY_LOCAL_MACHINE\SOFTWARE\Example">
+                <registry-value><entry name="Version" /></registry-value>
+              </entry>
+            </registry-key>
+          </windows>
+        </custom-checks>
+      </config-criteria>"#,
+    );
+
+    let checks = CscChecks::from_criteria(&root);
+    let template = checks.answers_template().unwrap();
+    let answers = CscAnswers::from_toml(&template).unwrap();
+
+    assert!(!checks.is_empty());
+    assert!(template.starts_with("# Answers to the client-side checks"));
+    assert_eq!(
+      answers.read_preference("com.example.settings", "ExampleKey").as_deref(),
+      Some("")
+    );
+    assert!(template.contains("  # \"falcon-sensor\",\n"));
+    assert!(!answers.process_exists("falcon-sensor"));
+    let uncommented = template.replace("  # \"falcon-sensor\"", "  \"falcon-sensor\"");
+    assert!(
+      CscAnswers::from_toml(&uncommented)
+        .unwrap()
+        .process_exists("falcon-sensor")
+    );
+    assert_eq!(
+      answers
+        .read_registry_value(r"HKEY_LOCAL_MACHINE\SOFTWARE\Example", Some("Version"))
+        .as_deref(),
+      Some("")
+    );
+    assert!(CscChecks::from_criteria(&parse_xml("<config-criteria/>")).is_empty());
+  }
+
   #[test]
   fn uses_pan_non_padded_md5_digest() {
     assert_eq!(pan_md5_hex(b""), "d41d8cd98f0b24e980998ecf8427e");
@@ -732,4 +1126,68 @@ mod tests {
     );
     assert!(!req.csc_data.contains(DIGEST_PLACEHOLDER));
   }
+
+  mod prop_tests {
+    use super::*;
+    use crate::utils::xml::{self, strategy};
+    use proptest::prelude::*;
+
+    const NAMES: &[&str] = &[
+      "config-criteria",
+      "custom-checks",
+      "mac-os",
+      "linux",
+      "windows",
+      "plist",
+      "process-list",
+      "registry-key",
+      "registry-value",
+      "entry",
+      "key",
+      "member",
+      "portal-csc-auth-cookie",
+      "config-digest",
+    ];
+
+    /// Finds every check and answers it with the names it was asked about, markup and all
+    struct AnsweringReader;
+
+    impl CscDataReader for AnsweringReader {
+      fn read_preference(&self, domain: &str, key: &str) -> Option<String> {
+        Some(format!("{domain}<&>{key}"))
+      }
+
+      fn process_exists(&self, _name: &str) -> bool {
+        true
+      }
+
+      fn registry_key_exists(&self, _key: &str) -> bool {
+        true
+      }
+
+      fn read_registry_value(&self, key: &str, name: Option<&str>) -> Option<String> {
+        Some(format!("{key}\\{}", name.unwrap_or_default()))
+      }
+    }
+
+    proptest! {
+      #[test]
+      fn build_csc_request_never_panics(
+        xml in strategy::malformed(NAMES),
+        client_os in prop::sample::select(vec![ClientOs::Linux, ClientOs::Mac, ClientOs::Windows]),
+        answer in any::<bool>(),
+      ) {
+        let Ok(root) = xml::parse(&xml) else {
+          return Ok(());
+        };
+        let reader: &dyn CscDataReader = if answer { &AnsweringReader } else { &CscAnswers::default() };
+
+        if let Ok(req) = build_csc_request_with_reader(&root, "alice", &gp_params(client_os), reader) {
+          prop_assert!(!req.auth_cookie.is_empty());
+          prop_assert!(!req.csc_data.contains(DIGEST_PLACEHOLDER));
+          prop_assert!(xml::parse(&req.csc_data).is_ok(), "{}", req.csc_data);
+        }
+      }
+    }
+  }
 }
@@ -0,0 +1,283 @@
+use std::{
+  fs,
+  io::Write,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  gateway::Gateway,
+  utils::{normalize_server, remove_url_scheme},
+};
+
+use super::{AgentSettings, PortalConfig};
+
+const CACHE_VERSION: u32 = 1;
+
+/// The gateways the portal pushed last time, to reach them when the portal itself is down
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedGateways {
+  portal: String,
+  gateways: Vec<Gateway>,
+  connect_method: Option<String>,
+  /// The region the portal prelogin reported, the priority rules are matched against it
+  region: Option<String>,
+  /// Enforced by the service on the connections it starts from the cache
+  #[serde(default)]
+  agent_settings: AgentSettings,
+  saved_at: u64,
+}
+
+impl CachedGateways {
+  pub fn new(portal_config: &PortalConfig, region: Option<&str>) -> Self {
+    Self {
+      portal: portal_key(portal_config.portal()),
+      gateways: portal_config.gateways().into_iter().cloned().collect(),
+      connect_method: portal_config.connect_method().map(String::from),
+      region: region.filter(|region| !region.is_empty()).map(String::from),
+      agent_settings: portal_config.agent_settings().clone(),
+      saved_at: now(),
+    }
+  }
+
+  pub fn portal(&self) -> &str {
+    &self.portal
+  }
+
+  pub fn connect_method(&self) -> Option<&str> {
+    self.connect_method.as_deref()
+  }
+
+  pub fn agent_settings(&self) -> &AgentSettings {
+    &self.agent_settings
+  }
+
+  pub fn saved_at(&self) -> u64 {
+    self.saved_at
+  }
+
+  /// The gateways in the order to try them: the ones with a priority rule for the region or `Any`
+  /// by the rule priority, then the others by their own priority
+  pub fn gateways_by_priority(&self) -> Vec<&Gateway> {
+    let region = self.region.as_deref().unwrap_or("Any");
+    let mut gateways = self.gateways.iter().collect::<Vec<_>>();
+
+    gateways.sort_by_key(|gateway| {
+      let rule_priority = gateway
+        .priority_rules
+        .iter()
+        .filter(|rule| rule.name == region || rule.name == "Any")
+        .map(|rule| rule.priority)
+        .min();
+
+      (
+        rule_priority.is_none(),
+        rule_priority.unwrap_or(u32::MAX),
+        gateway.priority,
+      )
+    });
+
+    gateways
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct GatewayCache {
+  version: u32,
+  #[serde(default)]
+  entries: Vec<CachedGateways>,
+}
+
+impl Default for GatewayCache {
+  fn default() -> Self {
+    Self {
+      version: CACHE_VERSION,
+      entries: vec![],
+    }
+  }
+}
+
+pub fn cache_path(custom: Option<&str>) -> PathBuf {
+  if let Some(p) = custom {
+    return PathBuf::from(p);
+  }
+  let home = std::env::var("HOME").unwrap_or_else(|_| "/root".to_string());
+  PathBuf::from(home).join(".config/gpclient/gateways.json")
+}
+
+pub fn load(path: &Path, portal: &str) -> Option<CachedGateways> {
+  let portal = portal_key(portal);
+
+  read_cache(path)
+    .entries
+    .into_iter()
+    .find(|entry| entry.portal == portal && !entry.gateways.is_empty())
+}
+
+/// The cached portals with gateways, the most recently saved first
+pub fn list(path: &Path) -> Vec<CachedGateways> {
+  let mut entries = read_cache(path)
+    .entries
+    .into_iter()
+    .filter(|entry| !entry.gateways.is_empty())
+    .collect::<Vec<_>>();
+  entries.sort_by_key(|entry| std::cmp::Reverse(entry.saved_at));
+  entries
+}
+
+/// Replace the entry of the portal
+pub fn save(path: &Path, cached: &CachedGateways) -> anyhow::Result<()> {
+  let mut cache = read_cache(path);
+  cache.entries.retain(|entry| entry.portal != cached.portal);
+  cache.entries.push(cached.clone());
+
+  let parent = path
+    .parent()
+    .ok_or_else(|| anyhow::anyhow!("gateway cache path has no parent directory"))?;
+  fs::create_dir_all(parent)?;
+
+  let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+  tmp.write_all(&serde_json::to_vec_pretty(&cache)?)?;
+  tmp.flush()?;
+  tmp.persist(path)?;
+  Ok(())
+}
+
+fn portal_key(portal: &str) -> String {
+  normalize_server(portal)
+    .map(|portal| remove_url_scheme(&portal))
+    .unwrap_or_else(|_| portal.to_string())
+}
+
+fn read_cache(path: &Path) -> GatewayCache {
+  let Ok(bytes) = fs::read(path) else {
+    return GatewayCache::default();
+  };
+
+  match serde_json::from_slice::<GatewayCache>(&bytes) {
+    Ok(cache) if cache.version == CACHE_VERSION => cache,
+    Ok(_) | Err(_) => {
+      warn!("Ignoring the unreadable gateway cache {}", path.display());
+      GatewayCache::default()
+    }
+  }
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::gateway::PriorityRule;
+
+  use super::*;
+
+  fn gateway(name: &str, priority: u32, rules: &[(&str, u32)]) -> Gateway {
+    let mut gateway = Gateway::new(name.to_string(), format!("{name}.example.com"));
+    gateway.priority = priority;
+    gateway.priority_rules = rules
+      .iter()
+      .map(|(name, priority)| PriorityRule {
+        name: name.to_string(),
+        priority: *priority,
+      })
+      .collect();
+    gateway
+  }
+
+  fn cached(portal: &str, region: Option<&str>, gateways: Vec<Gateway>) -> CachedGateways {
+    CachedGateways {
+      portal: portal_key(portal),
+      gateways,
+      connect_method: Some("on-demand".to_string()),
+      region: region.map(String::from),
+      agent_settings: AgentSettings::default(),
+      saved_at: 1,
+    }
+  }
+
+  fn names(gateways: Vec<&Gateway>) -> Vec<&str> {
+    gateways.into_iter().map(|gateway| gateway.name()).collect()
+  }
+
+  #[test]
+  fn gateways_are_ordered_by_the_region_rules_first() {
+    let cached = cached(
+      "vpn.example.com",
+      Some("US"),
+      vec![
+        gateway("eu", 1, &[("EU", 1)]),
+        gateway("us", 3, &[("US", 2)]),
+        gateway("any", 2, &[("Any", 5)]),
+        gateway("plain", 0, &[]),
+      ],
+    );
+
+    assert_eq!(names(cached.gateways_by_priority()), vec!["us", "any", "plain", "eu"]);
+  }
+
+  #[test]
+  fn gateways_without_region_use_any_rules_then_priority() {
+    let cached = cached(
+      "vpn.example.com",
+      None,
+      vec![
+        gateway("b", 2, &[]),
+        gateway("a", 1, &[]),
+        gateway("any", 9, &[("Any", 1)]),
+      ],
+    );
+
+    assert_eq!(names(cached.gateways_by_priority()), vec!["any", "a", "b"]);
+  }
+
+  #[test]
+  fn save_replaces_the_entry_of_the_portal() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("gateways.json");
+
+    save(&path, &cached("vpn.example.com", None, vec![gateway("old", 1, &[])])).unwrap();
+    save(
+      &path,
+      &cached("other.example.com", None, vec![gateway("other", 1, &[])]),
+    )
+    .unwrap();
+    save(
+      &path,
+      &cached("https://vpn.example.com", None, vec![gateway("new", 1, &[])]),
+    )
+    .unwrap();
+
+    let loaded = load(&path, "vpn.example.com").unwrap();
+    assert_eq!(names(loaded.gateways_by_priority()), vec!["new"]);
+    assert_eq!(loaded.connect_method(), Some("on-demand"));
+    assert!(load(&path, "other.example.com").is_some());
+    assert!(load(&path, "unknown.example.com").is_none());
+  }
+
+  #[test]
+  fn entry_without_gateways_is_not_loaded() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("gateways.json");
+
+    save(&path, &cached("vpn.example.com", None, vec![])).unwrap();
+    assert!(load(&path, "vpn.example.com").is_none());
+  }
+
+  #[test]
+  fn unreadable_cache_is_ignored() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("gateways.json");
+    fs::write(&path, "{").unwrap();
+
+    assert!(load(&path, "vpn.example.com").is_none());
+  }
+}
@@ -1,6 +1,11 @@
+mod agent_settings;
+pub mod client_version;
 mod config;
 pub(crate) mod csc;
+pub mod gateway_cache;
 mod prelogin;
 
+pub use agent_settings::{AgentSettings, DisableOverride, UserOverride};
 pub use config::*;
+pub use csc::{CscAnswers, CscChecks};
 pub use prelogin::*;
@@ -1,18 +1,25 @@
+use std::sync::Arc;
+
 use anyhow::{anyhow, bail};
 use log::{debug, info, warn};
-use reqwest::{Client, StatusCode};
+use reqwest::{Client, RequestBuilder, StatusCode};
 use serde::Serialize;
 use specta::Type;
-use xmltree::Element;
 
 use crate::{
   error::PortalError,
   gp_params::GpParams,
+  kerberos::{SpnegoProvider, SystemSpnego, is_negotiate_challenge, negotiate_header},
   os_profile::PreloginBrowserMode,
   params::{gateway_prelogin, portal_prelogin},
-  utils::{base64, normalize_server, parse_gp_response, xml::ElementExt},
+  utils::{
+    base64, http_record, normalize_server, parse_gp_response,
+    xml::{self, ElementExt},
+  },
 };
 
+use super::client_version::{detect_upgrade_required, xml_error_message};
+
 #[derive(Debug, Serialize, Type, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct SamlPrelogin {
@@ -64,11 +71,36 @@ impl StandardPrelogin {
   }
 }
 
+/// The result of a Kerberos (SPNEGO) prelogin, the server already authenticated the user
+#[derive(Debug, Serialize, Type, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct KerberosPrelogin {
+  region: String,
+  is_gateway: bool,
+  username: String,
+  prelogin_cookie: String,
+}
+
+impl KerberosPrelogin {
+  pub fn region(&self) -> &str {
+    &self.region
+  }
+
+  pub fn username(&self) -> &str {
+    &self.username
+  }
+
+  pub fn prelogin_cookie(&self) -> &str {
+    &self.prelogin_cookie
+  }
+}
+
 #[derive(Debug, Serialize, Type, Clone)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum Prelogin {
   Saml(SamlPrelogin),
   Standard(StandardPrelogin),
+  Kerberos(KerberosPrelogin),
 }
 
 impl Prelogin {
@@ -76,6 +108,7 @@ impl Prelogin {
     match self {
       Prelogin::Saml(saml) => saml.region(),
       Prelogin::Standard(standard) => standard.region(),
+      Prelogin::Kerberos(kerberos) => kerberos.region(),
     }
   }
 
@@ -83,14 +116,16 @@ impl Prelogin {
     match self {
       Prelogin::Saml(saml) => saml.is_gateway,
       Prelogin::Standard(standard) => standard.is_gateway,
+      Prelogin::Kerberos(kerberos) => kerberos.is_gateway,
     }
   }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Clone, Default)]
 pub struct PreloginOptions {
   external_browser_requested: bool,
   gateway_external_browser_allowed: bool,
+  spnego: Option<Arc<dyn SpnegoProvider>>,
 }
 
 impl PreloginOptions {
@@ -104,7 +139,23 @@ impl PreloginOptions {
     self
   }
 
-  fn browser_mode(self, is_gateway: bool) -> PreloginBrowserMode {
+  /// Answer a `WWW-Authenticate: Negotiate` challenge with a Kerberos service ticket of the system,
+  /// Kerberos support is advertised on every OS when the GSSAPI library is available
+  pub fn kerberos(self, kerberos: bool) -> Self {
+    self.spnego_provider(kerberos.then(|| Arc::new(SystemSpnego) as Arc<dyn SpnegoProvider>))
+  }
+
+  /// Answer a `WWW-Authenticate: Negotiate` challenge with the token of the provider
+  pub fn spnego_provider(mut self, spnego: Option<Arc<dyn SpnegoProvider>>) -> Self {
+    self.spnego = spnego;
+    self
+  }
+
+  fn advertises_kerberos(&self) -> bool {
+    self.spnego.as_ref().is_some_and(|spnego| spnego.is_available())
+  }
+
+  fn browser_mode(&self, is_gateway: bool) -> PreloginBrowserMode {
     if self.external_browser_requested && (!is_gateway || self.gateway_external_browser_allowed) {
       PreloginBrowserMode::External
     } else {
@@ -122,11 +173,16 @@ pub async fn prelogin(portal: &str, gp_params: &GpParams, options: PreloginOptio
   let path = if is_gateway { "ssl-vpn" } else { "global-protect" };
   let prelogin_url = format!("{portal}/{}/prelogin.esp", path);
   let browser_mode = options.browser_mode(is_gateway);
-  let request_params = if is_gateway {
+  let mut request_params = if is_gateway {
     gateway_prelogin::build(gp_params.os_profile(), browser_mode)
   } else {
     portal_prelogin::build(gp_params.os_profile(), browser_mode)
   };
+  // The official Linux client has no Kerberos support, the portal only challenges the clients
+  // that advertise it
+  if options.advertises_kerberos() && request_param(&request_params, "kerberos-support").is_none() {
+    request_params.query.push(("kerberos-support".into(), "yes".into()));
+  }
   let default_browser = request_param(&request_params, "default-browser").unwrap_or("");
 
   info!(
@@ -135,21 +191,51 @@ pub async fn prelogin(portal: &str, gp_params: &GpParams, options: PreloginOptio
   );
 
   let client = Client::try_from(gp_params)?;
+  let build_request = |kerberos_support: bool| {
+    let query = request_params
+      .query
+      .iter()
+      .filter(|(key, _)| kerberos_support || key != "kerberos-support")
+      .collect::<Vec<_>>();
 
-  let mut request = client.post(&prelogin_url);
-  if !request_params.query.is_empty() {
-    request = request.query(&request_params.query);
-  }
-  if !request_params.body.is_empty() {
-    request = request.form(&request_params.body);
-  }
+    let mut request = client.post(&prelogin_url);
+    if !query.is_empty() {
+      request = request.query(&query);
+    }
+    if !request_params.body.is_empty() {
+      request = request.form(&request_params.body);
+    }
+    request
+  };
+  let send_prelogin = |kerberos_support: bool| async move {
+    http_record::send("prelogin", build_request(kerberos_support))
+      .await
+      .map_err(|e| {
+        warn!("Network error: {:?}", e);
+        anyhow::anyhow!(PortalError::NetworkError(e))
+      })
+  };
 
-  let res = request.send().await.map_err(|e| {
-    warn!("Network error: {:?}", e);
-    anyhow::anyhow!(PortalError::NetworkError(e))
-  })?;
+  let mut res = send_prelogin(true).await?;
+
+  if is_negotiate_challenge(res.headers()) {
+    if let Some(spnego) = &options.spnego {
+      info!("{} offers Kerberos authentication", prelogin_type);
+      match kerberos_prelogin(build_request(true), Arc::clone(spnego), &portal, is_gateway).await {
+        Ok(prelogin) => return Ok(prelogin),
+        Err(err) => warn!("Kerberos authentication failed, falling back: {}", err),
+      }
+    }
+
+    // The challenge carries no prelogin, SAML or the standard form is offered without Kerberos
+    res = send_prelogin(false).await?;
+  }
 
   let res_xml = parse_gp_response(res).await.or_else(|err| {
+    if let Some(upgrade_required) = err.client_upgrade_required() {
+      bail!(upgrade_required)
+    }
+
     if err.status == StatusCode::NOT_FOUND {
       bail!(PortalError::PreloginError("Prelogin endpoint not found".to_string()))
     }
@@ -166,12 +252,79 @@ pub async fn prelogin(portal: &str, gp_params: &GpParams, options: PreloginOptio
 
   let prelogin = parse_res_xml(&res_xml, is_gateway).map_err(|err| {
     warn!("Parse response error, response: {}", res_xml);
-    PortalError::PreloginError(err.to_string())
+    match xml_error_message(&res_xml).and_then(|message| detect_upgrade_required(&message)) {
+      Some(upgrade_required) => anyhow!(upgrade_required),
+      None => anyhow!(PortalError::PreloginError(err.to_string())),
+    }
   })?;
 
   Ok(prelogin)
 }
 
+async fn kerberos_prelogin(
+  request: RequestBuilder,
+  spnego: Arc<dyn SpnegoProvider>,
+  server: &str,
+  is_gateway: bool,
+) -> anyhow::Result<Prelogin> {
+  let host = url::Url::parse(server)?
+    .host_str()
+    .ok_or_else(|| anyhow!("Invalid server: {}", server))?
+    .to_string();
+
+  // Acquiring the ticket may contact the KDC, keep it off the async workers
+  let token = tokio::task::spawn_blocking(move || spnego.initial_token(&host)).await??;
+
+  send_kerberos_prelogin(request, &token, is_gateway).await
+}
+
+async fn send_kerberos_prelogin(request: RequestBuilder, token: &[u8], is_gateway: bool) -> anyhow::Result<Prelogin> {
+  let res = http_record::send(
+    "prelogin-kerberos",
+    request.header(reqwest::header::AUTHORIZATION, negotiate_header(token)),
+  )
+  .await?;
+  let res_xml = parse_gp_response(res).await.map_err(|err| anyhow!("{}", err.reason))?;
+
+  debug!("Kerberos prelogin response XML: {}", res_xml);
+
+  parse_kerberos_res_xml(&res_xml, is_gateway)
+}
+
+/// A successful SPNEGO prelogin answers with the authenticated user and a prelogin cookie,
+/// the same pair the SAML callback carries
+fn parse_kerberos_res_xml(res_xml: &str, is_gateway: bool) -> anyhow::Result<Prelogin> {
+  let root = xml::parse(res_xml)?;
+
+  let status = root.descendant_text("status").unwrap_or_default();
+  if status.to_uppercase() != "SUCCESS" {
+    let msg = root
+      .descendant_text("msg")
+      .unwrap_or_else(|| String::from("Unknown error"));
+    bail!("{}", msg)
+  }
+
+  let username = root
+    .descendant_text("username")
+    .or_else(|| root.descendant_text("saml-username"))
+    .filter(|username| !username.is_empty());
+  let prelogin_cookie = root
+    .descendant_text("prelogin-cookie")
+    .filter(|cookie| !cookie.is_empty());
+  let (Some(username), Some(prelogin_cookie)) = (username, prelogin_cookie) else {
+    bail!("The server did not accept the Kerberos ticket")
+  };
+
+  Ok(Prelogin::Kerberos(KerberosPrelogin {
+    region: root
+      .descendant_text("region")
+      .unwrap_or_else(|| String::from("Unknown")),
+    is_gateway,
+    username,
+    prelogin_cookie,
+  }))
+}
+
 fn request_param<'a>(params: &'a crate::params::RequestParams, name: &str) -> Option<&'a str> {
   params
     .body
@@ -180,8 +333,8 @@ fn request_param<'a>(params: &'a crate::params::RequestParams, name: &str) -> Op
     .find_map(|(key, value)| (key == name).then_some(value.as_str()))
 }
 
-fn parse_res_xml(res_xml: &str, is_gateway: bool) -> anyhow::Result<Prelogin> {
-  let root = Element::parse(res_xml.as_bytes())?;
+pub(crate) fn parse_res_xml(res_xml: &str, is_gateway: bool) -> anyhow::Result<Prelogin> {
+  let root = xml::parse(res_xml)?;
 
   let status = root
     .descendant_text("status")
@@ -242,9 +395,17 @@ fn parse_res_xml(res_xml: &str, is_gateway: bool) -> anyhow::Result<Prelogin> {
 
 #[cfg(test)]
 mod tests {
-  use super::PreloginOptions;
+  use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::Arc,
+    thread::{self, JoinHandle},
+  };
+
+  use super::{Prelogin, PreloginOptions, parse_kerberos_res_xml, parse_res_xml, prelogin};
   use crate::{
     gp_params::GpParams,
+    kerberos::{SpnegoProvider, negotiate_header},
     os_profile::{ClientOs, HostIdentity, OsProfile, PreloginBrowserMode},
     params::{gateway_prelogin, portal_prelogin},
   };
@@ -316,4 +477,321 @@ mod tests {
 
     assert_eq!(options.browser_mode(true), PreloginBrowserMode::External);
   }
+
+  #[test]
+  fn parses_kerberos_prelogin_success() {
+    let res_xml = r#"<?xml version="1.0" encoding="UTF-8" ?>
+<prelogin-response>
+  <status>Success</status>
+  <region>US</region>
+  <username>alice@EXAMPLE.COM</username>
+  <prelogin-cookie>kerberos-cookie</prelogin-cookie>
+</prelogin-response>"#;
+
+    let Prelogin::Kerberos(prelogin) = parse_kerberos_res_xml(res_xml, true).unwrap() else {
+      panic!("expected a Kerberos prelogin");
+    };
+    assert_eq!(prelogin.username(), "alice@EXAMPLE.COM");
+    assert_eq!(prelogin.prelogin_cookie(), "kerberos-cookie");
+    assert_eq!(prelogin.region(), "US");
+    assert!(prelogin.is_gateway);
+  }
+
+  #[test]
+  fn kerberos_prelogin_without_cookie_is_rejected() {
+    let res_xml = r#"<prelogin-response>
+  <status>Success</status>
+  <saml-auth-method>REDIRECT</saml-auth-method>
+  <saml-request>aHR0cHM6Ly9pZHA=</saml-request>
+</prelogin-response>"#;
+
+    assert!(parse_kerberos_res_xml(res_xml, false).is_err());
+  }
+
+  #[test]
+  fn kerberos_prelogin_error_reports_message() {
+    let res_xml = r#"<prelogin-response>
+  <status>Error</status>
+  <msg>Kerberos ticket validation failed</msg>
+</prelogin-response>"#;
+
+    let err = parse_kerberos_res_xml(res_xml, false).unwrap_err();
+    assert_eq!(err.to_string(), "Kerberos ticket validation failed");
+  }
+
+  struct FakeSpnego(Option<&'static [u8]>);
+
+  impl SpnegoProvider for FakeSpnego {
+    fn initial_token(&self, _host: &str) -> anyhow::Result<Vec<u8>> {
+      self
+        .0
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow::anyhow!("No Kerberos credentials available"))
+    }
+  }
+
+  const NEGOTIATE_CHALLENGE: &str =
+    "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Negotiate\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+  fn ok_response(body: &str) -> String {
+    format!(
+      "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+      body.len(),
+      body
+    )
+  }
+
+  /// Answer the requests with the responses in order, the request heads are returned lowercased
+  fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let handle = thread::spawn(move || {
+      responses
+        .into_iter()
+        .map(|response| {
+          let (mut stream, _) = listener.accept().unwrap();
+          let mut reader = BufReader::new(stream.try_clone().unwrap());
+          let mut head = String::new();
+          // Up to the blank line ending the head
+          while reader.read_line(&mut head).unwrap() > 2 {}
+          let head = head.to_lowercase();
+          let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .map_or(0, |length| length.trim().parse().unwrap());
+          reader.read_exact(&mut vec![0; length]).unwrap();
+
+          stream.write_all(response.as_bytes()).unwrap();
+          head
+        })
+        .collect()
+    });
+
+    (url, handle)
+  }
+
+  fn spnego(token: Option<&'static [u8]>) -> PreloginOptions {
+    PreloginOptions::default().spnego_provider(Some(Arc::new(FakeSpnego(token))))
+  }
+
+  #[tokio::test]
+  async fn answers_a_negotiate_challenge_with_the_token() {
+    let (url, server) = serve(vec![
+      NEGOTIATE_CHALLENGE.to_string(),
+      ok_response(
+        "<prelogin-response><status>Success</status><username>alice@EXAMPLE.COM</username>\
+         <prelogin-cookie>kerberos-cookie</prelogin-cookie></prelogin-response>",
+      ),
+    ]);
+
+    let prelogin = prelogin(&url, &gp_params(ClientOs::Mac), spnego(Some(b"ticket")))
+      .await
+      .unwrap();
+
+    let Prelogin::Kerberos(prelogin) = prelogin else {
+      panic!("expected a Kerberos prelogin");
+    };
+    assert_eq!(prelogin.prelogin_cookie(), "kerberos-cookie");
+
+    let requests = server.join().unwrap();
+    assert!(!requests[0].contains("authorization:"));
+    let authorization = format!("authorization: {}", negotiate_header(b"ticket")).to_lowercase();
+    assert!(requests[1].contains(&authorization));
+  }
+
+  #[tokio::test]
+  async fn reissues_the_prelogin_without_kerberos_when_no_ticket_is_available() {
+    let (url, server) = serve(vec![
+      NEGOTIATE_CHALLENGE.to_string(),
+      ok_response("<prelogin-response><status>Success</status><region>US</region></prelogin-response>"),
+    ]);
+
+    let prelogin = prelogin(&url, &gp_params(ClientOs::Mac), spnego(None)).await.unwrap();
+
+    assert!(matches!(prelogin, Prelogin::Standard(_)));
+    let requests = server.join().unwrap();
+    assert!(requests[0].contains("kerberos-support=yes"));
+    assert!(!requests[1].contains("kerberos-support"));
+    assert!(!requests[1].contains("authorization:"));
+  }
+
+  #[tokio::test]
+  async fn advertises_kerberos_on_linux_with_a_provider() {
+    let (url, server) = serve(vec![
+      ok_response("<prelogin-response><status>Success</status><region>US</region></prelogin-response>"),
+      ok_response("<prelogin-response><status>Success</status><region>US</region></prelogin-response>"),
+    ]);
+
+    prelogin(&url, &gp_params(ClientOs::Linux), spnego(None)).await.unwrap();
+    prelogin(&url, &gp_params(ClientOs::Linux), PreloginOptions::default())
+      .await
+      .unwrap();
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].contains("kerberos-support=yes"));
+    assert!(!requests[1].contains("kerberos-support"));
+  }
+
+  /// Obtains a real service ticket from a throwaway MIT KDC, needs the KDC and the client tools,
+  /// e.g., the `krb5-kdc`, `krb5-admin-server` and `krb5-user` packages:
+  ///
+  /// ```sh
+  /// cargo test -p gpapi kerberos_with_mit_kdc -- --ignored
+  /// ```
+  #[tokio::test]
+  #[ignore]
+  async fn kerberos_with_mit_kdc() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir_path = dir.path().display().to_string();
+    let kdc_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+    std::fs::write(
+      dir.path().join("krb5.conf"),
+      format!(
+        "[libdefaults]\n default_realm = GP.TEST\n dns_lookup_kdc = false\n dns_lookup_realm = false\n \
+         dns_canonicalize_hostname = false\n rdns = false\n\
+         [realms]\n GP.TEST = {{\n  kdc = 127.0.0.1:{kdc_port}\n }}\n\
+         [domain_realm]\n localhost = GP.TEST\n"
+      ),
+    )
+    .unwrap();
+    std::fs::write(
+      dir.path().join("kdc.conf"),
+      format!(
+        "[realms]\n GP.TEST = {{\n  database_name = {dir_path}/principal\n  key_stash_file = {dir_path}/stash\n  \
+         kdc_listen = {kdc_port}\n  kdc_tcp_listen = {kdc_port}\n }}\n"
+      ),
+    )
+    .unwrap();
+
+    // SAFETY: the ignored test is run on its own, GSSAPI reads the environment of the process
+    unsafe {
+      std::env::set_var("KRB5_CONFIG", dir.path().join("krb5.conf"));
+      std::env::set_var("KRB5_KDC_PROFILE", dir.path().join("kdc.conf"));
+      std::env::set_var("KRB5CCNAME", format!("FILE:{dir_path}/ccache"));
+    }
+
+    let run = |program: &str, args: &[&str]| {
+      let status = std::process::Command::new(program).args(args).status().unwrap();
+      assert!(status.success(), "{program} {args:?} failed");
+    };
+    let keytab = format!("{dir_path}/alice.keytab");
+    run("kdb5_util", &["create", "-s", "-r", "GP.TEST", "-P", "master"]);
+    run("kadmin.local", &["-q", "addprinc -randkey alice"]);
+    run("kadmin.local", &["-q", "addprinc -randkey HTTP/localhost"]);
+    run("kadmin.local", &["-q", &format!("ktadd -k {keytab} alice")]);
+
+    let mut kdc = std::process::Command::new("krb5kdc").arg("-n").spawn().unwrap();
+    thread::sleep(std::time::Duration::from_secs(1));
+    run("kinit", &["-kt", &keytab, "alice"]);
+
+    let (url, server) = serve(vec![
+      NEGOTIATE_CHALLENGE.to_string(),
+      ok_response(
+        "<prelogin-response><status>Success</status><username>alice@GP.TEST</username>\
+         <prelogin-cookie>kerberos-cookie</prelogin-cookie></prelogin-response>",
+      ),
+    ]);
+    let url = url.replace("127.0.0.1", "localhost");
+    let options = PreloginOptions::default().kerberos(true);
+    let result = prelogin(&url, &gp_params(ClientOs::Linux), options).await;
+
+    let klist = std::process::Command::new("klist").output().unwrap();
+    kdc.kill().unwrap();
+    kdc.wait().unwrap();
+
+    assert!(matches!(result.unwrap(), Prelogin::Kerberos(_)));
+    let requests = server.join().unwrap();
+    assert!(requests[0].contains("kerberos-support=yes"));
+    assert!(requests[1].contains("authorization: negotiate "));
+    assert!(String::from_utf8_lossy(&klist.stdout).contains("HTTP/localhost@GP.TEST"));
+  }
+
+  #[tokio::test]
+  async fn reissues_the_prelogin_without_kerberos_when_disabled() {
+    let (url, server) = serve(vec![
+      NEGOTIATE_CHALLENGE.to_string(),
+      ok_response("<prelogin-response><status>Success</status><region>US</region></prelogin-response>"),
+    ]);
+
+    let prelogin = prelogin(&url, &gp_params(ClientOs::Mac), PreloginOptions::default())
+      .await
+      .unwrap();
+
+    assert!(matches!(prelogin, Prelogin::Standard(_)));
+    assert_eq!(server.join().unwrap().len(), 2);
+  }
+
+  mod prop_tests {
+    use super::*;
+    use crate::utils::{base64, xml::strategy};
+    use proptest::prelude::*;
+
+    const NAMES: &[&str] = &[
+      "prelogin-response",
+      "status",
+      "msg",
+      "region",
+      "saml-auth-method",
+      "saml-request",
+      "saml-default-browser",
+      "username-label",
+      "password-label",
+      "authentication-message",
+    ];
+
+    proptest! {
+      #[test]
+      fn parse_res_xml_never_panics(xml in strategy::malformed(NAMES), is_gateway in any::<bool>()) {
+        let _ = parse_res_xml(&xml, is_gateway);
+      }
+
+      /// Only the status is required, every other field falls back to a default
+      #[test]
+      fn success_response_with_fields_missing_is_standard_prelogin(
+        region in proptest::option::of("[A-Za-z0-9_-]{1,16}"),
+        username_label in proptest::option::of("[A-Za-z][A-Za-z ]{0,14}[A-Za-z]"),
+        auth_message in proptest::option::of("[A-Za-z][A-Za-z ]{0,30}[A-Za-z]"),
+      ) {
+        let field = |name: &str, value: &Option<String>| {
+          value.as_ref().map(|value| format!("<{name}>{value}</{name}>")).unwrap_or_default()
+        };
+        let xml = format!(
+          "<prelogin-response><status>Success</status>{}{}{}</prelogin-response>",
+          field("region", &region),
+          field("username-label", &username_label),
+          field("authentication-message", &auth_message),
+        );
+
+        let Prelogin::Standard(prelogin) = parse_res_xml(&xml, false).unwrap() else {
+          panic!("expected a standard prelogin");
+        };
+        prop_assert_eq!(prelogin.region(), region.as_deref().unwrap_or("Unknown"));
+        prop_assert_eq!(prelogin.label_username(), username_label.as_deref().unwrap_or("Username"));
+        prop_assert_eq!(prelogin.label_password(), "Password");
+        prop_assert_eq!(
+          prelogin.auth_message(),
+          auth_message.as_deref().unwrap_or("Please enter the login credentials")
+        );
+      }
+
+      /// A SAML request that is not base64 of UTF-8 is an error, not a panic
+      #[test]
+      fn saml_request_in_an_unexpected_encoding_is_rejected(request in prop::collection::vec(any::<u8>(), 0..256)) {
+        let xml = format!(
+          "<prelogin-response><status>Success</status><saml-auth-method>REDIRECT</saml-auth-method><saml-request>{}</saml-request></prelogin-response>",
+          base64::encode(&request)
+        );
+
+        match (parse_res_xml(&xml, false), String::from_utf8(request)) {
+          (Ok(Prelogin::Saml(prelogin)), Ok(request)) => prop_assert_eq!(prelogin.saml_request(), request),
+          // An empty element has no text, so the response is not treated as SAML
+          (Ok(Prelogin::Standard(_)), Ok(request)) => prop_assert!(request.is_empty()),
+          (Err(_), Err(_)) => {}
+          (result, request) => prop_assert!(false, "unexpected {:?} for {:?}", result.map(|_| ()), request),
+        }
+      }
+    }
+  }
 }
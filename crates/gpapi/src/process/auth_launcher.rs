@@ -31,6 +31,8 @@ pub struct SamlAuthLauncher<'a> {
   #[cfg(feature = "webview-auth")]
   default_browser: bool,
   browser: Option<&'a str>,
+  remote_bind: Option<&'a str>,
+  remote_timeout: Option<u64>,
   verbose: Option<&'a str>,
   log_format: LogFormat,
 }
@@ -57,6 +59,8 @@ impl<'a> SamlAuthLauncher<'a> {
       #[cfg(feature = "webview-auth")]
       default_browser: false,
       browser: None,
+      remote_bind: None,
+      remote_timeout: None,
       verbose: None,
       log_format: LogFormat::Text,
     }
@@ -132,6 +136,18 @@ impl<'a> SamlAuthLauncher<'a> {
     self
   }
 
+  /// The address the `remote` browser authentication listens on
+  pub fn remote_bind(mut self, remote_bind: Option<&'a str>) -> Self {
+    self.remote_bind = remote_bind;
+    self
+  }
+
+  /// Seconds the `remote` browser authentication waits for the authentication data
+  pub fn remote_timeout(mut self, remote_timeout: Option<u64>) -> Self {
+    self.remote_timeout = remote_timeout;
+    self
+  }
+
   /// Render the child's logs the same way as ours.
   ///
   /// gpauth inherits this process's stderr, so both processes must use the same
@@ -214,6 +230,14 @@ impl<'a> SamlAuthLauncher<'a> {
       auth_cmd.arg("--browser").arg(browser);
     }
 
+    if let Some(remote_bind) = self.remote_bind {
+      auth_cmd.arg("--remote-bind").arg(remote_bind);
+    }
+
+    if let Some(remote_timeout) = self.remote_timeout {
+      auth_cmd.arg("--remote-timeout").arg(remote_timeout.to_string());
+    }
+
     auth_cmd.arg("--log-format").arg(self.log_format.as_str());
 
     if let Some(verbose) = self.verbose {
@@ -333,4 +357,20 @@ mod tests {
     assert_eq!(launcher.sslkey, Some("/tmp/client.key"));
     assert_eq!(launcher.key_password, Some("secret"));
   }
+
+  #[test]
+  fn remote_options_are_forwarded_to_the_child() {
+    let args = child_args(
+      SamlAuthLauncher::new("portal.example.com")
+        .browser(Some("remote"))
+        .remote_bind(Some("0.0.0.0:8443"))
+        .remote_timeout(Some(120)),
+    );
+
+    assert!(args.windows(2).any(|pair| pair == ["--remote-bind", "0.0.0.0:8443"]));
+    assert!(args.windows(2).any(|pair| pair == ["--remote-timeout", "120"]));
+
+    let args = child_args(SamlAuthLauncher::new("portal.example.com"));
+    assert!(!args.iter().any(|arg| arg.starts_with("--remote")));
+  }
 }
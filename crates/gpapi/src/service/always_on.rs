@@ -0,0 +1,227 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  os_profile::ClientOs,
+  portal::gateway_cache::CachedGateways,
+  utils::{normalize_server, remove_url_scheme},
+};
+
+/// The local override of the always-on mode, read by the service at launch
+pub const ALWAYS_ON_CONFIG_PATH: &str = "/etc/gpservice/always-on.toml";
+
+/// The connect methods of an always-on portal, the VPN is expected to be up from login
+const ALWAYS_ON_CONNECT_METHODS: [&str; 2] = ["user-logon", "pre-logon"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlwaysOnMode {
+  /// Follow the connect method of the portal config
+  #[default]
+  Auto,
+  /// Keep the portal connected whatever the portal config says
+  On,
+  Off,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct AlwaysOnConfig {
+  pub mode: AlwaysOnMode,
+  /// The portal to keep connected, the most recently used one when not set
+  pub portal: Option<String>,
+  /// The client OS the credentials were cached for, the runtime OS when not set
+  pub os: Option<ClientOs>,
+  /// The user the credentials were cached for, any user when not set
+  pub username: Option<String>,
+  pub min_retry_secs: u64,
+  pub max_retry_secs: u64,
+}
+
+impl Default for AlwaysOnConfig {
+  fn default() -> Self {
+    Self {
+      mode: AlwaysOnMode::Auto,
+      portal: None,
+      os: None,
+      username: None,
+      min_retry_secs: 5,
+      max_retry_secs: 300,
+    }
+  }
+}
+
+impl AlwaysOnConfig {
+  /// The cached portal to keep connected, `None` when always-on is off for every portal
+  pub fn select_portal<'a>(&self, cached: &'a [CachedGateways]) -> Option<&'a CachedGateways> {
+    if self.mode == AlwaysOnMode::Off {
+      return None;
+    }
+
+    cached
+      .iter()
+      .filter(|entry| {
+        self
+          .portal
+          .as_deref()
+          .is_none_or(|portal| same_server(portal, entry.portal()))
+      })
+      .find(|entry| self.mode == AlwaysOnMode::On || is_always_on_method(entry.connect_method()))
+  }
+
+  pub fn backoff(&self) -> Backoff {
+    Backoff::new(
+      Duration::from_secs(self.min_retry_secs.max(1)),
+      Duration::from_secs(self.max_retry_secs.max(self.min_retry_secs).max(1)),
+    )
+  }
+}
+
+pub fn is_always_on_method(connect_method: Option<&str>) -> bool {
+  connect_method.is_some_and(|method| ALWAYS_ON_CONNECT_METHODS.contains(&method))
+}
+
+/// Whether two server strings, e.g., `https://vpn.example.com/` and `vpn.example.com`, are the same server
+pub fn same_server(a: &str, b: &str) -> bool {
+  let key = |portal: &str| {
+    normalize_server(portal)
+      .map(|portal| remove_url_scheme(&portal))
+      .unwrap_or_else(|_| portal.to_string())
+  };
+
+  key(a) == key(b)
+}
+
+/// The delay before the next attempt, doubled after each failure up to the maximum
+#[derive(Debug, Clone)]
+pub struct Backoff {
+  min: Duration,
+  max: Duration,
+  next: Duration,
+}
+
+impl Backoff {
+  pub fn new(min: Duration, max: Duration) -> Self {
+    Self { min, max, next: min }
+  }
+
+  pub fn next_delay(&mut self) -> Duration {
+    let delay = self.next;
+    self.next = (self.next * 2).min(self.max);
+    delay
+  }
+
+  pub fn max(&self) -> Duration {
+    self.max
+  }
+
+  pub fn reset(&mut self) {
+    self.next = self.min;
+  }
+}
+
+pub fn config_path(custom: Option<&str>) -> PathBuf {
+  PathBuf::from(custom.unwrap_or(ALWAYS_ON_CONFIG_PATH))
+}
+
+/// The override, the defaults apply when the file does not exist or cannot be read
+pub fn load(path: &Path) -> AlwaysOnConfig {
+  let Ok(content) = fs::read_to_string(path) else {
+    return AlwaysOnConfig::default();
+  };
+
+  toml::from_str(&content).unwrap_or_else(|err| {
+    warn!("Ignoring the unreadable always-on config {}: {}", path.display(), err);
+    AlwaysOnConfig::default()
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cached(entries: &str) -> Vec<CachedGateways> {
+    serde_json::from_str(entries).unwrap()
+  }
+
+  fn entries() -> Vec<CachedGateways> {
+    cached(
+      r#"[
+        {"portal": "ondemand.example.com", "gateways": [], "connectMethod": "on-demand", "region": null, "savedAt": 3},
+        {"portal": "vpn.example.com", "gateways": [], "connectMethod": "user-logon", "region": null, "savedAt": 2}
+      ]"#,
+    )
+  }
+
+  #[test]
+  fn auto_mode_follows_the_portal_connect_method() {
+    let entries = entries();
+    let config = AlwaysOnConfig::default();
+
+    assert_eq!(config.select_portal(&entries).unwrap().portal(), "vpn.example.com");
+
+    let config = AlwaysOnConfig {
+      portal: Some("https://ondemand.example.com".to_string()),
+      ..Default::default()
+    };
+    assert!(config.select_portal(&entries).is_none());
+  }
+
+  #[test]
+  fn local_override_forces_the_mode() {
+    let entries = entries();
+    let on = AlwaysOnConfig {
+      mode: AlwaysOnMode::On,
+      ..Default::default()
+    };
+    let off = AlwaysOnConfig {
+      mode: AlwaysOnMode::Off,
+      ..Default::default()
+    };
+
+    assert_eq!(on.select_portal(&entries).unwrap().portal(), "ondemand.example.com");
+    assert!(off.select_portal(&entries).is_none());
+  }
+
+  #[test]
+  fn backoff_doubles_up_to_the_maximum() {
+    let mut backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(12));
+
+    assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(10));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(12));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(12));
+
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+  }
+
+  #[test]
+  fn loads_the_override_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("always-on.toml");
+
+    assert_eq!(load(&path), AlwaysOnConfig::default());
+
+    fs::write(
+      &path,
+      "mode = \"on\"\nportal = \"vpn.example.com\"\nos = \"Windows\"\nmax-retry-secs = 60\n",
+    )
+    .unwrap();
+    let config = load(&path);
+    assert_eq!(config.mode, AlwaysOnMode::On);
+    assert_eq!(config.portal.as_deref(), Some("vpn.example.com"));
+    assert_eq!(config.os, Some(ClientOs::Windows));
+    assert_eq!(config.min_retry_secs, 5);
+    assert_eq!(config.max_retry_secs, 60);
+
+    fs::write(&path, "mode = \"sometimes\"").unwrap();
+    assert_eq!(load(&path), AlwaysOnConfig::default());
+  }
+}
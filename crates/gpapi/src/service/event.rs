@@ -1,12 +1,62 @@
 use serde::{Deserialize, Serialize};
 
-use crate::service::{vpn_env::VpnEnv, vpn_state::VpnState};
+use crate::{
+  gateway::SessionEvent,
+  service::{
+    vpn_env::VpnEnv,
+    vpn_state::{VpnConnections, VpnState},
+  },
+  session::SessionInfo,
+};
 
 /// Events that can be emitted by the service
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum WsEvent {
   VpnEnv(VpnEnv),
+  /// The state of the default connection
   VpnState(VpnState),
+  /// The state of all named connections
+  VpnConnections(VpnConnections),
   ActiveGui,
   ResumeConnection,
+  /// The always-on connection to the portal needs interactive authentication
+  AuthRequired(String),
+  /// The session of the named connection ends soon, it is extended right away when `will_extend`
+  #[serde(rename_all = "camelCase")]
+  SessionExpiring {
+    name: String,
+    message: String,
+    will_extend: bool,
+  },
+  /// The session of the named connection was extended
+  #[serde(rename_all = "camelCase")]
+  SessionExtended {
+    name: String,
+    session_info: SessionInfo,
+  },
+  SessionExtensionFailed {
+    name: String,
+    error: String,
+  },
+  /// The portal policy does not let the user disable the named connection
+  DisconnectRefused {
+    name: String,
+    reason: String,
+  },
+}
+
+impl WsEvent {
+  /// The event to tell the clients what happened to the session of the named connection
+  pub fn session(name: &str, event: SessionEvent) -> Self {
+    let name = name.to_string();
+    match event {
+      SessionEvent::Expiring { message, will_extend } => Self::SessionExpiring {
+        name,
+        message,
+        will_extend,
+      },
+      SessionEvent::Extended(session_info) => Self::SessionExtended { name, session_info },
+      SessionEvent::ExtensionFailed(error) => Self::SessionExtensionFailed { name, error },
+    }
+  }
 }
@@ -1,3 +1,4 @@
+pub mod always_on;
 pub mod event;
 pub mod request;
 pub mod vpn_env;
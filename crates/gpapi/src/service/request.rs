@@ -4,11 +4,13 @@ use serde::{Deserialize, Serialize};
 use specta::Type;
 
 use crate::{
+  credential::AuthCookieCredential,
   gateway::Gateway,
   os_profile::{ClientOs, OsProfile},
+  portal::UserOverride,
 };
 
-use super::vpn_state::ConnectInfo;
+use super::vpn_state::{ConnectInfo, DEFAULT_CONNECTION};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LaunchGuiRequest {
@@ -34,6 +36,8 @@ impl LaunchGuiRequest {
 pub struct ConnectArgs {
   cookie: String,
   vpnc_script: Option<String>,
+  #[serde(default)]
+  interface: Option<String>,
 
   user_agent: Option<String>,
   os: Option<ClientOs>,
@@ -58,6 +62,9 @@ pub struct ConnectArgs {
   no_xmlpost: bool,
   #[serde(rename = "allowExtendSession")]
   allow_extend_session: bool,
+  /// The portal auth cookie to log in to the gateway again when extending the session
+  #[serde(default, rename = "extensionAuthCookie")]
+  extension_auth_cookie: Option<AuthCookieCredential>,
 }
 
 impl ConnectArgs {
@@ -65,6 +72,7 @@ impl ConnectArgs {
     Self {
       cookie,
       vpnc_script: None,
+      interface: None,
       user_agent: None,
       os: None,
       os_version: None,
@@ -84,6 +92,7 @@ impl ConnectArgs {
       force_dpd: 0,
       no_xmlpost: false,
       allow_extend_session: false,
+      extension_auth_cookie: None,
     }
   }
 
@@ -95,6 +104,10 @@ impl ConnectArgs {
     self.vpnc_script.clone()
   }
 
+  pub fn interface(&self) -> Option<String> {
+    self.interface.clone()
+  }
+
   pub fn user_agent(&self) -> Option<String> {
     self.user_agent.clone()
   }
@@ -174,10 +187,17 @@ impl ConnectArgs {
   pub fn allow_extend_session(&self) -> bool {
     self.allow_extend_session
   }
+
+  pub fn extension_auth_cookie(&self) -> Option<&AuthCookieCredential> {
+    self.extension_auth_cookie.as_ref()
+  }
 }
 
 #[derive(Debug, Deserialize, Serialize, Type, Clone)]
 pub struct ConnectRequest {
+  /// The connection to start, requests without a name use the default connection
+  #[serde(default)]
+  name: Option<String>,
   info: ConnectInfo,
   args: ConnectArgs,
 }
@@ -185,11 +205,22 @@ pub struct ConnectRequest {
 impl ConnectRequest {
   pub fn new(info: ConnectInfo, cookie: String) -> Self {
     Self {
+      name: None,
       info,
       args: ConnectArgs::new(cookie),
     }
   }
 
+  pub fn with_name<T: Into<Option<String>>>(mut self, name: T) -> Self {
+    self.name = name.into();
+    self
+  }
+
+  pub fn with_interface<T: Into<Option<String>>>(mut self, interface: T) -> Self {
+    self.args.interface = interface.into();
+    self
+  }
+
   pub fn with_vpnc_script<T: Into<Option<String>>>(mut self, vpnc_script: T) -> Self {
     self.args.vpnc_script = vpnc_script.into();
     self
@@ -274,6 +305,15 @@ impl ConnectRequest {
     self
   }
 
+  pub fn with_extension_auth_cookie<T: Into<Option<AuthCookieCredential>>>(mut self, auth_cookie: T) -> Self {
+    self.args.extension_auth_cookie = auth_cookie.into();
+    self
+  }
+
+  pub fn name(&self) -> &str {
+    self.name.as_deref().unwrap_or(DEFAULT_CONNECTION)
+  }
+
   pub fn gateway(&self) -> &Gateway {
     self.info.gateway()
   }
@@ -287,8 +327,47 @@ impl ConnectRequest {
   }
 }
 
+/// Disconnect the named connection, or the default one when no name is given
 #[derive(Debug, Deserialize, Serialize, Type)]
-pub struct DisconnectRequest;
+pub struct DisconnectRequest(Option<String>);
+
+impl DisconnectRequest {
+  pub fn new<T: Into<Option<String>>>(name: T) -> Self {
+    Self(name.into())
+  }
+
+  pub fn name(&self) -> &str {
+    self.0.as_deref().unwrap_or(DEFAULT_CONNECTION)
+  }
+}
+
+/// Disconnect a connection the portal does not let the user disable freely, with the comment or
+/// the passcode it requires
+#[derive(Debug, Deserialize, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct OverrideDisconnectRequest {
+  #[serde(default)]
+  name: Option<String>,
+  #[serde(flatten)]
+  user_override: UserOverride,
+}
+
+impl OverrideDisconnectRequest {
+  pub fn new<T: Into<Option<String>>>(name: T, user_override: UserOverride) -> Self {
+    Self {
+      name: name.into(),
+      user_override,
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    self.name.as_deref().unwrap_or(DEFAULT_CONNECTION)
+  }
+
+  pub fn user_override(&self) -> &UserOverride {
+    &self.user_override
+  }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateLogLevelRequest(pub String);
@@ -298,6 +377,7 @@ pub struct UpdateLogLevelRequest(pub String);
 pub enum WsRequest {
   Connect(Box<ConnectRequest>),
   Disconnect(DisconnectRequest),
+  OverrideDisconnect(OverrideDisconnectRequest),
   UpdateLogLevel(UpdateLogLevelRequest),
 }
 
@@ -349,4 +429,48 @@ mod tests {
       Some(profile.host_identity().host_id().to_string())
     );
   }
+
+  #[test]
+  fn requests_without_a_name_use_the_default_connection() {
+    let value = json!({
+      "info": test_connect_info(),
+      "args": serde_json::to_value(ConnectArgs::new("cookie".to_string())).unwrap(),
+    });
+    let req: ConnectRequest = serde_json::from_value(value).unwrap();
+    assert_eq!(req.name(), DEFAULT_CONNECTION);
+
+    let req: WsRequest = serde_json::from_value(json!({ "Disconnect": null })).unwrap();
+    let WsRequest::Disconnect(req) = req else {
+      panic!("expected a disconnect request");
+    };
+    assert_eq!(req.name(), DEFAULT_CONNECTION);
+  }
+
+  #[test]
+  fn override_disconnect_request_flattens_the_user_override() {
+    let value = json!({ "OverrideDisconnect": { "comment": "travelling" } });
+    let req: WsRequest = serde_json::from_value(value).unwrap();
+    let WsRequest::OverrideDisconnect(req) = req else {
+      panic!("expected an override disconnect request");
+    };
+
+    assert_eq!(req.name(), DEFAULT_CONNECTION);
+    assert_eq!(req.user_override().comment.as_deref(), Some("travelling"));
+    assert_eq!(req.user_override().passcode, None);
+  }
+
+  #[test]
+  fn named_request_keeps_name_and_interface() {
+    let req = ConnectRequest::new(test_connect_info(), "cookie".to_string())
+      .with_name("partner".to_string())
+      .with_interface("gp-partner".to_string());
+    let value = serde_json::to_value(&req).unwrap();
+
+    assert_eq!(value["name"], json!("partner"));
+    assert_eq!(value["args"]["interface"], json!("gp-partner"));
+    assert_eq!(
+      serde_json::to_value(DisconnectRequest::new("partner".to_string())).unwrap(),
+      json!("partner")
+    );
+  }
 }
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{os_profile::HostIdentity, service::vpn_state::VpnState};
+use crate::{
+  os_profile::HostIdentity,
+  service::vpn_state::{VpnConnections, VpnState},
+};
 
 /// The single DTO carrying all host facts gpservice ships to gpgui.
 ///
@@ -21,6 +24,10 @@ pub struct VpnEnv {
   /// The VPN connection state
   pub vpn_state: VpnState,
 
+  /// The state of all named connections
+  #[serde(default)]
+  pub connections: VpnConnections,
+
   /// The default VPN script path
   pub vpnc_script: Option<String>,
 
@@ -1,13 +1,80 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
-use crate::{gateway::Gateway, session::SessionInfo};
+use crate::{gateway::Gateway, portal::AgentSettings, session::SessionInfo};
 
 #[derive(Debug, Deserialize, Serialize, Type, Clone)]
 pub struct ConnectInfo {
   portal: String,
   gateway: Gateway,
   gateways: Vec<Gateway>,
+  /// The agent settings of the portal config to show, the service enforces the ones it cached
+  #[serde(default, rename = "agentSettings")]
+  agent_settings: AgentSettings,
+}
+
+/// The tunnel configuration pushed by the gateway
+#[derive(Debug, Deserialize, Serialize, Type, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelInfo {
+  pub ipv4: Option<String>,
+  pub netmask: Option<String>,
+  pub ipv6: Option<String>,
+  pub netmask6: Option<String>,
+  pub dns: Vec<String>,
+  pub search_domains: Vec<String>,
+  /// Routes sent through the tunnel, empty for a full tunnel
+  pub split_include: Vec<String>,
+  /// Routes kept off the tunnel
+  pub split_exclude: Vec<String>,
+  pub mtu: Option<u32>,
+}
+
+impl TunnelInfo {
+  pub fn log_summary(&self) -> String {
+    let list = |values: &[String]| {
+      if values.is_empty() {
+        "none".to_string()
+      } else {
+        values.join(",")
+      }
+    };
+    let address = |addr: &Option<String>, netmask: &Option<String>| match (addr, netmask) {
+      (Some(addr), Some(netmask)) => format!("{addr}/{netmask}"),
+      (Some(addr), None) => addr.clone(),
+      (None, _) => "none".to_string(),
+    };
+
+    format!(
+      "ipv4={}, ipv6={}, dns={}, search_domains={}, split_include={}, split_exclude={}, mtu={}",
+      address(&self.ipv4, &self.netmask),
+      address(&self.ipv6, &self.netmask6),
+      list(&self.dns),
+      list(&self.search_domains),
+      list(&self.split_include),
+      list(&self.split_exclude),
+      self.mtu.map_or_else(|| "none".to_string(), |mtu| mtu.to_string())
+    )
+  }
+}
+
+#[cfg(feature = "openconnect")]
+impl From<&openconnect::TunnelInfo> for TunnelInfo {
+  fn from(tunnel_info: &openconnect::TunnelInfo) -> Self {
+    Self {
+      ipv4: tunnel_info.ipv4.clone(),
+      netmask: tunnel_info.netmask.clone(),
+      ipv6: tunnel_info.ipv6.clone(),
+      netmask6: tunnel_info.netmask6.clone(),
+      dns: tunnel_info.dns.clone(),
+      search_domains: tunnel_info.search_domains.clone(),
+      split_include: tunnel_info.split_include.clone(),
+      split_exclude: tunnel_info.split_exclude.clone(),
+      mtu: tunnel_info.mtu,
+    }
+  }
 }
 
 #[derive(Debug, Deserialize, Serialize, Type, Clone)]
@@ -15,6 +82,8 @@ pub struct ConnectInfo {
 pub struct ConnectedInfo {
   info: Box<ConnectInfo>,
   session_info: Option<SessionInfo>,
+  #[serde(default)]
+  tunnel_info: Option<TunnelInfo>,
 }
 
 impl ConnectedInfo {
@@ -22,9 +91,19 @@ impl ConnectedInfo {
     Self {
       info: Box::new(info),
       session_info,
+      tunnel_info: None,
     }
   }
 
+  pub fn with_tunnel_info(mut self, tunnel_info: Option<TunnelInfo>) -> Self {
+    self.tunnel_info = tunnel_info;
+    self
+  }
+
+  pub fn tunnel_info(&self) -> Option<&TunnelInfo> {
+    self.tunnel_info.as_ref()
+  }
+
   pub fn info(&self) -> &ConnectInfo {
     &self.info
   }
@@ -32,6 +111,11 @@ impl ConnectedInfo {
   pub fn session_info(&self) -> Option<&SessionInfo> {
     self.session_info.as_ref()
   }
+
+  pub fn with_session_info(mut self, session_info: Option<SessionInfo>) -> Self {
+    self.session_info = session_info;
+    self
+  }
 }
 
 impl ConnectInfo {
@@ -40,23 +124,225 @@ impl ConnectInfo {
       portal,
       gateway,
       gateways,
+      agent_settings: AgentSettings::default(),
     }
   }
 
+  /// The settings to show the WS clients, the passcode hashes are dropped
+  pub fn with_agent_settings(mut self, agent_settings: AgentSettings) -> Self {
+    self.agent_settings = agent_settings.without_passcodes();
+    self
+  }
+
+  pub fn portal(&self) -> &str {
+    &self.portal
+  }
+
   pub fn gateway(&self) -> &Gateway {
     &self.gateway
   }
+
+  pub fn agent_settings(&self) -> &AgentSettings {
+    &self.agent_settings
+  }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Why a connection ended
+#[derive(Debug, Deserialize, Serialize, Type, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
+pub enum DisconnectReason {
+  /// Disconnected by the user or a disconnect request
+  UserRequested,
+  /// The session reached the lifetime set by the gateway
+  SessionExpired,
+  /// The gateway ended the session
+  GatewayLogout,
+  /// The gateway rejected the authentication cookie
+  AuthFailed,
+  /// Dead peer detection found the gateway unreachable
+  DpdTimeout,
+  /// The network connection was lost and could not be re-established
+  NetworkLost,
+  /// The tunnel was never established
+  ConnectFailed,
+  Error,
+}
+
+impl DisconnectReason {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::UserRequested => "userRequested",
+      Self::SessionExpired => "sessionExpired",
+      Self::GatewayLogout => "gatewayLogout",
+      Self::AuthFailed => "authFailed",
+      Self::DpdTimeout => "dpdTimeout",
+      Self::NetworkLost => "networkLost",
+      Self::ConnectFailed => "connectFailed",
+      Self::Error => "error",
+    }
+  }
+}
+
+impl std::fmt::Display for DisconnectReason {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+#[cfg(feature = "openconnect")]
+impl From<openconnect::VpnExitReason> for DisconnectReason {
+  fn from(reason: openconnect::VpnExitReason) -> Self {
+    use openconnect::VpnExitReason;
+
+    match reason {
+      VpnExitReason::Requested => Self::UserRequested,
+      VpnExitReason::ConnectFailed => Self::ConnectFailed,
+      VpnExitReason::AuthFailed => Self::AuthFailed,
+      VpnExitReason::SessionExpired => Self::SessionExpired,
+      VpnExitReason::GatewayLogout => Self::GatewayLogout,
+      VpnExitReason::DpdTimeout => Self::DpdTimeout,
+      VpnExitReason::NetworkLost => Self::NetworkLost,
+      VpnExitReason::Error => Self::Error,
+    }
+  }
+}
+
+/// The state of a connection.
+///
+/// `Disconnected` is sent as `"disconnected"` like before when the reason is unknown, and as
+/// `{"disconnected": "<reason>"}` when the connection has ended for a known reason.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(from = "VpnStateWire", into = "VpnStateWire")]
 pub enum VpnState {
-  Disconnected,
+  /// Not connected, with the reason when a connection has ended
+  Disconnected(Option<DisconnectReason>),
   Connecting(Box<ConnectInfo>),
   Connected(Box<ConnectedInfo>),
   Disconnecting,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum VpnStateWire {
+  Unit(UnitVpnState),
+  Tagged(TaggedVpnState),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum UnitVpnState {
+  Disconnected,
+  Disconnecting,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum TaggedVpnState {
+  Disconnected(DisconnectReason),
+  Connecting(Box<ConnectInfo>),
+  Connected(Box<ConnectedInfo>),
+}
+
+impl From<VpnStateWire> for VpnState {
+  fn from(state: VpnStateWire) -> Self {
+    match state {
+      VpnStateWire::Unit(UnitVpnState::Disconnected) => Self::Disconnected(None),
+      VpnStateWire::Unit(UnitVpnState::Disconnecting) => Self::Disconnecting,
+      VpnStateWire::Tagged(TaggedVpnState::Disconnected(reason)) => Self::Disconnected(Some(reason)),
+      VpnStateWire::Tagged(TaggedVpnState::Connecting(info)) => Self::Connecting(info),
+      VpnStateWire::Tagged(TaggedVpnState::Connected(info)) => Self::Connected(info),
+    }
+  }
+}
+
+impl From<VpnState> for VpnStateWire {
+  fn from(state: VpnState) -> Self {
+    match state {
+      VpnState::Disconnected(None) => Self::Unit(UnitVpnState::Disconnected),
+      VpnState::Disconnecting => Self::Unit(UnitVpnState::Disconnecting),
+      VpnState::Disconnected(Some(reason)) => Self::Tagged(TaggedVpnState::Disconnected(reason)),
+      VpnState::Connecting(info) => Self::Tagged(TaggedVpnState::Connecting(info)),
+      VpnState::Connected(info) => Self::Tagged(TaggedVpnState::Connected(info)),
+    }
+  }
+}
+
+/// The connection used when a request does not name one, it is the one the GUI shows
+pub const DEFAULT_CONNECTION: &str = "default";
+
+/// The longest interface name Linux accepts, `IFNAMSIZ` without the terminating NUL
+const IFNAME_MAX_LEN: usize = 15;
+
+/// Connection names end up in interface names, so keep them short and shell-safe
+pub fn is_valid_connection_name(name: &str) -> bool {
+  !name.is_empty() && name.len() <= 32 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The interface of a connection when the request does not specify one.
+///
+/// The default connection keeps the interface openconnect picks, the others get
+/// `gp-<name>`. Longer names than the 15 characters Linux allows are shortened and
+/// suffixed with a hash of the full name, so names sharing a prefix don't share an interface.
+pub fn default_interface(name: &str) -> Option<String> {
+  if name == DEFAULT_CONNECTION {
+    return None;
+  }
+
+  let interface = format!("gp-{}", name);
+  if interface.chars().count() <= IFNAME_MAX_LEN {
+    return Some(interface);
+  }
+
+  let prefix: String = interface.chars().take(IFNAME_MAX_LEN - 5).collect();
+  let hash = format!("{:x}", md5::compute(name));
+  Some(format!("{}-{}", prefix, &hash[..4]))
+}
+
+/// The state of every named connection.
+///
+/// Disconnected ones are not listed, unless they ended with a reason, which is kept until
+/// the connection is started again.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct VpnConnections {
+  states: BTreeMap<String, VpnState>,
+}
+
+impl VpnConnections {
+  pub fn get(&self, name: &str) -> VpnState {
+    self.states.get(name).cloned().unwrap_or(VpnState::Disconnected(None))
+  }
+
+  /// The state of the default connection
+  pub fn primary(&self) -> VpnState {
+    self.get(DEFAULT_CONNECTION)
+  }
+
+  /// Why the connection last ended, `None` when it is running or ended without a known reason
+  pub fn disconnect_reason(&self, name: &str) -> Option<DisconnectReason> {
+    match self.states.get(name) {
+      Some(VpnState::Disconnected(reason)) => *reason,
+      _ => None,
+    }
+  }
+
+  pub fn set(&mut self, name: &str, state: VpnState) {
+    if matches!(state, VpnState::Disconnected(None)) {
+      self.states.remove(name);
+    } else {
+      self.states.insert(name.to_string(), state);
+    }
+  }
+
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.states.keys().map(String::as_str)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.states.is_empty()
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -80,4 +366,148 @@ mod tests {
     assert_eq!(value["connected"]["sessionInfo"]["lifetimeSecs"], 43_200);
     assert_eq!(value["connected"]["sessionInfo"]["allowExtendSession"], true);
   }
+
+  #[test]
+  fn connected_state_serializes_tunnel_info() {
+    let gateway = Gateway::new("vpn".to_string(), "vpn.example.com".to_string());
+    let connect_info = ConnectInfo::new("portal.example.com".to_string(), gateway.clone(), vec![gateway]);
+    let tunnel_info = TunnelInfo {
+      ipv4: Some("10.0.0.10".to_string()),
+      dns: vec!["10.0.0.53".to_string()],
+      split_include: vec!["10.0.0.0/8".to_string()],
+      mtu: Some(1400),
+      ..Default::default()
+    };
+
+    let connected = ConnectedInfo::new(connect_info, None).with_tunnel_info(Some(tunnel_info));
+    let value = serde_json::to_value(VpnState::Connected(Box::new(connected))).unwrap();
+
+    assert_eq!(value["connected"]["tunnelInfo"]["ipv4"], "10.0.0.10");
+    assert_eq!(value["connected"]["tunnelInfo"]["splitInclude"][0], "10.0.0.0/8");
+    assert_eq!(value["connected"]["tunnelInfo"]["mtu"], 1400);
+  }
+
+  #[test]
+  fn tunnel_info_summary_lists_the_routes() {
+    let tunnel_info = TunnelInfo {
+      ipv4: Some("10.0.0.10".to_string()),
+      netmask: Some("255.255.255.255".to_string()),
+      search_domains: vec!["corp.example.com".to_string(), "example.com".to_string()],
+      split_include: vec!["10.0.0.0/8".to_string()],
+      ..Default::default()
+    };
+
+    let summary = tunnel_info.log_summary();
+
+    assert!(summary.contains("ipv4=10.0.0.10/255.255.255.255"));
+    assert!(summary.contains("ipv6=none"));
+    assert!(summary.contains("search_domains=corp.example.com,example.com"));
+    assert!(summary.contains("split_include=10.0.0.0/8"));
+    assert!(summary.contains("mtu=none"));
+  }
+
+  #[test]
+  fn disconnected_connections_are_dropped_from_the_map() {
+    let gateway = Gateway::new("vpn".to_string(), "vpn.example.com".to_string());
+    let connect_info = ConnectInfo::new("portal.example.com".to_string(), gateway.clone(), vec![gateway]);
+    let mut connections = VpnConnections::default();
+
+    connections.set("partner", VpnState::Connecting(Box::new(connect_info)));
+    assert!(matches!(connections.get("partner"), VpnState::Connecting(_)));
+    assert!(matches!(connections.primary(), VpnState::Disconnected(None)));
+
+    connections.set("partner", VpnState::Disconnected(None));
+    assert!(connections.is_empty());
+  }
+
+  #[test]
+  fn keeps_the_reason_of_an_ended_connection() {
+    let gateway = Gateway::new("vpn".to_string(), "vpn.example.com".to_string());
+    let connect_info = ConnectInfo::new("portal.example.com".to_string(), gateway.clone(), vec![gateway]);
+    let mut connections = VpnConnections::default();
+
+    connections.set(
+      "partner",
+      VpnState::Disconnected(Some(DisconnectReason::SessionExpired)),
+    );
+
+    assert!(matches!(
+      connections.get("partner"),
+      VpnState::Disconnected(Some(DisconnectReason::SessionExpired))
+    ));
+    assert_eq!(
+      connections.disconnect_reason("partner"),
+      Some(DisconnectReason::SessionExpired)
+    );
+    let value = serde_json::to_value(&connections).unwrap();
+    assert_eq!(value["states"]["partner"]["disconnected"], "sessionExpired");
+
+    connections.set("partner", VpnState::Connecting(Box::new(connect_info)));
+    assert_eq!(connections.disconnect_reason("partner"), None);
+  }
+
+  #[test]
+  fn disconnected_state_keeps_its_wire_form() {
+    assert_eq!(
+      serde_json::to_value(VpnState::Disconnected(None)).unwrap(),
+      serde_json::json!("disconnected")
+    );
+    assert_eq!(
+      serde_json::to_value(VpnState::Disconnecting).unwrap(),
+      serde_json::json!("disconnecting")
+    );
+    assert!(matches!(
+      serde_json::from_value(serde_json::json!("disconnected")).unwrap(),
+      VpnState::Disconnected(None)
+    ));
+  }
+
+  #[test]
+  fn disconnected_state_carries_the_reason() {
+    let value = serde_json::to_value(VpnState::Disconnected(Some(DisconnectReason::DpdTimeout))).unwrap();
+    assert_eq!(value, serde_json::json!({ "disconnected": "dpdTimeout" }));
+
+    assert!(matches!(
+      serde_json::from_value(value).unwrap(),
+      VpnState::Disconnected(Some(DisconnectReason::DpdTimeout))
+    ));
+  }
+
+  #[test]
+  fn connecting_state_round_trips() {
+    let gateway = Gateway::new("vpn".to_string(), "vpn.example.com".to_string());
+    let connect_info = ConnectInfo::new("portal.example.com".to_string(), gateway.clone(), vec![gateway]);
+
+    let value = serde_json::to_value(VpnState::Connecting(Box::new(connect_info))).unwrap();
+    assert_eq!(value["connecting"]["portal"], "portal.example.com");
+
+    let VpnState::Connecting(info) = serde_json::from_value(value).unwrap() else {
+      panic!("not a connecting state");
+    };
+    assert_eq!(info.portal(), "portal.example.com");
+  }
+
+  #[test]
+  fn named_connections_get_their_own_interface() {
+    assert_eq!(default_interface(DEFAULT_CONNECTION), None);
+    assert_eq!(default_interface("partner").as_deref(), Some("gp-partner"));
+    assert_eq!(default_interface("a-very-long-connection").unwrap().len(), 15);
+    assert!(
+      default_interface("a-very-long-connection")
+        .unwrap()
+        .starts_with("gp-a-very-")
+    );
+    assert_ne!(
+      default_interface("partner-europe-1"),
+      default_interface("partner-europe-2")
+    );
+  }
+
+  #[test]
+  fn validates_connection_names() {
+    assert!(is_valid_connection_name("prod_1"));
+    assert!(!is_valid_connection_name(""));
+    assert!(!is_valid_connection_name("prod net"));
+    assert!(!is_valid_connection_name("../etc"));
+  }
 }
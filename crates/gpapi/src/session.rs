@@ -18,6 +18,8 @@ pub struct SessionRequestArgs {
   sslkey: Option<String>,
   key_password: Option<String>,
   disable_ipv6: bool,
+  #[serde(default)]
+  ignore_tls_errors: bool,
 }
 
 impl SessionRequestArgs {
@@ -29,6 +31,7 @@ impl SessionRequestArgs {
       sslkey: None,
       key_password: None,
       disable_ipv6: false,
+      ignore_tls_errors: false,
     }
   }
 
@@ -57,6 +60,11 @@ impl SessionRequestArgs {
     self
   }
 
+  pub fn with_ignore_tls_errors(mut self, ignore_tls_errors: bool) -> Self {
+    self.ignore_tls_errors = ignore_tls_errors;
+    self
+  }
+
   pub fn cookie(&self) -> &str {
     &self.cookie
   }
@@ -80,6 +88,10 @@ impl SessionRequestArgs {
   pub fn disable_ipv6(&self) -> bool {
     self.disable_ipv6
   }
+
+  pub fn ignore_tls_errors(&self) -> bool {
+    self.ignore_tls_errors
+  }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, Type)]
@@ -94,6 +106,22 @@ pub struct SessionInfo {
   pub allow_extend_session: bool,
 }
 
+/// The session openconnect reports, extending it is up to the portal so it is not allowed here
+#[cfg(feature = "openconnect")]
+impl From<openconnect::VpnSessionInfo> for SessionInfo {
+  fn from(info: openconnect::VpnSessionInfo) -> Self {
+    Self::from_vpn_session_fields(
+      info.lifetime_secs,
+      info.user_expires,
+      info.lifetime_warning.map(|warning| SessionWarning {
+        prior_secs: warning.prior_secs,
+        message: warning.message,
+      }),
+      false,
+    )
+  }
+}
+
 impl SessionInfo {
   pub fn with_computed_human_times(mut self) -> Self {
     self.expires_in_human = build_human_readable_expiry(self.user_expires, self.lifetime_secs);
@@ -29,6 +29,10 @@ pub fn decrypt<T>(key: &Key, encrypted: Vec<u8>) -> anyhow::Result<T>
 where
   T: DeserializeOwned,
 {
+  if encrypted.len() < 12 {
+    anyhow::bail!("Encrypted data is too short");
+  }
+
   let cipher = ChaCha20Poly1305::new(key);
 
   let nonce = &encrypted[..12];
@@ -0,0 +1,452 @@
+//! Record the portal and gateway HTTP exchanges to a directory, or serve them back
+//! from one instead of the network, to debug a captured session offline.
+//!
+//! Each exchange is a `<seq>-<name>.json` file, the secrets are redacted before
+//! they are written. Replay serves the recordings of each name in the order they
+//! were recorded.
+
+use std::{
+  collections::{BTreeMap, HashMap, VecDeque},
+  fs,
+  path::{Path, PathBuf},
+  sync::{
+    Mutex, OnceLock,
+    atomic::{AtomicUsize, Ordering},
+  },
+};
+
+use anyhow::{Context, bail};
+use log::{info, warn};
+use regex::Regex;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use url::form_urlencoded;
+
+use super::redact::{Redaction, redact_form_params, redact_uri};
+
+#[derive(Debug, Clone)]
+pub enum HttpRecordMode {
+  Record(PathBuf),
+  Replay(PathBuf),
+}
+
+static RECORDER: OnceLock<HttpRecorder> = OnceLock::new();
+
+/// Record or replay the exchanges of this process, can only be set once
+pub fn init(mode: HttpRecordMode) -> anyhow::Result<()> {
+  let recorder = match mode {
+    HttpRecordMode::Record(dir) => HttpRecorder::record(dir)?,
+    HttpRecordMode::Replay(dir) => HttpRecorder::replay(&dir)?,
+  };
+
+  RECORDER
+    .set(recorder)
+    .map_err(|_| anyhow::anyhow!("HTTP recording is already initialized"))
+}
+
+/// Send the request, `name` identifies the exchange in the recordings
+pub(crate) async fn send(name: &str, request: RequestBuilder) -> reqwest::Result<Response> {
+  match RECORDER.get() {
+    Some(recorder) => recorder.send(name, request).await,
+    None => request.send().await,
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Exchange {
+  name: String,
+  request: RecordedRequest,
+  response: Option<RecordedResponse>,
+  /// The network error, when no response was received
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RecordedRequest {
+  method: String,
+  url: String,
+  body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RecordedResponse {
+  status: u16,
+  headers: BTreeMap<String, String>,
+  body: String,
+}
+
+enum HttpRecorder {
+  Record {
+    dir: PathBuf,
+    seq: AtomicUsize,
+    redaction: Redaction,
+  },
+  Replay(Mutex<HashMap<String, VecDeque<Exchange>>>),
+}
+
+impl HttpRecorder {
+  fn record(dir: PathBuf) -> anyhow::Result<Self> {
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create the record directory {}", dir.display()))?;
+    if fs::read_dir(&dir)?.next().is_some() {
+      bail!("The record directory {} is not empty", dir.display());
+    }
+
+    info!("Recording the HTTP exchanges to {}", dir.display());
+    Ok(Self::Record {
+      dir,
+      seq: AtomicUsize::new(1),
+      redaction: Redaction::new(),
+    })
+  }
+
+  fn replay(dir: &Path) -> anyhow::Result<Self> {
+    let mut paths = fs::read_dir(dir)
+      .with_context(|| format!("Failed to read the replay directory {}", dir.display()))?
+      .filter_map(Result::ok)
+      .map(|entry| entry.path())
+      .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+      .collect::<Vec<_>>();
+    // The sequence number prefix keeps the recorded order
+    paths.sort();
+
+    let mut exchanges: HashMap<String, VecDeque<Exchange>> = HashMap::new();
+    for path in paths {
+      let content = fs::read_to_string(&path)?;
+      let exchange: Exchange =
+        serde_json::from_str(&content).with_context(|| format!("Invalid recording {}", path.display()))?;
+      exchanges.entry(exchange.name.clone()).or_default().push_back(exchange);
+    }
+
+    info!(
+      "Replaying {} HTTP exchange(s) from {}",
+      exchanges.values().map(VecDeque::len).sum::<usize>(),
+      dir.display()
+    );
+    Ok(Self::Replay(Mutex::new(exchanges)))
+  }
+
+  async fn send(&self, name: &str, request: RequestBuilder) -> reqwest::Result<Response> {
+    match self {
+      Self::Record { dir, seq, redaction } => {
+        let (client, request) = request.build_split();
+        let request = request?;
+        let recorded_request = RecordedRequest {
+          method: request.method().to_string(),
+          url: request.url().to_string(),
+          body: request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(|body| String::from_utf8_lossy(body).into_owned())
+            .unwrap_or_default(),
+        };
+
+        let result = client.execute(request).await;
+        let (response, recorded_response, error) = match result {
+          Ok(response) => {
+            let (response, recorded) = buffer_response(response).await?;
+            (Ok(response), Some(recorded), None)
+          }
+          Err(err) => {
+            let error = err.to_string();
+            (Err(err), None, Some(error))
+          }
+        };
+
+        let exchange = Exchange {
+          name: name.to_string(),
+          request: recorded_request,
+          response: recorded_response,
+          error,
+        };
+        let path = dir.join(format!("{:03}-{}.json", seq.fetch_add(1, Ordering::SeqCst), name));
+        if let Err(err) = write_exchange(&path, &redact_exchange(exchange, redaction)) {
+          warn!("Failed to record the {} exchange to {}: {}", name, path.display(), err);
+        }
+
+        response
+      }
+      Self::Replay(exchanges) => {
+        let exchange = exchanges.lock().unwrap().get_mut(name).and_then(VecDeque::pop_front);
+
+        Ok(replay_response(name, exchange))
+      }
+    }
+  }
+}
+
+/// Read the body so it can be recorded, and rebuild the response around it
+async fn buffer_response(response: Response) -> reqwest::Result<(Response, RecordedResponse)> {
+  let status = response.status();
+  let headers = response.headers().clone();
+  let body = response.bytes().await?;
+
+  let recorded = RecordedResponse {
+    status: status.as_u16(),
+    headers: headers
+      .iter()
+      .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or_default().to_string()))
+      .collect(),
+    body: String::from_utf8_lossy(&body).into_owned(),
+  };
+
+  let mut rebuilt = http::Response::new(body);
+  *rebuilt.status_mut() = status;
+  *rebuilt.headers_mut() = headers;
+
+  Ok((Response::from(rebuilt), recorded))
+}
+
+fn replay_response(name: &str, exchange: Option<Exchange>) -> Response {
+  let recorded = match exchange {
+    Some(Exchange {
+      response: Some(response),
+      ..
+    }) => response,
+    Some(Exchange { error, .. }) => {
+      let error = error.unwrap_or_default();
+      warn!("Replaying the recorded {} network error: {}", name, error);
+      return unavailable_response(&format!("recorded network error: {error}"));
+    }
+    None => {
+      warn!("No recording left for the {} exchange", name);
+      return unavailable_response(&format!("no recording left for {name}"));
+    }
+  };
+
+  info!("Replaying the {} exchange, status: {}", name, recorded.status);
+  let mut builder = http::Response::builder().status(recorded.status);
+  for (name, value) in &recorded.headers {
+    builder = builder.header(name, value);
+  }
+
+  builder
+    .body(recorded.body)
+    .map(Response::from)
+    .unwrap_or_else(|err| unavailable_response(&format!("invalid recording: {err}")))
+}
+
+/// The replay has no network error to return, so a missing exchange is a GlobalProtect error response
+fn unavailable_response(reason: &str) -> Response {
+  let response = http::Response::builder()
+    .status(StatusCode::SERVICE_UNAVAILABLE)
+    .header("x-private-pan-globalprotect", reason)
+    .body(String::new())
+    .expect("the replay error response should be valid");
+
+  Response::from(response)
+}
+
+fn write_exchange(path: &Path, exchange: &Exchange) -> anyhow::Result<()> {
+  fs::write(path, serde_json::to_string_pretty(exchange)?)?;
+  Ok(())
+}
+
+/// The request form params carrying credentials, their values are redacted in the later exchanges too
+const SECRET_PARAMS: &[&str] = &[
+  "passwd",
+  "token",
+  "portal-userauthcookie",
+  "portal-prelogonuserauthcookie",
+  "prelogin-cookie",
+  "authcookie",
+  "cookie",
+];
+
+/// The response elements carrying credentials, they are redacted in the later exchanges too
+const SECRET_ELEMENTS: &[&str] = &[
+  "portal-userauthcookie",
+  "portal-prelogonuserauthcookie",
+  "prelogin-cookie",
+  "authcookie",
+  "saml-request",
+];
+
+fn redact_exchange(mut exchange: Exchange, redaction: &Redaction) -> Exchange {
+  // The form values sent so far may show up in the responses
+  let form = form_urlencoded::parse(exchange.request.body.as_bytes())
+    .map(|(key, value)| (key.into_owned(), value.into_owned()))
+    .collect::<Vec<_>>();
+  let form = form
+    .iter()
+    .map(|(key, value)| (key.as_str(), value.as_str()))
+    .collect::<Vec<_>>();
+  add_secrets(
+    redaction,
+    form
+      .iter()
+      .filter(|(key, _)| SECRET_PARAMS.contains(key))
+      .map(|(_, value)| *value),
+  );
+
+  exchange.request.url = redact_uri(&exchange.request.url);
+  exchange.request.body = redact_form_params(&form);
+
+  if let Some(response) = exchange.response.as_mut() {
+    add_secrets(
+      redaction,
+      secret_element_values(&response.body).iter().map(String::as_str),
+    );
+    response.body = redaction.redact_str(&response.body);
+
+    for (name, value) in response.headers.iter_mut() {
+      if name.eq_ignore_ascii_case("set-cookie") {
+        *value = "[**********]".to_string();
+      } else {
+        *value = redaction.redact_str(value);
+      }
+    }
+  }
+
+  exchange
+}
+
+fn add_secrets<'a>(redaction: &Redaction, values: impl Iterator<Item = &'a str>) {
+  // Short values would redact unrelated text
+  for value in values.filter(|value| value.len() >= 6) {
+    if let Err(err) = redaction.add_value(value) {
+      warn!("Failed to add a value to redact: {}", err);
+    }
+  }
+}
+
+fn secret_element_values(body: &str) -> Vec<String> {
+  let mut values = Vec::new();
+
+  for element in SECRET_ELEMENTS {
+    let pattern = format!("<{element}>([^<]+)</{element}>");
+    let Ok(regex) = Regex::new(&pattern) else {
+      continue;
+    };
+    values.extend(regex.captures_iter(body).map(|captures| captures[1].trim().to_string()));
+  }
+
+  // The gateway login response lists the auth cookie as a bare hex argument
+  if let Ok(regex) = Regex::new("<argument>([0-9a-fA-F]{32,})</argument>") {
+    values.extend(regex.captures_iter(body).map(|captures| captures[1].to_string()));
+  }
+
+  values
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn exchange(body: &str, response_body: &str) -> Exchange {
+    Exchange {
+      name: "login".to_string(),
+      request: RecordedRequest {
+        method: "POST".to_string(),
+        url: "https://vpn.example.com/ssl-vpn/login.esp?user=alice".to_string(),
+        body: body.to_string(),
+      },
+      response: Some(RecordedResponse {
+        status: 200,
+        headers: BTreeMap::from([("set-cookie".to_string(), "SESSID=secret".to_string())]),
+        body: response_body.to_string(),
+      }),
+      error: None,
+    }
+  }
+
+  #[test]
+  fn redacts_the_credentials_of_an_exchange() {
+    let redaction = Redaction::new();
+    let exchange = exchange(
+      "user=alice&passwd=hunter2-secret&clientos=Linux",
+      "<jnlp><argument>0123456789abcdef0123456789abcdef</argument><argument>hunter2-secret</argument>\
+       <argument>10.1.2.3</argument></jnlp>",
+    );
+
+    let redacted = redact_exchange(exchange, &redaction);
+    let json = serde_json::to_string(&redacted).unwrap();
+
+    assert!(!json.contains("hunter2-secret"));
+    assert!(!json.contains("0123456789abcdef0123456789abcdef"));
+    assert!(!json.contains("10.1.2.3"));
+    assert!(!json.contains("SESSID=secret"));
+    assert!(redacted.request.body.contains("clientos=Linux"));
+  }
+
+  #[test]
+  fn only_the_secret_params_are_redacted_later() {
+    let redaction = Redaction::new();
+    redact_exchange(
+      exchange("user=alice&passwd=hunter2-secret&computer=workstation", ""),
+      &redaction,
+    );
+
+    let redacted = redact_exchange(exchange("", "<echo>hunter2-secret on workstation</echo>"), &redaction);
+    let body = redacted.response.unwrap().body;
+
+    assert!(!body.contains("hunter2-secret"));
+    assert!(body.contains("workstation"));
+  }
+
+  #[test]
+  fn keeps_the_order_of_the_form_params() {
+    let redacted = redact_exchange(
+      exchange("user=alice&passwd=secret&clientos=Linux", ""),
+      &Redaction::new(),
+    );
+
+    assert_eq!(
+      redacted.request.body,
+      "user=redacted-5&passwd=redacted-6&clientos=Linux"
+    );
+  }
+
+  #[test]
+  fn secrets_seen_earlier_are_redacted_later() {
+    let redaction = Redaction::new();
+    redact_exchange(
+      exchange(
+        "",
+        "<policy><portal-userauthcookie>portal-cookie-value</portal-userauthcookie></policy>",
+      ),
+      &redaction,
+    );
+
+    let redacted = redact_exchange(exchange("", "<echo>portal-cookie-value</echo>"), &redaction);
+
+    assert!(!redacted.response.unwrap().body.contains("portal-cookie-value"));
+  }
+
+  #[tokio::test]
+  async fn replays_the_recordings_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    for (seq, body) in [(1, "<first/>"), (2, "<second/>")] {
+      let path = dir.path().join(format!("{seq:03}-prelogin.json"));
+      let exchange = Exchange {
+        name: "prelogin".to_string(),
+        ..exchange("", body)
+      };
+      write_exchange(&path, &exchange).unwrap();
+    }
+    let recorder = HttpRecorder::replay(dir.path()).unwrap();
+    let client = reqwest::Client::new();
+
+    for expected in ["<first/>", "<second/>"] {
+      let response = recorder
+        .send("prelogin", client.post("https://vpn.example.com"))
+        .await
+        .unwrap();
+      assert_eq!(response.text().await.unwrap(), expected);
+    }
+
+    let response = recorder
+      .send("prelogin", client.post("https://vpn.example.com"))
+      .await
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+  }
+
+  #[test]
+  fn refuses_a_record_directory_that_is_not_empty() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("001-prelogin.json"), "{}").unwrap();
+
+    assert!(HttpRecorder::record(dir.path().to_path_buf()).is_err());
+  }
+}
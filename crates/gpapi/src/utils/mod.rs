@@ -5,8 +5,10 @@ pub mod checksum;
 pub mod crypto;
 pub mod endpoint;
 pub mod env_utils;
+pub mod http_record;
 pub mod lock_file;
 pub mod openssl;
+pub mod pkcs11;
 pub mod redact;
 pub mod request;
 #[cfg(feature = "tauri")]
@@ -20,6 +22,11 @@ pub use shutdown_signal::shutdown_signal;
 use reqwest::{Response, StatusCode, Url};
 use thiserror::Error;
 
+use crate::{
+  error::ClientUpgradeRequired,
+  portal::client_version::{detect_upgrade_required, xml_error_message},
+};
+
 /// Normalize the server URL to the format `https://<host>:<port>`
 pub fn normalize_server(server: &str) -> anyhow::Result<String> {
   let server = if server.starts_with("https://") || server.starts_with("http://") {
@@ -57,6 +64,13 @@ impl GpError {
   pub fn is_status_error(&self) -> bool {
     self.status.is_client_error() || self.status.is_server_error()
   }
+
+  /// The client upgrade requirement told in the error header or the XML error of the body, an
+  /// HTML error page is not looked into
+  pub(crate) fn client_upgrade_required(&self) -> Option<ClientUpgradeRequired> {
+    detect_upgrade_required(&self.reason)
+      .or_else(|| xml_error_message(&self.body).and_then(|message| detect_upgrade_required(&message)))
+  }
 }
 
 pub async fn parse_gp_response(res: Response) -> anyhow::Result<String, GpError> {
@@ -93,3 +107,40 @@ async fn parse_gp_error(res: Response) -> (String, String) {
 
   (reason, res)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn gp_error(reason: &str, body: &str) -> GpError {
+    GpError {
+      status: StatusCode::FORBIDDEN,
+      reason: reason.to_string(),
+      body: body.to_string(),
+    }
+  }
+
+  #[test]
+  fn upgrade_required_is_read_from_the_header_or_the_xml_error() {
+    assert!(
+      gp_error("Client version is not supported", "")
+        .client_upgrade_required()
+        .is_some()
+    );
+
+    let err = gp_error(
+      "<none>",
+      "<response status=\"error\"><error>Please upgrade your GlobalProtect app</error></response>",
+    );
+    assert!(err.client_upgrade_required().is_some());
+  }
+
+  #[test]
+  fn upgrade_required_is_not_read_from_an_html_page() {
+    let err = gp_error(
+      "<none>",
+      "<html><body>Forbidden. Please upgrade your browser, the minimum version is 100.0</body></html>",
+    );
+    assert!(err.client_upgrade_required().is_none());
+  }
+}
@@ -0,0 +1,144 @@
+use anyhow::{Context, bail};
+
+// Signing the handshake with a key that stays on the token needs rustls, native-tls cannot
+#[cfg(feature = "pkcs11")]
+mod tls;
+
+#[cfg(feature = "pkcs11")]
+pub use tls::client_config;
+
+pub fn is_pkcs11_uri(s: &str) -> bool {
+  s.starts_with("pkcs11:")
+}
+
+/// A parsed RFC 7512 `pkcs11:` URI, only the attributes needed to find a certificate and its key
+#[derive(Debug, Default, PartialEq)]
+pub struct Pkcs11Uri {
+  token: Option<String>,
+  manufacturer: Option<String>,
+  serial: Option<String>,
+  model: Option<String>,
+  object: Option<String>,
+  id: Option<Vec<u8>>,
+  object_type: Option<String>,
+  pin_value: Option<String>,
+  module_path: Option<String>,
+}
+
+impl Pkcs11Uri {
+  pub fn parse(uri: &str) -> anyhow::Result<Self> {
+    let Some(rest) = uri.strip_prefix("pkcs11:") else {
+      bail!("Not a PKCS#11 URI: {}", uri);
+    };
+
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let path_attrs = path.split(';').filter(|attr| !attr.is_empty());
+    let query_attrs = query.split('&').filter(|attr| !attr.is_empty());
+
+    let mut parsed = Self::default();
+    for attr in path_attrs.chain(query_attrs) {
+      let (name, value) = attr
+        .split_once('=')
+        .with_context(|| format!("Invalid PKCS#11 URI attribute: {}", attr))?;
+      let value = urlencoding::decode_binary(value.as_bytes());
+      let text = || String::from_utf8_lossy(&value).into_owned();
+
+      match name {
+        "token" => parsed.token = Some(text()),
+        "manufacturer" => parsed.manufacturer = Some(text()),
+        "serial" => parsed.serial = Some(text()),
+        "model" => parsed.model = Some(text()),
+        "object" => parsed.object = Some(text()),
+        "id" => parsed.id = Some(value.into_owned()),
+        "type" => parsed.object_type = Some(text()),
+        "pin-value" => parsed.pin_value = Some(text()),
+        "module-path" => parsed.module_path = Some(text()),
+        // Library and slot attributes, and vendor extensions are not needed to find the objects
+        _ => {}
+      }
+    }
+
+    Ok(parsed)
+  }
+}
+
+/// The key URI to pass to openconnect for the tunnel, carrying the PIN so that the tunnel does not
+/// prompt for it again. The certificate URI is logged, so the PIN goes to the key URI only.
+pub fn tunnel_key_uri(cert: &str, key: Option<&str>, pin: Option<&str>) -> Option<String> {
+  let Some(pin) = pin else {
+    return key.map(ToString::to_string);
+  };
+
+  let key = key.unwrap_or(cert);
+  if !is_pkcs11_uri(key) || key.contains("pin-value=") {
+    return Some(key.to_string());
+  }
+
+  // Point the certificate URI at the private key with the same id and label
+  let (path, query) = key.split_once('?').unwrap_or((key, ""));
+  let path = path
+    .split(';')
+    .map(|attr| if attr == "type=cert" { "type=private" } else { attr })
+    .collect::<Vec<_>>()
+    .join(";");
+
+  let pin = format!("pin-value={}", urlencoding::encode(pin));
+  let query = if query.is_empty() {
+    pin
+  } else {
+    format!("{}&{}", query, pin)
+  };
+
+  Some(format!("{}?{}", path, query))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_pkcs11_uri() {
+    let uri = Pkcs11Uri::parse(
+      "pkcs11:model=PKCS%2315%20emulated;token=PIV%20Card;id=%01%02;object=Cert%20Auth;type=cert?pin-value=123456&module-path=/usr/lib/opensc-pkcs11.so",
+    )
+    .unwrap();
+
+    assert_eq!(uri.token.as_deref(), Some("PIV Card"));
+    assert_eq!(uri.model.as_deref(), Some("PKCS#15 emulated"));
+    assert_eq!(uri.id, Some(vec![1, 2]));
+    assert_eq!(uri.object.as_deref(), Some("Cert Auth"));
+    assert_eq!(uri.object_type.as_deref(), Some("cert"));
+    assert_eq!(uri.pin_value.as_deref(), Some("123456"));
+    assert_eq!(uri.module_path.as_deref(), Some("/usr/lib/opensc-pkcs11.so"));
+  }
+
+  #[test]
+  fn rejects_invalid_uri() {
+    assert!(Pkcs11Uri::parse("/path/to/cert.pem").is_err());
+    assert!(Pkcs11Uri::parse("pkcs11:token").is_err());
+    assert_eq!(Pkcs11Uri::parse("pkcs11:").unwrap(), Pkcs11Uri::default());
+  }
+
+  #[test]
+  fn tunnel_key_uri_carries_pin() {
+    let cert = "pkcs11:token=PIV;id=%01;type=cert";
+
+    assert_eq!(tunnel_key_uri(cert, None, None), None);
+    assert_eq!(
+      tunnel_key_uri(cert, None, Some("12 34")).as_deref(),
+      Some("pkcs11:token=PIV;id=%01;type=private?pin-value=12%2034")
+    );
+    assert_eq!(
+      tunnel_key_uri(cert, Some("pkcs11:id=%02?module-path=/lib/p11.so"), Some("1234")).as_deref(),
+      Some("pkcs11:id=%02?module-path=/lib/p11.so&pin-value=1234")
+    );
+    assert_eq!(
+      tunnel_key_uri(cert, Some("pkcs11:id=%02?pin-value=0000"), Some("1234")).as_deref(),
+      Some("pkcs11:id=%02?pin-value=0000")
+    );
+    assert_eq!(
+      tunnel_key_uri("cert.pem", Some("key.pem"), Some("secret")).as_deref(),
+      Some("key.pem")
+    );
+  }
+}
@@ -0,0 +1,489 @@
+use std::{
+  collections::HashMap,
+  fmt,
+  sync::{Arc, Mutex, OnceLock},
+};
+
+use anyhow::{Context, bail};
+use cryptoki::{
+  context::{CInitializeArgs, CInitializeFlags, Pkcs11},
+  error::{Error as CryptokiError, RvError},
+  mechanism::{Mechanism, MechanismType, rsa::PkcsMgfType, rsa::PkcsPssParams},
+  object::{Attribute, AttributeType, ObjectClass, ObjectHandle},
+  session::{Session, UserType},
+  slot::Slot,
+  types::AuthPin,
+};
+use log::{info, warn};
+use openssl::{
+  bn::BigNum,
+  ecdsa::EcdsaSig,
+  hash::{MessageDigest, hash},
+  nid::Nid,
+  pkey::Id,
+  x509::X509,
+};
+use rustls::{
+  ClientConfig, DigitallySignedStruct, RootCertStore, SignatureAlgorithm, SignatureScheme,
+  client::{
+    ResolvesClientCert,
+    danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+  },
+  crypto::{CryptoProvider, aws_lc_rs, verify_tls12_signature, verify_tls13_signature},
+  pki_types::{CertificateDer, ServerName, UnixTime},
+  sign::{CertifiedKey, Signer, SigningKey},
+};
+
+use super::Pkcs11Uri;
+use crate::utils::request::RequestIdentityError;
+
+/// The p11-kit proxy loads every module registered on the system, e.g., OpenSC for PIV cards
+/// and YubiKeys, or SoftHSM
+const DEFAULT_MODULE: &str = "p11-kit-proxy.so";
+
+impl Pkcs11Uri {
+  fn matches_token(&self, info: &cryptoki::slot::TokenInfo) -> bool {
+    let matches = |expected: &Option<String>, actual: &str| expected.as_deref().is_none_or(|value| value == actual);
+
+    matches(&self.token, info.label())
+      && matches(&self.manufacturer, info.manufacturer_id())
+      && matches(&self.serial, info.serial_number())
+      && matches(&self.model, info.model())
+  }
+
+  fn object_template(&self, class: ObjectClass) -> Vec<Attribute> {
+    let mut template = vec![Attribute::Class(class)];
+    if let Some(id) = &self.id {
+      template.push(Attribute::Id(id.clone()));
+    }
+    if let Some(object) = &self.object {
+      template.push(Attribute::Label(object.as_bytes().to_vec()));
+    }
+    template
+  }
+}
+
+/// Build a TLS config that presents the certificate on the token and signs with its key,
+/// the private key never leaves the token
+pub fn client_config(
+  cert_uri: &str,
+  key_uri: Option<&str>,
+  pin: Option<&str>,
+  ignore_tls_errors: bool,
+) -> anyhow::Result<ClientConfig> {
+  let cert_uri = Pkcs11Uri::parse(cert_uri)?;
+  let key_uri = key_uri.map(Pkcs11Uri::parse).transpose()?;
+  let pin = cert_uri
+    .pin_value
+    .as_deref()
+    .or_else(|| key_uri.as_ref().and_then(|uri| uri.pin_value.as_deref()))
+    .or(pin);
+
+  let module = cert_uri.module_path.as_deref().unwrap_or(DEFAULT_MODULE);
+  let pkcs11 = load_module(module)?;
+  let (slot, token_label) = find_token(&pkcs11, &cert_uri)?;
+
+  let session = pkcs11.open_ro_session(slot)?;
+  login(&pkcs11, &session, slot, &token_label, pin)?;
+
+  let cert_der = find_certificate(&session, &cert_uri)?;
+  let key = find_private_key(&session, &cert_uri, key_uri.as_ref(), &cert_der)?;
+  let key_kind = KeyKind::from_certificate(&cert_der)?;
+  info!("Using the client certificate on PKCS#11 token '{}'", token_label);
+
+  let signing_key = Pkcs11SigningKey {
+    session: Arc::new(Mutex::new(session)),
+    key,
+    kind: key_kind,
+  };
+  let certified_key = CertifiedKey::new(vec![CertificateDer::from(cert_der)], Arc::new(signing_key));
+  let resolver = Arc::new(ClientCertResolver(Arc::new(certified_key)));
+
+  let provider = Arc::new(aws_lc_rs::default_provider());
+  let builder = ClientConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+  let config = if ignore_tls_errors {
+    builder
+      .dangerous()
+      .with_custom_certificate_verifier(Arc::new(NoVerifier(provider)))
+      .with_client_cert_resolver(resolver)
+  } else {
+    builder
+      .with_root_certificates(native_roots())
+      .with_client_cert_resolver(resolver)
+  };
+
+  Ok(config)
+}
+
+/// Modules are loaded and initialized once per process, a second `C_Initialize` fails
+fn load_module(module: &str) -> anyhow::Result<Pkcs11> {
+  static MODULES: OnceLock<Mutex<HashMap<String, Pkcs11>>> = OnceLock::new();
+
+  let mut modules = MODULES.get_or_init(Default::default).lock().unwrap();
+  if let Some(pkcs11) = modules.get(module) {
+    return Ok(pkcs11.clone());
+  }
+
+  let pkcs11 = Pkcs11::new(module).with_context(|| format!("Failed to load PKCS#11 module {}", module))?;
+  match pkcs11.initialize(CInitializeArgs::new(CInitializeFlags::OS_LOCKING_OK)) {
+    Ok(()) | Err(CryptokiError::Pkcs11(RvError::CryptokiAlreadyInitialized, _)) => {}
+    Err(err) => return Err(err).with_context(|| format!("Failed to initialize PKCS#11 module {}", module)),
+  }
+
+  modules.insert(module.to_string(), pkcs11.clone());
+  Ok(pkcs11)
+}
+
+fn find_token(pkcs11: &Pkcs11, uri: &Pkcs11Uri) -> anyhow::Result<(Slot, String)> {
+  for slot in pkcs11.get_slots_with_token()? {
+    let info = pkcs11.get_token_info(slot)?;
+    if uri.matches_token(&info) {
+      return Ok((slot, info.label().to_string()));
+    }
+  }
+
+  bail!("No PKCS#11 token matches the certificate URI")
+}
+
+fn login(pkcs11: &Pkcs11, session: &Session, slot: Slot, token_label: &str, pin: Option<&str>) -> anyhow::Result<()> {
+  if !pkcs11.get_token_info(slot)?.login_required() {
+    return Ok(());
+  }
+
+  let Some(pin) = pin else {
+    bail!(RequestIdentityError::NoPin(token_label.to_string()));
+  };
+
+  match session.login(UserType::User, Some(&AuthPin::from(pin.to_string()))) {
+    Ok(()) | Err(CryptokiError::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => Ok(()),
+    Err(CryptokiError::Pkcs11(RvError::PinIncorrect | RvError::PinLenRange, _)) => {
+      warn!("Incorrect PIN for PKCS#11 token '{}'", token_label);
+      bail!(RequestIdentityError::IncorrectPin(token_label.to_string()))
+    }
+    Err(err) => Err(err).with_context(|| format!("Failed to log in to PKCS#11 token '{}'", token_label)),
+  }
+}
+
+fn find_certificate(session: &Session, uri: &Pkcs11Uri) -> anyhow::Result<Vec<u8>> {
+  let handle = session
+    .find_objects(&uri.object_template(ObjectClass::CERTIFICATE))?
+    .into_iter()
+    .next()
+    .context("No certificate matches the PKCS#11 URI")?;
+
+  match session.get_attributes(handle, &[AttributeType::Value])?.pop() {
+    Some(Attribute::Value(der)) => Ok(der),
+    _ => bail!("Failed to read the certificate from the PKCS#11 token"),
+  }
+}
+
+/// Use the key URI when given, otherwise the key with the same id as the certificate
+fn find_private_key(
+  session: &Session,
+  cert_uri: &Pkcs11Uri,
+  key_uri: Option<&Pkcs11Uri>,
+  cert_der: &[u8],
+) -> anyhow::Result<ObjectHandle> {
+  let template = match key_uri {
+    Some(key_uri) => key_uri.object_template(ObjectClass::PRIVATE_KEY),
+    None => {
+      let cert = session.find_objects(&cert_uri.object_template(ObjectClass::CERTIFICATE))?;
+      let cert_id = cert.into_iter().find_map(|handle| {
+        match session.get_attributes(handle, &[AttributeType::Value, AttributeType::Id]) {
+          Ok(attrs) if matches!(attrs.first(), Some(Attribute::Value(der)) if der == cert_der) => {
+            attrs.into_iter().find_map(|attr| match attr {
+              Attribute::Id(id) => Some(id),
+              _ => None,
+            })
+          }
+          _ => None,
+        }
+      });
+
+      match cert_id {
+        Some(id) => vec![Attribute::Class(ObjectClass::PRIVATE_KEY), Attribute::Id(id)],
+        None => cert_uri.object_template(ObjectClass::PRIVATE_KEY),
+      }
+    }
+  };
+
+  session
+    .find_objects(&template)?
+    .into_iter()
+    .next()
+    .ok_or_else(|| RequestIdentityError::NoKey.into())
+}
+
+fn native_roots() -> RootCertStore {
+  let mut roots = RootCertStore::empty();
+  let result = rustls_native_certs::load_native_certs();
+  for err in &result.errors {
+    warn!("Failed to load system root certificates: {}", err);
+  }
+  let (added, ignored) = roots.add_parsable_certificates(result.certs);
+  info!("Loaded {} system root certificates, ignored {}", added, ignored);
+
+  roots
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KeyKind {
+  Rsa,
+  EcP256,
+  EcP384,
+}
+
+impl KeyKind {
+  fn from_certificate(der: &[u8]) -> anyhow::Result<Self> {
+    let public_key = X509::from_der(der)?.public_key()?;
+    match public_key.id() {
+      Id::RSA => Ok(KeyKind::Rsa),
+      Id::EC => match public_key.ec_key()?.group().curve_name() {
+        Some(Nid::X9_62_PRIME256V1) => Ok(KeyKind::EcP256),
+        Some(Nid::SECP384R1) => Ok(KeyKind::EcP384),
+        curve => bail!("Unsupported EC curve of the client certificate: {:?}", curve),
+      },
+      id => bail!("Unsupported key type of the client certificate: {:?}", id),
+    }
+  }
+
+  /// Schemes in the order of preference
+  fn schemes(self) -> &'static [SignatureScheme] {
+    match self {
+      KeyKind::Rsa => &[
+        SignatureScheme::RSA_PSS_SHA256,
+        SignatureScheme::RSA_PSS_SHA384,
+        SignatureScheme::RSA_PSS_SHA512,
+        SignatureScheme::RSA_PKCS1_SHA256,
+        SignatureScheme::RSA_PKCS1_SHA384,
+        SignatureScheme::RSA_PKCS1_SHA512,
+      ],
+      KeyKind::EcP256 => &[SignatureScheme::ECDSA_NISTP256_SHA256],
+      KeyKind::EcP384 => &[SignatureScheme::ECDSA_NISTP384_SHA384],
+    }
+  }
+}
+
+struct Pkcs11SigningKey {
+  session: Arc<Mutex<Session>>,
+  key: ObjectHandle,
+  kind: KeyKind,
+}
+
+impl fmt::Debug for Pkcs11SigningKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Pkcs11SigningKey").field("kind", &self.kind).finish()
+  }
+}
+
+impl SigningKey for Pkcs11SigningKey {
+  fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+    let scheme = self.kind.schemes().iter().find(|scheme| offered.contains(scheme))?;
+
+    Some(Box::new(Pkcs11Signer {
+      session: Arc::clone(&self.session),
+      key: self.key,
+      scheme: *scheme,
+    }))
+  }
+
+  fn algorithm(&self) -> SignatureAlgorithm {
+    match self.kind {
+      KeyKind::Rsa => SignatureAlgorithm::RSA,
+      KeyKind::EcP256 | KeyKind::EcP384 => SignatureAlgorithm::ECDSA,
+    }
+  }
+}
+
+struct Pkcs11Signer {
+  session: Arc<Mutex<Session>>,
+  key: ObjectHandle,
+  scheme: SignatureScheme,
+}
+
+impl fmt::Debug for Pkcs11Signer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Pkcs11Signer").field("scheme", &self.scheme).finish()
+  }
+}
+
+impl Signer for Pkcs11Signer {
+  fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+    let session = self.session.lock().unwrap();
+    let sign = |mechanism: &Mechanism, data: &[u8]| {
+      session.sign(mechanism, self.key, data).map_err(|err| {
+        warn!("PKCS#11 signing failed: {}", err);
+        rustls::Error::General(format!("PKCS#11 signing failed: {}", err))
+      })
+    };
+
+    match self.scheme {
+      SignatureScheme::ECDSA_NISTP256_SHA256 | SignatureScheme::ECDSA_NISTP384_SHA384 => {
+        // CKM_ECDSA signs a digest and returns r || s, TLS expects the DER encoding
+        let digest = if self.scheme == SignatureScheme::ECDSA_NISTP256_SHA256 {
+          MessageDigest::sha256()
+        } else {
+          MessageDigest::sha384()
+        };
+        let digest = hash(digest, message).map_err(|err| rustls::Error::General(err.to_string()))?;
+        let signature = sign(&Mechanism::Ecdsa, &digest)?;
+        ecdsa_signature_to_der(&signature).map_err(|err| rustls::Error::General(err.to_string()))
+      }
+      scheme => sign(&rsa_mechanism(scheme), message),
+    }
+  }
+
+  fn scheme(&self) -> SignatureScheme {
+    self.scheme
+  }
+}
+
+fn rsa_mechanism(scheme: SignatureScheme) -> Mechanism<'static> {
+  let pss = |hash_alg, mgf, s_len| PkcsPssParams { hash_alg, mgf, s_len };
+
+  match scheme {
+    SignatureScheme::RSA_PSS_SHA256 => {
+      Mechanism::Sha256RsaPkcsPss(pss(MechanismType::SHA256, PkcsMgfType::MGF1_SHA256, 32.into()))
+    }
+    SignatureScheme::RSA_PSS_SHA384 => {
+      Mechanism::Sha384RsaPkcsPss(pss(MechanismType::SHA384, PkcsMgfType::MGF1_SHA384, 48.into()))
+    }
+    SignatureScheme::RSA_PSS_SHA512 => {
+      Mechanism::Sha512RsaPkcsPss(pss(MechanismType::SHA512, PkcsMgfType::MGF1_SHA512, 64.into()))
+    }
+    SignatureScheme::RSA_PKCS1_SHA384 => Mechanism::Sha384RsaPkcs,
+    SignatureScheme::RSA_PKCS1_SHA512 => Mechanism::Sha512RsaPkcs,
+    _ => Mechanism::Sha256RsaPkcs,
+  }
+}
+
+fn ecdsa_signature_to_der(signature: &[u8]) -> anyhow::Result<Vec<u8>> {
+  if signature.is_empty() || signature.len() % 2 != 0 {
+    bail!("Invalid ECDSA signature length: {}", signature.len());
+  }
+
+  let (r, s) = signature.split_at(signature.len() / 2);
+  let sig = EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?;
+
+  Ok(sig.to_der()?)
+}
+
+#[derive(Debug)]
+struct ClientCertResolver(Arc<CertifiedKey>);
+
+impl ResolvesClientCert for ClientCertResolver {
+  fn resolve(&self, _root_hint_subjects: &[&[u8]], sigschemes: &[SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+    self.0.key.choose_scheme(sigschemes)?;
+    Some(Arc::clone(&self.0))
+  }
+
+  fn has_certs(&self) -> bool {
+    true
+  }
+}
+
+/// The counterpart of `danger_accept_invalid_certs` for the preconfigured TLS config
+#[derive(Debug)]
+struct NoVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+  fn verify_server_cert(
+    &self,
+    _end_entity: &CertificateDer<'_>,
+    _intermediates: &[CertificateDer<'_>],
+    _server_name: &ServerName<'_>,
+    _ocsp_response: &[u8],
+    _now: UnixTime,
+  ) -> Result<ServerCertVerified, rustls::Error> {
+    Ok(ServerCertVerified::assertion())
+  }
+
+  fn verify_tls12_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+  }
+
+  fn verify_tls13_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.0.signature_verification_algorithms.supported_schemes()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn converts_raw_ecdsa_signature_to_der() {
+    let mut raw = vec![0u8; 64];
+    raw[31] = 1;
+    raw[63] = 2;
+
+    let der = ecdsa_signature_to_der(&raw).unwrap();
+    let sig = EcdsaSig::from_der(&der).unwrap();
+    assert_eq!(sig.r().to_vec(), vec![1]);
+    assert_eq!(sig.s().to_vec(), vec![2]);
+
+    assert!(ecdsa_signature_to_der(&[1, 2, 3]).is_err());
+  }
+
+  #[test]
+  fn rsa_mechanisms_match_schemes() {
+    assert_eq!(
+      rsa_mechanism(SignatureScheme::RSA_PSS_SHA384).mechanism_type(),
+      MechanismType::SHA384_RSA_PKCS_PSS
+    );
+    assert_eq!(
+      rsa_mechanism(SignatureScheme::RSA_PKCS1_SHA256).mechanism_type(),
+      MechanismType::SHA256_RSA_PKCS
+    );
+  }
+
+  /// Run against a SoftHSM token, e.g.:
+  ///
+  /// ```sh
+  /// softhsm2-util --init-token --free --label gp-test --pin 1234 --so-pin 0000
+  /// p11tool --provider /usr/lib/softhsm/libsofthsm2.so --login --write \
+  ///   --load-privkey key.pem --load-certificate cert.pem --label client --id 01 "pkcs11:token=gp-test"
+  /// GP_PKCS11_TEST_URI="pkcs11:token=gp-test;id=%01?module-path=/usr/lib/softhsm/libsofthsm2.so" \
+  ///   cargo test -p gpapi pkcs11 -- --ignored
+  /// ```
+  #[test]
+  #[ignore]
+  fn signs_with_softhsm_token() {
+    let uri = std::env::var("GP_PKCS11_TEST_URI").unwrap();
+    let pin = std::env::var("GP_PKCS11_TEST_PIN").unwrap_or_else(|_| "1234".to_string());
+
+    let err = client_config(&uri, None, Some("wrong-pin"), false).unwrap_err();
+    assert!(matches!(
+      err.downcast_ref::<RequestIdentityError>(),
+      Some(RequestIdentityError::IncorrectPin(_))
+    ));
+
+    let config = client_config(&uri, None, Some(&pin), false).unwrap();
+    let certified_key = config
+      .client_auth_cert_resolver
+      .resolve(
+        &[],
+        &[SignatureScheme::RSA_PSS_SHA256, SignatureScheme::ECDSA_NISTP256_SHA256],
+      )
+      .unwrap();
+    let signer = certified_key
+      .key
+      .choose_scheme(&[SignatureScheme::RSA_PSS_SHA256, SignatureScheme::ECDSA_NISTP256_SHA256])
+      .unwrap();
+    assert!(!signer.sign(b"message").unwrap().is_empty());
+  }
+}
@@ -124,12 +124,7 @@ pub fn redact_uri(uri: &str) -> String {
 }
 
 pub fn redact_form_params(params: &[(&str, &str)]) -> String {
-  let mut pairs = params
-    .iter()
-    .map(|(key, value)| (*key, redact_form_value(key, value)))
-    .collect::<Vec<_>>();
-
-  pairs.sort_by(|(left, _), (right, _)| left.cmp(right));
+  let pairs = params.iter().map(|(key, value)| (*key, redact_form_value(key, value)));
 
   form_urlencoded::Serializer::new(String::new())
     .extend_pairs(pairs)
@@ -240,7 +235,7 @@ mod tests {
 
     assert_eq!(
       redact_form_params(&params),
-      "csc-support=yes&portal-userauthcookie=redacted-13&prelogin-cookie="
+      "portal-userauthcookie=redacted-13&csc-support=yes&prelogin-cookie="
     );
   }
 
@@ -4,7 +4,9 @@ use anyhow::bail;
 use log::warn;
 use openssl::pkey::PKey;
 use pem::parse_many;
-use reqwest::Identity;
+use reqwest::{ClientBuilder, Identity};
+
+use super::pkcs11;
 
 #[derive(Debug, thiserror::Error)]
 pub enum RequestIdentityError {
@@ -14,6 +16,37 @@ pub enum RequestIdentityError {
   NoPassphrase(&'static str),
   #[error("Failed to decrypt private key")]
   DecryptError(&'static str),
+  #[error("No PIN provided for token {0}")]
+  NoPin(String),
+  #[error("Incorrect PIN for token {0}")]
+  IncorrectPin(String),
+}
+
+/// Configure the client certificate of the HTTP client, the certificate can be a file or a
+/// `pkcs11:` URI of a smart card or token, in which case the passphrase is the PIN
+pub fn with_client_certificate(
+  builder: ClientBuilder,
+  cert: &str,
+  key: Option<&str>,
+  passphrase: Option<&str>,
+  ignore_tls_errors: bool,
+) -> anyhow::Result<ClientBuilder> {
+  if pkcs11::is_pkcs11_uri(cert) {
+    #[cfg(feature = "pkcs11")]
+    {
+      let config = pkcs11::client_config(cert, key, passphrase, ignore_tls_errors)?;
+      return Ok(builder.use_preconfigured_tls(config));
+    }
+    #[cfg(not(feature = "pkcs11"))]
+    {
+      // Only the token TLS config verifies the server itself
+      let _ = ignore_tls_errors;
+      bail!("PKCS#11 certificates are not supported, gpapi is built without the `pkcs11` feature");
+    }
+  }
+
+  let identity = create_identity(cert, key, passphrase)?;
+  Ok(builder.identity(identity))
 }
 
 /// Create an identity object from a certificate and key
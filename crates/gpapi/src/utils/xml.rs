@@ -1,5 +1,78 @@
+use anyhow::bail;
 use xmltree::Element;
 
+/// `xmltree` builds the tree recursively, so a document nested deep enough overflows the stack
+/// and aborts the process. Real responses are a dozen levels deep at most.
+const MAX_DEPTH: usize = 256;
+
+/// Parse an XML document received from a server, rejecting one nested too deeply to parse safely
+pub fn parse(xml: &str) -> anyhow::Result<Element> {
+  let depth = nesting_depth(xml);
+  if depth > MAX_DEPTH {
+    bail!("XML document is nested too deeply: {depth} levels, at most {MAX_DEPTH} are allowed");
+  }
+
+  Ok(Element::parse(xml.as_bytes())?)
+}
+
+/// The deepest element nesting the parser would reach, without building the tree.
+///
+/// Comments, CDATA sections, declarations and quoted attribute values are skipped, so markup
+/// inside them is not counted.
+fn nesting_depth(xml: &str) -> usize {
+  let mut depth = 0usize;
+  let mut max_depth = 0;
+  let mut rest = xml;
+
+  while let Some(start) = rest.find('<') {
+    rest = &rest[start + 1..];
+
+    let terminator = if rest.starts_with("!--") {
+      "-->"
+    } else if rest.starts_with("![CDATA[") {
+      "]]>"
+    } else if rest.starts_with('?') {
+      "?>"
+    } else if rest.starts_with('!') || rest.starts_with('/') {
+      if rest.starts_with('/') {
+        depth = depth.saturating_sub(1);
+      }
+      ">"
+    } else {
+      let (len, self_closing) = scan_start_tag(rest);
+      if !self_closing {
+        depth += 1;
+        max_depth = max_depth.max(depth);
+      }
+      rest = &rest[len..];
+      continue;
+    };
+
+    rest = rest.find(terminator).map_or("", |end| &rest[end + terminator.len()..]);
+  }
+
+  max_depth
+}
+
+/// The length of a start tag up to and including its closing `>`, and whether it closes itself
+fn scan_start_tag(tag: &str) -> (usize, bool) {
+  let mut quote = None;
+  let mut previous = 0u8;
+
+  for (i, byte) in tag.bytes().enumerate() {
+    match (quote, byte) {
+      (Some(q), _) if byte == q => quote = None,
+      (Some(_), _) => {}
+      (None, b'"' | b'\'') => quote = Some(byte),
+      (None, b'>') => return (i + 1, previous == b'/'),
+      _ => {}
+    }
+    previous = byte;
+  }
+
+  (tag.len(), false)
+}
+
 pub trait ElementExt {
   /// Recursively find all descendants with the given name
   fn descendants(&self, name: &str) -> Vec<&Element>;
@@ -85,3 +158,135 @@ impl ElementExt for Element {
     self.attributes.get(name).map(|s| s.as_str())
   }
 }
+
+/// Generators for the documents a server could send, shared by the parsers' property tests
+#[cfg(test)]
+pub(crate) mod strategy {
+  use proptest::{prelude::*, sample::Index};
+
+  /// Text that is awkward for a parser: empty, markup characters, the placeholders servers use
+  /// for missing values, numbers out of range, any Unicode and very long runs
+  pub(crate) fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+      4 => "\\PC{0,24}",
+      2 => "[0-9]{1,24}",
+      1 => Just(String::new()),
+      1 => prop::sample::select(vec!["yes", "no", "Success", "(null)", "-1", "%zz", "<&>\"'", "\u{feff}", "\0"])
+        .prop_map(str::to_string),
+      1 => ("\\PC{1,4}", 1usize..4096).prop_map(|(text, count)| text.repeat(count)),
+    ]
+  }
+
+  /// A well-formed document whose element names are drawn from `names`, so that the trees walk
+  /// the paths a parser looks for, in any order and with any of the fields missing
+  pub(crate) fn document(names: &'static [&'static str]) -> impl Strategy<Value = String> {
+    let leaf = (prop::sample::select(names), attributes(), text())
+      .prop_map(|(name, attributes, text)| format!("<{name}{attributes}>{}</{name}>", escape(&text)));
+
+    leaf.prop_recursive(6, 48, 6, move |inner| {
+      (
+        prop::sample::select(names),
+        attributes(),
+        prop::collection::vec(inner, 0..6),
+      )
+        .prop_map(|(name, attributes, children)| format!("<{name}{attributes}>{}</{name}>", children.concat()))
+    })
+  }
+
+  /// A document from [`document`] that may be damaged: cut short, with markup left unescaped or
+  /// a closing tag lost, or declared in an encoding it is not in
+  pub(crate) fn malformed(names: &'static [&'static str]) -> impl Strategy<Value = String> {
+    (document(names), 0..5u8, any::<Index>()).prop_map(|(doc, damage, index)| match damage {
+      0 => doc,
+      1 => doc.chars().take(index.index(doc.chars().count() + 1)).collect(),
+      2 => doc.replace("&amp;", "&").replace("&lt;", "<"),
+      3 => doc.replacen("</", "<", 1),
+      _ => format!("<?xml version=\"1.0\" encoding=\"UTF-16\"?>\u{feff}{doc}"),
+    })
+  }
+
+  fn attributes() -> impl Strategy<Value = String> {
+    prop::collection::vec((prop::sample::select(vec!["name", "status"]), text()), 0..2).prop_map(|attributes| {
+      attributes
+        .iter()
+        .map(|(key, value)| format!(" {key}=\"{}\"", escape(value)))
+        .collect()
+    })
+  }
+
+  fn escape(text: &str) -> String {
+    text
+      .chars()
+      .filter(|c| !c.is_control() || c.is_whitespace())
+      .collect::<String>()
+      .replace('&', "&amp;")
+      .replace('<', "&lt;")
+      .replace('>', "&gt;")
+      .replace('"', "&quot;")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_a_document_within_the_depth_limit() {
+    let xml = "<a>".repeat(MAX_DEPTH) + &"</a>".repeat(MAX_DEPTH);
+
+    assert!(parse(&xml).is_ok());
+  }
+
+  #[test]
+  fn rejects_a_document_nested_too_deeply_instead_of_overflowing_the_stack() {
+    let xml = "<a>".repeat(100_000) + &"</a>".repeat(100_000);
+
+    let err = parse(&xml).unwrap_err();
+
+    assert!(err.to_string().contains("nested too deeply"), "{err}");
+  }
+
+  #[test]
+  fn nesting_depth_counts_only_open_elements() {
+    assert_eq!(nesting_depth(""), 0);
+    assert_eq!(nesting_depth("<?xml version=\"1.0\"?><a><b/><c><d/></c></a>"), 2);
+    assert_eq!(nesting_depth("<a></a><b></b><c/>"), 1);
+  }
+
+  #[test]
+  fn nesting_depth_skips_markup_in_comments_cdata_and_attributes() {
+    assert_eq!(nesting_depth("<a><!-- <b><c> --></a>"), 1);
+    assert_eq!(nesting_depth("<a><![CDATA[<b><c>]]></a>"), 1);
+    assert_eq!(nesting_depth("<a x=\"/>\" y='>'><b></b></a>"), 2);
+    assert_eq!(nesting_depth("<!DOCTYPE a><a><b></b></a>"), 2);
+  }
+
+  #[test]
+  fn nesting_depth_is_not_lowered_by_close_tags_in_comments() {
+    let xml = "<a><!--".to_string() + &"</a>".repeat(10) + "-->" + &"<a>".repeat(10);
+
+    assert_eq!(nesting_depth(&xml), 11);
+  }
+
+  mod prop_tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+      #[test]
+      fn parse_never_panics_on_arbitrary_input(input in "\\PC{0,512}") {
+        let _ = parse(&input);
+      }
+
+      #[test]
+      fn parse_accepts_every_generated_document(doc in strategy::document(&["a", "b", "c"])) {
+        prop_assert!(parse(&doc).is_ok(), "{}", doc);
+      }
+
+      #[test]
+      fn parse_never_panics_on_damaged_documents(doc in strategy::malformed(&["a", "b", "c"])) {
+        let _ = parse(&doc);
+      }
+    }
+  }
+}
@@ -1,6 +1,9 @@
 use crate::Vpn;
 use log::{debug, info, trace, warn};
-use std::ffi::{c_char, c_int, c_long, c_void};
+use std::{
+  ffi::{c_char, c_int, c_long, c_void},
+  ptr::NonNull,
+};
 
 /// ConnectOptions struct for FFI, the field names and order must match the C definition.
 #[repr(C)]
@@ -39,6 +42,19 @@ pub(crate) struct ConnectOptions {
   pub no_xmlpost: u32,
 }
 
+// Events reported by the C code, must match `enum vpn_event`
+pub(crate) const VPN_EVENT_CONNECTING: c_int = 0;
+pub(crate) const VPN_EVENT_TUNNEL_UP: c_int = 1;
+pub(crate) const VPN_EVENT_RECONNECTING: c_int = 2;
+pub(crate) const VPN_EVENT_RECONNECTED: c_int = 3;
+pub(crate) const VPN_EVENT_PAUSED: c_int = 4;
+
+// How the mainloop ended, must match `enum vpn_exit`
+pub(crate) const VPN_EXIT_CANCELLED: c_int = 0;
+pub(crate) const VPN_EXIT_AUTH_FAILED: c_int = 1;
+pub(crate) const VPN_EXIT_REMOTE_CLOSED: c_int = 2;
+pub(crate) const VPN_EXIT_TIMEOUT: c_int = 3;
+
 #[repr(C)]
 #[derive(Debug)]
 pub(crate) struct VpnSessionInfoRaw {
@@ -47,44 +63,128 @@ pub(crate) struct VpnSessionInfoRaw {
   pub user_expires: c_long,
   pub lifetime_warning_prior: c_int,
   pub lifetime_warning_message: *const c_char,
+  pub client_ip: *const c_char,
+  pub client_ipv6: *const c_char,
+
+  pub netmask: *const c_char,
+  pub netmask6: *const c_char,
+  pub dns: [*const c_char; 3],
+  pub domain: *const c_char,
+  pub mtu: c_int,
+  pub split_includes: *const SplitInclude,
+  pub split_excludes: *const SplitInclude,
+}
+
+/// `struct oc_split_include` from openconnect.h, a linked list of routes
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct SplitInclude {
+  pub route: *const c_char,
+  pub next: *const SplitInclude,
 }
 
 #[link(name = "vpn")]
 unsafe extern "C" {
-  #[link_name = "vpn_connect"]
-  fn vpn_connect(
+  #[link_name = "vpn_new"]
+  fn vpn_new(
     options: *const ConnectOptions,
-    callback: extern "C" fn(i32, *const VpnSessionInfoRaw, *mut c_void),
-  ) -> c_int;
+    callback: extern "C" fn(c_int, *const VpnSessionInfoRaw, *mut c_void),
+  ) -> *mut c_void;
+
+  #[link_name = "vpn_run"]
+  fn vpn_run(instance: *mut c_void) -> c_int;
 
   #[link_name = "vpn_disconnect"]
-  fn vpn_disconnect();
+  fn vpn_disconnect(instance: *mut c_void);
+
+  #[link_name = "vpn_pause"]
+  fn vpn_pause(instance: *mut c_void);
+
+  #[link_name = "vpn_reconnect"]
+  fn vpn_reconnect(instance: *mut c_void);
+
+  #[link_name = "vpn_free"]
+  fn vpn_free(instance: *mut c_void);
+
+  #[link_name = "vpn_exit_kind"]
+  fn vpn_exit_kind(ret: c_int) -> c_int;
 }
 
-pub(crate) fn connect(options: &ConnectOptions) -> i32 {
-  unsafe { vpn_connect(options, on_vpn_connected) }
+/// The per-connection state owned by the C code, freed when dropped.
+pub(crate) struct VpnInstance(NonNull<c_void>);
+
+// The instance is only written by `vpn_run`, the other threads only write to its command pipe
+unsafe impl Send for VpnInstance {}
+unsafe impl Sync for VpnInstance {}
+
+impl VpnInstance {
+  /// The options must stay alive until the instance is dropped
+  pub(crate) fn new(options: &ConnectOptions) -> Option<Self> {
+    NonNull::new(unsafe { vpn_new(options, on_vpn_event) }).map(Self)
+  }
+
+  pub(crate) fn run(&self) -> i32 {
+    unsafe { vpn_run(self.0.as_ptr()) }
+  }
+
+  pub(crate) fn disconnect(&self) {
+    unsafe { vpn_disconnect(self.0.as_ptr()) }
+  }
+
+  pub(crate) fn pause(&self) {
+    unsafe { vpn_pause(self.0.as_ptr()) }
+  }
+
+  pub(crate) fn reconnect(&self) {
+    unsafe { vpn_reconnect(self.0.as_ptr()) }
+  }
 }
 
-pub(crate) fn disconnect() {
-  unsafe { vpn_disconnect() }
+impl Drop for VpnInstance {
+  fn drop(&mut self) {
+    unsafe { vpn_free(self.0.as_ptr()) }
+  }
+}
+
+/// Classify the value returned by `vpn_run`, see `enum vpn_exit`
+pub(crate) fn exit_kind(ret: i32) -> i32 {
+  unsafe { vpn_exit_kind(ret) }
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn on_vpn_connected(pipe_fd: i32, session_info: *const VpnSessionInfoRaw, vpn: *mut c_void) {
+extern "C" fn on_vpn_event(event: c_int, session_info: *const VpnSessionInfoRaw, vpn: *mut c_void) {
   let vpn = unsafe { &*(vpn as *const Vpn) };
-  vpn.on_connected(pipe_fd, crate::vpn::session_info_from_raw(session_info));
+  vpn.on_event(event, session_info);
+}
+
+// Progress messages of a connection, logged and kept as hints for the disconnect reason
+#[unsafe(no_mangle)]
+extern "C" fn vpn_progress(level: i32, message: *const c_char, vpn: *mut c_void) {
+  let message = log_message(message);
+  log(level, message);
+
+  if !vpn.is_null() {
+    let vpn = unsafe { &*(vpn as *const Vpn) };
+    vpn.on_progress(message);
+  }
 }
 
 // Logger used in the C code.
-// level: 0 = error, 1 = info, 2 = debug, 3 = trace
-// map the error level log in openconnect to the warning level
 #[unsafe(no_mangle)]
 extern "C" fn vpn_log(level: i32, message: *const c_char) {
+  log(level, log_message(message));
+}
+
+fn log_message<'a>(message: *const c_char) -> &'a str {
   let message = unsafe { std::ffi::CStr::from_ptr(message) };
   let message = message.to_str().unwrap_or("Invalid log message");
   // Strip the trailing newline
-  let message = message.trim_end_matches('\n');
+  message.trim_end_matches('\n')
+}
 
+// level: 0 = error, 1 = info, 2 = debug, 3 = trace
+// map the error level log in openconnect to the warning level
+fn log(level: i32, message: &str) {
   if level == 0 {
     warn!("{}", message);
   } else if level == 1 {
@@ -1,5 +1,7 @@
+#include <errno.h>
 #include <openconnect.h>
 #include <stdarg.h>
+#include <stdatomic.h>
 #include <stdio.h>
 #include <stdlib.h>
 #include <sys/utsname.h>
@@ -7,16 +9,35 @@
 
 #include "vpn.h"
 
-void *g_user_data;
+struct vpn_instance {
+	struct openconnect_info *vpninfo;
+	void *user_data;
+
+	int cmd_pipe_fd;
+	const char *vpnc_script;
+	const char *vpnc_interface;
+	int script_tun;
+	int reconnect_timeout;
+	int no_dtls;
+
+	/* Wakes up the run loop while the connection is paused */
+	int resume_fds[2];
+	atomic_int pause_state;
+
+	vpn_event_callback on_event;
+};
+
+enum pause_state {
+	PAUSE_NONE = 0,
+	PAUSE_REQUESTED = 1,
+	PAUSE_WAITING = 2,
+};
 
-static int g_cmd_pipe_fd;
-static const char *g_vpnc_script;
-static const char *g_vpnc_interface;
-static int g_script_tun;
-static vpn_connected_callback on_vpn_connected;
+#define RESUME_CMD_RECONNECT 'r'
+#define RESUME_CMD_CANCEL 'x'
 
 /* Validate the peer certificate */
-static int validate_peer_cert(__attribute__((unused)) void *_vpninfo,
+static int validate_peer_cert(__attribute__((unused)) void *_instance,
 			      const char *reason)
 {
 	INFO("Accepting the server certificate though %s", reason);
@@ -24,9 +45,11 @@ static int validate_peer_cert(__attribute__((unused)) void *_vpninfo,
 }
 
 /* Print progress messages */
-static void print_progress(__attribute__((unused)) void *_vpninfo, int level,
-			   const char *format, ...)
+static void print_progress(void *_instance, int level, const char *format,
+			   ...)
 {
+	vpn_instance *instance = (vpn_instance *)_instance;
+
 	va_list args;
 	va_start(args, format);
 	char *message = format_message(format, args);
@@ -35,54 +58,134 @@ static void print_progress(__attribute__((unused)) void *_vpninfo, int level,
 	if (message == NULL) {
 		ERROR("Failed to format log message");
 	} else {
-		vpn_log(level, message);
+		vpn_progress(level, message, instance->user_data);
 		free(message);
 	}
 }
 
-static void setup_tun_handler(void *_vpninfo)
+static void write_cmd(int fd, char cmd)
 {
+	if (write(fd, &cmd, 1) < 0) {
+		ERROR("Failed to write to command pipe, VPN command %c may not "
+		      "be applied",
+		      cmd);
+	}
+}
+
+static void notify_session(vpn_instance *instance, int event)
+{
+	struct openconnect_info *vpninfo = instance->vpninfo;
+	const struct oc_ip_info *ip_info = NULL;
+	openconnect_get_ip_info(vpninfo, &ip_info, NULL, NULL);
+
+	vpn_session_info session_info = {
+		.auth_expiration = (long)openconnect_get_auth_expiration(vpninfo),
+		.lifetime_secs = openconnect_get_gp_session_lifetime(vpninfo),
+		.user_expires = (long)openconnect_get_gp_user_expires(vpninfo),
+		.lifetime_warning_prior =
+		    openconnect_get_gp_lifetime_notify_prior(vpninfo),
+		.lifetime_warning_message =
+		    openconnect_get_gp_lifetime_notify_message(vpninfo),
+		.client_ip = ip_info ? ip_info->addr : NULL,
+		.client_ipv6 = ip_info ? ip_info->addr6 : NULL,
+	};
+
+	if (ip_info) {
+		session_info.netmask = ip_info->netmask;
+		session_info.netmask6 = ip_info->netmask6;
+		for (int i = 0; i < 3; i++) {
+			session_info.dns[i] = ip_info->dns[i];
+		}
+		session_info.domain = ip_info->domain;
+		session_info.mtu = ip_info->mtu;
+		session_info.split_includes = ip_info->split_includes;
+		session_info.split_excludes = ip_info->split_excludes;
+	}
+	instance->on_event(event, &session_info, instance->user_data);
+}
+
+static void notify(vpn_instance *instance, int event)
+{
+	instance->on_event(event, NULL, instance->user_data);
+}
+
+/* The instance is registered as the openconnect privdata, so every callback
+ * receives it instead of the openconnect_info */
+static void setup_tun_handler(void *_instance)
+{
+	vpn_instance *instance = _instance;
+	struct openconnect_info *_vpninfo = instance->vpninfo;
 	int ret;
-	if (g_script_tun) {
-		ret = openconnect_setup_tun_script(_vpninfo, g_vpnc_script);
+	if (instance->script_tun) {
+		ret = openconnect_setup_tun_script(_vpninfo,
+						   instance->vpnc_script);
 	} else {
-		ret = openconnect_setup_tun_device(_vpninfo, g_vpnc_script,
-						   g_vpnc_interface);
+		ret = openconnect_setup_tun_device(_vpninfo,
+						   instance->vpnc_script,
+						   instance->vpnc_interface);
 	}
 
 	if (!ret) {
-		vpn_session_info session_info = {
-			.auth_expiration = (long)openconnect_get_auth_expiration(_vpninfo),
-			.lifetime_secs = openconnect_get_gp_session_lifetime(_vpninfo),
-			.user_expires = (long)openconnect_get_gp_user_expires(_vpninfo),
-			.lifetime_warning_prior =
-			    openconnect_get_gp_lifetime_notify_prior(_vpninfo),
-			.lifetime_warning_message =
-			    openconnect_get_gp_lifetime_notify_message(_vpninfo),
-		};
-		on_vpn_connected(g_cmd_pipe_fd, &session_info, g_user_data);
+		INFO("Connected to VPN, pipe_fd: %d", instance->cmd_pipe_fd);
+		notify_session(instance, VPN_EVENT_TUNNEL_UP);
 	}
 }
 
-/* Initialize VPN connection */
-int vpn_connect(const vpn_options *options, vpn_connected_callback callback)
+/* Called after the session is re-established, the gateway may have changed
+ * the addresses or the session lifetime */
+static void reconnected_handler(void *_instance)
+{
+	notify_session(_instance, VPN_EVENT_RECONNECTED);
+}
+
+/* Block a paused connection until it is resumed, returns 0 if cancelled */
+static int wait_for_resume(vpn_instance *instance)
+{
+	char cmd;
+	ssize_t len;
+
+	do {
+		len = read(instance->resume_fds[0], &cmd, 1);
+	} while (len < 0 && errno == EINTR);
+
+	atomic_store(&instance->pause_state, PAUSE_NONE);
+	return len == 1 && cmd == RESUME_CMD_RECONNECT;
+}
+
+/* Create a VPN instance, the options must outlive it */
+vpn_instance *vpn_new(const vpn_options *options, vpn_event_callback callback)
 {
+	vpn_instance *instance;
 	struct openconnect_info *vpninfo;
 	struct utsname utsbuf;
 	const char *effective_local_hostname = NULL;
 
-	g_user_data = options->user_data;
-	g_vpnc_script = options->script;
-	g_vpnc_interface = options->interface;
-	g_script_tun = options->script_tun;
-	on_vpn_connected = callback;
+	instance = calloc(1, sizeof(*instance));
+	if (!instance) {
+		ERROR("Failed to allocate the VPN instance");
+		return NULL;
+	}
+
+	instance->user_data = options->user_data;
+	instance->cmd_pipe_fd = -1;
+	instance->vpnc_script = options->script;
+	instance->vpnc_interface = options->interface;
+	instance->script_tun = options->script_tun;
+	instance->reconnect_timeout = options->reconnect_timeout;
+	instance->no_dtls = options->no_dtls;
+	instance->resume_fds[0] = -1;
+	instance->resume_fds[1] = -1;
+	atomic_init(&instance->pause_state, PAUSE_NONE);
+	instance->on_event = callback;
 
 	INFO("USER_AGENT: %s", options->user_agent);
 	INFO("OS: %s", options->os);
 	INFO("CLIENT_VERSION: %s", options->client_version);
 	INFO("HOST_ID: %s", options->host_id ? options->host_id : "(not set)");
 	INFO("VPNC_SCRIPT: %s", options->script);
-	INFO("SCRIPT_TUN: %d", g_script_tun);
+	INFO("INTERFACE: %s",
+	     options->interface ? options->interface : "(not set)");
+	INFO("SCRIPT_TUN: %d", options->script_tun);
 	INFO("CSD_USER: %d", options->csd_uid);
 	INFO("CSD_WRAPPER: %s", options->csd_wrapper);
 	INFO("RECONNECT_TIMEOUT: %d", options->reconnect_timeout);
@@ -94,13 +197,16 @@ int vpn_connect(const vpn_options *options, vpn_connected_callback callback)
 
 	vpninfo =
 	    openconnect_vpninfo_new(options->user_agent, validate_peer_cert,
-				    NULL, NULL, print_progress, NULL);
+				    NULL, NULL, print_progress, instance);
 
 	if (!vpninfo) {
 		ERROR("openconnect_vpninfo_new failed");
-		return 1;
+		free(instance);
+		return NULL;
 	}
 
+	instance->vpninfo = vpninfo;
+
 	openconnect_set_loglevel(vpninfo, PRG_TRACE);
 	openconnect_init_ssl();
 	openconnect_set_protocol(vpninfo, "gp");
@@ -168,48 +274,149 @@ int vpn_connect(const vpn_options *options, vpn_connected_callback callback)
 		openconnect_set_dpd(vpninfo, options->dpd_interval);
 	}
 
-	g_cmd_pipe_fd = openconnect_setup_cmd_pipe(vpninfo);
-	if (g_cmd_pipe_fd < 0) {
+	instance->cmd_pipe_fd = openconnect_setup_cmd_pipe(vpninfo);
+	if (instance->cmd_pipe_fd < 0) {
 		ERROR("openconnect_setup_cmd_pipe failed");
-		return 1;
+		vpn_free(instance);
+		return NULL;
+	}
+
+	if (pipe(instance->resume_fds) < 0) {
+		ERROR("Failed to create the resume pipe");
+		vpn_free(instance);
+		return NULL;
 	}
 
+	return instance;
+}
+
+/* Run the VPN connection until it is stopped or fails */
+int vpn_run(vpn_instance *instance)
+{
+	struct openconnect_info *vpninfo = instance->vpninfo;
+
+	notify(instance, VPN_EVENT_CONNECTING);
+
 	// Essential step
 	if (openconnect_make_cstp_connection(vpninfo) != 0) {
 		ERROR("openconnect_make_cstp_connection failed");
 		return 1;
 	}
 
-	if (options->no_dtls || openconnect_setup_dtls(vpninfo, 60) != 0) {
+	if (instance->no_dtls || openconnect_setup_dtls(vpninfo, 60) != 0) {
 		openconnect_disable_dtls(vpninfo);
 	}
 
 	// Essential step
 	openconnect_set_setup_tun_handler(vpninfo, setup_tun_handler);
+	openconnect_set_reconnected_handler(vpninfo, reconnected_handler);
 
 	while (1) {
 		int ret = openconnect_mainloop(vpninfo,
-					       options->reconnect_timeout, 10);
+					       instance->reconnect_timeout, 10);
 
 		if (ret) {
 			INFO("openconnect_mainloop returned %d, exiting", ret);
-			openconnect_vpninfo_free(vpninfo);
 			return ret;
 		}
 
+		int expected = PAUSE_REQUESTED;
+		if (atomic_compare_exchange_strong(&instance->pause_state,
+						   &expected, PAUSE_WAITING)) {
+			INFO("VPN connection paused");
+			notify(instance, VPN_EVENT_PAUSED);
+
+			if (!wait_for_resume(instance)) {
+				INFO("VPN connection cancelled while paused");
+				return -EINTR;
+			}
+		}
+
 		INFO("openconnect_mainloop returned 0, reconnecting");
+		notify(instance, VPN_EVENT_RECONNECTING);
 	}
 }
 
 /* Stop the VPN connection */
-void vpn_disconnect()
+void vpn_disconnect(vpn_instance *instance)
+{
+	INFO("Stopping VPN connection: %d", instance->cmd_pipe_fd);
+
+	write_cmd(instance->cmd_pipe_fd, OC_CMD_CANCEL);
+	// A paused connection is not in the mainloop, wake it up as well
+	write_cmd(instance->resume_fds[1], RESUME_CMD_CANCEL);
+}
+
+/* Pause the VPN connection, the tunnel device is kept until it is resumed */
+void vpn_pause(vpn_instance *instance)
+{
+	int expected = PAUSE_NONE;
+
+	if (!atomic_compare_exchange_strong(&instance->pause_state, &expected,
+					    PAUSE_REQUESTED)) {
+		INFO("VPN connection is already paused");
+		return;
+	}
+
+	INFO("Pausing VPN connection: %d", instance->cmd_pipe_fd);
+	write_cmd(instance->cmd_pipe_fd, OC_CMD_PAUSE);
+}
+
+/* Resume a paused VPN connection, or re-establish a running one */
+void vpn_reconnect(vpn_instance *instance)
+{
+	int expected = PAUSE_WAITING;
+
+	if (atomic_compare_exchange_strong(&instance->pause_state, &expected,
+					   PAUSE_NONE)) {
+		INFO("Resuming VPN connection: %d", instance->cmd_pipe_fd);
+		write_cmd(instance->resume_fds[1], RESUME_CMD_RECONNECT);
+		return;
+	}
+
+	// A pending pause is turned into a reconnect, the pause command is
+	// already on its way to the mainloop
+	expected = PAUSE_REQUESTED;
+	if (atomic_compare_exchange_strong(&instance->pause_state, &expected,
+					   PAUSE_NONE)) {
+		return;
+	}
+
+	INFO("Reconnecting VPN connection: %d", instance->cmd_pipe_fd);
+	write_cmd(instance->cmd_pipe_fd, OC_CMD_PAUSE);
+}
+
+/* Release the VPN instance, the command pipe is closed with the vpninfo */
+void vpn_free(vpn_instance *instance)
 {
-	char cmd = OC_CMD_CANCEL;
+	if (!instance) {
+		return;
+	}
+
+	openconnect_vpninfo_free(instance->vpninfo);
 
-	INFO("Stopping VPN connection: %d", g_cmd_pipe_fd);
+	if (instance->resume_fds[0] >= 0) {
+		close(instance->resume_fds[0]);
+		close(instance->resume_fds[1]);
+	}
+
+	free(instance);
+}
 
-	if (write(g_cmd_pipe_fd, &cmd, 1) < 0) {
-		ERROR("Failed to write to command pipe, VPN connection may not "
-		      "be stopped");
+/* Classify the value returned by vpn_run */
+int vpn_exit_kind(int ret)
+{
+	switch (-ret) {
+	case EINTR:
+	case ECONNABORTED:
+		return VPN_EXIT_CANCELLED;
+	case EPERM:
+		return VPN_EXIT_AUTH_FAILED;
+	case EPIPE:
+		return VPN_EXIT_REMOTE_CLOSED;
+	case ETIMEDOUT:
+		return VPN_EXIT_TIMEOUT;
+	default:
+		return VPN_EXIT_ERROR;
 	}
 }
@@ -9,11 +9,41 @@ typedef struct vpn_session_info {
 	long user_expires;
 	int lifetime_warning_prior;
 	const char *lifetime_warning_message;
+	const char *client_ip;
+	const char *client_ipv6;
+
+	/* The tunnel configuration, as passed to vpnc-script */
+	const char *netmask;
+	const char *netmask6;
+	const char *dns[3];
+	const char *domain;
+	int mtu;
+	const struct oc_split_include *split_includes;
+	const struct oc_split_include *split_excludes;
 } vpn_session_info;
 
-typedef void (*vpn_connected_callback)(int cmd_pipe_fd,
-				       const vpn_session_info *session_info,
-				       void *user_data);
+/* Events reported to the Rust side, the values must match ffi/mod.rs */
+enum vpn_event {
+	VPN_EVENT_CONNECTING = 0,
+	VPN_EVENT_TUNNEL_UP = 1,
+	VPN_EVENT_RECONNECTING = 2,
+	VPN_EVENT_RECONNECTED = 3,
+	VPN_EVENT_PAUSED = 4,
+};
+
+/* How the mainloop ended, the values must match ffi/mod.rs */
+enum vpn_exit {
+	VPN_EXIT_CANCELLED = 0,
+	VPN_EXIT_AUTH_FAILED = 1,
+	VPN_EXIT_REMOTE_CLOSED = 2,
+	VPN_EXIT_TIMEOUT = 3,
+	VPN_EXIT_ERROR = 4,
+};
+
+/* session_info is only set for the tunnel up and reconnected events */
+typedef void (*vpn_event_callback)(int event,
+				   const vpn_session_info *session_info,
+				   void *user_data);
 
 typedef struct vpn_options {
 	void *user_data;
@@ -49,10 +79,20 @@ typedef struct vpn_options {
 	const int no_xmlpost;
 } vpn_options;
 
-int vpn_connect(const vpn_options *options, vpn_connected_callback callback);
-void vpn_disconnect();
+/* Per-connection state, several instances can run in one process */
+typedef struct vpn_instance vpn_instance;
+
+vpn_instance *vpn_new(const vpn_options *options, vpn_event_callback callback);
+int vpn_run(vpn_instance *instance);
+void vpn_disconnect(vpn_instance *instance);
+void vpn_pause(vpn_instance *instance);
+void vpn_reconnect(vpn_instance *instance);
+void vpn_free(vpn_instance *instance);
+int vpn_exit_kind(int ret);
 
 extern void vpn_log(int level, const char *msg);
+/* Like vpn_log, for the openconnect messages of a connection */
+extern void vpn_progress(int level, const char *msg, void *user_data);
 
 static char *format_message(const char *format, va_list args)
 {
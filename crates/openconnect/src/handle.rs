@@ -0,0 +1,282 @@
+use std::{fmt, sync::Arc};
+
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+  ffi,
+  vpn::{Vpn, VpnError, VpnSessionInfo},
+};
+
+/// Progress of a connection started with `Vpn::start`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VpnEvent {
+  /// Establishing the CSTP connection to the gateway
+  Connecting,
+  /// The tunnel device is configured, with the addresses assigned by the gateway
+  TunnelUp(VpnSessionInfo),
+  /// The connection was interrupted and is being re-established
+  Reconnecting,
+  /// The session was re-established, the gateway may have changed the addresses or the lifetime
+  SessionInfoChanged(VpnSessionInfo),
+  /// The connection was paused, the tunnel device is kept until it is reconnected
+  Paused,
+  /// The connection ended, always the last event
+  Disconnected { reason: VpnExitReason, exit_code: i32 },
+}
+
+/// Why a connection ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VpnExitReason {
+  /// Disconnected on request
+  Requested,
+  /// The tunnel was never established
+  ConnectFailed,
+  /// The gateway rejected the session cookie
+  AuthFailed,
+  /// The session reached the lifetime set by the gateway
+  SessionExpired,
+  /// The gateway ended the session
+  GatewayLogout,
+  /// Dead peer detection found the gateway unreachable and reconnecting timed out
+  DpdTimeout,
+  /// The connection was lost and could not be re-established in time
+  NetworkLost,
+  Error,
+}
+
+/// Hints collected while the connection runs, used to tell apart exits with the same return code
+#[derive(Debug, Default)]
+pub(crate) struct ExitHints {
+  dead_peer: bool,
+  session_expired: bool,
+  session_expires_at: Option<u64>,
+}
+
+/// Fragments of the openconnect messages that explain why a connection ended
+const DEAD_PEER_MESSAGES: &[&str] = &["detected dead peer"];
+const SESSION_EXPIRED_MESSAGES: &[&str] = &["session expired", "session has expired", "lifetime expired"];
+
+impl ExitHints {
+  pub(crate) fn observe_message(&mut self, message: &str) {
+    let message = message.to_lowercase();
+
+    if DEAD_PEER_MESSAGES.iter().any(|fragment| message.contains(fragment)) {
+      self.dead_peer = true;
+    }
+
+    if SESSION_EXPIRED_MESSAGES
+      .iter()
+      .any(|fragment| message.contains(fragment))
+    {
+      self.session_expired = true;
+    }
+  }
+
+  /// The session was re-established, earlier failures no longer explain the exit
+  pub(crate) fn reconnected(&mut self) {
+    self.dead_peer = false;
+  }
+
+  pub(crate) fn set_session_expires_at(&mut self, expires_at: Option<u64>) {
+    self.session_expires_at = expires_at;
+  }
+
+  fn session_expired(&self, now: u64) -> bool {
+    // The gateway ends the session around its expiry, not at the exact second
+    const EXPIRY_SLACK_SECS: u64 = 60;
+
+    self.session_expired
+      || self
+        .session_expires_at
+        .is_some_and(|expires_at| now + EXPIRY_SLACK_SECS >= expires_at)
+  }
+}
+
+impl VpnExitReason {
+  pub(crate) fn classify(exit_kind: i32, tunnel_up: bool, requested: bool, hints: &ExitHints, now: u64) -> Self {
+    if requested || exit_kind == ffi::VPN_EXIT_CANCELLED {
+      return Self::Requested;
+    }
+
+    let session_expired = tunnel_up && hints.session_expired(now);
+    match exit_kind {
+      ffi::VPN_EXIT_AUTH_FAILED if session_expired => Self::SessionExpired,
+      ffi::VPN_EXIT_AUTH_FAILED => Self::AuthFailed,
+      _ if !tunnel_up => Self::ConnectFailed,
+      ffi::VPN_EXIT_REMOTE_CLOSED if session_expired => Self::SessionExpired,
+      ffi::VPN_EXIT_REMOTE_CLOSED => Self::GatewayLogout,
+      ffi::VPN_EXIT_TIMEOUT if hints.dead_peer => Self::DpdTimeout,
+      ffi::VPN_EXIT_TIMEOUT => Self::NetworkLost,
+      _ => Self::Error,
+    }
+  }
+}
+
+impl fmt::Display for VpnExitReason {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let reason = match self {
+      Self::Requested => "disconnected on request",
+      Self::ConnectFailed => "failed to establish the tunnel",
+      Self::AuthFailed => "the gateway rejected the session",
+      Self::SessionExpired => "the session has expired",
+      Self::GatewayLogout => "the gateway ended the session",
+      Self::DpdTimeout => "the gateway stopped responding",
+      Self::NetworkLost => "the network connection was lost",
+      Self::Error => "openconnect error",
+    };
+
+    write!(f, "{}", reason)
+  }
+}
+
+/// A running connection, returned by `Vpn::start`
+pub struct VpnHandle {
+  events: mpsc::UnboundedReceiver<VpnEvent>,
+  controller: VpnController,
+}
+
+impl VpnHandle {
+  pub(crate) fn new(events: mpsc::UnboundedReceiver<VpnEvent>, controller: VpnController) -> Self {
+    Self { events, controller }
+  }
+
+  /// The next event, `None` after `VpnEvent::Disconnected` has been received
+  pub async fn next_event(&mut self) -> Option<VpnEvent> {
+    self.events.recv().await
+  }
+
+  /// A controller that can be moved to other tasks
+  pub fn controller(&self) -> VpnController {
+    self.controller.clone()
+  }
+}
+
+/// Sends commands to a running connection through its command pipe
+#[derive(Clone)]
+pub struct VpnController {
+  vpn: Arc<Vpn>,
+  exit_code: watch::Receiver<Option<i32>>,
+}
+
+impl VpnController {
+  pub(crate) fn new(vpn: Arc<Vpn>, exit_code: watch::Receiver<Option<i32>>) -> Self {
+    Self { vpn, exit_code }
+  }
+
+  /// Disconnect and wait for the connection to end, returns the openconnect exit code
+  pub async fn disconnect(&self) -> i32 {
+    self.vpn.disconnect();
+    self.wait().await
+  }
+
+  /// Pause the connection, the tunnel device is kept until `reconnect` is called
+  pub async fn pause(&self) -> Result<(), VpnError> {
+    self.vpn.pause()
+  }
+
+  /// Resume a paused connection, or re-establish a running one
+  pub async fn reconnect(&self) -> Result<(), VpnError> {
+    self.vpn.reconnect()
+  }
+
+  /// Wait for the connection to end, returns the openconnect exit code
+  pub async fn wait(&self) -> i32 {
+    let mut exit_code = self.exit_code.clone();
+    let exit_code = exit_code.wait_for(Option::is_some).await.map(|code| *code);
+
+    // The sender is only dropped after the exit code is sent
+    exit_code.ok().flatten().unwrap_or(1)
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.exit_code.borrow().is_none()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn classify(exit_kind: i32, tunnel_up: bool, requested: bool, hints: &ExitHints) -> VpnExitReason {
+    VpnExitReason::classify(exit_kind, tunnel_up, requested, hints, 1_000)
+  }
+
+  #[test]
+  fn requested_disconnect_wins_over_the_exit_kind() {
+    let reason = classify(ffi::VPN_EXIT_REMOTE_CLOSED, true, true, &ExitHints::default());
+
+    assert_eq!(reason, VpnExitReason::Requested);
+  }
+
+  #[test]
+  fn failures_before_the_tunnel_is_up_are_connect_failures() {
+    let hints = ExitHints::default();
+
+    assert_eq!(
+      classify(ffi::VPN_EXIT_TIMEOUT, false, false, &hints),
+      VpnExitReason::ConnectFailed
+    );
+    assert_eq!(
+      classify(ffi::VPN_EXIT_AUTH_FAILED, false, false, &hints),
+      VpnExitReason::AuthFailed
+    );
+  }
+
+  #[test]
+  fn classifies_exits_after_the_tunnel_is_up() {
+    let hints = ExitHints::default();
+
+    assert_eq!(
+      classify(ffi::VPN_EXIT_AUTH_FAILED, true, false, &hints),
+      VpnExitReason::AuthFailed
+    );
+    assert_eq!(
+      classify(ffi::VPN_EXIT_REMOTE_CLOSED, true, false, &hints),
+      VpnExitReason::GatewayLogout
+    );
+    assert_eq!(
+      classify(ffi::VPN_EXIT_TIMEOUT, true, false, &hints),
+      VpnExitReason::NetworkLost
+    );
+  }
+
+  #[test]
+  fn dead_peer_message_marks_a_dpd_timeout_until_reconnected() {
+    let mut hints = ExitHints::default();
+    hints.observe_message("DPD detected dead peer!");
+
+    assert_eq!(
+      classify(ffi::VPN_EXIT_TIMEOUT, true, false, &hints),
+      VpnExitReason::DpdTimeout
+    );
+
+    hints.reconnected();
+    assert_eq!(
+      classify(ffi::VPN_EXIT_TIMEOUT, true, false, &hints),
+      VpnExitReason::NetworkLost
+    );
+  }
+
+  #[test]
+  fn exits_around_the_session_expiry_are_session_expired() {
+    let mut hints = ExitHints::default();
+    hints.set_session_expires_at(Some(1_030));
+
+    assert_eq!(
+      classify(ffi::VPN_EXIT_REMOTE_CLOSED, true, false, &hints),
+      VpnExitReason::SessionExpired
+    );
+
+    hints.set_session_expires_at(Some(10_000));
+    assert_eq!(
+      classify(ffi::VPN_EXIT_REMOTE_CLOSED, true, false, &hints),
+      VpnExitReason::GatewayLogout
+    );
+
+    hints.observe_message("Session has expired");
+    assert_eq!(
+      classify(ffi::VPN_EXIT_AUTH_FAILED, true, false, &hints),
+      VpnExitReason::SessionExpired
+    );
+  }
+}
@@ -1,6 +1,8 @@
 mod ffi;
+mod handle;
 mod vpn;
 mod vpn_utils;
 
+pub use handle::*;
 pub use vpn::*;
 pub use vpn_utils::*;
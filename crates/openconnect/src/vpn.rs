@@ -1,21 +1,48 @@
 use std::{
-  ffi::{CStr, CString, c_char},
+  ffi::{CStr, CString, c_char, c_int},
   fmt,
-  sync::{Arc, RwLock},
+  sync::{
+    Arc, Mutex, RwLock,
+    atomic::{AtomicBool, Ordering},
+  },
+  thread,
+  time::{SystemTime, UNIX_EPOCH},
 };
 
-use log::info;
+use log::{info, warn};
+use tokio::sync::{mpsc, watch};
 
 use crate::ffi;
-use crate::vpn_utils::{check_executable, find_csd_wrapper, find_vpnc_script};
+use crate::handle::{ExitHints, VpnController, VpnEvent, VpnExitReason, VpnHandle};
+use crate::vpn_utils::{check_executable, find_csd_wrapper, find_vpnc_script, is_valid_netns_name, netns_vpnc_script};
 
-type OnConnectedCallback = Arc<RwLock<Option<Box<dyn FnOnce(VpnSessionInfo) + 'static + Send + Sync>>>>;
+type EventSink = RwLock<Option<Box<dyn Fn(VpnEvent) + 'static + Send + Sync>>>;
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct VpnSessionInfo {
   pub lifetime_secs: Option<u32>,
   pub user_expires: Option<u32>,
   pub lifetime_warning: Option<VpnSessionWarning>,
+  /// The tunnel addresses assigned by the gateway
+  pub client_ip: Option<String>,
+  pub client_ipv6: Option<String>,
+  pub tunnel_info: TunnelInfo,
+}
+
+/// The tunnel configuration pushed by the gateway, the same values vpnc-script receives
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TunnelInfo {
+  pub ipv4: Option<String>,
+  pub netmask: Option<String>,
+  pub ipv6: Option<String>,
+  pub netmask6: Option<String>,
+  pub dns: Vec<String>,
+  pub search_domains: Vec<String>,
+  /// Routes sent through the tunnel, empty for a full tunnel
+  pub split_include: Vec<String>,
+  /// Routes kept off the tunnel
+  pub split_exclude: Vec<String>,
+  pub mtu: Option<u32>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -24,6 +51,13 @@ pub struct VpnSessionWarning {
   pub message: String,
 }
 
+fn now_secs() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_secs())
+    .unwrap_or_default()
+}
+
 pub(crate) fn session_info_from_raw(raw: *const ffi::VpnSessionInfoRaw) -> VpnSessionInfo {
   if raw.is_null() {
     return VpnSessionInfo::default();
@@ -41,9 +75,43 @@ pub(crate) fn session_info_from_raw(raw: *const ffi::VpnSessionInfoRaw) -> VpnSe
       (Some(prior_secs), Some(message)) => Some(VpnSessionWarning { prior_secs, message }),
       _ => None,
     },
+    client_ip: unsafe { optional_c_string(raw.client_ip) }.filter(|ip| !ip.is_empty()),
+    client_ipv6: unsafe { optional_c_string(raw.client_ipv6) }.filter(|ip| !ip.is_empty()),
+    tunnel_info: tunnel_info_from_raw(raw),
+  }
+}
+
+fn tunnel_info_from_raw(raw: &ffi::VpnSessionInfoRaw) -> TunnelInfo {
+  let non_empty = |value: *const c_char| unsafe { optional_c_string(value) }.filter(|value| !value.is_empty());
+
+  TunnelInfo {
+    ipv4: non_empty(raw.client_ip),
+    netmask: non_empty(raw.netmask),
+    ipv6: non_empty(raw.client_ipv6),
+    netmask6: non_empty(raw.netmask6),
+    dns: raw.dns.iter().filter_map(|dns| non_empty(*dns)).collect(),
+    search_domains: non_empty(raw.domain)
+      .map(|domain| domain.split_whitespace().map(ToOwned::to_owned).collect())
+      .unwrap_or_default(),
+    split_include: unsafe { split_routes(raw.split_includes) },
+    split_exclude: unsafe { split_routes(raw.split_excludes) },
+    mtu: positive_i64_to_u32(raw.mtu as i64),
   }
 }
 
+unsafe fn split_routes(mut node: *const ffi::SplitInclude) -> Vec<String> {
+  let mut routes = Vec::new();
+
+  while let Some(split) = unsafe { node.as_ref() } {
+    if let Some(route) = unsafe { optional_c_string(split.route) } {
+      routes.push(route);
+    }
+    node = split.next;
+  }
+
+  routes
+}
+
 unsafe fn optional_c_string(value: *const c_char) -> Option<String> {
   if value.is_null() {
     return None;
@@ -87,7 +155,11 @@ pub struct Vpn {
   dpd_interval: u32,
   no_xmlpost: bool,
 
-  callback: OnConnectedCallback,
+  event_sink: EventSink,
+  instance: RwLock<Option<Arc<ffi::VpnInstance>>>,
+  tunnel_up: AtomicBool,
+  disconnect_requested: AtomicBool,
+  exit_hints: Mutex<ExitHints>,
 }
 
 impl Vpn {
@@ -95,23 +167,145 @@ impl Vpn {
     VpnBuilder::new(server, cookie)
   }
 
+  /// Connect and block until the connection ends, returns the openconnect exit code
   pub fn connect(&self, on_connected: impl FnOnce(VpnSessionInfo) + 'static + Send + Sync) -> i32 {
-    self.callback.write().unwrap().replace(Box::new(on_connected));
+    let on_connected = Mutex::new(Some(on_connected));
+    let (exit_code, _) = self.run(move |event| {
+      if let VpnEvent::TunnelUp(session_info) = event
+        && let Some(on_connected) = on_connected.lock().unwrap().take()
+      {
+        on_connected(session_info);
+      }
+    });
+
+    exit_code
+  }
+
+  /// Connect on a dedicated thread, the returned handle reports the progress as events
+  pub fn start(self) -> VpnHandle {
+    let (event_tx, event_rx) = mpsc::unbounded_channel();
+    let (exit_code_tx, exit_code_rx) = watch::channel(None);
+    let vpn = Arc::new(self);
+    let controller = VpnController::new(Arc::clone(&vpn), exit_code_rx);
+
+    thread::spawn(move || {
+      let sink_tx = event_tx.clone();
+      let (exit_code, reason) = vpn.run(move |event| {
+        sink_tx.send(event).ok();
+      });
+
+      info!("VPN disconnected: {}, exit code: {}", reason, exit_code);
+      event_tx.send(VpnEvent::Disconnected { reason, exit_code }).ok();
+      exit_code_tx.send(Some(exit_code)).ok();
+    });
+
+    VpnHandle::new(event_rx, controller)
+  }
+
+  fn run(&self, sink: impl Fn(VpnEvent) + 'static + Send + Sync) -> (i32, VpnExitReason) {
+    self.event_sink.write().unwrap().replace(Box::new(sink));
+    self.tunnel_up.store(false, Ordering::SeqCst);
+    *self.exit_hints.lock().unwrap() = ExitHints::default();
     let options = self.build_connect_options();
 
-    ffi::connect(&options)
+    let exit_code = match ffi::VpnInstance::new(&options).map(Arc::new) {
+      Some(instance) => {
+        self.instance.write().unwrap().replace(Arc::clone(&instance));
+        // Disconnect was requested before the instance existed
+        if self.disconnect_requested.load(Ordering::SeqCst) {
+          instance.disconnect();
+        }
+
+        let ret = instance.run();
+        self.instance.write().unwrap().take();
+        ret
+      }
+      None => 1,
+    };
+
+    self.event_sink.write().unwrap().take();
+    let reason = VpnExitReason::classify(
+      ffi::exit_kind(exit_code),
+      self.tunnel_up.load(Ordering::SeqCst),
+      self.disconnect_requested.load(Ordering::SeqCst),
+      &self.exit_hints.lock().unwrap(),
+      now_secs(),
+    );
+
+    (exit_code, reason)
   }
 
-  pub(crate) fn on_connected(&self, pipe_fd: i32, session_info: VpnSessionInfo) {
-    info!("Connected to VPN, pipe_fd: {}", pipe_fd);
+  pub(crate) fn on_event(&self, event: c_int, session_info: *const ffi::VpnSessionInfoRaw) {
+    let event = match event {
+      ffi::VPN_EVENT_CONNECTING => VpnEvent::Connecting,
+      ffi::VPN_EVENT_TUNNEL_UP => {
+        self.tunnel_up.store(true, Ordering::SeqCst);
+        let session_info = session_info_from_raw(session_info);
+        self.track_session_expiry(&session_info);
+        VpnEvent::TunnelUp(session_info)
+      }
+      ffi::VPN_EVENT_RECONNECTING => VpnEvent::Reconnecting,
+      ffi::VPN_EVENT_RECONNECTED => {
+        let session_info = session_info_from_raw(session_info);
+        self.exit_hints.lock().unwrap().reconnected();
+        self.track_session_expiry(&session_info);
+        VpnEvent::SessionInfoChanged(session_info)
+      }
+      ffi::VPN_EVENT_PAUSED => VpnEvent::Paused,
+      _ => {
+        warn!("Unknown VPN event: {}", event);
+        return;
+      }
+    };
 
-    if let Some(callback) = self.callback.write().unwrap().take() {
-      callback(session_info);
+    if let Some(sink) = self.event_sink.read().unwrap().as_ref() {
+      sink(event);
     }
   }
 
+  pub(crate) fn on_progress(&self, message: &str) {
+    self.exit_hints.lock().unwrap().observe_message(message);
+  }
+
+  fn track_session_expiry(&self, session_info: &VpnSessionInfo) {
+    let expires_at = session_info.user_expires.map(u64::from).or_else(|| {
+      session_info
+        .lifetime_secs
+        .map(|lifetime| now_secs() + u64::from(lifetime))
+    });
+
+    self.exit_hints.lock().unwrap().set_session_expires_at(expires_at);
+  }
+
+  /// Stop this connection, other `Vpn` instances in the process are not affected
   pub fn disconnect(&self) {
-    ffi::disconnect();
+    self.disconnect_requested.store(true, Ordering::SeqCst);
+
+    match self.instance.read().unwrap().as_ref() {
+      Some(instance) => instance.disconnect(),
+      None => info!("VPN is not running, skip disconnect"),
+    }
+  }
+
+  /// Pause the connection, the tunnel device is kept until `reconnect` is called
+  pub fn pause(&self) -> Result<(), VpnError> {
+    self.running_instance()?.pause();
+    Ok(())
+  }
+
+  /// Resume a paused connection, or re-establish a running one
+  pub fn reconnect(&self) -> Result<(), VpnError> {
+    self.running_instance()?.reconnect();
+    Ok(())
+  }
+
+  fn running_instance(&self) -> Result<Arc<ffi::VpnInstance>, VpnError> {
+    self
+      .instance
+      .read()
+      .unwrap()
+      .clone()
+      .ok_or_else(|| VpnError::new(String::from("VPN is not running")))
   }
 
   fn build_connect_options(&self) -> ffi::ConnectOptions {
@@ -182,6 +376,7 @@ pub struct VpnBuilder {
   script: Option<String>,
   interface: Option<String>,
   script_tun: bool,
+  netns: Option<String>,
 
   user_agent: Option<String>,
   os: Option<String>,
@@ -215,6 +410,7 @@ impl VpnBuilder {
       script: None,
       interface: None,
       script_tun: false,
+      netns: None,
 
       user_agent: None,
       os: None,
@@ -255,6 +451,12 @@ impl VpnBuilder {
     self
   }
 
+  /// Move the tunnel interface into the named network namespace once it is created
+  pub fn netns<T: Into<Option<String>>>(mut self, netns: T) -> Self {
+    self.netns = netns.into();
+    self
+  }
+
   pub fn user_agent<T: Into<Option<String>>>(mut self, user_agent: T) -> Self {
     self.user_agent = user_agent.into();
     self
@@ -355,6 +557,24 @@ impl VpnBuilder {
     }
   }
 
+  fn wrap_script_for_netns(&self, script: &str) -> Result<String, VpnError> {
+    let Some(netns) = &self.netns else {
+      return Ok(script.to_owned());
+    };
+
+    if !is_valid_netns_name(netns) {
+      return Err(VpnError::new(format!("Invalid network namespace name: {}", netns)));
+    }
+
+    if self.script_tun {
+      return Err(VpnError::new(String::from(
+        "Network namespace is not supported with script-tun mode",
+      )));
+    }
+
+    Ok(netns_vpnc_script(script, netns))
+  }
+
   fn determine_csd_wrapper(&self) -> Result<Option<&str>, VpnError> {
     if !self.hip {
       return Ok(None);
@@ -373,7 +593,7 @@ impl VpnBuilder {
   }
 
   pub fn build(self) -> Result<Vpn, VpnError> {
-    let script = self.determine_script()?.to_owned();
+    let script = self.wrap_script_for_netns(self.determine_script()?)?;
     let csd_wrapper = self.determine_csd_wrapper()?.map(|s| s.to_owned());
 
     let user_agent = self.user_agent.unwrap_or_default();
@@ -409,7 +629,11 @@ impl VpnBuilder {
       dpd_interval: self.dpd_interval,
       no_xmlpost: self.no_xmlpost,
 
-      callback: Default::default(),
+      event_sink: Default::default(),
+      instance: Default::default(),
+      tunnel_up: Default::default(),
+      disconnect_requested: Default::default(),
+      exit_hints: Default::default(),
     })
   }
 
@@ -423,15 +647,34 @@ mod tests {
   use super::*;
   use std::ffi::CString;
 
+  fn raw_session_info() -> ffi::VpnSessionInfoRaw {
+    ffi::VpnSessionInfoRaw {
+      auth_expiration: 0,
+      lifetime_secs: 0,
+      user_expires: 0,
+      lifetime_warning_prior: 0,
+      lifetime_warning_message: std::ptr::null(),
+      client_ip: std::ptr::null(),
+      client_ipv6: std::ptr::null(),
+      netmask: std::ptr::null(),
+      netmask6: std::ptr::null(),
+      dns: [std::ptr::null(); 3],
+      domain: std::ptr::null(),
+      mtu: 0,
+      split_includes: std::ptr::null(),
+      split_excludes: std::ptr::null(),
+    }
+  }
+
   #[test]
   fn maps_session_info_from_callback_payload() {
     let message = CString::new("Session expires soon").unwrap();
     let raw = ffi::VpnSessionInfoRaw {
-      auth_expiration: 0,
       lifetime_secs: 43_200,
       user_expires: 1_776_828_409,
       lifetime_warning_prior: 1_800,
       lifetime_warning_message: message.as_ptr(),
+      ..raw_session_info()
     };
 
     let info = session_info_from_raw(&raw);
@@ -451,10 +694,7 @@ mod tests {
   fn falls_back_to_auth_expiration_when_user_expires_is_absent() {
     let raw = ffi::VpnSessionInfoRaw {
       auth_expiration: 1_776_828_409,
-      lifetime_secs: 0,
-      user_expires: 0,
-      lifetime_warning_prior: 0,
-      lifetime_warning_message: std::ptr::null(),
+      ..raw_session_info()
     };
 
     let info = session_info_from_raw(&raw);
@@ -465,8 +705,21 @@ mod tests {
   }
 
   #[test]
-  fn connect_options_include_host_id() {
-    let vpn = Vpn {
+  fn maps_tunnel_addresses_from_callback_payload() {
+    let ip = CString::new("10.0.0.10").unwrap();
+    let raw = ffi::VpnSessionInfoRaw {
+      client_ip: ip.as_ptr(),
+      ..raw_session_info()
+    };
+
+    let info = session_info_from_raw(&raw);
+
+    assert_eq!(info.client_ip.as_deref(), Some("10.0.0.10"));
+    assert_eq!(info.client_ipv6, None);
+  }
+
+  fn test_vpn() -> Vpn {
+    Vpn {
       server: CString::new("gateway.example.com").unwrap(),
       cookie: CString::new("cookie").unwrap(),
       user_agent: CString::new("agent").unwrap(),
@@ -490,12 +743,121 @@ mod tests {
       no_dtls: false,
       dpd_interval: 0,
       no_xmlpost: false,
-      callback: Default::default(),
+      event_sink: Default::default(),
+      instance: Default::default(),
+      tunnel_up: Default::default(),
+      disconnect_requested: Default::default(),
+      exit_hints: Default::default(),
+    }
+  }
+
+  #[test]
+  fn maps_tunnel_info_from_callback_payload() {
+    let ip = CString::new("10.0.0.10").unwrap();
+    let netmask = CString::new("255.255.255.255").unwrap();
+    let dns = CString::new("10.0.0.53").unwrap();
+    let domain = CString::new("corp.example.com example.com").unwrap();
+    let route1 = CString::new("10.0.0.0/255.0.0.0").unwrap();
+    let route2 = CString::new("172.16.0.0/12").unwrap();
+    let exclude = CString::new("10.1.0.0/16").unwrap();
+    let second = ffi::SplitInclude {
+      route: route2.as_ptr(),
+      next: std::ptr::null(),
+    };
+    let first = ffi::SplitInclude {
+      route: route1.as_ptr(),
+      next: &second,
+    };
+    let excluded = ffi::SplitInclude {
+      route: exclude.as_ptr(),
+      next: std::ptr::null(),
     };
+    let raw = ffi::VpnSessionInfoRaw {
+      client_ip: ip.as_ptr(),
+      netmask: netmask.as_ptr(),
+      dns: [dns.as_ptr(), std::ptr::null(), std::ptr::null()],
+      domain: domain.as_ptr(),
+      mtu: 1400,
+      split_includes: &first,
+      split_excludes: &excluded,
+      ..raw_session_info()
+    };
+
+    let tunnel = session_info_from_raw(&raw).tunnel_info;
+
+    assert_eq!(tunnel.ipv4.as_deref(), Some("10.0.0.10"));
+    assert_eq!(tunnel.netmask.as_deref(), Some("255.255.255.255"));
+    assert_eq!(tunnel.ipv6, None);
+    assert_eq!(tunnel.dns, vec!["10.0.0.53"]);
+    assert_eq!(tunnel.search_domains, vec!["corp.example.com", "example.com"]);
+    assert_eq!(tunnel.split_include, vec!["10.0.0.0/255.0.0.0", "172.16.0.0/12"]);
+    assert_eq!(tunnel.split_exclude, vec!["10.1.0.0/16"]);
+    assert_eq!(tunnel.mtu, Some(1400));
+  }
+
+  #[test]
+  fn connect_options_include_host_id() {
+    let vpn = test_vpn();
 
     let options = vpn.build_connect_options();
 
     let host_id = unsafe { CStr::from_ptr(options.host_id) }.to_str().unwrap();
     assert_eq!(host_id, "profile-host-id");
   }
+
+  #[test]
+  fn forwards_events_and_tracks_the_tunnel() {
+    let vpn = test_vpn();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink_events = Arc::clone(&events);
+    vpn
+      .event_sink
+      .write()
+      .unwrap()
+      .replace(Box::new(move |event| sink_events.lock().unwrap().push(event)));
+
+    let ip = CString::new("10.0.0.10").unwrap();
+    let raw = ffi::VpnSessionInfoRaw {
+      client_ip: ip.as_ptr(),
+      ..raw_session_info()
+    };
+
+    vpn.on_event(ffi::VPN_EVENT_CONNECTING, std::ptr::null());
+    vpn.on_event(ffi::VPN_EVENT_TUNNEL_UP, &raw);
+    vpn.on_event(ffi::VPN_EVENT_RECONNECTING, std::ptr::null());
+    vpn.on_event(99, std::ptr::null());
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0], VpnEvent::Connecting);
+    assert!(matches!(&events[1], VpnEvent::TunnelUp(info) if info.client_ip.as_deref() == Some("10.0.0.10")));
+    assert_eq!(events[2], VpnEvent::Reconnecting);
+    assert!(vpn.tunnel_up.load(Ordering::SeqCst));
+  }
+
+  #[test]
+  fn pause_requires_a_running_connection() {
+    let vpn = test_vpn();
+
+    assert!(vpn.pause().is_err());
+    assert!(vpn.reconnect().is_err());
+  }
+
+  #[test]
+  fn netns_wraps_the_vpnc_script() {
+    let builder = Vpn::builder("gateway.example.com", "cookie").netns(Some("corp".to_string()));
+
+    let script = builder.wrap_script_for_netns("/bin/true").unwrap();
+
+    assert!(script.contains("ip netns exec 'corp' '/bin/true'"));
+  }
+
+  #[test]
+  fn netns_is_rejected_with_script_tun() {
+    let builder = Vpn::builder("gateway.example.com", "cookie")
+      .netns(Some("corp".to_string()))
+      .script_tun(true);
+
+    assert!(builder.wrap_script_for_netns("/bin/true").is_err());
+  }
 }
@@ -66,3 +66,94 @@ pub fn check_executable(file: &str) -> Result<(), io::Error> {
 
   Ok(())
 }
+
+/// Directory `ip netns exec` bind-mounts over `/etc` inside a named namespace
+const NETNS_ETC_DIR: &str = "/etc/netns";
+
+/// Whether `name` is usable as a network namespace name. Only a conservative character set
+/// is accepted since the name ends up in the shell command given to openconnect.
+pub fn is_valid_netns_name(name: &str) -> bool {
+  !name.is_empty()
+    && name.len() <= 64
+    && name != "."
+    && name != ".."
+    && name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// The resolv.conf that `ip netns exec <netns>` exposes as `/etc/resolv.conf`
+pub fn netns_resolv_conf(netns: &str) -> String {
+  format!("{}/{}/resolv.conf", NETNS_ETC_DIR, netns)
+}
+
+/// Build the command openconnect runs in place of the vpnc-script when the tunnel lives in a
+/// network namespace.
+///
+/// openconnect creates the tun device in the current namespace and then runs the script with
+/// `/bin/sh -c`. On `connect` the wrapper moves the device into the namespace first, so the
+/// vpnc-script configures addresses and routes there, then writes the namespace resolv.conf
+/// from the DNS settings pushed by the gateway.
+pub fn netns_vpnc_script(script: &str, netns: &str) -> String {
+  let resolv_conf = shell_quote(&netns_resolv_conf(netns));
+  let netns = shell_quote(netns);
+  let script = shell_quote(script);
+
+  format!(
+    r#"if [ "$reason" = connect ]; then
+  ip link set dev "$TUNDEV" netns {netns} || exit 1
+fi
+ip netns exec {netns} {script} || exit $?
+if [ "$reason" = connect ] || [ "$reason" = reconnect ]; then
+  {{
+    for ns in $INTERNAL_IP4_DNS $INTERNAL_IP6_DNS; do
+      echo "nameserver $ns"
+    done
+    if [ -n "$CISCO_DEF_DOMAIN" ]; then
+      echo "search $CISCO_DEF_DOMAIN"
+    fi
+  }} > {resolv_conf}
+fi
+"#
+  )
+}
+
+fn shell_quote(value: &str) -> String {
+  format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn netns_name_rejects_shell_and_path_characters() {
+    assert!(is_valid_netns_name("corp-vpn"));
+    assert!(is_valid_netns_name("vpn_1.test"));
+
+    assert!(!is_valid_netns_name(""));
+    assert!(!is_valid_netns_name(".."));
+    assert!(!is_valid_netns_name("a/b"));
+    assert!(!is_valid_netns_name("vpn; rm -rf /"));
+    assert!(!is_valid_netns_name("it's"));
+  }
+
+  #[test]
+  fn netns_script_moves_device_before_running_vpnc_script() {
+    let script = netns_vpnc_script("/usr/share/vpnc-scripts/vpnc-script", "corp");
+
+    let move_at = script.find(r#"ip link set dev "$TUNDEV" netns 'corp'"#).unwrap();
+    let exec_at = script
+      .find("ip netns exec 'corp' '/usr/share/vpnc-scripts/vpnc-script'")
+      .unwrap();
+    assert!(move_at < exec_at);
+    assert!(script.contains("> '/etc/netns/corp/resolv.conf'"));
+  }
+
+  #[test]
+  fn netns_script_quotes_the_vpnc_script_path() {
+    let script = netns_vpnc_script("/opt/it's here/vpnc-script", "corp");
+
+    assert!(script.contains(r"ip netns exec 'corp' '/opt/it'\''s here/vpnc-script'"));
+  }
+}